@@ -657,4 +657,19 @@ struct RawSegmentedChangelogConfig {
     // `bonsai_changesets_to_include` then every reseeding would add B and it's
     // ancestors to the reseeded segmented changelog.
     8: optional list<string> bonsai_changesets_to_include,
+
+    // Bookmark prefixes whose bookmarks are included in the segmented
+    // changelog during reseeding, e.g. "heads/". Other bookmarks, such as
+    // scratch bookmarks, are not used as heads.
+    9: optional list<string> bookmark_prefixes_to_include,
+
+    // How many ids to reserve after each head when building the segmented
+    // changelog. Defaults to 2^26.
+    10: optional i64 heads_reserve_size,
+
+    // Additional bookmarks to include in the segmented changelog during
+    // reseeding. Unlike `master_bookmark`, these are optional: a bookmark
+    // that does not exist is logged and skipped rather than failing the
+    // rebuild.
+    11: optional list<string> bookmarks_to_include,
 } (rust.exhaustive)
@@ -9,6 +9,9 @@
 
 mod repo;
 
-pub use crate::repo::{save_bonsai_changesets, BlobRepo, BlobRepoInner};
+pub use crate::repo::{
+    save_bonsai_changesets, save_bonsai_changesets_with_ids, BlobRepo, BlobRepoInner,
+    SavedBonsaiChangesets,
+};
 pub use changeset_fetcher::ChangesetFetcher;
 pub use filestore::StoreRequest;
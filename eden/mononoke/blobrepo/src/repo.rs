@@ -6,7 +6,7 @@
  */
 
 use anyhow::{format_err, Error};
-use blobstore::Blobstore;
+use blobstore::{Blobstore, Loadable, LoadableError};
 use bonsai_git_mapping::{ArcBonsaiGitMapping, BonsaiGitMapping};
 use bonsai_globalrev_mapping::{
     ArcBonsaiGlobalrevMapping, BonsaiGlobalrevMapping, BonsaisOrGlobalrevs,
@@ -21,21 +21,22 @@ use bookmarks::{
 use cacheblob::LeaseOps;
 use changeset_fetcher::SimpleChangesetFetcher;
 use changeset_fetcher::{ArcChangesetFetcher, ChangesetFetcher};
-use changesets::{ChangesetInsert, Changesets, ChangesetsRef};
+use changesets::{ChangesetInsert, Changesets, ChangesetsRef, SortOrder};
 use cloned::cloned;
 use context::CoreContext;
 use ephemeral_blobstore::Bubble;
 use filenodes::{ArcFilenodes, Filenodes};
 use filestore::FilestoreConfig;
 use futures::{
-    future::{try_join, BoxFuture},
-    stream::FuturesUnordered,
+    future::{try_join, try_join_all, BoxFuture, TryFutureExt},
+    stream::{self, FuturesUnordered},
     Stream, TryStreamExt,
 };
 use mercurial_mutation::{ArcHgMutationStore, HgMutationStore};
 use metaconfig_types::{DerivedDataConfig, DerivedDataTypesConfig};
 use mononoke_types::{
-    BlobstoreValue, BonsaiChangeset, ChangesetId, Generation, Globalrev, MononokeId, RepositoryId,
+    BlobstoreValue, BonsaiChangeset, ChangesetId, ContentId, Generation, Globalrev, MononokeId,
+    RepositoryId,
 };
 use phases::Phases;
 use pushrebase_mutation_mapping::{ArcPushrebaseMutationMapping, PushrebaseMutationMapping};
@@ -51,16 +52,23 @@ use topo_sort::sort_topological;
 
 define_stats! {
     prefix = "mononoke.blobrepo";
+    all_changeset_ids: timeseries(Rate, Sum),
     changeset_exists_by_bonsai: timeseries(Rate, Sum),
+    changesets_exist: timeseries(Rate, Sum),
     get_bonsai_heads_maybe_stale: timeseries(Rate, Sum),
     get_bonsai_publishing_bookmarks_maybe_stale: timeseries(Rate, Sum),
     get_bookmark: timeseries(Rate, Sum),
     get_bookmarks_by_prefix_maybe_stale: timeseries(Rate, Sum),
     get_changeset_parents_by_bonsai: timeseries(Rate, Sum),
     get_generation_number: timeseries(Rate, Sum),
+    load_changeset_with_parents: timeseries(Rate, Sum),
     update_bookmark_transaction: timeseries(Rate, Sum),
 }
 
+/// Number of changeset ids fetched from the changesets table per query when
+/// streaming all changeset ids.
+const ALL_CHANGESET_IDS_PAGE_SIZE: u64 = 10000;
+
 // NOTE: this structure and its fields are public to enable `DangerousOverride` functionality
 #[facet::container]
 #[derive(Clone)]
@@ -237,6 +245,105 @@ impl BlobRepo {
         Ok(changeset.is_some())
     }
 
+    /// Returns the subset of `changesetids` that exist in the repo, using a single batched
+    /// lookup rather than one query per changeset.
+    pub async fn changesets_exist(
+        &self,
+        ctx: CoreContext,
+        changesetids: &[ChangesetId],
+    ) -> Result<HashSet<ChangesetId>, Error> {
+        STATS::changesets_exist.add_value(1);
+        if changesetids.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let changesets = self
+            .inner
+            .changesets
+            .get_many(ctx, changesetids.to_vec())
+            .await?;
+        Ok(changesets.into_iter().map(|entry| entry.cs_id).collect())
+    }
+
+    /// Streams the ids of all changesets in the repo, in the order in which they were added
+    /// to the changesets table.  The table is read in pages, so memory use is bounded
+    /// regardless of the size of the repo.
+    ///
+    /// Use `all_changeset_ids_after` to resume an interrupted scan.
+    pub fn all_changeset_ids(
+        &self,
+        ctx: CoreContext,
+    ) -> impl Stream<Item = Result<ChangesetId, Error>> + 'static {
+        self.all_changeset_ids_impl(ctx, None)
+    }
+
+    /// Streams the ids of all changesets that were added to the repo after `after`, in the
+    /// same order as `all_changeset_ids`.  Passing the last id returned by an interrupted
+    /// scan resumes it where it left off.
+    pub fn all_changeset_ids_after(
+        &self,
+        ctx: CoreContext,
+        after: ChangesetId,
+    ) -> impl Stream<Item = Result<ChangesetId, Error>> + 'static {
+        self.all_changeset_ids_impl(ctx, Some(after))
+    }
+
+    fn all_changeset_ids_impl(
+        &self,
+        ctx: CoreContext,
+        after: Option<ChangesetId>,
+    ) -> impl Stream<Item = Result<ChangesetId, Error>> + 'static {
+        STATS::all_changeset_ids.add_value(1);
+        let changesets = self.inner.changesets.clone();
+        async move {
+            let known_heads = match after {
+                Some(after) => {
+                    // The bounds silently start from the beginning for unknown commits, so
+                    // check that it exists rather than restarting the scan.
+                    if changesets.get(ctx.clone(), after).await?.is_none() {
+                        return Err(format_err!("Commit {} does not exist in the repo", after));
+                    }
+                    vec![after]
+                }
+                None => vec![],
+            };
+            // Add one to the upper bound to make the range half-open: [min, max).
+            let (min_id, max_id) = match changesets
+                .enumeration_bounds(&ctx, false, known_heads)
+                .await?
+            {
+                Some((min_id, max_id)) => (min_id, max_id + 1),
+                None => (0, 0),
+            };
+
+            let pages = stream::try_unfold(min_id, move |lower| {
+                cloned!(ctx, changesets);
+                async move {
+                    if lower >= max_id {
+                        return Ok(None);
+                    }
+                    let page: Vec<(ChangesetId, u64)> = changesets
+                        .list_enumeration_range(
+                            &ctx,
+                            lower,
+                            max_id,
+                            Some((SortOrder::Ascending, ALL_CHANGESET_IDS_PAGE_SIZE)),
+                            false,
+                        )
+                        .try_collect()
+                        .await?;
+                    let next = match page.last() {
+                        Some((_, id)) if page.len() as u64 == ALL_CHANGESET_IDS_PAGE_SIZE => id + 1,
+                        _ => max_id,
+                    };
+                    let ids = page.into_iter().map(|(cs_id, _)| Ok(cs_id));
+                    Ok::<_, Error>(Some((stream::iter(ids), next)))
+                }
+            });
+            Ok(pages.try_flatten())
+        }
+        .try_flatten_stream()
+    }
+
     pub async fn get_changeset_parents_by_bonsai(
         &self,
         ctx: CoreContext,
@@ -250,6 +357,45 @@ impl BlobRepo {
         Ok(parents)
     }
 
+    /// Loads the bonsai changeset `changesetid` together with the bonsai changesets of its
+    /// parents, in the same order as its parents.  The parents are loaded concurrently as soon
+    /// as the changeset is.
+    ///
+    /// Fails if the changeset does not exist.  A parent that is missing from a changeset that
+    /// exists means that the repo is corrupt, and is reported with a different error.
+    pub async fn load_changeset_with_parents(
+        &self,
+        ctx: CoreContext,
+        changesetid: ChangesetId,
+    ) -> Result<(BonsaiChangeset, Vec<BonsaiChangeset>), Error> {
+        STATS::load_changeset_with_parents.add_value(1);
+        let blobstore = self.blobstore();
+        let changeset = changesetid
+            .load(&ctx, blobstore)
+            .await
+            .map_err(|e| match e {
+                LoadableError::Missing(_) => {
+                    format_err!("Commit {} does not exist in the repo", changesetid)
+                }
+                LoadableError::Error(e) => e,
+            })?;
+        let parents = try_join_all(changeset.parents().map(|parent| {
+            let ctx = &ctx;
+            async move {
+                parent.load(ctx, blobstore).await.map_err(|e| match e {
+                    LoadableError::Missing(_) => format_err!(
+                        "Commit {} has missing parent {}, the repo is corrupt",
+                        changesetid,
+                        parent
+                    ),
+                    LoadableError::Error(e) => e,
+                })
+            }
+        }))
+        .await?;
+        Ok((changeset, parents))
+    }
+
     pub fn get_bonsai_bookmark(
         &self,
         ctx: CoreContext,
@@ -442,6 +588,30 @@ pub async fn save_bonsai_changesets(
     ctx: CoreContext,
     container: &(impl ChangesetsRef + RepoBlobstoreRef),
 ) -> Result<(), Error> {
+    save_bonsai_changesets_with_ids(bonsai_changesets, ctx, container).await?;
+    Ok(())
+}
+
+/// Ids collected by `save_bonsai_changesets_with_ids` while saving changesets.
+#[derive(Clone, Debug, Default)]
+pub struct SavedBonsaiChangesets {
+    /// Bonsai changesets whose blobs were written, in no particular order.
+    pub changeset_ids: Vec<ChangesetId>,
+    /// File contents referenced by the file changes of the saved changesets.
+    pub content_ids: HashSet<ContentId>,
+}
+
+/// Same as `save_bonsai_changesets`, but also returns the ids of the changesets that were
+/// written and of the file contents they reference, so that callers (e.g. replication)
+/// don't need to rescan the blobstore to find them.
+///
+/// Filenodes are not reported: they are derived data, and are written when the hg
+/// changesets are derived rather than by this function.
+pub async fn save_bonsai_changesets_with_ids(
+    bonsai_changesets: Vec<BonsaiChangeset>,
+    ctx: CoreContext,
+    container: &(impl ChangesetsRef + RepoBlobstoreRef),
+) -> Result<SavedBonsaiChangesets, Error> {
     let complete_changesets = container.changesets();
     let blobstore = container.repo_blobstore();
 
@@ -506,24 +676,33 @@ pub async fn save_bonsai_changesets(
             |(_, bcs)| {
                 cloned!(ctx, blobstore);
                 async move {
+                    let content_ids: Vec<ContentId> = bcs
+                        .file_changes()
+                        .filter_map(|(_, fc)| fc.simplify().map(|bc| bc.content_id()))
+                        .collect();
                     let bonsai_blob = bcs.into_blob();
                     let bcs_id = bonsai_blob.id().clone();
                     let blobstore_key = bcs_id.blobstore_key();
                     blobstore
                         .put(&ctx, blobstore_key, bonsai_blob.into())
                         .await?;
-                    Ok(())
+                    Ok((bcs_id, content_ids))
                 }
             }
         })
         .collect::<FuturesUnordered<_>>()
         .try_collect::<Vec<_>>();
 
-    try_join(bonsai_objects, parents_to_check).await?;
+    let (saved_objects, _) = try_join(bonsai_objects, parents_to_check).await?;
 
     for bonsai_complete in bonsai_complete_futs {
         bonsai_complete.await?;
     }
 
-    Ok(())
+    let mut saved = SavedBonsaiChangesets::default();
+    for (bcs_id, content_ids) in saved_objects {
+        saved.changeset_ids.push(bcs_id);
+        saved.content_ids.extend(content_ids);
+    }
+    Ok(saved)
 }
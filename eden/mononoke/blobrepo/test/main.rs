@@ -27,6 +27,7 @@ use context::CoreContext;
 use fbinit::FacebookInit;
 use fixtures::{create_bonsai_changeset, many_files_dirs, merge_uneven};
 use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use futures::stream::TryStreamExt;
 use memblob::Memblob;
 use mercurial_derived_data::get_manifest_from_bonsai;
 use mercurial_types::{
@@ -44,7 +45,7 @@ use mononoke_types::{
 };
 use scuba_ext::MononokeScubaSampleBuilder;
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     sync::Arc,
 };
 use test_repo_factory::TestRepoFactory;
@@ -796,6 +797,168 @@ async fn test_hg_commit_generation_simple(fb: FacebookInit) {
     assert_eq!(map_bcs_id, Some(bcs_id));
 }
 
+#[fbinit::test]
+async fn test_save_bonsai_changesets_with_ids(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let repo: BlobRepo = test_repo_factory::build_empty()?;
+
+    let foo = make_file_change(&ctx, "foo", &repo).await?;
+    let bar = make_file_change(&ctx, "bar", &repo).await?;
+    let first = make_bonsai_changeset(None, None, vec![("foo", foo.clone())]);
+    let second = make_bonsai_changeset(
+        Some(first.get_changeset_id()),
+        None,
+        vec![("bar", bar.clone()), ("foo", FileChange::Deletion)],
+    );
+    let first_id = first.get_changeset_id();
+    let second_id = second.get_changeset_id();
+
+    let mut saved =
+        blobrepo::save_bonsai_changesets_with_ids(vec![first, second], ctx.clone(), &repo).await?;
+    saved.changeset_ids.sort();
+    let mut expected_changeset_ids = vec![first_id, second_id];
+    expected_changeset_ids.sort();
+    assert_eq!(saved.changeset_ids, expected_changeset_ids);
+    assert_eq!(
+        saved.content_ids,
+        [foo, bar]
+            .iter()
+            .map(|fc| fc.simplify().unwrap().content_id())
+            .collect::<HashSet<_>>()
+    );
+
+    for cs_id in expected_changeset_ids {
+        assert!(repo.changeset_exists_by_bonsai(ctx.clone(), cs_id).await?);
+    }
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_changesets_exist(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let repo: BlobRepo = test_repo_factory::build_empty()?;
+
+    let first = make_bonsai_changeset(None, None, vec![]);
+    let second = make_bonsai_changeset(Some(first.get_changeset_id()), None, vec![]);
+    let missing = make_bonsai_changeset(Some(second.get_changeset_id()), None, vec![]);
+    let first_id = first.get_changeset_id();
+    let second_id = second.get_changeset_id();
+    let missing_id = missing.get_changeset_id();
+
+    blobrepo::save_bonsai_changesets(vec![first, second], ctx.clone(), &repo).await?;
+
+    let existing = repo
+        .changesets_exist(ctx.clone(), &[first_id, missing_id, second_id])
+        .await?;
+    assert_eq!(
+        existing,
+        [first_id, second_id].into_iter().collect::<HashSet<_>>()
+    );
+    assert!(repo.changesets_exist(ctx, &[]).await?.is_empty());
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_load_changeset_with_parents(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let repo: BlobRepo = test_repo_factory::build_empty()?;
+
+    let p1 = make_bonsai_changeset(None, None, vec![]);
+    let p2 = make_bonsai_changeset(None, None, vec![("file", FileChange::Deletion)]);
+    let merge = make_bonsai_changeset(
+        Some(p1.get_changeset_id()),
+        Some(p2.get_changeset_id()),
+        vec![],
+    );
+    let merge_id = merge.get_changeset_id();
+    blobrepo::save_bonsai_changesets(
+        vec![p1.clone(), p2.clone(), merge.clone()],
+        ctx.clone(),
+        &repo,
+    )
+    .await?;
+
+    let (changeset, parents) = repo
+        .load_changeset_with_parents(ctx.clone(), merge_id)
+        .await?;
+    assert_eq!(changeset, merge);
+    assert_eq!(parents, vec![p1.clone(), p2]);
+
+    let (changeset, parents) = repo
+        .load_changeset_with_parents(ctx.clone(), p1.get_changeset_id())
+        .await?;
+    assert_eq!(changeset, p1);
+    assert!(parents.is_empty());
+
+    // A missing changeset and a missing parent are reported differently.
+    let orphan_parent = make_bonsai_changeset(None, None, vec![("other", FileChange::Deletion)]);
+    let orphan = make_bonsai_changeset(Some(orphan_parent.get_changeset_id()), None, vec![]);
+    let orphan_id = orphan.into_blob().store(&ctx, repo.blobstore()).await?;
+    let err = repo
+        .load_changeset_with_parents(ctx.clone(), orphan_parent.get_changeset_id())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("does not exist"));
+    let err = repo
+        .load_changeset_with_parents(ctx, orphan_id)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("missing parent"));
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_all_changeset_ids(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let repo: BlobRepo = test_repo_factory::build_empty()?;
+
+    assert!(
+        repo.all_changeset_ids(ctx.clone())
+            .try_collect::<Vec<_>>()
+            .await?
+            .is_empty()
+    );
+
+    let first = make_bonsai_changeset(None, None, vec![]);
+    let second = make_bonsai_changeset(Some(first.get_changeset_id()), None, vec![]);
+    let third = make_bonsai_changeset(Some(second.get_changeset_id()), None, vec![]);
+    let ids = vec![
+        first.get_changeset_id(),
+        second.get_changeset_id(),
+        third.get_changeset_id(),
+    ];
+
+    blobrepo::save_bonsai_changesets(vec![first, second, third], ctx.clone(), &repo).await?;
+
+    let all: Vec<_> = repo.all_changeset_ids(ctx.clone()).try_collect().await?;
+    assert_eq!(all, ids);
+
+    let after_first: Vec<_> = repo
+        .all_changeset_ids_after(ctx.clone(), ids[0])
+        .try_collect()
+        .await?;
+    assert_eq!(after_first, &ids[1..]);
+
+    let after_last: Vec<_> = repo
+        .all_changeset_ids_after(ctx.clone(), ids[2])
+        .try_collect()
+        .await?;
+    assert!(after_last.is_empty());
+
+    let missing = make_bonsai_changeset(Some(ids[2]), None, vec![]);
+    assert!(
+        repo.all_changeset_ids_after(ctx, missing.get_changeset_id())
+            .try_collect::<Vec<_>>()
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
 #[fbinit::test]
 async fn test_hg_commit_generation_stack(fb: FacebookInit) {
     let repo = fixtures::linear::getrepo(fb).await;
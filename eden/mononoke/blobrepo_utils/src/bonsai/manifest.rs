@@ -13,7 +13,7 @@ use blobstore::{Blobstore, Loadable};
 use cacheblob::MemWritesBlobstore;
 use cloned::cloned;
 use context::CoreContext;
-use futures::{future::try_join, FutureExt, TryFutureExt, TryStreamExt};
+use futures::{compat::Future01CompatExt, future::try_join, FutureExt, TryFutureExt, TryStreamExt};
 use futures_ext::{try_boxfuture, BoxFuture, FutureExt as _, StreamExt as _};
 use futures_old::{
     future::{self, Either},
@@ -26,6 +26,7 @@ use mercurial_types::{
     HgChangesetId, HgFileNodeId, HgManifestId, HgNodeHash,
 };
 use mononoke_types::{DateTime, FileType};
+use serde::Serialize;
 use slog::{debug, Logger};
 use std::{collections::HashSet, fmt, sync::Arc};
 
@@ -56,6 +57,85 @@ impl BonsaiMFVerifyResult {
             _ => false,
         }
     }
+
+    /// Build a report of this result that can be serialized, with the changes behind any
+    /// difference listed.
+    pub async fn report(&self, ctx: CoreContext) -> Result<BonsaiMFVerifyReport, Error> {
+        let report = match self {
+            BonsaiMFVerifyResult::Valid {
+                lookup_mf_id,
+                computed_mf_id,
+            } => BonsaiMFVerifyReport::Valid {
+                lookup_mf_id: *lookup_mf_id,
+                computed_mf_id: *computed_mf_id,
+            },
+            BonsaiMFVerifyResult::ValidDifferentId(difference) => {
+                BonsaiMFVerifyReport::ValidDifferentId(difference.report(ctx).await?)
+            }
+            BonsaiMFVerifyResult::Invalid(difference) => {
+                BonsaiMFVerifyReport::Invalid(difference.report(ctx).await?)
+            }
+            BonsaiMFVerifyResult::Ignored(changeset_id) => {
+                BonsaiMFVerifyReport::Ignored(*changeset_id)
+            }
+        };
+        Ok(report)
+    }
+}
+
+/// A `BonsaiMFVerifyResult` as it is written to reports.
+#[derive(Clone, Debug, Serialize)]
+pub enum BonsaiMFVerifyReport {
+    Valid {
+        lookup_mf_id: HgNodeHash,
+        computed_mf_id: HgNodeHash,
+    },
+    ValidDifferentId(BonsaiMFVerifyDifferenceReport),
+    Invalid(BonsaiMFVerifyDifferenceReport),
+    Ignored(HgChangesetId),
+}
+
+/// A `BonsaiMFVerifyDifference` as it is written to reports.
+#[derive(Clone, Debug, Serialize)]
+pub struct BonsaiMFVerifyDifferenceReport {
+    pub lookup_mf_id: HgNodeHash,
+    pub expected_mf_id: HgNodeHash,
+    pub roundtrip_mf_id: HgNodeHash,
+    /// The entries that differ, sorted by path.
+    pub changes: Vec<BonsaiMFVerifyChange>,
+}
+
+/// An entry that differs between the original manifest and the roundtripped one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BonsaiMFVerifyChange {
+    /// The path of the entry, or the empty string for the root.
+    pub path: String,
+    /// The node id of the entry in the original manifest, if it is there.
+    pub lookup_node_id: Option<HgNodeHash>,
+    /// The node id of the entry in the roundtripped manifest, if it is there.
+    pub roundtrip_node_id: Option<HgNodeHash>,
+}
+
+impl BonsaiMFVerifyChange {
+    fn new(diff: Diff<Entry<HgManifestId, (FileType, HgFileNodeId)>>) -> Self {
+        let (path, lookup, roundtrip) = match diff {
+            Diff::Added(path, entry) => (path, None, Some(entry)),
+            Diff::Removed(path, entry) => (path, Some(entry), None),
+            Diff::Changed(path, lookup, roundtrip) => (path, Some(lookup), Some(roundtrip)),
+        };
+        Self {
+            path: path.map_or_else(String::new, |path| path.to_string()),
+            lookup_node_id: lookup.as_ref().map(entry_node_id),
+            roundtrip_node_id: roundtrip.as_ref().map(entry_node_id),
+        }
+    }
+}
+
+fn entry_node_id(entry: &Entry<HgManifestId, (FileType, HgFileNodeId)>) -> HgNodeHash {
+    match entry {
+        Entry::Tree(mf_id) => mf_id.into_nodehash(),
+        Entry::Leaf((_, filenode_id)) => filenode_id.into_nodehash(),
+    }
 }
 
 #[derive(Clone)]
@@ -71,6 +151,20 @@ pub struct BonsaiMFVerifyDifference {
 }
 
 impl BonsaiMFVerifyDifference {
+    pub fn new(
+        repo: BlobRepo,
+        lookup_mf_id: HgNodeHash,
+        expected_mf_id: HgNodeHash,
+        roundtrip_mf_id: HgNodeHash,
+    ) -> Self {
+        Self {
+            lookup_mf_id,
+            expected_mf_id,
+            roundtrip_mf_id,
+            repo,
+        }
+    }
+
     /// What entries changed from the original manifest to the roundtripped one.
     pub fn changes(
         &self,
@@ -110,6 +204,25 @@ impl BonsaiMFVerifyDifference {
             .not_empty()
     }
 
+    /// Build a report of this difference that can be serialized, listing what `changes`
+    /// returns.
+    pub async fn report(&self, ctx: CoreContext) -> Result<BonsaiMFVerifyDifferenceReport, Error> {
+        let mut changes = self
+            .changes(ctx)
+            .map(BonsaiMFVerifyChange::new)
+            .collect()
+            .compat()
+            .await?;
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(BonsaiMFVerifyDifferenceReport {
+            lookup_mf_id: self.lookup_mf_id,
+            expected_mf_id: self.expected_mf_id,
+            roundtrip_mf_id: self.roundtrip_mf_id,
+            changes,
+        })
+    }
+
     // XXX might need to return repo here if callers want to do direct queries
 }
 
@@ -301,12 +414,12 @@ impl ChangesetVisitor for BonsaiMFVerifyVisitor {
                                 computed_mf_id: roundtrip_mf_id,
                             }))
                         } else {
-                            let difference = BonsaiMFVerifyDifference {
+                            let difference = BonsaiMFVerifyDifference::new(
+                                repo,
                                 lookup_mf_id,
                                 expected_mf_id,
                                 roundtrip_mf_id,
-                                repo,
-                            };
+                            );
 
                             if broken_merge {
                                 // This is a (potentially) broken merge. Ignore tree changes and
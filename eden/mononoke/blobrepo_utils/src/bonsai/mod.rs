@@ -7,4 +7,7 @@
 
 mod manifest;
 
-pub use self::manifest::{BonsaiMFVerify, BonsaiMFVerifyDifference, BonsaiMFVerifyResult};
+pub use self::manifest::{
+    BonsaiMFVerify, BonsaiMFVerifyChange, BonsaiMFVerifyDifference,
+    BonsaiMFVerifyDifferenceReport, BonsaiMFVerifyReport, BonsaiMFVerifyResult,
+};
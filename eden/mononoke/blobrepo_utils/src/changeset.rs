@@ -19,6 +19,7 @@ use futures_old::{
     Future, Stream,
 };
 use mercurial_types::{blobs::HgBlobChangeset, HgChangesetId};
+use serde::Serialize;
 use slog::{o, Logger};
 use std::sync::Arc;
 
@@ -43,7 +44,7 @@ pub trait ChangesetVisitor: Clone + Send + Sync + 'static {
 }
 
 /// Information about the specific changeset whose result is provided.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
 pub struct ChangesetVisitMeta {
     pub changeset_id: HgChangesetId,
     pub follow_remaining: usize,
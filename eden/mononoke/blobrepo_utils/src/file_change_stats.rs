@@ -0,0 +1,197 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use crate::changeset::ChangesetVisitor;
+use anyhow::{format_err, Error};
+use blobrepo::BlobRepo;
+use blobrepo_hg::BlobRepoHg;
+use blobstore::Loadable;
+use context::CoreContext;
+use futures::{FutureExt, TryFutureExt, TryStreamExt};
+use futures_ext::{BoxFuture, FutureExt as _};
+use manifest::{Entry, ManifestOps};
+use mercurial_types::{blobs::HgBlobChangeset, HgChangesetId};
+use mononoke_types::{FileChange, MPath};
+use serde::Serialize;
+use slog::Logger;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+/// Counts of the file changes made by some changesets.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct FileChangeStats {
+    /// Files that were not in any parent of the changeset that changed them.
+    pub added: u64,
+    /// Files that were in a parent of the changeset that changed them.
+    pub modified: u64,
+    pub deleted: u64,
+    /// Total size of the new content of added and modified files.
+    pub bytes_changed: u64,
+}
+
+impl FileChangeStats {
+    fn merge(&mut self, other: &FileChangeStats) {
+        self.added += other.added;
+        self.modified += other.modified;
+        self.deleted += other.deleted;
+        self.bytes_changed += other.bytes_changed;
+    }
+}
+
+/// The stats accumulated by a `FileChangeStatsVisitor` over all the changesets it visited.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct FileChangeStatsTotals {
+    pub changesets: u64,
+    pub total: FileChangeStats,
+    /// The same stats, by the extension of the file name: the part after its last `.`, or an
+    /// empty string for names without one. Empty unless the visitor was created with
+    /// `by_extension`.
+    pub by_extension: HashMap<String, FileChangeStats>,
+}
+
+/// A `ChangesetVisitor` that counts the files added, modified and deleted by each changeset, and
+/// the size of their new content. Each visit produces the stats of that changeset, and the totals
+/// over all visited changesets are available from `totals` once `visit_changesets` is done.
+///
+/// Only tracked changes are counted. Untracked changes only appear in snapshots, not in history.
+#[derive(Clone, Debug)]
+pub struct FileChangeStatsVisitor {
+    by_extension: bool,
+    totals: Arc<Mutex<FileChangeStatsTotals>>,
+}
+
+impl FileChangeStatsVisitor {
+    pub fn new(by_extension: bool) -> Self {
+        Self {
+            by_extension,
+            totals: Arc::new(Mutex::new(FileChangeStatsTotals::default())),
+        }
+    }
+
+    /// The stats of all the changesets visited so far, by this visitor and its clones.
+    pub fn totals(&self) -> FileChangeStatsTotals {
+        self.totals.lock().expect("lock poisoned").clone()
+    }
+
+    async fn visit_impl(
+        &self,
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        changeset: &HgBlobChangeset,
+    ) -> Result<FileChangeStats, Error> {
+        let hg_cs_id = changeset.get_changeset_id();
+        let bcs_id = repo
+            .get_bonsai_from_hg(ctx.clone(), hg_cs_id)
+            .await?
+            .ok_or_else(|| format_err!("No bonsai changeset for {}", hg_cs_id))?;
+        let bonsai = bcs_id.load(ctx, repo.blobstore()).await?;
+
+        let changed: Vec<MPath> = bonsai
+            .file_changes()
+            .filter(|(_, change)| matches!(change, FileChange::Change(_)))
+            .map(|(path, _)| path.clone())
+            .collect();
+        let in_parents = files_in_parents(ctx, repo, changeset, changed).await?;
+
+        let mut stats = FileChangeStats::default();
+        let mut by_extension: HashMap<String, FileChangeStats> = HashMap::new();
+        for (path, change) in bonsai.file_changes() {
+            let mut file_stats = FileChangeStats::default();
+            match change {
+                FileChange::Change(tc) => {
+                    if in_parents.contains(path) {
+                        file_stats.modified = 1;
+                    } else {
+                        file_stats.added = 1;
+                    }
+                    file_stats.bytes_changed = tc.size();
+                }
+                FileChange::Deletion => file_stats.deleted = 1,
+                FileChange::UntrackedChange(_) | FileChange::UntrackedDeletion => continue,
+            }
+            stats.merge(&file_stats);
+            if self.by_extension {
+                by_extension
+                    .entry(extension(path))
+                    .or_default()
+                    .merge(&file_stats);
+            }
+        }
+
+        let mut totals = self.totals.lock().expect("lock poisoned");
+        totals.changesets += 1;
+        totals.total.merge(&stats);
+        for (ext, ext_stats) in by_extension {
+            totals
+                .by_extension
+                .entry(ext)
+                .or_default()
+                .merge(&ext_stats);
+        }
+        Ok(stats)
+    }
+}
+
+impl ChangesetVisitor for FileChangeStatsVisitor {
+    type Item = FileChangeStats;
+
+    fn visit(
+        self,
+        ctx: CoreContext,
+        _logger: Logger,
+        repo: BlobRepo,
+        changeset: HgBlobChangeset,
+        _follow_remaining: usize,
+    ) -> BoxFuture<Self::Item, Error> {
+        async move { self.visit_impl(&ctx, &repo, &changeset).await }
+            .boxed()
+            .compat()
+            .boxify()
+    }
+}
+
+/// Which of `paths` are files in at least one parent of `changeset`.
+async fn files_in_parents(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    changeset: &HgBlobChangeset,
+    paths: Vec<MPath>,
+) -> Result<HashSet<MPath>, Error> {
+    let mut found = HashSet::new();
+    if paths.is_empty() {
+        return Ok(found);
+    }
+    for parent in changeset.p1().into_iter().chain(changeset.p2()) {
+        let parent = HgChangesetId::new(parent)
+            .load(ctx, repo.blobstore())
+            .await?;
+        let files: Vec<_> = parent
+            .manifestid()
+            .find_entries(ctx.clone(), repo.get_blobstore(), paths.clone())
+            .try_filter_map(|(path, entry)| async move {
+                Ok(match entry {
+                    Entry::Leaf(_) => path,
+                    Entry::Tree(_) => None,
+                })
+            })
+            .try_collect()
+            .await?;
+        found.extend(files);
+    }
+    Ok(found)
+}
+
+fn extension(path: &MPath) -> String {
+    let name = path.basename().as_ref();
+    match name.iter().rposition(|b| *b == b'.') {
+        // A leading dot marks a hidden file, not an extension.
+        Some(pos) if pos > 0 => String::from_utf8_lossy(&name[pos + 1..]).into_owned(),
+        _ => String::new(),
+    }
+}
@@ -12,10 +12,17 @@
 mod bonsai;
 mod changeset;
 mod errors;
+mod file_change_stats;
 
-pub use crate::bonsai::{BonsaiMFVerify, BonsaiMFVerifyDifference, BonsaiMFVerifyResult};
-pub use crate::changeset::{visit_changesets, ChangesetVisitor};
+pub use crate::bonsai::{
+    BonsaiMFVerify, BonsaiMFVerifyChange, BonsaiMFVerifyDifference,
+    BonsaiMFVerifyDifferenceReport, BonsaiMFVerifyReport, BonsaiMFVerifyResult,
+};
+pub use crate::changeset::{visit_changesets, ChangesetVisitMeta, ChangesetVisitor};
 pub use crate::errors::ErrorKind;
+pub use crate::file_change_stats::{
+    FileChangeStats, FileChangeStatsTotals, FileChangeStatsVisitor,
+};
 
 use anyhow::Result;
 use blobrepo::BlobRepo;
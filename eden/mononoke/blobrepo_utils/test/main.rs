@@ -107,3 +107,182 @@ mod test {
     test_verify!(unshared_merge_even);
     test_verify!(unshared_merge_uneven);
 }
+
+#[fbinit::test]
+async fn test_file_change_stats(fb: fbinit::FacebookInit) {
+    use std::str::FromStr;
+
+    use blobrepo_utils::{visit_changesets, FileChangeStats, FileChangeStatsVisitor};
+    use context::CoreContext;
+    use futures::compat::Future01CompatExt;
+    use futures_old::Stream;
+    use mercurial_types::HgChangesetId;
+
+    let ctx = CoreContext::test_mock(fb);
+    let repo = linear::getrepo(fb).await;
+    let head = HgChangesetId::from_str("79a13814c5ce7330173ec04d279bf95ab3f652fb").unwrap();
+
+    let visitor = FileChangeStatsVisitor::new(true);
+    let results = visit_changesets(
+        ctx.clone(),
+        ctx.logger().clone(),
+        repo,
+        visitor.clone(),
+        vec![head],
+        1024,
+    )
+    .collect()
+    .compat()
+    .await
+    .unwrap();
+    assert_eq!(results.len(), 11);
+
+    // The head only modifies "10".
+    let (stats, _) = results
+        .iter()
+        .find(|(_, meta)| meta.changeset_id == head)
+        .unwrap();
+    assert_eq!(
+        *stats,
+        FileChangeStats {
+            added: 0,
+            modified: 1,
+            deleted: 0,
+            bytes_changed: 11,
+        }
+    );
+
+    // Each of the other changesets adds one file and modifies or adds "files".
+    let totals = visitor.totals();
+    assert_eq!(totals.changesets, 11);
+    let expected = FileChangeStats {
+        added: 11,
+        modified: 10,
+        deleted: 0,
+        bytes_changed: 143,
+    };
+    assert_eq!(totals.total, expected);
+    // None of the files have an extension.
+    assert_eq!(totals.by_extension.len(), 1);
+    assert_eq!(totals.by_extension[""], expected);
+}
+
+#[fbinit::test]
+async fn test_serialize_verify_result(fb: fbinit::FacebookInit) {
+    use blobrepo_utils::{BonsaiMFVerifyResult, ChangesetVisitMeta};
+    use context::CoreContext;
+    use mercurial_types_mocks::nodehash::{ONES_CSID, ONES_HASH, TWOS_HASH};
+    use serde_json::json;
+
+    let ctx = CoreContext::test_mock(fb);
+
+    let valid = BonsaiMFVerifyResult::Valid {
+        lookup_mf_id: ONES_HASH,
+        computed_mf_id: TWOS_HASH,
+    };
+    let valid = valid.report(ctx.clone()).await.unwrap();
+    assert_eq!(
+        serde_json::to_value(&valid).unwrap(),
+        json!({
+            "Valid": {
+                "lookup_mf_id": "1111111111111111111111111111111111111111",
+                "computed_mf_id": "2222222222222222222222222222222222222222",
+            }
+        })
+    );
+
+    let ignored = BonsaiMFVerifyResult::Ignored(ONES_CSID);
+    let ignored = ignored.report(ctx).await.unwrap();
+    assert_eq!(
+        serde_json::to_value(&ignored).unwrap(),
+        json!({ "Ignored": "1111111111111111111111111111111111111111" })
+    );
+
+    let meta = ChangesetVisitMeta {
+        changeset_id: ONES_CSID,
+        follow_remaining: 3,
+    };
+    assert_eq!(
+        serde_json::to_value(&meta).unwrap(),
+        json!({
+            "changeset_id": "1111111111111111111111111111111111111111",
+            "follow_remaining": 3,
+        })
+    );
+}
+
+#[fbinit::test]
+async fn test_difference_report(fb: fbinit::FacebookInit) {
+    use std::str::FromStr;
+
+    use blobrepo_utils::{BonsaiMFVerifyDifference, BonsaiMFVerifyResult};
+    use blobstore::Loadable;
+    use context::CoreContext;
+    use manifest::ManifestOps;
+    use mercurial_types::{HgChangesetId, HgManifestId};
+    use mononoke_types::MPath;
+    use serde_json::json;
+
+    let ctx = CoreContext::test_mock(fb);
+    let repo = linear::getrepo(fb).await;
+
+    let manifest_id = |hash: &str| {
+        let cs_id = HgChangesetId::from_str(hash).unwrap();
+        let ctx = ctx.clone();
+        let repo = repo.clone();
+        async move {
+            let cs = cs_id.load(&ctx, repo.blobstore()).await.unwrap();
+            cs.manifestid()
+        }
+    };
+    let filenode_id = |mf_id: HgManifestId, path: &str| {
+        let path = MPath::new(path).unwrap();
+        let entry = mf_id.find_entry(ctx.clone(), repo.get_blobstore(), Some(path));
+        async move {
+            let (_, filenode_id) = entry.await.unwrap().unwrap().into_leaf().unwrap();
+            filenode_id.into_nodehash().to_string()
+        }
+    };
+
+    // "added 2" adds the file "2" and modifies "files".
+    let lookup_mf_id = manifest_id("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536").await;
+    let roundtrip_mf_id = manifest_id("3e0e761030db6e479a7fb58b12881883f9f8c63f").await;
+    let difference = BonsaiMFVerifyDifference::new(
+        repo.clone(),
+        lookup_mf_id.into_nodehash(),
+        roundtrip_mf_id.into_nodehash(),
+        roundtrip_mf_id.into_nodehash(),
+    );
+
+    let report = BonsaiMFVerifyResult::Invalid(difference)
+        .report(ctx.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&report).unwrap(),
+        json!({
+            "Invalid": {
+                "lookup_mf_id": lookup_mf_id.to_string(),
+                "expected_mf_id": roundtrip_mf_id.to_string(),
+                "roundtrip_mf_id": roundtrip_mf_id.to_string(),
+                "changes": [
+                    {
+                        "path": "",
+                        "lookup_node_id": lookup_mf_id.to_string(),
+                        "roundtrip_node_id": roundtrip_mf_id.to_string(),
+                    },
+                    {
+                        "path": "2",
+                        "lookup_node_id": null,
+                        "roundtrip_node_id": filenode_id(roundtrip_mf_id, "2").await,
+                    },
+                    {
+                        "path": "files",
+                        "lookup_node_id": filenode_id(lookup_mf_id, "files").await,
+                        "roundtrip_node_id": filenode_id(roundtrip_mf_id, "files").await,
+                    },
+                ],
+            }
+        })
+    );
+}
@@ -72,6 +72,23 @@ const INITIAL_VERSION: u64 = 0;
 const COUNTED_ID: &str = "sqlblob";
 pub type CountedSqlblob = CountedBlobstore<Sqlblob>;
 
+/// What `Sqlblob::set_generation` would do for a key
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GenerationCheck {
+    /// Number of chunks whose generation would be updated
+    pub chunks_to_mark: u64,
+    /// Whether the value would be converted to the inline form
+    pub would_inline: bool,
+}
+
+fn current_ctime() -> Result<i64> {
+    let ctime = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(offset) => offset.as_secs().try_into(),
+        Err(negative) => negative.duration().as_secs().try_into().map(|v: i64| -v),
+    }?;
+    Ok(ctime)
+}
+
 pub struct Sqlblob {
     data_store: Arc<DataSqlStore>,
     chunk_store: Arc<ChunkSqlStore>,
@@ -400,6 +417,49 @@ impl Sqlblob {
         }
     }
 
+    /// Check what `set_generation` would do for a key, without changing anything
+    pub async fn check_generation(
+        &self,
+        key: &str,
+        inline_small_values: bool,
+    ) -> Result<GenerationCheck> {
+        let chunked = self.data_store.get(key).await?;
+        if let Some(chunked) = chunked {
+            let check_chunk_generations: FuturesUnordered<_> = (0..chunked.count)
+                .map(|chunk_num| {
+                    self.chunk_store.check_generation(
+                        &chunked.id,
+                        chunk_num,
+                        chunked.chunking_method,
+                    )
+                })
+                .collect();
+            let states: Vec<Option<(bool, u64)>> = check_chunk_generations.try_collect().await?;
+            let chunks_to_mark = states
+                .iter()
+                .filter(|state| matches!(state, Some((true, _))))
+                .count() as u64;
+            // Mirrors the conditions under which set_generation inlines a value
+            let would_inline = inline_small_values
+                && matches!(states.as_slice(), [Some((_, value_len))] if *value_len <= MAX_INLINE_LEN)
+                && current_ctime()? - chunked.ctime >= self.ctime_inline_grace;
+            Ok(GenerationCheck {
+                chunks_to_mark,
+                would_inline,
+            })
+        } else {
+            bail!("key does not exist");
+        }
+    }
+
+    /// Returns the number of chunks that `set_initial_generation` would give
+    /// a generation to
+    pub async fn count_initial_generation(&self, shard_num: usize) -> Result<u64> {
+        self.chunk_store
+            .count_needs_initial_generation(shard_num)
+            .await
+    }
+
     /// Mark the generation for a key
     /// If its value was small enough to inline, then also inline it if requested
     pub async fn set_generation(&self, key: &str, inline_small_values: bool) -> Result<()> {
@@ -430,14 +490,7 @@ impl Sqlblob {
                     let value_len: u64 = small_value.as_bytes().len().try_into()?;
                     if value_len <= MAX_INLINE_LEN {
                         if let Some(old_ctime) = small_value.as_meta().ctime() {
-                            let ctime = {
-                                match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                                    Ok(offset) => offset.as_secs().try_into(),
-                                    Err(negative) => {
-                                        negative.duration().as_secs().try_into().map(|v: i64| -v)
-                                    }
-                                }
-                            }?;
+                            let ctime = current_ctime()?;
                             // Optimisitic update to convert to the inline form. This only updates for actually old ctimes
                             // (one day or more), so that we don't attempt to inline any new data from e.g. a packer write
                             if ctime - old_ctime >= self.ctime_inline_grace {
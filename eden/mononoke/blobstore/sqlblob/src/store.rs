@@ -184,6 +184,12 @@ queries! {
         LIMIT {limit}"
     }
 
+    read CountNeedsInitialGeneration() -> (u64) {
+        "SELECT CAST(COUNT(1) AS UNSIGNED)
+        FROM chunk LEFT JOIN chunk_generation ON chunk.id = chunk_generation.id
+        WHERE chunk_generation.last_seen_generation IS NULL"
+    }
+
     read GetAllKeys() -> (Vec<u8>) {
         "SELECT id FROM data"
     }
@@ -596,6 +602,35 @@ impl ChunkSqlStore {
         Ok(None)
     }
 
+    // Read-only counterpart of set_generation, for dry runs.
+    // Returns whether the chunk's generation would be updated, and the length of the chunk value
+    pub(crate) async fn check_generation(
+        &self,
+        key: &str,
+        chunk_num: u32,
+        chunking_method: ChunkingMethod,
+    ) -> Result<Option<(bool, u64)>, Error> {
+        if let Some(shard_id) = self.shard(key, chunk_num, chunking_method) {
+            let mark_generation = self.gc_generations.get().mark_generation as u64;
+            let rows = {
+                let rows = GetChunkGeneration::query(&self.read_connection[shard_id], &key).await?;
+                if rows.is_empty() {
+                    GetChunkGeneration::query(&self.read_master_connection[shard_id], &key).await?
+                } else {
+                    rows
+                }
+            };
+            let state = match rows.into_iter().next() {
+                Some((found_generation, value_len)) => {
+                    (found_generation < mark_generation, value_len)
+                }
+                None => (true, self.get_len(shard_id, key).await?),
+            };
+            return Ok(Some(state));
+        }
+        Ok(None)
+    }
+
     // Returns a HashMap from generation->(size, chunk_id_count)
     // Its a chunk id count as some chunk ids have multiple chunks of CHUNK_SIZE
     // but chunk_generation doesn't record that (it doesn't need to)
@@ -612,6 +647,16 @@ impl ChunkSqlStore {
             })
     }
 
+    // Returns the number of chunks set_initial_generation would give a generation to
+    pub(crate) async fn count_needs_initial_generation(
+        &self,
+        shard_num: usize,
+    ) -> Result<u64, Error> {
+        let rows =
+            CountNeedsInitialGeneration::query(&self.read_master_connection[shard_num]).await?;
+        Ok(rows.into_iter().next().map_or(0, |(count,)| count))
+    }
+
     pub(crate) async fn set_initial_generation(&self, shard_num: usize) -> Result<(), Error> {
         loop {
             self.delay.delay(shard_num).await;
@@ -383,3 +383,79 @@ async fn generations(fb: FacebookInit) -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[fbinit::test]
+async fn check_generations(fb: FacebookInit) -> Result<(), Error> {
+    let (test_source, config_store) = get_test_config_store();
+    let bs = Sqlblob::with_sqlite_in_memory(
+        DEFAULT_PUT_BEHAVIOUR,
+        &config_store,
+        false, // store small values in chunks, so that they can be inlined
+        0,     // no grace period for ctime updates,
+    )?;
+    let ctx = CoreContext::test_mock(fb);
+    borrowed!(ctx);
+
+    let small_key = "manifoldblob_test_check_small".to_string();
+    let large_key = "manifoldblob_test_check_large".to_string();
+    let small_bytes = {
+        let mut bytes_in = [0u8; MAX_INLINE_LEN as usize];
+        thread_rng().fill_bytes(&mut bytes_in);
+        BlobstoreBytes::from_bytes(Bytes::copy_from_slice(&bytes_in))
+    };
+    let large_bytes = {
+        let mut bytes_in = [0u8; 1024];
+        thread_rng().fill_bytes(&mut bytes_in);
+        BlobstoreBytes::from_bytes(Bytes::copy_from_slice(&bytes_in))
+    };
+    bs.put(ctx, small_key.clone(), small_bytes).await?;
+    bs.put(ctx, large_key.clone(), large_bytes).await?;
+
+    let mut needs_initial_generation = 0;
+    for shard in 0..SQLITE_SHARD_NUM.get() {
+        needs_initial_generation += bs.count_initial_generation(shard).await?;
+    }
+    assert_eq!(needs_initial_generation, 0, "Puts should set a generation");
+
+    set_test_generations(test_source.as_ref(), 4, 3, 0, INITIAL_VERSION + 1);
+    tokio::time::sleep(UPDATE_WAIT_TIME).await;
+
+    assert_eq!(
+        bs.check_generation(&small_key, true).await?,
+        GenerationCheck {
+            chunks_to_mark: 1,
+            would_inline: true,
+        }
+    );
+    assert_eq!(
+        bs.check_generation(&small_key, false).await?,
+        GenerationCheck {
+            chunks_to_mark: 1,
+            would_inline: false,
+        }
+    );
+    assert_eq!(
+        bs.check_generation(&large_key, true).await?,
+        GenerationCheck {
+            chunks_to_mark: 1,
+            would_inline: false,
+        }
+    );
+    // Checking must not change anything
+    assert_eq!(bs.get_chunk_generations(&small_key).await?, vec![Some(2)]);
+    assert_eq!(bs.get_chunk_generations(&large_key).await?, vec![Some(2)]);
+
+    bs.set_generation(&large_key, true).await?;
+    assert_eq!(
+        bs.check_generation(&large_key, true).await?,
+        GenerationCheck {
+            chunks_to_mark: 0,
+            would_inline: false,
+        }
+    );
+    assert!(bs
+        .check_generation("manifoldblob_test_check_missing", true)
+        .await
+        .is_err());
+    Ok(())
+}
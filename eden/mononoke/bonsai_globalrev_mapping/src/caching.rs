@@ -18,17 +18,70 @@ use fbinit::FacebookInit;
 use fbthrift::compact_protocol;
 use memcache::{KeyGen, MemcacheClient};
 use mononoke_types::{ChangesetId, Globalrev, RepositoryId};
+use stats::prelude::*;
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tunables::tunables;
 
 use bonsai_globalrev_mapping_thrift as thrift;
 
-use super::{BonsaiGlobalrevMapping, BonsaiGlobalrevMappingEntry, BonsaisOrGlobalrevs};
+use super::{BonsaiGlobalrevMapping, BonsaiGlobalrevMappingEntry, BonsaisOrGlobalrevs, Freshness};
 
+define_stats! {
+    prefix = "mononoke.bonsai_globalrev_mapping.cache";
+    hits: dynamic_timeseries("{}.hit", (repo: String); Rate, Sum),
+    misses: dynamic_timeseries("{}.miss", (repo: String); Rate, Sum),
+    negative_hits: dynamic_timeseries("{}.negative_hit", (repo: String); Rate, Sum),
+    db_fallbacks: dynamic_timeseries("{}.db_fallback", (repo: String); Rate, Sum),
+}
+
+/// How lookups through a `CachingBonsaiGlobalrevMapping` were served. The same numbers are
+/// exported per repo as `mononoke.bonsai_globalrev_mapping.cache.<repo_id>.*` stats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheCounters {
+    /// Keys found in cachelib or memcache.
+    pub hits: u64,
+    /// Keys that had to be fetched from the database.
+    pub misses: u64,
+    /// Globalrevs known to be missing, answered without looking them up.
+    pub negative_hits: u64,
+    /// Queries sent to the database for keys that were not cached.
+    pub db_fallbacks: u64,
+}
+
+/// Upper bound on the number of globalrevs remembered as missing. Expired entries are pruned
+/// when it is reached, and everything is forgotten if that is not enough.
+const MAX_MISSING_GLOBALREVS: usize = 100_000;
+
+fn negative_ttl() -> Option<Duration> {
+    let ttl_ms = match tunables()
+        .get_bonsai_globalrev_mapping_negative_cache_ttl_ms()
+        .try_into()
+    {
+        Ok(0) => 1000,            // 0 means default.
+        Ok(duration) => duration, // Use provided duration.
+        Err(_) => return None,    // Negative values mean no negative caching.
+    };
+
+    Some(Duration::from_millis(ttl_ms))
+}
+
+/// Caches globalrev mapping entries in cachelib and memcache.
+///
+/// Globalrevs that turn out to have no bonsai are also remembered in process for a short
+/// time (see `negative_ttl`), so that repeated lookups of a missing globalrev don't all go to
+/// the database. `bulk_import` through this mapping forgets them straight away, but globalrevs
+/// assigned by other means (e.g. on other hosts) only become visible once the entry expires.
 #[derive(Clone)]
 pub struct CachingBonsaiGlobalrevMapping<T> {
     cachelib: CachelibHandler<BonsaiGlobalrevMappingEntry>,
     memcache: MemcacheHandler,
     keygen: KeyGen,
+    missing: Arc<Mutex<HashMap<(RepositoryId, Globalrev), Instant>>>,
+    counters: Arc<Mutex<CacheCounters>>,
     inner: T,
 }
 
@@ -41,6 +94,8 @@ impl<T> CachingBonsaiGlobalrevMapping<T> {
                 .expect("Memcache initialization failed")
                 .into(),
             keygen: Self::create_key_gen(),
+            missing: Default::default(),
+            counters: Default::default(),
         }
     }
 
@@ -50,6 +105,8 @@ impl<T> CachingBonsaiGlobalrevMapping<T> {
             cachelib: CachelibHandler::create_mock(),
             memcache: MemcacheHandler::create_mock(),
             keygen: Self::create_key_gen(),
+            missing: Default::default(),
+            counters: Default::default(),
         }
     }
 
@@ -66,49 +123,236 @@ impl<T> CachingBonsaiGlobalrevMapping<T> {
     pub fn cachelib(&self) -> &CachelibHandler<BonsaiGlobalrevMappingEntry> {
         &self.cachelib
     }
+
+    /// How lookups through this mapping were served so far, across all repos.
+    pub fn counters(&self) -> CacheCounters {
+        *self.counters.lock().expect("lock poisoned")
+    }
+
+    fn add_to_counters(&self, repo_id: RepositoryId, delta: CacheCounters) {
+        let repo = (repo_id.id().to_string(),);
+        STATS::hits.add_value(delta.hits as i64, repo.clone());
+        STATS::misses.add_value(delta.misses as i64, repo.clone());
+        STATS::negative_hits.add_value(delta.negative_hits as i64, repo.clone());
+        STATS::db_fallbacks.add_value(delta.db_fallbacks as i64, repo);
+
+        let mut counters = self.counters.lock().expect("lock poisoned");
+        counters.hits += delta.hits;
+        counters.misses += delta.misses;
+        counters.negative_hits += delta.negative_hits;
+        counters.db_fallbacks += delta.db_fallbacks;
+    }
+
+    fn without_known_missing(
+        &self,
+        repo_id: RepositoryId,
+        globalrevs: Vec<Globalrev>,
+    ) -> Vec<Globalrev> {
+        let now = Instant::now();
+        let missing = self.missing.lock().expect("lock poisoned");
+        globalrevs
+            .into_iter()
+            .filter(|globalrev| match missing.get(&(repo_id, *globalrev)) {
+                Some(expiry) => *expiry <= now,
+                None => true,
+            })
+            .collect()
+    }
+
+    fn record_missing(
+        &self,
+        repo_id: RepositoryId,
+        globalrevs: impl IntoIterator<Item = Globalrev>,
+        ttl: Duration,
+    ) {
+        let now = Instant::now();
+        let mut missing = self.missing.lock().expect("lock poisoned");
+        if missing.len() >= MAX_MISSING_GLOBALREVS {
+            missing.retain(|_, expiry| *expiry > now);
+            if missing.len() >= MAX_MISSING_GLOBALREVS {
+                missing.clear();
+            }
+        }
+        let expiry = now + ttl;
+        for globalrev in globalrevs {
+            missing.insert((repo_id, globalrev), expiry);
+        }
+    }
+
+    fn forget_missing(&self, entries: &[BonsaiGlobalrevMappingEntry]) {
+        let mut missing = self.missing.lock().expect("lock poisoned");
+        for entry in entries {
+            missing.remove(&(entry.repo_id, entry.globalrev));
+        }
+    }
 }
 
-#[async_trait]
-impl<T> BonsaiGlobalrevMapping for CachingBonsaiGlobalrevMapping<T>
+impl<T: BonsaiGlobalrevMapping> CachingBonsaiGlobalrevMapping<T> {
+    /// Look up entries that are not cached with `get_with_freshness` if `freshness` is given,
+    /// and with `get` otherwise.
+    async fn get_from_inner(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        objects: BonsaisOrGlobalrevs,
+        freshness: Option<Freshness>,
+    ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
+        match freshness {
+            Some(freshness) => {
+                self.inner
+                    .get_with_freshness(ctx, repo_id, objects, freshness)
+                    .await
+            }
+            None => self.inner.get(ctx, repo_id, objects).await,
+        }
+    }
+}
+
+impl<T> CachingBonsaiGlobalrevMapping<T>
 where
     T: BonsaiGlobalrevMapping + Clone + Sync + Send + 'static,
 {
-    async fn bulk_import(
+    /// Look up the given changesets or Globalrevs in a single batch and cache what is found,
+    /// without returning it. Use this ahead of many single lookups of a known set of keys, so
+    /// that they are served from the cache instead of querying the database one by one.
+    ///
+    /// Only the given kind of key is cached: prefetching Globalrevs does not speed up later
+    /// lookups of their changesets, and vice versa.
+    pub async fn prefetch(
         &self,
         ctx: &CoreContext,
-        entries: &[BonsaiGlobalrevMappingEntry],
+        repo_id: RepositoryId,
+        objects: BonsaisOrGlobalrevs,
     ) -> Result<(), Error> {
-        self.inner.bulk_import(ctx, entries).await
+        if objects.is_empty() {
+            return Ok(());
+        }
+        self.get(ctx, repo_id, objects).await?;
+        Ok(())
     }
 
-    async fn get(
+    /// Look up the given changesets or Globalrevs in the cache, and the ones that are not
+    /// cached with `get_from_inner`.
+    async fn get_cached(
         &self,
         ctx: &CoreContext,
         repo_id: RepositoryId,
         objects: BonsaisOrGlobalrevs,
+        freshness: Option<Freshness>,
     ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
-        let ctx = (ctx, repo_id, self);
-
-        let res = match objects {
-            BonsaisOrGlobalrevs::Bonsai(cs_ids) => get_or_fill(ctx, cs_ids.into_iter().collect())
-                .await
-                .with_context(|| "Error fetching globalrevs via cache")?
-                .into_iter()
-                .map(|(_, val)| val)
-                .collect(),
+        // Number of keys that were not cached, counted as they are fetched from the database.
+        let misses = AtomicUsize::new(0);
+        let ctx = (ctx, repo_id, self, &misses, freshness);
+
+        let (res, looked_up) = match objects {
+            BonsaisOrGlobalrevs::Bonsai(cs_ids) => {
+                let cs_ids: HashSet<_> = cs_ids.into_iter().collect();
+                let looked_up = cs_ids.len();
+                let res: Vec<_> = get_or_fill(ctx, cs_ids)
+                    .await
+                    .with_context(|| "Error fetching globalrevs via cache")?
+                    .into_iter()
+                    .map(|(_, val)| val)
+                    .collect();
+                (res, looked_up)
+            }
             BonsaisOrGlobalrevs::Globalrev(globalrevs) => {
-                get_or_fill(ctx, globalrevs.into_iter().collect())
+                let ttl = negative_ttl();
+                let requested = globalrevs.len();
+                let globalrevs = match ttl {
+                    Some(_) => self.without_known_missing(repo_id, globalrevs),
+                    None => globalrevs,
+                };
+                self.add_to_counters(
+                    repo_id,
+                    CacheCounters {
+                        negative_hits: (requested - globalrevs.len()) as u64,
+                        ..Default::default()
+                    },
+                );
+                let globalrevs_set: HashSet<_> = globalrevs.iter().cloned().collect();
+                let looked_up = globalrevs_set.len();
+                let res: Vec<_> = get_or_fill(ctx, globalrevs_set)
                     .await
                     .with_context(|| "Error fetching bonsais via cache")?
                     .into_iter()
                     .map(|(_, val)| val)
-                    .collect()
+                    .collect();
+                // A Globalrev missing from a replica may just not have reached it yet, so only
+                // remember the ones that are missing from the master too.
+                if let (Some(ttl), None) = (ttl, freshness) {
+                    let found: HashSet<_> = res.iter().map(|entry| entry.globalrev).collect();
+                    self.record_missing(
+                        repo_id,
+                        globalrevs
+                            .into_iter()
+                            .filter(|globalrev| !found.contains(globalrev)),
+                        ttl,
+                    );
+                }
+                (res, looked_up)
             }
         };
 
+        let misses = misses.load(Ordering::Relaxed);
+        self.add_to_counters(
+            repo_id,
+            CacheCounters {
+                hits: looked_up.saturating_sub(misses) as u64,
+                ..Default::default()
+            },
+        );
 
         Ok(res)
     }
+}
+
+#[async_trait]
+impl<T> BonsaiGlobalrevMapping for CachingBonsaiGlobalrevMapping<T>
+where
+    T: BonsaiGlobalrevMapping + Clone + Sync + Send + 'static,
+{
+    async fn bulk_import(
+        &self,
+        ctx: &CoreContext,
+        entries: &[BonsaiGlobalrevMappingEntry],
+    ) -> Result<(), Error> {
+        self.inner.bulk_import(ctx, entries).await?;
+        self.forget_missing(entries);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        objects: BonsaisOrGlobalrevs,
+    ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
+        self.get_cached(ctx, repo_id, objects, None).await
+    }
+
+    async fn get_with_freshness(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        objects: BonsaisOrGlobalrevs,
+        freshness: Freshness,
+    ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
+        match freshness {
+            // The cache remembers missing Globalrevs for a while, so bypass it.
+            Freshness::MostRecent => {
+                self.inner
+                    .get_with_freshness(ctx, repo_id, objects, freshness)
+                    .await
+            }
+            // Cached entries never change, so they are fine to serve. Misses are looked up on a
+            // replica only.
+            Freshness::MaybeStale => {
+                self.get_cached(ctx, repo_id, objects, Some(freshness))
+                    .await
+            }
+        }
+    }
 
     async fn get_closest_globalrev(
         &self,
@@ -121,6 +365,30 @@ where
             .await
     }
 
+    async fn find_globalrev_gaps(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        start: Globalrev,
+        end: Globalrev,
+    ) -> Result<Vec<Range<Globalrev>>, Error> {
+        self.inner
+            .find_globalrev_gaps(ctx, repo_id, start, end)
+            .await
+    }
+
+    async fn get_ordered_by_globalrev(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        after: Option<Globalrev>,
+        limit: u64,
+    ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
+        self.inner
+            .get_ordered_by_globalrev(ctx, repo_id, after, limit)
+            .await
+    }
+
     async fn get_max(
         &self,
         ctx: &CoreContext,
@@ -128,6 +396,14 @@ where
     ) -> Result<Option<Globalrev>, Error> {
         self.inner.get_max(ctx, repo_id).await
     }
+
+    async fn get_max_many(
+        &self,
+        ctx: &CoreContext,
+        repo_ids: &[RepositoryId],
+    ) -> Result<HashMap<RepositoryId, Globalrev>, Error> {
+        self.inner.get_max_many(ctx, repo_ids).await
+    }
 }
 
 impl MemcacheEntity for BonsaiGlobalrevMappingEntry {
@@ -167,21 +443,23 @@ type CacheRequest<'a, T> = (
     &'a CoreContext,
     RepositoryId,
     &'a CachingBonsaiGlobalrevMapping<T>,
+    &'a AtomicUsize,
+    Option<Freshness>,
 );
 
 impl<T> EntityStore<BonsaiGlobalrevMappingEntry> for CacheRequest<'_, T> {
     fn cachelib(&self) -> &CachelibHandler<BonsaiGlobalrevMappingEntry> {
-        let (_, _, mapping) = self;
+        let (_, _, mapping, _, _) = self;
         &mapping.cachelib
     }
 
     fn keygen(&self) -> &KeyGen {
-        let (_, _, mapping) = self;
+        let (_, _, mapping, _, _) = self;
         &mapping.keygen
     }
 
     fn memcache(&self) -> &MemcacheHandler {
-        let (_, _, mapping) = self;
+        let (_, _, mapping, _, _) = self;
         &mapping.memcache
     }
 
@@ -198,7 +476,7 @@ where
     T: BonsaiGlobalrevMapping,
 {
     fn get_cache_key(&self, key: &ChangesetId) -> String {
-        let (_, repo_id, _) = self;
+        let (_, repo_id, _, _, _) = self;
         format!("{}.bonsai.{}", repo_id, key)
     }
 
@@ -206,14 +484,23 @@ where
         &self,
         keys: HashSet<ChangesetId>,
     ) -> Result<HashMap<ChangesetId, BonsaiGlobalrevMappingEntry>, Error> {
-        let (ctx, repo_id, mapping) = self;
+        let (ctx, repo_id, mapping, misses, freshness) = self;
+        misses.fetch_add(keys.len(), Ordering::Relaxed);
+        mapping.add_to_counters(
+            *repo_id,
+            CacheCounters {
+                misses: keys.len() as u64,
+                db_fallbacks: 1,
+                ..Default::default()
+            },
+        );
 
         let res = mapping
-            .inner
-            .get(
+            .get_from_inner(
                 ctx,
                 *repo_id,
                 BonsaisOrGlobalrevs::Bonsai(keys.into_iter().collect()),
+                *freshness,
             )
             .await
             .with_context(|| "Error fetching globalrevs from bonsais from SQL")?;
@@ -228,7 +515,7 @@ where
     T: BonsaiGlobalrevMapping,
 {
     fn get_cache_key(&self, key: &Globalrev) -> String {
-        let (_, repo_id, _) = self;
+        let (_, repo_id, _, _, _) = self;
         format!("{}.globalrev.{}", repo_id, key.id())
     }
 
@@ -236,14 +523,23 @@ where
         &self,
         keys: HashSet<Globalrev>,
     ) -> Result<HashMap<Globalrev, BonsaiGlobalrevMappingEntry>, Error> {
-        let (ctx, repo_id, mapping) = self;
+        let (ctx, repo_id, mapping, misses, freshness) = self;
+        misses.fetch_add(keys.len(), Ordering::Relaxed);
+        mapping.add_to_counters(
+            *repo_id,
+            CacheCounters {
+                misses: keys.len() as u64,
+                db_fallbacks: 1,
+                ..Default::default()
+            },
+        );
 
         let res = mapping
-            .inner
-            .get(
+            .get_from_inner(
                 ctx,
                 *repo_id,
                 BonsaisOrGlobalrevs::Globalrev(keys.into_iter().collect()),
+                *freshness,
             )
             .await
             .with_context(|| "Error fetching bonsais from globalrevs from SQL")?;
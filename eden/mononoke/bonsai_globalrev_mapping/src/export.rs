@@ -0,0 +1,180 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Export the mapping of a repo to a file, and import it into another mapping.
+//!
+//! The format is:
+//!
+//! ```plain,ignore
+//! EXPORT := MAGIC (4B, "BGRX") + VERSION (1B, 1) + CHUNK * N + END (u32, 0)
+//! CHUNK  := COUNT (u32, > 0) + ENTRY * COUNT
+//! ENTRY  := CHANGESET_ID (32B) + GLOBALREV (u64)
+//! ```
+//!
+//! Integers are big-endian. Entries are in increasing Globalrev order. The repo id is not
+//! stored, so an export can be imported into a repo with a different id.
+
+use anyhow::{bail, Context, Error};
+use context::CoreContext;
+use mononoke_types::{ChangesetId, Globalrev, RepositoryId};
+use std::collections::HashSet;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{BonsaiGlobalrevMapping, BonsaiGlobalrevMappingEntry, BonsaisOrGlobalrevs, Freshness};
+
+const MAGIC: &[u8] = b"BGRX";
+const VERSION: u8 = 1;
+
+/// How many entries `export_globalrevs` reads per query, and writes per chunk.
+const EXPORT_BATCH_SIZE: u64 = 10000;
+/// How many entries `import_globalrevs` imports at once.
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Write all the entries of `repo_id` in `mapping` to `writer`, and return how many there were.
+///
+/// The entries are read in batches, in increasing Globalrev order, and written as they are read,
+/// so the whole mapping is never held in memory. This reads from a replica, so very recently
+/// assigned Globalrevs may be missing.
+pub async fn export_globalrevs(
+    ctx: &CoreContext,
+    repo_id: RepositoryId,
+    mapping: &impl BonsaiGlobalrevMapping,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<u64, Error> {
+    writer.write_all(MAGIC).await?;
+    writer.write_u8(VERSION).await?;
+
+    let mut exported = 0;
+    let mut after = None;
+    loop {
+        let entries = mapping
+            .get_ordered_by_globalrev(ctx, repo_id, after, EXPORT_BATCH_SIZE)
+            .await?;
+        let last = match entries.last() {
+            Some(last) => last.globalrev,
+            None => break,
+        };
+
+        let mut chunk = Vec::with_capacity(4 + entries.len() * 40);
+        chunk.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for entry in &entries {
+            chunk.extend_from_slice(entry.bcs_id.as_ref());
+            chunk.extend_from_slice(&entry.globalrev.id().to_be_bytes());
+        }
+        writer.write_all(&chunk).await?;
+
+        exported += entries.len() as u64;
+        after = Some(last);
+    }
+
+    writer.write_u32(0).await?;
+    writer.flush().await?;
+    Ok(exported)
+}
+
+/// Import the entries written by `export_globalrevs` from `reader` into `mapping`, for
+/// `repo_id`, and return how many there were.
+///
+/// The entries are imported with `bulk_import` in batches as they are read, so an export that
+/// turns out to be truncated or corrupt may have been imported in part. This is meant for
+/// filling an empty mapping: `bulk_import` skips entries whose changeset or Globalrev is
+/// already mapped differently, so each batch is read back after it is imported, and the import
+/// fails at the first such conflict. Entries that are already in the mapping are fine.
+pub async fn import_globalrevs(
+    ctx: &CoreContext,
+    repo_id: RepositoryId,
+    mapping: &impl BonsaiGlobalrevMapping,
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<u64, Error> {
+    let mut magic = [0u8; 4];
+    reader
+        .read_exact(&mut magic)
+        .await
+        .context("reading globalrevs export header")?;
+    if magic != MAGIC {
+        bail!("not a globalrevs export");
+    }
+    let version = reader.read_u8().await?;
+    if version != VERSION {
+        bail!("unsupported globalrevs export version {}", version);
+    }
+
+    let mut imported = 0;
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    loop {
+        let count = reader
+            .read_u32()
+            .await
+            .context("globalrevs export is truncated")?;
+        if count == 0 {
+            break;
+        }
+        for _ in 0..count {
+            let mut bcs_id = [0u8; 32];
+            reader
+                .read_exact(&mut bcs_id)
+                .await
+                .context("globalrevs export is truncated")?;
+            let globalrev = reader
+                .read_u64()
+                .await
+                .context("globalrevs export is truncated")?;
+            batch.push(BonsaiGlobalrevMappingEntry::new(
+                repo_id,
+                ChangesetId::from_bytes(bcs_id)?,
+                Globalrev::new(globalrev),
+            ));
+            if batch.len() == IMPORT_BATCH_SIZE {
+                import_batch(ctx, repo_id, mapping, &batch).await?;
+                imported += batch.len() as u64;
+                batch.clear();
+            }
+        }
+    }
+
+    let mut trailing = [0u8; 1];
+    if reader.read(&mut trailing).await? != 0 {
+        bail!("unexpected data after the globalrevs export");
+    }
+    if !batch.is_empty() {
+        import_batch(ctx, repo_id, mapping, &batch).await?;
+        imported += batch.len() as u64;
+    }
+    Ok(imported)
+}
+
+/// Import `batch` with `bulk_import`, and fail if any entry did not end up in the mapping
+/// because it conflicts with an entry that was already there.
+async fn import_batch(
+    ctx: &CoreContext,
+    repo_id: RepositoryId,
+    mapping: &impl BonsaiGlobalrevMapping,
+    batch: &[BonsaiGlobalrevMappingEntry],
+) -> Result<(), Error> {
+    mapping.bulk_import(ctx, batch).await?;
+
+    let bcs_ids = batch.iter().map(|entry| entry.bcs_id).collect();
+    let stored: HashSet<_> = mapping
+        .get_with_freshness(
+            ctx,
+            repo_id,
+            BonsaisOrGlobalrevs::Bonsai(bcs_ids),
+            Freshness::MostRecent,
+        )
+        .await?
+        .into_iter()
+        .collect();
+    if let Some(entry) = batch.iter().find(|entry| !stored.contains(entry)) {
+        bail!(
+            "cannot import {} as Globalrev {}: the changeset or the Globalrev is already mapped \
+            differently",
+            entry.bcs_id,
+            entry.globalrev.id()
+        );
+    }
+    Ok(())
+}
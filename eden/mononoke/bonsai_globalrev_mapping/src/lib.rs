@@ -8,6 +8,7 @@
 #![deny(warnings)]
 
 mod caching;
+mod export;
 mod sql;
 
 use abomonation_derive::Abomonation;
@@ -16,10 +17,14 @@ use async_trait::async_trait;
 use auto_impl::auto_impl;
 use context::CoreContext;
 use mononoke_types::{ChangesetId, Globalrev, RepositoryId};
+use std::collections::HashMap;
+use std::ops::Range;
 
-pub use crate::caching::CachingBonsaiGlobalrevMapping;
+pub use crate::caching::{CacheCounters, CachingBonsaiGlobalrevMapping};
+pub use crate::export::{export_globalrevs, import_globalrevs};
 pub use crate::sql::{
-    add_globalrevs, bulk_import_globalrevs, AddGlobalrevsErrorKind, SqlBonsaiGlobalrevMapping,
+    add_globalrevs, bulk_import_globalrevs, AddGlobalrevsErrorKind, BulkImportResult,
+    SqlBonsaiGlobalrevMapping,
 };
 
 #[derive(Abomonation, Clone, Debug, Eq, Hash, PartialEq)]
@@ -77,6 +82,17 @@ impl From<Vec<Globalrev>> for BonsaisOrGlobalrevs {
     }
 }
 
+/// How fresh the entries returned by `BonsaiGlobalrevMapping::get_with_freshness` must be.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Freshness {
+    /// Read from the master, which has every entry that was added. Only use this when the
+    /// entries may have been added very recently.
+    MostRecent,
+    /// Read from a replica, which may lag behind the master, or from a cache. Recently added
+    /// entries may be missing.
+    MaybeStale,
+}
+
 #[facet::facet]
 #[async_trait]
 #[auto_impl(&, Arc, Box)]
@@ -87,6 +103,8 @@ pub trait BonsaiGlobalrevMapping: Send + Sync {
         entries: &[BonsaiGlobalrevMappingEntry],
     ) -> Result<(), Error>;
 
+    /// Get the entries for the given changesets or Globalrevs. Entries that are not found on a
+    /// replica are looked up on the master, so recently added entries are found.
     async fn get(
         &self,
         ctx: &CoreContext,
@@ -94,6 +112,17 @@ pub trait BonsaiGlobalrevMapping: Send + Sync {
         field: BonsaisOrGlobalrevs,
     ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error>;
 
+    /// Like `get`, but reads from the master or from a replica depending on `freshness`. With
+    /// `Freshness::MaybeStale`, entries that did not reach the replica yet may be missing from
+    /// the result.
+    async fn get_with_freshness(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        field: BonsaisOrGlobalrevs,
+        freshness: Freshness,
+    ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error>;
+
     async fn get_globalrev_from_bonsai(
         &self,
         ctx: &CoreContext,
@@ -129,6 +158,34 @@ pub trait BonsaiGlobalrevMapping: Send + Sync {
         globalrev: Globalrev,
     ) -> Result<Option<Globalrev>, Error>;
 
+    /// Find the Globalrevs in `start..=end` that are not mapped to any changeset, as ranges of
+    /// consecutive missing Globalrevs in increasing order. Globalrevs are assigned densely, so
+    /// a gap means that an assignment failed. An empty result means there are no gaps.
+    /// The ranges are half-open, so a missing Globalrev `u64::MAX` is never reported.
+    ///
+    /// This reads from a replica, so very recently assigned Globalrevs may be reported as
+    /// missing. Use an `end` no greater than the result of an earlier `get_max` to avoid that.
+    async fn find_globalrev_gaps(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        start: Globalrev,
+        end: Globalrev,
+    ) -> Result<Vec<Range<Globalrev>>, Error>;
+
+    /// Get the entries with a Globalrev greater than `after`, or all entries if `after` is
+    /// `None`, in increasing Globalrev order, and at most `limit` of them. Pass the last
+    /// Globalrev of a page as `after` to get the next page.
+    ///
+    /// This reads from a replica, so very recently assigned Globalrevs may be missing.
+    async fn get_ordered_by_globalrev(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        after: Option<Globalrev>,
+        limit: u64,
+    ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error>;
+
     /// Read the most recent Globalrev. This produces the freshest data possible, and is meant to
     /// be used for Globalrev assignment.
     async fn get_max(
@@ -136,4 +193,20 @@ pub trait BonsaiGlobalrevMapping: Send + Sync {
         ctx: &CoreContext,
         repo_id: RepositoryId,
     ) -> Result<Option<Globalrev>, Error>;
+
+    /// Read the most recent Globalrev of each of the given repos, with the same freshness as
+    /// `get_max`. Repos that have no Globalrevs are absent from the result.
+    async fn get_max_many(
+        &self,
+        ctx: &CoreContext,
+        repo_ids: &[RepositoryId],
+    ) -> Result<HashMap<RepositoryId, Globalrev>, Error> {
+        let mut maxima = HashMap::new();
+        for repo_id in repo_ids {
+            if let Some(globalrev) = self.get_max(ctx, *repo_id).await? {
+                maxima.insert(*repo_id, globalrev);
+            }
+        }
+        Ok(maxima)
+    }
 }
@@ -10,15 +10,17 @@
 use ::sql::{queries, Connection, Transaction};
 use anyhow::Error;
 use async_trait::async_trait;
+use bonsai_int_mapping::{BonsaiIntMappingQueries, BonsaisOrRevs, SqlBonsaiIntMapping};
 use context::{CoreContext, PerfCounterType};
 use mononoke_types::{BonsaiChangeset, ChangesetId, Globalrev, RepositoryId};
 use slog::warn;
 use sql_construct::{SqlConstruct, SqlConstructFromMetadataDatabaseConfig};
 use sql_ext::SqlConnections;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use thiserror::Error;
 
-use super::{BonsaiGlobalrevMapping, BonsaiGlobalrevMappingEntry, BonsaisOrGlobalrevs};
+use super::{BonsaiGlobalrevMapping, BonsaiGlobalrevMappingEntry, BonsaisOrGlobalrevs, Freshness};
 
 queries! {
     write DangerouslyAddGlobalrevs(values: (
@@ -30,6 +32,26 @@ queries! {
         "{insert_or_ignore} INTO bonsai_globalrev_mapping (repo_id, bcs_id, globalrev) VALUES {values}"
     }
 
+    write InsertGlobalrevsIdempotent(values: (
+        repo_id: RepositoryId,
+        bcs_id: ChangesetId,
+        globalrev: Globalrev,
+    )) {
+        none,
+        // Rows that already exist are left as they are; the caller checks that they match.
+        mysql("INSERT INTO bonsai_globalrev_mapping (repo_id, bcs_id, globalrev) VALUES {values} ON DUPLICATE KEY UPDATE globalrev = globalrev")
+        sqlite("INSERT OR IGNORE INTO bonsai_globalrev_mapping (repo_id, bcs_id, globalrev) VALUES {values}")
+    }
+
+    write DangerouslyDeleteGlobalrevs(
+        repo_id: RepositoryId,
+        >list bcs_id: ChangesetId
+    ) {
+        none,
+        "DELETE FROM bonsai_globalrev_mapping
+         WHERE repo_id = {repo_id} AND bcs_id IN {bcs_id}"
+    }
+
     read SelectMappingByBonsai(
         repo_id: RepositoryId,
         >list bcs_id: ChangesetId
@@ -58,6 +80,54 @@ queries! {
         "
     }
 
+    read SelectMaxEntries(>list repo_id: RepositoryId) -> (RepositoryId, Globalrev) {
+        "
+        SELECT repo_id, MAX(globalrev)
+        FROM bonsai_globalrev_mapping
+        WHERE repo_id IN {repo_id}
+        GROUP BY repo_id
+        "
+    }
+
+    read SelectGlobalrevsInRange(
+        repo_id: RepositoryId,
+        start: Globalrev,
+        end: Globalrev,
+        limit: u64,
+    ) -> (Globalrev,) {
+        "
+        SELECT globalrev
+        FROM bonsai_globalrev_mapping
+        WHERE repo_id = {repo_id} AND globalrev >= {start} AND globalrev <= {end}
+        ORDER BY globalrev ASC
+        LIMIT {limit}
+        "
+    }
+
+    read SelectMappingOrdered(repo_id: RepositoryId, limit: u64) -> (ChangesetId, Globalrev) {
+        "
+        SELECT bcs_id, globalrev
+        FROM bonsai_globalrev_mapping
+        WHERE repo_id = {repo_id}
+        ORDER BY globalrev ASC
+        LIMIT {limit}
+        "
+    }
+
+    read SelectMappingOrderedAfter(
+        repo_id: RepositoryId,
+        after: Globalrev,
+        limit: u64,
+    ) -> (ChangesetId, Globalrev) {
+        "
+        SELECT bcs_id, globalrev
+        FROM bonsai_globalrev_mapping
+        WHERE repo_id = {repo_id} AND globalrev > {after}
+        ORDER BY globalrev ASC
+        LIMIT {limit}
+        "
+    }
+
     read SelectClosestGlobalrev(repo_id: RepositoryId, rev: Globalrev) -> (Globalrev,) {
         "
         SELECT globalrev
@@ -69,11 +139,73 @@ queries! {
     }
 }
 
+/// The queries of the `bonsai_globalrev_mapping` table.
+struct GlobalrevQueries;
+
+#[async_trait]
+impl BonsaiIntMappingQueries for GlobalrevQueries {
+    type Rev = Globalrev;
+
+    async fn insert_or_ignore(
+        connection: &Connection,
+        rows: &[(&RepositoryId, &ChangesetId, &Globalrev)],
+    ) -> Result<(), Error> {
+        DangerouslyAddGlobalrevs::query(connection, rows).await?;
+        Ok(())
+    }
+
+    async fn select_by_bonsai(
+        connection: &Connection,
+        repo_id: &RepositoryId,
+        bcs_ids: &[ChangesetId],
+    ) -> Result<Vec<(ChangesetId, Globalrev)>, Error> {
+        Ok(SelectMappingByBonsai::query(connection, repo_id, bcs_ids).await?)
+    }
+
+    async fn select_by_rev(
+        connection: &Connection,
+        repo_id: &RepositoryId,
+        revs: &[Globalrev],
+    ) -> Result<Vec<(ChangesetId, Globalrev)>, Error> {
+        Ok(SelectMappingByGlobalrev::query(connection, repo_id, revs).await?)
+    }
+
+    async fn select_max(
+        connection: &Connection,
+        repo_id: &RepositoryId,
+    ) -> Result<Option<Globalrev>, Error> {
+        let row = SelectMaxEntry::query(connection, repo_id)
+            .await?
+            .into_iter()
+            .next();
+        Ok(row.map(|r| r.0))
+    }
+}
+
+/// How many Globalrevs `find_globalrev_gaps` reads per query.
+const GAPS_BATCH_SIZE: u64 = 10000;
+
+/// The outcome of `SqlBonsaiGlobalrevMapping::bulk_import_idempotent`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BulkImportResult {
+    /// Entries that were added by the import.
+    pub inserted: Vec<BonsaiGlobalrevMappingEntry>,
+    /// Entries that were already in the mapping.
+    pub already_present: Vec<BonsaiGlobalrevMappingEntry>,
+}
+
+impl From<BonsaisOrGlobalrevs> for BonsaisOrRevs<Globalrev> {
+    fn from(objects: BonsaisOrGlobalrevs) -> Self {
+        match objects {
+            BonsaisOrGlobalrevs::Bonsai(bcs_ids) => BonsaisOrRevs::Bonsai(bcs_ids),
+            BonsaisOrGlobalrevs::Globalrev(globalrevs) => BonsaisOrRevs::Rev(globalrevs),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SqlBonsaiGlobalrevMapping {
-    write_connection: Connection,
-    read_connection: Connection,
-    read_master_connection: Connection,
+    inner: SqlBonsaiIntMapping<GlobalrevQueries>,
 }
 
 impl SqlConstruct for SqlBonsaiGlobalrevMapping {
@@ -84,15 +216,132 @@ impl SqlConstruct for SqlBonsaiGlobalrevMapping {
 
     fn from_sql_connections(connections: SqlConnections) -> Self {
         Self {
-            write_connection: connections.write_connection,
-            read_connection: connections.read_connection,
-            read_master_connection: connections.read_master_connection,
+            inner: SqlBonsaiIntMapping::new(connections),
         }
     }
 }
 
 impl SqlConstructFromMetadataDatabaseConfig for SqlBonsaiGlobalrevMapping {}
 
+impl SqlBonsaiGlobalrevMapping {
+    /// Remove the mapping entries for the given changesets in this repo, returning how many
+    /// were deleted. This is meant for stripping commits from a repo, and nothing else.
+    ///
+    /// This is destructive: it leaves gaps in the Globalrev sequence, which breaks the
+    /// assumption that Globalrevs are contiguous and monotonic. It also does not invalidate
+    /// any caches in front of this mapping, so stale entries may still be served from there.
+    pub async fn delete_entries(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        bcs_ids: &[ChangesetId],
+    ) -> Result<usize, Error> {
+        if bcs_ids.is_empty() {
+            return Ok(0);
+        }
+
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlWrites);
+
+        let res =
+            DangerouslyDeleteGlobalrevs::query(self.inner.write_connection(), &repo_id, bcs_ids)
+                .await?;
+
+        Ok(res.affected_rows() as usize)
+    }
+
+    /// Import the given entries, succeeding for entries that are already in the mapping with
+    /// the same Globalrev. This lets an importer re-submit entries without checking for them
+    /// first.
+    ///
+    /// Fails with `AddGlobalrevsErrorKind::Conflict` if a changeset is already mapped to a
+    /// different Globalrev, or a Globalrev to a different changeset. Conflicts with existing
+    /// entries are detected before anything is written. Conflicts among `entries` themselves,
+    /// or with a concurrent import, are only detected afterwards, so some of the other entries
+    /// may have been written.
+    pub async fn bulk_import_idempotent(
+        &self,
+        ctx: &CoreContext,
+        entries: &[BonsaiGlobalrevMappingEntry],
+    ) -> Result<BulkImportResult, AddGlobalrevsErrorKind> {
+        let mut result = BulkImportResult::default();
+        let existing = self.select_overlapping(ctx, entries).await?;
+        let existing_bcs_ids: HashSet<_> = existing.iter().map(|e| (e.repo_id, e.bcs_id)).collect();
+        let existing_globalrevs: HashSet<_> =
+            existing.iter().map(|e| (e.repo_id, e.globalrev)).collect();
+
+        let mut to_insert: Vec<BonsaiGlobalrevMappingEntry> = Vec::new();
+        for entry in entries {
+            if existing.contains(entry) {
+                result.already_present.push(entry.clone());
+            } else if existing_bcs_ids.contains(&(entry.repo_id, entry.bcs_id))
+                || existing_globalrevs.contains(&(entry.repo_id, entry.globalrev))
+            {
+                return Err(AddGlobalrevsErrorKind::Conflict);
+            } else if !to_insert.contains(entry) {
+                to_insert.push(entry.clone());
+            }
+        }
+
+        if to_insert.is_empty() {
+            return Ok(result);
+        }
+
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlWrites);
+
+        let rows: Vec<_> = to_insert
+            .iter()
+            .map(
+                |
+                    BonsaiGlobalrevMappingEntry {
+                        repo_id,
+                        bcs_id,
+                        globalrev,
+                    },
+                | (repo_id, bcs_id, globalrev),
+            )
+            .collect();
+        InsertGlobalrevsIdempotent::query(self.inner.write_connection(), &rows[..]).await?;
+
+        // Entries that conflict with each other, or with a concurrent import, were silently
+        // skipped by the insert, so check that everything we wrote is there.
+        let stored = self.select_overlapping(ctx, &to_insert).await?;
+        if to_insert.iter().any(|entry| !stored.contains(entry)) {
+            return Err(AddGlobalrevsErrorKind::Conflict);
+        }
+
+        result.inserted = to_insert;
+        Ok(result)
+    }
+
+    /// Fetch the entries that share a changeset or a Globalrev with any of `entries`.
+    async fn select_overlapping(
+        &self,
+        ctx: &CoreContext,
+        entries: &[BonsaiGlobalrevMappingEntry],
+    ) -> Result<HashSet<BonsaiGlobalrevMappingEntry>, Error> {
+        let mut by_repo: HashMap<RepositoryId, (Vec<ChangesetId>, Vec<Globalrev>)> = HashMap::new();
+        for entry in entries {
+            let (bcs_ids, globalrevs) = by_repo.entry(entry.repo_id).or_default();
+            bcs_ids.push(entry.bcs_id);
+            globalrevs.push(entry.globalrev);
+        }
+
+        let mut overlapping = HashSet::new();
+        for (repo_id, (bcs_ids, globalrevs)) in by_repo {
+            ctx.perf_counters()
+                .increment_counter(PerfCounterType::SqlReadsMaster);
+            let conn = self.inner.read_master_connection();
+            let bcs_ids = BonsaisOrGlobalrevs::Bonsai(bcs_ids);
+            overlapping.extend(select_mapping(conn, repo_id, bcs_ids).await?);
+            let globalrevs = BonsaisOrGlobalrevs::Globalrev(globalrevs);
+            overlapping.extend(select_mapping(conn, repo_id, globalrevs).await?);
+        }
+        Ok(overlapping)
+    }
+}
+
 #[async_trait]
 impl BonsaiGlobalrevMapping for SqlBonsaiGlobalrevMapping {
     async fn bulk_import(
@@ -100,9 +349,6 @@ impl BonsaiGlobalrevMapping for SqlBonsaiGlobalrevMapping {
         ctx: &CoreContext,
         entries: &[BonsaiGlobalrevMappingEntry],
     ) -> Result<(), Error> {
-        ctx.perf_counters()
-            .increment_counter(PerfCounterType::SqlWrites);
-
         let entries: Vec<_> = entries
             .iter()
             .map(
@@ -116,9 +362,7 @@ impl BonsaiGlobalrevMapping for SqlBonsaiGlobalrevMapping {
             )
             .collect();
 
-        DangerouslyAddGlobalrevs::query(&self.write_connection, &entries[..]).await?;
-
-        Ok(())
+        self.inner.bulk_import(ctx, &entries[..]).await
     }
 
     async fn get(
@@ -127,24 +371,39 @@ impl BonsaiGlobalrevMapping for SqlBonsaiGlobalrevMapping {
         repo_id: RepositoryId,
         objects: BonsaisOrGlobalrevs,
     ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
-        ctx.perf_counters()
-            .increment_counter(PerfCounterType::SqlReadsReplica);
-
-        let mut mappings = select_mapping(&self.read_connection, repo_id, &objects).await?;
-
-        let left_to_fetch = filter_fetched_objects(objects, &mappings[..]);
-
-        if left_to_fetch.is_empty() {
-            return Ok(mappings);
-        }
-
-        ctx.perf_counters()
-            .increment_counter(PerfCounterType::SqlReadsMaster);
+        Ok(self
+            .inner
+            .get(ctx, repo_id, objects.into())
+            .await?
+            .into_iter()
+            .map(|(bcs_id, globalrev)| BonsaiGlobalrevMappingEntry {
+                repo_id,
+                bcs_id,
+                globalrev,
+            })
+            .collect())
+    }
 
-        let mut master_mappings =
-            select_mapping(&self.read_master_connection, repo_id, &left_to_fetch).await?;
-        mappings.append(&mut master_mappings);
-        Ok(mappings)
+    async fn get_with_freshness(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        objects: BonsaisOrGlobalrevs,
+        freshness: Freshness,
+    ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
+        let (counter, connection) = match freshness {
+            Freshness::MostRecent => (
+                PerfCounterType::SqlReadsMaster,
+                self.inner.read_master_connection(),
+            ),
+            Freshness::MaybeStale => (
+                PerfCounterType::SqlReadsReplica,
+                self.inner.read_connection(),
+            ),
+        };
+        ctx.perf_counters().increment_counter(counter);
+
+        select_mapping(connection, repo_id, objects).await
     }
 
     async fn get_closest_globalrev(
@@ -156,7 +415,7 @@ impl BonsaiGlobalrevMapping for SqlBonsaiGlobalrevMapping {
         ctx.perf_counters()
             .increment_counter(PerfCounterType::SqlReadsReplica);
 
-        let row = SelectClosestGlobalrev::query(&self.read_connection, &repo_id, &globalrev)
+        let row = SelectClosestGlobalrev::query(self.inner.read_connection(), &repo_id, &globalrev)
             .await?
             .into_iter()
             .next();
@@ -164,81 +423,128 @@ impl BonsaiGlobalrevMapping for SqlBonsaiGlobalrevMapping {
         Ok(row.map(|r| r.0))
     }
 
-    async fn get_max(
+    async fn find_globalrev_gaps(
         &self,
         ctx: &CoreContext,
         repo_id: RepositoryId,
-    ) -> Result<Option<Globalrev>, Error> {
+        start: Globalrev,
+        end: Globalrev,
+    ) -> Result<Vec<Range<Globalrev>>, Error> {
+        let mut gaps = Vec::new();
+        // The Globalrev that follows the last one read, if the sequence is dense.
+        let mut next = start.id();
+        while next <= end.id() {
+            ctx.perf_counters()
+                .increment_counter(PerfCounterType::SqlReadsReplica);
+
+            let rows = SelectGlobalrevsInRange::query(
+                self.inner.read_connection(),
+                &repo_id,
+                &Globalrev::new(next),
+                &end,
+                &GAPS_BATCH_SIZE,
+            )
+            .await?;
+            let done = (rows.len() as u64) < GAPS_BATCH_SIZE;
+
+            for (globalrev,) in rows {
+                if globalrev.id() > next {
+                    gaps.push(Globalrev::new(next)..globalrev);
+                }
+                next = match globalrev.id().checked_add(1) {
+                    Some(next) => next,
+                    // Nothing can follow the largest possible Globalrev.
+                    None => return Ok(gaps),
+                };
+            }
+            if done {
+                break;
+            }
+        }
+
+        if next <= end.id() {
+            // The range can't include u64::MAX itself, so clamp it.
+            gaps.push(Globalrev::new(next)..Globalrev::new(end.id().saturating_add(1)));
+        }
+        Ok(gaps)
+    }
+
+    async fn get_ordered_by_globalrev(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        after: Option<Globalrev>,
+        limit: u64,
+    ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
+        if limit == 0 {
+            return Ok(vec![]);
+        }
+
         ctx.perf_counters()
-            .increment_counter(PerfCounterType::SqlReadsMaster);
+            .increment_counter(PerfCounterType::SqlReadsReplica);
 
-        let row = SelectMaxEntry::query(&self.read_master_connection, &repo_id)
-            .await?
+        let rows = match after {
+            Some(after) => {
+                SelectMappingOrderedAfter::query(
+                    self.inner.read_connection(),
+                    &repo_id,
+                    &after,
+                    &limit,
+                )
+                .await?
+            }
+            None => {
+                SelectMappingOrdered::query(self.inner.read_connection(), &repo_id, &limit).await?
+            }
+        };
+
+        Ok(rows
             .into_iter()
-            .next();
+            .map(|(bcs_id, globalrev)| BonsaiGlobalrevMappingEntry {
+                repo_id,
+                bcs_id,
+                globalrev,
+            })
+            .collect())
+    }
 
-        Ok(row.map(|r| r.0))
+    async fn get_max(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+    ) -> Result<Option<Globalrev>, Error> {
+        self.inner.get_max(ctx, repo_id).await
     }
-}
 
-fn filter_fetched_objects(
-    objects: BonsaisOrGlobalrevs,
-    mappings: &[BonsaiGlobalrevMappingEntry],
-) -> BonsaisOrGlobalrevs {
-    match objects {
-        BonsaisOrGlobalrevs::Bonsai(cs_ids) => {
-            let bcs_fetched: HashSet<_> = mappings.iter().map(|m| &m.bcs_id).collect();
-
-            BonsaisOrGlobalrevs::Bonsai(
-                cs_ids
-                    .iter()
-                    .filter_map(|cs| {
-                        if !bcs_fetched.contains(cs) {
-                            Some(*cs)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect(),
-            )
-        }
-        BonsaisOrGlobalrevs::Globalrev(globalrevs) => {
-            let globalrevs_fetched: HashSet<_> = mappings.iter().map(|m| &m.globalrev).collect();
-
-            BonsaisOrGlobalrevs::Globalrev(
-                globalrevs
-                    .iter()
-                    .filter_map(|globalrev| {
-                        if !globalrevs_fetched.contains(globalrev) {
-                            Some(*globalrev)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect(),
-            )
+    async fn get_max_many(
+        &self,
+        ctx: &CoreContext,
+        repo_ids: &[RepositoryId],
+    ) -> Result<HashMap<RepositoryId, Globalrev>, Error> {
+        if repo_ids.is_empty() {
+            return Ok(HashMap::new());
         }
+
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+
+        let rows = SelectMaxEntries::query(self.inner.read_master_connection(), repo_ids).await?;
+
+        Ok(rows.into_iter().collect())
     }
 }
 
 async fn select_mapping(
     connection: &Connection,
     repo_id: RepositoryId,
-    objects: &BonsaisOrGlobalrevs,
+    objects: BonsaisOrGlobalrevs,
 ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
-    if objects.is_empty() {
-        return Ok(vec![]);
-    }
-
-    let rows = match objects {
-        BonsaisOrGlobalrevs::Bonsai(bcs_ids) => {
-            SelectMappingByBonsai::query(&connection, &repo_id, &bcs_ids[..]).await?
-        }
-        BonsaisOrGlobalrevs::Globalrev(globalrevs) => {
-            SelectMappingByGlobalrev::query(&connection, &repo_id, &globalrevs[..]).await?
-        }
-    };
-
+    let rows = bonsai_int_mapping::select_mapping::<GlobalrevQueries>(
+        connection,
+        repo_id,
+        &objects.into(),
+    )
+    .await?;
 
     Ok(rows
         .into_iter()
@@ -12,16 +12,19 @@ use assert_matches::assert_matches;
 use context::CoreContext;
 use fbinit::FacebookInit;
 use mercurial_types_mocks::globalrev::*;
+use mononoke_types::Globalrev;
 use mononoke_types_mocks::changesetid as bonsai;
-use mononoke_types_mocks::repo::{REPO_ONE, REPO_ZERO};
+use mononoke_types_mocks::repo::{REPO_ONE, REPO_TWO, REPO_ZERO};
 use sql::Connection;
 use sql_construct::SqlConstruct;
 use sql_ext::{open_sqlite_in_memory, SqlConnections};
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use bonsai_globalrev_mapping::{
-    add_globalrevs, AddGlobalrevsErrorKind, BonsaiGlobalrevMapping, BonsaiGlobalrevMappingEntry,
-    BonsaisOrGlobalrevs, CachingBonsaiGlobalrevMapping, SqlBonsaiGlobalrevMapping,
+    add_globalrevs, export_globalrevs, import_globalrevs, AddGlobalrevsErrorKind,
+    BonsaiGlobalrevMapping, BonsaiGlobalrevMappingEntry, BonsaisOrGlobalrevs, BulkImportResult,
+    CacheCounters, CachingBonsaiGlobalrevMapping, Freshness, SqlBonsaiGlobalrevMapping,
 };
 
 #[fbinit::test]
@@ -103,6 +106,47 @@ async fn test_missing(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::test]
+async fn test_get_with_freshness(fb: FacebookInit) -> Result<(), Error> {
+    fn conn() -> Result<Connection, Error> {
+        let conn = open_sqlite_in_memory()?;
+        conn.execute_batch(SqlBonsaiGlobalrevMapping::CREATION_QUERY)?;
+        Ok(Connection::with_sqlite(conn))
+    }
+
+    let ctx = CoreContext::test_mock(fb);
+    // The replica never receives the writes made to the master.
+    let master = conn()?;
+    let replica = conn()?;
+    let mapping = SqlBonsaiGlobalrevMapping::from_sql_connections(SqlConnections {
+        write_connection: master.clone(),
+        read_connection: replica,
+        read_master_connection: master,
+    });
+
+    let entry = BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::ONES_CSID,
+        globalrev: GLOBALREV_ZERO,
+    };
+    mapping.bulk_import(&ctx, &[entry.clone()]).await?;
+
+    let field = || BonsaisOrGlobalrevs::Globalrev(vec![GLOBALREV_ZERO]);
+    let result = mapping
+        .get_with_freshness(&ctx, REPO_ZERO, field(), Freshness::MostRecent)
+        .await?;
+    assert_eq!(result, vec![entry.clone()]);
+    let result = mapping
+        .get_with_freshness(&ctx, REPO_ZERO, field(), Freshness::MaybeStale)
+        .await?;
+    assert_eq!(result, vec![]);
+    // `get` falls back to the master for entries missing on the replica.
+    let result = mapping.get(&ctx, REPO_ZERO, field()).await?;
+    assert_eq!(result, vec![entry]);
+
+    Ok(())
+}
+
 #[fbinit::test]
 async fn test_get_max(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
@@ -132,6 +176,104 @@ async fn test_get_max(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::test]
+async fn test_get_max_many(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+
+    assert_eq!(HashMap::new(), mapping.get_max_many(&ctx, &[]).await?);
+    assert_eq!(
+        HashMap::new(),
+        mapping.get_max_many(&ctx, &[REPO_ZERO, REPO_ONE]).await?
+    );
+
+    let entries = [
+        BonsaiGlobalrevMappingEntry {
+            repo_id: REPO_ZERO,
+            bcs_id: bonsai::ONES_CSID,
+            globalrev: GLOBALREV_ZERO,
+        },
+        BonsaiGlobalrevMappingEntry {
+            repo_id: REPO_ZERO,
+            bcs_id: bonsai::TWOS_CSID,
+            globalrev: GLOBALREV_TWO,
+        },
+        BonsaiGlobalrevMappingEntry {
+            repo_id: REPO_ONE,
+            bcs_id: bonsai::ONES_CSID,
+            globalrev: GLOBALREV_ONE,
+        },
+    ];
+    mapping.bulk_import(&ctx, &entries).await?;
+
+    // Repos without Globalrevs are left out.
+    let expected: HashMap<_, _> = vec![(REPO_ZERO, GLOBALREV_TWO), (REPO_ONE, GLOBALREV_ONE)]
+        .into_iter()
+        .collect();
+    assert_eq!(
+        expected,
+        mapping
+            .get_max_many(&ctx, &[REPO_ZERO, REPO_ONE, REPO_TWO])
+            .await?
+    );
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_delete_entries(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+
+    let e0 = BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::ONES_CSID,
+        globalrev: GLOBALREV_ZERO,
+    };
+    let e1 = BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::TWOS_CSID,
+        globalrev: GLOBALREV_ONE,
+    };
+    let ex = BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ONE,
+        bcs_id: bonsai::ONES_CSID,
+        globalrev: GLOBALREV_ZERO,
+    };
+    mapping
+        .bulk_import(&ctx, &[e0.clone(), e1.clone(), ex.clone()])
+        .await?;
+
+    assert_eq!(0, mapping.delete_entries(&ctx, REPO_ZERO, &[]).await?);
+    assert_eq!(
+        1,
+        mapping
+            .delete_entries(&ctx, REPO_ZERO, &[bonsai::ONES_CSID, bonsai::THREES_CSID])
+            .await?
+    );
+
+    let result = mapping
+        .get(
+            &ctx,
+            REPO_ZERO,
+            BonsaisOrGlobalrevs::Bonsai(vec![bonsai::ONES_CSID, bonsai::TWOS_CSID]),
+        )
+        .await?;
+    assert_eq!(result, vec![e1]);
+
+    // Other repos are left alone.
+    let result = mapping
+        .get(
+            &ctx,
+            REPO_ONE,
+            BonsaisOrGlobalrevs::Bonsai(vec![bonsai::ONES_CSID]),
+        )
+        .await?;
+    assert_eq!(result, vec![ex]);
+
+    Ok(())
+}
+
 #[fbinit::test]
 async fn test_add_globalrevs(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
@@ -197,6 +339,77 @@ async fn test_add_globalrevs(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::test]
+async fn test_bulk_import_idempotent(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+
+    let entry1 = BonsaiGlobalrevMappingEntry::new(REPO_ZERO, bonsai::ONES_CSID, GLOBALREV_ZERO);
+    let entry2 = BonsaiGlobalrevMappingEntry::new(REPO_ZERO, bonsai::TWOS_CSID, GLOBALREV_ONE);
+
+    let result = mapping
+        .bulk_import_idempotent(&ctx, &[entry1.clone()])
+        .await?;
+    assert_eq!(
+        result,
+        BulkImportResult {
+            inserted: vec![entry1.clone()],
+            already_present: vec![],
+        }
+    );
+
+    // Re-submitting an entry succeeds.
+    let result = mapping
+        .bulk_import_idempotent(&ctx, &[entry1.clone(), entry2.clone()])
+        .await?;
+    assert_eq!(
+        result,
+        BulkImportResult {
+            inserted: vec![entry2.clone()],
+            already_present: vec![entry1.clone()],
+        }
+    );
+
+    // Mapping a changeset or a Globalrev to something else fails, and writes nothing.
+    let entry3 = BonsaiGlobalrevMappingEntry::new(REPO_ZERO, bonsai::THREES_CSID, GLOBALREV_TWO);
+    for conflict in [
+        BonsaiGlobalrevMappingEntry::new(REPO_ZERO, bonsai::ONES_CSID, GLOBALREV_THREE),
+        BonsaiGlobalrevMappingEntry::new(REPO_ZERO, bonsai::FOURS_CSID, GLOBALREV_ONE),
+    ] {
+        let res = mapping
+            .bulk_import_idempotent(&ctx, &[entry3.clone(), conflict])
+            .await;
+        assert_matches!(res, Err(AddGlobalrevsErrorKind::Conflict));
+    }
+    assert_eq!(
+        None,
+        mapping
+            .get_globalrev_from_bonsai(&ctx, REPO_ZERO, bonsai::THREES_CSID)
+            .await?
+    );
+
+    // Conflicts within the entries are detected too.
+    let res = mapping
+        .bulk_import_idempotent(
+            &ctx,
+            &[
+                entry3.clone(),
+                BonsaiGlobalrevMappingEntry::new(REPO_ZERO, bonsai::FOURS_CSID, GLOBALREV_TWO),
+            ],
+        )
+        .await;
+    assert_matches!(res, Err(AddGlobalrevsErrorKind::Conflict));
+
+    // The same changeset can have an entry in another repo.
+    let other_repo = BonsaiGlobalrevMappingEntry::new(REPO_ONE, bonsai::ONES_CSID, GLOBALREV_ONE);
+    let result = mapping
+        .bulk_import_idempotent(&ctx, &[other_repo.clone()])
+        .await?;
+    assert_eq!(result.inserted, vec![other_repo]);
+
+    Ok(())
+}
+
 #[fbinit::test]
 async fn test_closest_globalrev(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
@@ -254,6 +467,209 @@ async fn test_closest_globalrev(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::test]
+async fn test_find_globalrev_gaps(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+
+    let entries: Vec<_> = [
+        (bonsai::ONES_CSID, 1),
+        (bonsai::TWOS_CSID, 2),
+        (bonsai::THREES_CSID, 5),
+        (bonsai::FOURS_CSID, 6),
+        (bonsai::FIVES_CSID, 9),
+    ]
+    .iter()
+    .map(|(bcs_id, rev)| BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: *bcs_id,
+        globalrev: Globalrev::new(*rev),
+    })
+    .collect();
+    mapping.bulk_import(&ctx, &entries).await?;
+
+    let gaps = |start, end| {
+        mapping.find_globalrev_gaps(&ctx, REPO_ZERO, Globalrev::new(start), Globalrev::new(end))
+    };
+    let range = |start, end| Globalrev::new(start)..Globalrev::new(end);
+
+    assert_eq!(
+        gaps(0, 10).await?,
+        vec![range(0, 1), range(3, 5), range(7, 9), range(10, 11)]
+    );
+    assert_eq!(gaps(1, 9).await?, vec![range(3, 5), range(7, 9)]);
+    assert_eq!(gaps(4, 4).await?, vec![range(4, 5)]);
+
+    // Dense ranges have no gaps.
+    assert!(gaps(1, 2).await?.is_empty());
+    assert!(gaps(9, 9).await?.is_empty());
+    assert!(gaps(9, 1).await?.is_empty());
+
+    // Other repos are not affected.
+    assert_eq!(
+        mapping
+            .find_globalrev_gaps(&ctx, REPO_ONE, Globalrev::new(1), Globalrev::new(2))
+            .await?,
+        vec![range(1, 3)]
+    );
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_get_ordered_by_globalrev(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+
+    // Imported out of order, and with a gap.
+    let entries: Vec<_> = [
+        (REPO_ZERO, bonsai::THREES_CSID, 5),
+        (REPO_ZERO, bonsai::ONES_CSID, 1),
+        (REPO_ZERO, bonsai::FOURS_CSID, 6),
+        (REPO_ZERO, bonsai::TWOS_CSID, 2),
+        (REPO_ONE, bonsai::FIVES_CSID, 3),
+    ]
+    .iter()
+    .map(|(repo_id, bcs_id, rev)| BonsaiGlobalrevMappingEntry {
+        repo_id: *repo_id,
+        bcs_id: *bcs_id,
+        globalrev: Globalrev::new(*rev),
+    })
+    .collect();
+    mapping.bulk_import(&ctx, &entries).await?;
+
+    let page = |after: Option<u64>, limit| {
+        mapping.get_ordered_by_globalrev(&ctx, REPO_ZERO, after.map(Globalrev::new), limit)
+    };
+    let revs = |entries: Vec<BonsaiGlobalrevMappingEntry>| {
+        entries
+            .into_iter()
+            .map(|entry| entry.globalrev.id())
+            .collect::<Vec<_>>()
+    };
+
+    let first = page(None, 2).await?;
+    assert_eq!(first, vec![entries[1].clone(), entries[3].clone()]);
+    assert_eq!(revs(page(Some(2), 2).await?), vec![5, 6]);
+    assert!(page(Some(6), 2).await?.is_empty());
+
+    // `after` does not need to be a mapped Globalrev.
+    assert_eq!(revs(page(Some(3), 10).await?), vec![5, 6]);
+    assert_eq!(revs(page(None, 10).await?), vec![1, 2, 5, 6]);
+    assert!(page(None, 0).await?.is_empty());
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_export_import(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+    let entries: Vec<_> = [
+        (bonsai::ONES_CSID, GLOBALREV_ONE),
+        (bonsai::TWOS_CSID, GLOBALREV_TWO),
+        (bonsai::THREES_CSID, GLOBALREV_THREE),
+    ]
+    .into_iter()
+    .map(|(bcs_id, globalrev)| BonsaiGlobalrevMappingEntry::new(REPO_ZERO, bcs_id, globalrev))
+    .collect();
+    mapping.bulk_import(&ctx, &entries).await?;
+    // Entries of other repos are not exported.
+    mapping
+        .bulk_import(
+            &ctx,
+            &[BonsaiGlobalrevMappingEntry::new(
+                REPO_ONE,
+                bonsai::FOURS_CSID,
+                GLOBALREV_ZERO,
+            )],
+        )
+        .await?;
+
+    let mut export = Vec::new();
+    assert_eq!(
+        export_globalrevs(&ctx, REPO_ZERO, &mapping, &mut export).await?,
+        3
+    );
+
+    // Import into another repo of another mapping.
+    let other = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+    assert_eq!(
+        import_globalrevs(&ctx, REPO_TWO, &other, &mut &export[..]).await?,
+        3
+    );
+    let imported = other
+        .get_ordered_by_globalrev(&ctx, REPO_TWO, None, 10)
+        .await?;
+    let expected: Vec<_> = entries
+        .iter()
+        .map(|entry| BonsaiGlobalrevMappingEntry::new(REPO_TWO, entry.bcs_id, entry.globalrev))
+        .collect();
+    assert_eq!(imported, expected);
+
+    // An empty repo exports to an empty, but valid, export.
+    let mut empty = Vec::new();
+    assert_eq!(
+        export_globalrevs(&ctx, REPO_TWO, &mapping, &mut empty).await?,
+        0
+    );
+    assert_eq!(
+        import_globalrevs(&ctx, REPO_ZERO, &other, &mut &empty[..]).await?,
+        0
+    );
+
+    // Corrupt exports are rejected.
+    let other = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+    assert!(
+        import_globalrevs(&ctx, REPO_ZERO, &other, &mut &export[..export.len() - 1])
+            .await
+            .is_err()
+    );
+    let mut bad_version = export.clone();
+    bad_version[4] = 2;
+    assert!(
+        import_globalrevs(&ctx, REPO_ZERO, &other, &mut &bad_version[..])
+            .await
+            .is_err()
+    );
+    let mut trailing = export.clone();
+    trailing.push(0);
+    assert!(
+        import_globalrevs(&ctx, REPO_ZERO, &other, &mut &trailing[..])
+            .await
+            .is_err()
+    );
+
+    // Entries that are already in the mapping are fine.
+    let other = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+    other.bulk_import(&ctx, &entries[..1]).await?;
+    assert_eq!(
+        import_globalrevs(&ctx, REPO_ZERO, &other, &mut &export[..]).await?,
+        3
+    );
+
+    // Entries whose changeset or Globalrev is already mapped differently fail the import.
+    for (bcs_id, globalrev) in [
+        (bonsai::ONES_CSID, GLOBALREV_ZERO),
+        (bonsai::FOURS_CSID, GLOBALREV_TWO),
+    ] {
+        let other = SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?;
+        let conflicting = BonsaiGlobalrevMappingEntry::new(REPO_ZERO, bcs_id, globalrev);
+        other.bulk_import(&ctx, &[conflicting.clone()]).await?;
+        assert!(
+            import_globalrevs(&ctx, REPO_ZERO, &other, &mut &export[..])
+                .await
+                .is_err()
+        );
+        let stored = other
+            .get(&ctx, REPO_ZERO, BonsaisOrGlobalrevs::Globalrev(vec![globalrev]))
+            .await?;
+        assert_eq!(stored, vec![conflicting]);
+    }
+
+    Ok(())
+}
+
 #[fbinit::test]
 async fn test_caching(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
@@ -344,3 +760,213 @@ async fn test_caching(fb: FacebookInit) -> Result<(), Error> {
 
     Ok(())
 }
+
+#[fbinit::test]
+async fn test_caching_prefetch(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = Arc::new(SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?);
+    let caching = CachingBonsaiGlobalrevMapping::new_test(mapping.clone());
+
+    let e0 = BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::ONES_CSID,
+        globalrev: GLOBALREV_ONE,
+    };
+    let e1 = BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::TWOS_CSID,
+        globalrev: GLOBALREV_TWO,
+    };
+    mapping.bulk_import(&ctx, &[e0, e1]).await?;
+
+    caching
+        .prefetch(
+            &ctx,
+            REPO_ZERO,
+            BonsaisOrGlobalrevs::Globalrev(vec![GLOBALREV_ONE, GLOBALREV_TWO]),
+        )
+        .await?;
+    assert_eq!(
+        caching.counters(),
+        CacheCounters {
+            misses: 2,
+            db_fallbacks: 1,
+            ..Default::default()
+        }
+    );
+
+    // Both Globalrevs are now served from the cache.
+    assert_eq!(
+        caching
+            .get_bonsai_from_globalrev(&ctx, REPO_ZERO, GLOBALREV_ONE)
+            .await?,
+        Some(bonsai::ONES_CSID)
+    );
+    assert_eq!(
+        caching
+            .get_bonsai_from_globalrev(&ctx, REPO_ZERO, GLOBALREV_TWO)
+            .await?,
+        Some(bonsai::TWOS_CSID)
+    );
+    assert_eq!(
+        caching.counters(),
+        CacheCounters {
+            hits: 2,
+            misses: 2,
+            db_fallbacks: 1,
+            ..Default::default()
+        }
+    );
+
+    // Prefetching nothing does not query anything.
+    caching
+        .prefetch(&ctx, REPO_ZERO, BonsaisOrGlobalrevs::Bonsai(vec![]))
+        .await?;
+    assert_eq!(caching.counters().db_fallbacks, 1);
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_caching_missing_globalrev(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = Arc::new(SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?);
+    let caching = CachingBonsaiGlobalrevMapping::new_test(mapping.clone());
+
+    let store = caching
+        .cachelib()
+        .mock_store()
+        .expect("new_test gives us a MockStore");
+
+    assert_eq!(
+        caching
+            .get_bonsai_from_globalrev(&ctx, REPO_ZERO, GLOBALREV_ONE)
+            .await?,
+        None
+    );
+    assert_eq!(store.stats().gets, 1);
+
+    // The missing globalrev is remembered, so the cache isn't consulted again.
+    assert_eq!(
+        caching
+            .get_bonsai_from_globalrev(&ctx, REPO_ZERO, GLOBALREV_ONE)
+            .await?,
+        None
+    );
+    assert_eq!(store.stats().gets, 1);
+
+    // Importing the globalrev makes it visible straight away.
+    let entry = BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::ONES_CSID,
+        globalrev: GLOBALREV_ONE,
+    };
+    caching.bulk_import(&ctx, &[entry]).await?;
+    assert_eq!(
+        caching
+            .get_bonsai_from_globalrev(&ctx, REPO_ZERO, GLOBALREV_ONE)
+            .await?,
+        Some(bonsai::ONES_CSID)
+    );
+    assert_eq!(store.stats().gets, 2);
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_caching_get_with_freshness(fb: FacebookInit) -> Result<(), Error> {
+    fn conn() -> Result<Connection, Error> {
+        let conn = open_sqlite_in_memory()?;
+        conn.execute_batch(SqlBonsaiGlobalrevMapping::CREATION_QUERY)?;
+        Ok(Connection::with_sqlite(conn))
+    }
+
+    let ctx = CoreContext::test_mock(fb);
+    // The replica never receives the writes made to the master.
+    let master = conn()?;
+    let replica = conn()?;
+    let mapping = SqlBonsaiGlobalrevMapping::from_sql_connections(SqlConnections {
+        write_connection: master.clone(),
+        read_connection: replica,
+        read_master_connection: master,
+    });
+    let caching = CachingBonsaiGlobalrevMapping::new_test(Arc::new(mapping));
+
+    let entry = BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::ONES_CSID,
+        globalrev: GLOBALREV_ZERO,
+    };
+    caching.bulk_import(&ctx, &[entry.clone()]).await?;
+
+    // Misses are looked up on the replica only.
+    let field = || BonsaisOrGlobalrevs::Globalrev(vec![GLOBALREV_ZERO]);
+    let result = caching
+        .get_with_freshness(&ctx, REPO_ZERO, field(), Freshness::MaybeStale)
+        .await?;
+    assert_eq!(result, vec![]);
+
+    // The Globalrev was not remembered as missing, so `get` finds it on the master.
+    let result = caching.get(&ctx, REPO_ZERO, field()).await?;
+    assert_eq!(result, vec![entry.clone()]);
+
+    // Now that it is cached, it is served even though the replica does not have it.
+    let result = caching
+        .get_with_freshness(&ctx, REPO_ZERO, field(), Freshness::MaybeStale)
+        .await?;
+    assert_eq!(result, vec![entry]);
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_caching_counters(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = Arc::new(SqlBonsaiGlobalrevMapping::with_sqlite_in_memory()?);
+    let caching = CachingBonsaiGlobalrevMapping::new_test(mapping.clone());
+
+    let entry = BonsaiGlobalrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::ONES_CSID,
+        globalrev: GLOBALREV_ONE,
+    };
+    mapping.bulk_import(&ctx, &[entry]).await?;
+
+    caching
+        .get_globalrev_from_bonsai(&ctx, REPO_ZERO, bonsai::ONES_CSID)
+        .await?;
+    assert_eq!(
+        caching.counters(),
+        CacheCounters {
+            hits: 0,
+            misses: 1,
+            negative_hits: 0,
+            db_fallbacks: 1,
+        }
+    );
+
+    caching
+        .get_globalrev_from_bonsai(&ctx, REPO_ZERO, bonsai::ONES_CSID)
+        .await?;
+    assert_eq!(caching.counters().hits, 1);
+    assert_eq!(caching.counters().db_fallbacks, 1);
+
+    // A missing globalrev goes to the database once, then is a negative hit.
+    caching
+        .get_bonsai_from_globalrev(&ctx, REPO_ZERO, GLOBALREV_TWO)
+        .await?;
+    caching
+        .get_bonsai_from_globalrev(&ctx, REPO_ZERO, GLOBALREV_TWO)
+        .await?;
+    assert_eq!(
+        caching.counters(),
+        CacheCounters {
+            hits: 1,
+            misses: 2,
+            negative_hits: 1,
+            db_fallbacks: 2,
+        }
+    );
+
+    Ok(())
+}
@@ -0,0 +1,209 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! SQL plumbing shared by the tables that map bonsai changesets to integer revisions, such as
+//! Globalrevs and Svnrevs. Each table implements `BonsaiIntMappingQueries` with its own
+//! `queries!` module, and `SqlBonsaiIntMapping` provides the operations common to all of them.
+
+#![deny(warnings)]
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use context::{CoreContext, PerfCounterType};
+use mononoke_types::{ChangesetId, RepositoryId};
+use sql::Connection;
+use sql_ext::SqlConnections;
+
+/// The queries of one table mapping bonsai changesets to integer revisions.
+#[async_trait]
+pub trait BonsaiIntMappingQueries: Send + Sync + 'static {
+    /// The revision type stored in the table.
+    type Rev: Copy + Eq + Hash + Send + Sync + 'static;
+
+    /// Insert the rows, skipping any that conflict with rows already in the table.
+    async fn insert_or_ignore(
+        connection: &Connection,
+        rows: &[(&RepositoryId, &ChangesetId, &Self::Rev)],
+    ) -> Result<(), Error>;
+
+    async fn select_by_bonsai(
+        connection: &Connection,
+        repo_id: &RepositoryId,
+        bcs_ids: &[ChangesetId],
+    ) -> Result<Vec<(ChangesetId, Self::Rev)>, Error>;
+
+    async fn select_by_rev(
+        connection: &Connection,
+        repo_id: &RepositoryId,
+        revs: &[Self::Rev],
+    ) -> Result<Vec<(ChangesetId, Self::Rev)>, Error>;
+
+    /// The largest revision in the table for the repo, if there is any.
+    async fn select_max(
+        connection: &Connection,
+        repo_id: &RepositoryId,
+    ) -> Result<Option<Self::Rev>, Error>;
+}
+
+/// The changesets or revisions to look up.
+pub enum BonsaisOrRevs<R> {
+    Bonsai(Vec<ChangesetId>),
+    Rev(Vec<R>),
+}
+
+impl<R> BonsaisOrRevs<R> {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            BonsaisOrRevs::Bonsai(v) => v.is_empty(),
+            BonsaisOrRevs::Rev(v) => v.is_empty(),
+        }
+    }
+}
+
+pub struct SqlBonsaiIntMapping<Q> {
+    write_connection: Connection,
+    read_connection: Connection,
+    read_master_connection: Connection,
+    queries: PhantomData<Q>,
+}
+
+impl<Q> Clone for SqlBonsaiIntMapping<Q> {
+    fn clone(&self) -> Self {
+        Self {
+            write_connection: self.write_connection.clone(),
+            read_connection: self.read_connection.clone(),
+            read_master_connection: self.read_master_connection.clone(),
+            queries: PhantomData,
+        }
+    }
+}
+
+impl<Q: BonsaiIntMappingQueries> SqlBonsaiIntMapping<Q> {
+    pub fn new(connections: SqlConnections) -> Self {
+        Self {
+            write_connection: connections.write_connection,
+            read_connection: connections.read_connection,
+            read_master_connection: connections.read_master_connection,
+            queries: PhantomData,
+        }
+    }
+
+    pub fn write_connection(&self) -> &Connection {
+        &self.write_connection
+    }
+
+    pub fn read_connection(&self) -> &Connection {
+        &self.read_connection
+    }
+
+    pub fn read_master_connection(&self) -> &Connection {
+        &self.read_master_connection
+    }
+
+    /// Insert the rows. Rows that conflict with rows already in the table are silently
+    /// skipped.
+    pub async fn bulk_import(
+        &self,
+        ctx: &CoreContext,
+        rows: &[(&RepositoryId, &ChangesetId, &Q::Rev)],
+    ) -> Result<(), Error> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlWrites);
+
+        Q::insert_or_ignore(&self.write_connection, rows).await
+    }
+
+    /// Look up the given changesets or revisions in a replica, and the ones that are not
+    /// found there in the master.
+    pub async fn get(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+        objects: BonsaisOrRevs<Q::Rev>,
+    ) -> Result<Vec<(ChangesetId, Q::Rev)>, Error> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsReplica);
+
+        let mut mappings = select_mapping::<Q>(&self.read_connection, repo_id, &objects).await?;
+
+        let left_to_fetch = filter_fetched_objects(objects, &mappings[..]);
+
+        if left_to_fetch.is_empty() {
+            return Ok(mappings);
+        }
+
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+
+        let mut master_mappings =
+            select_mapping::<Q>(&self.read_master_connection, repo_id, &left_to_fetch).await?;
+        mappings.append(&mut master_mappings);
+        Ok(mappings)
+    }
+
+    /// Read the largest revision for the repo from the master, so that it is as fresh as
+    /// possible.
+    pub async fn get_max(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+    ) -> Result<Option<Q::Rev>, Error> {
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsMaster);
+
+        Q::select_max(&self.read_master_connection, &repo_id).await
+    }
+}
+
+fn filter_fetched_objects<R: Copy + Eq + Hash>(
+    objects: BonsaisOrRevs<R>,
+    mappings: &[(ChangesetId, R)],
+) -> BonsaisOrRevs<R> {
+    match objects {
+        BonsaisOrRevs::Bonsai(cs_ids) => {
+            let bcs_fetched: HashSet<_> = mappings.iter().map(|(bcs_id, _)| bcs_id).collect();
+
+            BonsaisOrRevs::Bonsai(
+                cs_ids
+                    .into_iter()
+                    .filter(|cs| !bcs_fetched.contains(cs))
+                    .collect(),
+            )
+        }
+        BonsaisOrRevs::Rev(revs) => {
+            let revs_fetched: HashSet<_> = mappings.iter().map(|(_, rev)| rev).collect();
+
+            BonsaisOrRevs::Rev(
+                revs.into_iter()
+                    .filter(|rev| !revs_fetched.contains(rev))
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Look up the given changesets or revisions using `connection` only.
+pub async fn select_mapping<Q: BonsaiIntMappingQueries>(
+    connection: &Connection,
+    repo_id: RepositoryId,
+    objects: &BonsaisOrRevs<Q::Rev>,
+) -> Result<Vec<(ChangesetId, Q::Rev)>, Error> {
+    if objects.is_empty() {
+        return Ok(vec![]);
+    }
+
+    match objects {
+        BonsaisOrRevs::Bonsai(bcs_ids) => {
+            Q::select_by_bonsai(connection, &repo_id, &bcs_ids[..]).await
+        }
+        BonsaisOrRevs::Rev(revs) => Q::select_by_rev(connection, &repo_id, &revs[..]).await,
+    }
+}
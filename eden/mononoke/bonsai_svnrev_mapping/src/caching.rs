@@ -107,6 +107,14 @@ where
 
         Ok(res)
     }
+
+    async fn get_max(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+    ) -> Result<Option<Svnrev>, Error> {
+        self.inner.get_max(ctx, repo_id).await
+    }
 }
 
 impl MemcacheEntity for BonsaiSvnrevMappingEntry {
@@ -118,6 +118,14 @@ pub trait BonsaiSvnrevMapping: Send + Sync {
         Ok(result.into_iter().next().map(|entry| entry.bcs_id))
     }
 
+    /// Read the most recent Svnrev. This produces the freshest data possible, and is meant to be
+    /// used to find where an incremental import should resume from.
+    async fn get_max(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+    ) -> Result<Option<Svnrev>, Error>;
+
     async fn bulk_import_from_bonsai(
         &self,
         ctx: &CoreContext,
@@ -193,6 +201,10 @@ impl RepoBonsaiSvnrevMapping {
             .await
     }
 
+    pub async fn get_max(&self, ctx: &CoreContext) -> Result<Option<Svnrev>, Error> {
+        self.inner.get_max(ctx, self.repo_id).await
+    }
+
     pub async fn bulk_import_from_bonsai(
         &self,
         ctx: &CoreContext,
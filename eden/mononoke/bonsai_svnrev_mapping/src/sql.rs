@@ -10,12 +10,12 @@
 use ::sql::{queries, Connection};
 use anyhow::Error;
 use async_trait::async_trait;
-use context::{CoreContext, PerfCounterType};
+use bonsai_int_mapping::{BonsaiIntMappingQueries, BonsaisOrRevs, SqlBonsaiIntMapping};
+use context::CoreContext;
 use mononoke_types::{BonsaiChangeset, ChangesetId, RepositoryId, Svnrev};
 use slog::warn;
 use sql_construct::{SqlConstruct, SqlConstructFromMetadataDatabaseConfig};
 use sql_ext::SqlConnections;
-use std::collections::HashSet;
 use thiserror::Error;
 
 use super::{BonsaiSvnrevMapping, BonsaiSvnrevMappingEntry, BonsaisOrSvnrevs};
@@ -47,13 +47,73 @@ queries! {
          FROM bonsai_svnrev_mapping
          WHERE repo_id = {repo_id} AND svnrev in {svnrev}"
     }
+
+    read SelectMaxEntry(repo_id: RepositoryId) -> (Svnrev,) {
+        "
+        SELECT svnrev
+        FROM bonsai_svnrev_mapping
+        WHERE repo_id = {}
+        ORDER BY svnrev DESC
+        LIMIT 1
+        "
+    }
+}
+
+/// The queries of the `bonsai_svnrev_mapping` table.
+struct SvnrevQueries;
+
+#[async_trait]
+impl BonsaiIntMappingQueries for SvnrevQueries {
+    type Rev = Svnrev;
+
+    async fn insert_or_ignore(
+        connection: &Connection,
+        rows: &[(&RepositoryId, &ChangesetId, &Svnrev)],
+    ) -> Result<(), Error> {
+        DangerouslyAddSvnrevs::query(connection, rows).await?;
+        Ok(())
+    }
+
+    async fn select_by_bonsai(
+        connection: &Connection,
+        repo_id: &RepositoryId,
+        bcs_ids: &[ChangesetId],
+    ) -> Result<Vec<(ChangesetId, Svnrev)>, Error> {
+        Ok(SelectMappingByBonsai::query(connection, repo_id, bcs_ids).await?)
+    }
+
+    async fn select_by_rev(
+        connection: &Connection,
+        repo_id: &RepositoryId,
+        revs: &[Svnrev],
+    ) -> Result<Vec<(ChangesetId, Svnrev)>, Error> {
+        Ok(SelectMappingBySvnrev::query(connection, repo_id, revs).await?)
+    }
+
+    async fn select_max(
+        connection: &Connection,
+        repo_id: &RepositoryId,
+    ) -> Result<Option<Svnrev>, Error> {
+        let row = SelectMaxEntry::query(connection, repo_id)
+            .await?
+            .into_iter()
+            .next();
+        Ok(row.map(|r| r.0))
+    }
+}
+
+impl From<BonsaisOrSvnrevs> for BonsaisOrRevs<Svnrev> {
+    fn from(objects: BonsaisOrSvnrevs) -> Self {
+        match objects {
+            BonsaisOrSvnrevs::Bonsai(bcs_ids) => BonsaisOrRevs::Bonsai(bcs_ids),
+            BonsaisOrSvnrevs::Svnrev(svnrevs) => BonsaisOrRevs::Rev(svnrevs),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct SqlBonsaiSvnrevMapping {
-    write_connection: Connection,
-    read_connection: Connection,
-    read_master_connection: Connection,
+    inner: SqlBonsaiIntMapping<SvnrevQueries>,
 }
 
 impl SqlConstruct for SqlBonsaiSvnrevMapping {
@@ -64,9 +124,7 @@ impl SqlConstruct for SqlBonsaiSvnrevMapping {
 
     fn from_sql_connections(connections: SqlConnections) -> Self {
         Self {
-            write_connection: connections.write_connection,
-            read_connection: connections.read_connection,
-            read_master_connection: connections.read_master_connection,
+            inner: SqlBonsaiIntMapping::new(connections),
         }
     }
 }
@@ -80,9 +138,6 @@ impl BonsaiSvnrevMapping for SqlBonsaiSvnrevMapping {
         ctx: &CoreContext,
         entries: &[BonsaiSvnrevMappingEntry],
     ) -> Result<(), Error> {
-        ctx.perf_counters()
-            .increment_counter(PerfCounterType::SqlWrites);
-
         let entries: Vec<_> = entries
             .iter()
             .map(
@@ -96,9 +151,7 @@ impl BonsaiSvnrevMapping for SqlBonsaiSvnrevMapping {
             )
             .collect();
 
-        DangerouslyAddSvnrevs::query(&self.write_connection, &entries[..]).await?;
-
-        Ok(())
+        self.inner.bulk_import(ctx, &entries[..]).await
     }
 
     async fn get(
@@ -107,94 +160,26 @@ impl BonsaiSvnrevMapping for SqlBonsaiSvnrevMapping {
         repo_id: RepositoryId,
         objects: BonsaisOrSvnrevs,
     ) -> Result<Vec<BonsaiSvnrevMappingEntry>, Error> {
-        ctx.perf_counters()
-            .increment_counter(PerfCounterType::SqlReadsReplica);
-
-        let mut mappings = select_mapping(&self.read_connection, repo_id, &objects).await?;
-
-        let left_to_fetch = filter_fetched_objects(objects, &mappings[..]);
-
-        if left_to_fetch.is_empty() {
-            return Ok(mappings);
-        }
-
-        ctx.perf_counters()
-            .increment_counter(PerfCounterType::SqlReadsMaster);
-
-        let mut master_mappings =
-            select_mapping(&self.read_master_connection, repo_id, &left_to_fetch).await?;
-        mappings.append(&mut master_mappings);
-        Ok(mappings)
+        Ok(self
+            .inner
+            .get(ctx, repo_id, objects.into())
+            .await?
+            .into_iter()
+            .map(|(bcs_id, svnrev)| BonsaiSvnrevMappingEntry {
+                repo_id,
+                bcs_id,
+                svnrev,
+            })
+            .collect())
     }
-}
 
-fn filter_fetched_objects(
-    objects: BonsaisOrSvnrevs,
-    mappings: &[BonsaiSvnrevMappingEntry],
-) -> BonsaisOrSvnrevs {
-    match objects {
-        BonsaisOrSvnrevs::Bonsai(cs_ids) => {
-            let bcs_fetched: HashSet<_> = mappings.iter().map(|m| &m.bcs_id).collect();
-
-            BonsaisOrSvnrevs::Bonsai(
-                cs_ids
-                    .iter()
-                    .filter_map(|cs| {
-                        if !bcs_fetched.contains(cs) {
-                            Some(*cs)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect(),
-            )
-        }
-        BonsaisOrSvnrevs::Svnrev(svnrevs) => {
-            let svnrevs_fetched: HashSet<_> = mappings.iter().map(|m| &m.svnrev).collect();
-
-            BonsaisOrSvnrevs::Svnrev(
-                svnrevs
-                    .iter()
-                    .filter_map(|svnrev| {
-                        if !svnrevs_fetched.contains(svnrev) {
-                            Some(*svnrev)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect(),
-            )
-        }
-    }
-}
-
-async fn select_mapping(
-    connection: &Connection,
-    repo_id: RepositoryId,
-    objects: &BonsaisOrSvnrevs,
-) -> Result<Vec<BonsaiSvnrevMappingEntry>, Error> {
-    if objects.is_empty() {
-        return Ok(vec![]);
+    async fn get_max(
+        &self,
+        ctx: &CoreContext,
+        repo_id: RepositoryId,
+    ) -> Result<Option<Svnrev>, Error> {
+        self.inner.get_max(ctx, repo_id).await
     }
-
-    let rows = match objects {
-        BonsaisOrSvnrevs::Bonsai(bcs_ids) => {
-            SelectMappingByBonsai::query(&connection, &repo_id, &bcs_ids[..]).await?
-        }
-        BonsaisOrSvnrevs::Svnrev(svnrevs) => {
-            SelectMappingBySvnrev::query(&connection, &repo_id, &svnrevs[..]).await?
-        }
-    };
-
-
-    Ok(rows
-        .into_iter()
-        .map(move |(bcs_id, svnrev)| BonsaiSvnrevMappingEntry {
-            repo_id,
-            bcs_id,
-            svnrev,
-        })
-        .collect())
 }
 
 /// This method is for importing Svnrevs in bulk from a set of BonsaiChangesets where you know
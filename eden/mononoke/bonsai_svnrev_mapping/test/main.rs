@@ -100,6 +100,36 @@ async fn test_missing(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::test]
+async fn test_get_max(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mapping = SqlBonsaiSvnrevMapping::with_sqlite_in_memory()?;
+
+    assert_eq!(None, mapping.get_max(&ctx, REPO_ZERO).await?);
+
+    let e0 = BonsaiSvnrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::ONES_CSID,
+        svnrev: SVNREV_ONE,
+    };
+    let e1 = BonsaiSvnrevMappingEntry {
+        repo_id: REPO_ZERO,
+        bcs_id: bonsai::TWOS_CSID,
+        svnrev: SVNREV_TWO,
+    };
+    let ex = BonsaiSvnrevMappingEntry {
+        repo_id: REPO_ONE,
+        bcs_id: bonsai::THREES_CSID,
+        svnrev: SVNREV_ZERO,
+    };
+    mapping.bulk_import(&ctx, &[e0, e1, ex]).await?;
+
+    assert_eq!(Some(SVNREV_TWO), mapping.get_max(&ctx, REPO_ZERO).await?);
+    assert_eq!(Some(SVNREV_ZERO), mapping.get_max(&ctx, REPO_ONE).await?);
+
+    Ok(())
+}
+
 #[fbinit::test]
 async fn test_caching(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
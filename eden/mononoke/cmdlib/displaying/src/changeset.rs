@@ -59,6 +59,9 @@ impl TryFrom<&BonsaiChangeset> for DisplayChangeset {
 impl Display for DisplayChangeset {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         writeln!(fmt, "BonsaiChangesetId: {}", self.changeset_id)?;
+        for parent in self.parents.iter() {
+            writeln!(fmt, "Parent: {}", parent)?;
+        }
         writeln!(fmt, "Author: {}", self.author)?;
         writeln!(fmt, "Message: {}", self.message)?;
         writeln!(fmt, "FileChanges:")?;
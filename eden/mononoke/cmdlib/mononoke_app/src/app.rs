@@ -32,6 +32,7 @@ use crate::args::{ConfigArgs, ConfigMode, RepoArg, RepoArgs, RepoBlobstoreArgs};
 pub struct MononokeApp {
     pub fb: FacebookInit,
     config_mode: ConfigMode,
+    dry_run: bool,
     args: ArgMatches,
     env: Arc<MononokeEnvironment>,
     storage_configs: StorageConfigs,
@@ -43,6 +44,7 @@ impl MononokeApp {
     pub(crate) fn new(
         fb: FacebookInit,
         config_mode: ConfigMode,
+        dry_run: bool,
         args: ArgMatches,
         env: MononokeEnvironment,
     ) -> Result<Self> {
@@ -58,6 +60,7 @@ impl MononokeApp {
         Ok(MononokeApp {
             fb,
             config_mode,
+            dry_run,
             args,
             env,
             storage_configs,
@@ -142,6 +145,15 @@ impl MononokeApp {
         self.config_mode == ConfigMode::Production
     }
 
+    /// Returns true if the user asked for a dry run.  Commands that mutate
+    /// state should log what they would do instead of doing it.
+    ///
+    /// This is always false unless the app was built with
+    /// `MononokeAppBuilder::with_dry_run_support`.
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
     /// Get repo config based on user-provided arguments.
     pub fn repo_config(&self, repo_args: &RepoArgs) -> Result<(String, RepoConfig)> {
         match repo_args.id_or_name()? {
@@ -6,12 +6,13 @@
  */
 
 use anyhow::{Context, Result};
+use bonsai_globalrev_mapping::BonsaiGlobalrevMappingRef;
 use bonsai_hg_mapping::BonsaiHgMappingRef;
 use bookmarks::{BookmarkName, BookmarksRef};
 use clap::{ArgGroup, Args};
 use context::CoreContext;
 use mercurial_types::HgChangesetId;
-use mononoke_types::ChangesetId;
+use mononoke_types::{ChangesetId, Globalrev};
 use repo_identity::RepoIdentityRef;
 
 /// Command line arguments for specifying a changeset.
@@ -19,7 +20,7 @@ use repo_identity::RepoIdentityRef;
 #[clap(group(
     ArgGroup::new("changeset")
         .required(true)
-        .args(&["changeset-id", "hg-id", "bookmark"]),
+        .args(&["changeset-id", "hg-id", "globalrev", "bookmark"]),
 ))]
 pub struct ChangesetArgs {
     /// Bonsai changeset id
@@ -30,6 +31,10 @@ pub struct ChangesetArgs {
     #[clap(long)]
     hg_id: Option<HgChangesetId>,
 
+    /// Globalrev
+    #[clap(long)]
+    globalrev: Option<Globalrev>,
+
     /// Bookmark name
     #[clap(long, short = 'B')]
     bookmark: Option<BookmarkName>,
@@ -39,7 +44,7 @@ impl ChangesetArgs {
     pub async fn resolve_changeset(
         &self,
         ctx: &CoreContext,
-        repo: &(impl BookmarksRef + BonsaiHgMappingRef + RepoIdentityRef),
+        repo: &(impl BookmarksRef + BonsaiHgMappingRef + BonsaiGlobalrevMappingRef + RepoIdentityRef),
     ) -> Result<Option<ChangesetId>> {
         if let Some(bookmark) = &self.bookmark {
             repo.bookmarks()
@@ -51,6 +56,11 @@ impl ChangesetArgs {
                 .get_bonsai_from_hg(ctx, repo.repo_identity().id(), hg_id)
                 .await
                 .with_context(|| format!("Failed to resolve hg changeset id {}", hg_id))
+        } else if let Some(globalrev) = self.globalrev {
+            repo.bonsai_globalrev_mapping()
+                .get_bonsai_from_globalrev(ctx, repo.repo_identity().id(), globalrev)
+                .await
+                .with_context(|| format!("Failed to resolve globalrev {}", globalrev))
         } else {
             Ok(self.changeset_id)
         }
@@ -0,0 +1,16 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use clap::Args;
+
+/// Command line arguments for previewing mutations without performing them
+#[derive(Args, Debug)]
+pub struct DryRunArgs {
+    /// Log the writes that would be performed instead of performing them.
+    #[clap(long, global = true)]
+    pub dry_run: bool,
+}
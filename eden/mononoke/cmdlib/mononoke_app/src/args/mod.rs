@@ -9,6 +9,7 @@ use anyhow::{format_err, Result};
 
 mod changeset;
 mod config;
+mod dry_run;
 mod hooks;
 mod mcrouter;
 mod mysql;
@@ -22,6 +23,7 @@ pub use self::tunables::TunablesArgs;
 pub use crate::fb303::Fb303Args;
 pub use changeset::ChangesetArgs;
 pub use config::{ConfigArgs, ConfigMode};
+pub use dry_run::DryRunArgs;
 pub use hooks::HooksArgs;
 pub use mcrouter::{McrouterArgExtension, McrouterArgs};
 pub use mysql::MysqlArgs;
@@ -32,7 +32,9 @@ use tokio::runtime::Runtime;
 use tunables;
 
 use crate::app::MononokeApp;
-use crate::args::{parse_config_spec_to_path, ConfigArgs, MysqlArgs, RuntimeArgs, TunablesArgs};
+use crate::args::{
+    parse_config_spec_to_path, ConfigArgs, DryRunArgs, MysqlArgs, RuntimeArgs, TunablesArgs,
+};
 use crate::extension::{ArgExtension, ArgExtensionBox};
 
 pub struct MononokeAppBuilder {
@@ -42,6 +44,7 @@ pub struct MononokeAppBuilder {
     readonly_storage: ReadOnlyStorage,
     default_scuba_dataset: Option<String>,
     defaults: HashMap<&'static str, String>,
+    dry_run_support: bool,
 }
 
 #[derive(Args, Debug)]
@@ -96,6 +99,7 @@ impl MononokeAppBuilder {
             readonly_storage: ReadOnlyStorage(false),
             default_scuba_dataset: None,
             defaults: HashMap::new(),
+            dry_run_support: false,
         }
     }
 
@@ -114,6 +118,13 @@ impl MononokeAppBuilder {
         self
     }
 
+    /// Add a `--dry-run` argument.  Commands can check whether it was
+    /// passed with `MononokeApp::dry_run`.
+    pub fn with_dry_run_support(mut self) -> Self {
+        self.dry_run_support = true;
+        self
+    }
+
     pub fn with_arg_extension<Ext>(mut self, ext: Ext) -> Self
     where
         Ext: ArgExtension + 'static,
@@ -158,6 +169,9 @@ impl MononokeAppBuilder {
         let long_about = app.get_long_about();
 
         app = EnvironmentArgs::augment_args_for_update(app);
+        if self.dry_run_support {
+            app = DryRunArgs::augment_args_for_update(app);
+        }
         for ext in self.arg_extensions.iter() {
             app = ext.augment_args(app);
         }
@@ -179,13 +193,14 @@ impl MononokeAppBuilder {
         let args = app.get_matches();
         let env_args = EnvironmentArgs::from_arg_matches(&args)?;
         let config_mode = env_args.config_args.mode();
+        let dry_run = self.dry_run_support && DryRunArgs::from_arg_matches(&args)?.dry_run;
         let mut env = self.build_environment(env_args, &args)?;
 
         for ext in self.arg_extensions.iter() {
             ext.environment_hook(&args, &mut env)?;
         }
 
-        MononokeApp::new(self.fb, config_mode, args, env)
+        MononokeApp::new(self.fb, config_mode, dry_run, args, env)
     }
 
     fn build_environment(
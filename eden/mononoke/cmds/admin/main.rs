@@ -58,6 +58,7 @@ mod pushrebase;
 mod rebase;
 mod redaction;
 mod rsync;
+mod segmented_changelog;
 mod skiplist_subcommand;
 mod split_commit;
 mod subcommand_blame;
@@ -67,6 +68,7 @@ mod subcommand_phases;
 mod subcommand_skeleton_manifests;
 mod subcommand_unodes;
 mod truncate_segmented_changelog;
+mod verify_mappings;
 
 fn setup_app<'a, 'b>() -> MononokeClapApp<'a, 'b> {
     args::MononokeAppBuilder::new("Mononoke admin command line tool")
@@ -106,6 +108,8 @@ fn setup_app<'a, 'b>() -> MononokeClapApp<'a, 'b> {
         .subcommand(subcommand_skeleton_manifests::build_subcommand())
         .subcommand(split_commit::build_subcommand())
         .subcommand(truncate_segmented_changelog::build_subcommand())
+        .subcommand(segmented_changelog::build_subcommand())
+        .subcommand(verify_mappings::build_subcommand())
 }
 
 #[fbinit::main]
@@ -223,6 +227,13 @@ fn main(fb: FacebookInit) -> ExitCode {
                 )
                 .await
             }
+            (segmented_changelog::SEGMENTED_CHANGELOG, Some(sub_m)) => {
+                segmented_changelog::subcommand_segmented_changelog(fb, logger, &matches, sub_m)
+                    .await
+            }
+            (verify_mappings::VERIFY_MAPPINGS, Some(sub_m)) => {
+                verify_mappings::subcommand_verify_mappings(fb, logger, &matches, sub_m).await
+            }
             _ => Err(SubcommandError::InvalidArgs),
         }
     });
@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::{anyhow, Context};
+use blobrepo::BlobRepo;
+use blobstore_factory::{make_metadata_sql_factory, ReadOnlyStorage};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use cmdlib::args::{self, MononokeMatches};
+use context::CoreContext;
+use fbinit::FacebookInit;
+use metaconfig_types::MetadataDatabaseConfig;
+use segmented_changelog::{
+    seedheads_from_config, verify_segmented_changelog, SegmentedChangelogSqlConnections,
+};
+use slog::Logger;
+use sql_ext::facebook::MyAdmin;
+use sql_ext::replication::{NoReplicaLagMonitor, ReplicaLagMonitor};
+use std::sync::Arc;
+
+use crate::error::SubcommandError;
+
+pub const SEGMENTED_CHANGELOG: &str = "segmented-changelog";
+const VERIFY: &str = "verify";
+const ARG_SAMPLE_SIZE: &str = "sample-size";
+
+pub fn build_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name(SEGMENTED_CHANGELOG)
+        .about("inspect the segmented changelog of a repo")
+        .subcommand(
+            SubCommand::with_name(VERIFY)
+                .about("check the health of the saved segmented changelog")
+                .long_about(
+                    "Loads the segmented changelog that was last saved for the repo, and checks \
+                    that the seed heads from the config are in it, and that for a sample of ids \
+                    the IdMap and the IdDag agree with each other and with the parents of the \
+                    changesets. Reports all the problems found, and exits with an error if there \
+                    are any.",
+                )
+                .arg(
+                    Arg::with_name(ARG_SAMPLE_SIZE)
+                        .long(ARG_SAMPLE_SIZE)
+                        .help("how many ids, spread over the whole dag, to check")
+                        .takes_value(true)
+                        .default_value("1000"),
+                ),
+        )
+}
+
+pub async fn subcommand_segmented_changelog<'a>(
+    fb: FacebookInit,
+    logger: Logger,
+    matches: &'a MononokeMatches<'_>,
+    sub_m: &'a ArgMatches<'_>,
+) -> Result<(), SubcommandError> {
+    match sub_m.subcommand() {
+        (VERIFY, Some(sub_m)) => {
+            let sample_size = args::get_u64(sub_m, ARG_SAMPLE_SIZE, 1000);
+            verify(fb, logger, matches, sample_size).await
+        }
+        _ => Err(SubcommandError::InvalidArgs),
+    }
+}
+
+async fn verify<'a>(
+    fb: FacebookInit,
+    logger: Logger,
+    matches: &'a MononokeMatches<'_>,
+    sample_size: u64,
+) -> Result<(), SubcommandError> {
+    let ctx = CoreContext::new_with_logger(fb, logger.clone());
+    let repo: BlobRepo = args::open_repo(fb, &logger, &matches).await?;
+
+    let config_store = matches.config_store();
+    let (_, config) = args::get_config(config_store, matches)?;
+    let seed_heads = seedheads_from_config(&ctx, &config.segmented_changelog_config)?;
+    let storage_config = config.storage_config;
+
+    let db_address = match &storage_config.metadata {
+        MetadataDatabaseConfig::Local(_) => None,
+        MetadataDatabaseConfig::Remote(remote_config) => {
+            Some(remote_config.primary.db_address.clone())
+        }
+    };
+    let replica_lag_monitor: Arc<dyn ReplicaLagMonitor> = match db_address {
+        None => Arc::new(NoReplicaLagMonitor()),
+        Some(address) => {
+            let my_admin = MyAdmin::new(ctx.fb).context("building myadmin client")?;
+            Arc::new(my_admin.single_shard_lag_monitor(address))
+        }
+    };
+
+    let sql_factory = make_metadata_sql_factory(
+        ctx.fb,
+        storage_config.metadata,
+        matches.mysql_options().clone(),
+        ReadOnlyStorage(true),
+    )
+    .await
+    .context("constructing metadata sql factory")?;
+    let connections = sql_factory
+        .open::<SegmentedChangelogSqlConnections>()
+        .context("error opening segmented changelog sql connections")?;
+
+    let problems = verify_segmented_changelog(
+        &ctx,
+        repo.get_repoid(),
+        connections,
+        Arc::new(repo.get_blobstore()),
+        replica_lag_monitor,
+        repo.get_changeset_fetcher(),
+        repo.bookmarks().as_ref(),
+        &seed_heads,
+        sample_size,
+    )
+    .await
+    .context("while verifying")?;
+
+    for problem in &problems {
+        println!("PROBLEM: {}", problem);
+    }
+    println!("found {} problems", problems.len());
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("the segmented changelog has problems").into())
+    }
+}
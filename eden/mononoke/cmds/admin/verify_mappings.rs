@@ -0,0 +1,342 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::{anyhow, Error};
+use blobrepo::BlobRepo;
+use blobstore::{Loadable, LoadableError};
+use bonsai_globalrev_mapping::{BonsaiGlobalrevMapping, BonsaisOrGlobalrevs};
+use bonsai_hg_mapping::BonsaiHgMapping;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use cmdlib::{
+    args::{self, MononokeMatches},
+    helpers::csid_resolve,
+};
+use context::CoreContext;
+use fbinit::FacebookInit;
+use futures::{stream, StreamExt, TryStreamExt};
+use mercurial_types::HgChangesetId;
+use mononoke_types::{ChangesetId, Globalrev};
+use slog::Logger;
+use std::{cmp::min, collections::BTreeMap, fmt};
+
+use crate::error::SubcommandError;
+
+pub const VERIFY_MAPPINGS: &str = "verify-mappings";
+const ARG_HASH_OR_BOOKMARK: &str = "hash-or-bookmark";
+const ARG_RANGE: &str = "range";
+
+/// How many Globalrevs of a range are looked up at once.
+const RANGE_CHUNK_SIZE: u64 = 1000;
+/// How many changesets of a range are verified concurrently.
+const CONCURRENCY: usize = 100;
+
+pub fn build_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name(VERIFY_MAPPINGS)
+        .about("check that the bonsai, hg and globalrev mappings of changesets agree")
+        .long_about(
+            "For a changeset, looks up its hg changeset and its Globalrev, and checks that \
+            both map back to it, and that the Globalrev matches the one recorded in the \
+            changeset itself. Exits with an error if any mapping disagrees.",
+        )
+        .arg(
+            Arg::with_name(ARG_HASH_OR_BOOKMARK)
+                .help("(hg|bonsai) commit hash or bookmark")
+                .takes_value(true)
+                .required_unless(ARG_RANGE)
+                .conflicts_with(ARG_RANGE),
+        )
+        .arg(
+            Arg::with_name(ARG_RANGE)
+                .long(ARG_RANGE)
+                .help(
+                    "verify the changesets of all the Globalrevs from START to END, inclusive, \
+                    and summarize the discrepancies",
+                )
+                .takes_value(true)
+                .value_names(&["START", "END"]),
+        )
+}
+
+pub async fn subcommand_verify_mappings<'a>(
+    fb: FacebookInit,
+    logger: Logger,
+    matches: &'a MononokeMatches<'_>,
+    sub_matches: &'a ArgMatches<'_>,
+) -> Result<(), SubcommandError> {
+    let ctx = CoreContext::new_with_logger(fb, logger.clone());
+    let repo: BlobRepo = args::open_repo(fb, &logger, &matches).await?;
+
+    let clean = match sub_matches.values_of(ARG_RANGE) {
+        Some(mut range) => {
+            let mut parse = || -> Result<Globalrev, Error> {
+                let value = range
+                    .next()
+                    .ok_or_else(|| anyhow!("--{} needs START and END", ARG_RANGE))?;
+                let rev = value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid Globalrev: {}", value))?;
+                Ok(Globalrev::new(rev))
+            };
+            let start = parse()?;
+            let end = parse()?;
+            verify_range(&ctx, &repo, start, end).await?
+        }
+        None => {
+            let hash_or_bm = sub_matches.value_of(ARG_HASH_OR_BOOKMARK).ok_or_else(|| {
+                let err: SubcommandError = anyhow!("{} not set", ARG_HASH_OR_BOOKMARK).into();
+                err
+            })?;
+            let cs_id = csid_resolve(&ctx, repo.clone(), hash_or_bm).await?;
+            verify_one(&ctx, &repo, cs_id).await?
+        }
+    };
+
+    if clean {
+        Ok(())
+    } else {
+        Err(anyhow!("the mappings disagree").into())
+    }
+}
+
+/// A way in which the mappings of a changeset disagree.
+#[derive(Debug)]
+enum Discrepancy {
+    /// The bonsai changeset itself is missing from the blobstore.
+    MissingBonsai,
+    /// The changeset has no hg changeset.
+    MissingHg,
+    /// The hg changeset of the changeset maps back to another changeset, or to none.
+    HgMapsElsewhere {
+        hg_cs_id: HgChangesetId,
+        bcs_id: Option<ChangesetId>,
+    },
+    /// The Globalrev recorded in the changeset is not the one in the mapping.
+    GlobalrevMismatch {
+        in_changeset: Globalrev,
+        in_mapping: Option<Globalrev>,
+    },
+    /// The Globalrev of the changeset maps back to another changeset, or to none.
+    GlobalrevMapsElsewhere {
+        globalrev: Globalrev,
+        bcs_id: Option<ChangesetId>,
+    },
+}
+
+impl Discrepancy {
+    /// A short name for the kind of discrepancy, used to summarize them.
+    fn kind(&self) -> &'static str {
+        match self {
+            Discrepancy::MissingBonsai => "missing bonsai",
+            Discrepancy::MissingHg => "missing hg changeset",
+            Discrepancy::HgMapsElsewhere { .. } => "hg changeset maps to another bonsai",
+            Discrepancy::GlobalrevMismatch { .. } => "globalrev differs from the changeset's",
+            Discrepancy::GlobalrevMapsElsewhere { .. } => "globalrev maps to another bonsai",
+        }
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn or_none(value: Option<impl fmt::Display>) -> String {
+            value.map_or_else(|| "none".to_string(), |value| value.to_string())
+        }
+
+        match self {
+            Discrepancy::MissingBonsai => write!(f, "the bonsai changeset is missing"),
+            Discrepancy::MissingHg => write!(f, "there is no hg changeset"),
+            Discrepancy::HgMapsElsewhere { hg_cs_id, bcs_id } => write!(
+                f,
+                "hg changeset {} maps back to bonsai {}",
+                hg_cs_id,
+                or_none(*bcs_id)
+            ),
+            Discrepancy::GlobalrevMismatch {
+                in_changeset,
+                in_mapping,
+            } => write!(
+                f,
+                "the changeset has globalrev {}, but the mapping has {}",
+                in_changeset.id(),
+                or_none(in_mapping.map(|globalrev| globalrev.id()))
+            ),
+            Discrepancy::GlobalrevMapsElsewhere { globalrev, bcs_id } => write!(
+                f,
+                "globalrev {} maps back to bonsai {}",
+                globalrev.id(),
+                or_none(*bcs_id)
+            ),
+        }
+    }
+}
+
+/// The hg changeset and Globalrev of a changeset, and how the mappings disagree.
+struct Verified {
+    hg_cs_id: Option<HgChangesetId>,
+    globalrev: Option<Globalrev>,
+    discrepancies: Vec<Discrepancy>,
+}
+
+async fn verify_changeset(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    cs_id: ChangesetId,
+) -> Result<Verified, Error> {
+    let repo_id = repo.get_repoid();
+    let mut discrepancies = vec![];
+
+    let hg_cs_id = repo
+        .bonsai_hg_mapping()
+        .get_hg_from_bonsai(ctx, repo_id, cs_id)
+        .await?;
+    match hg_cs_id {
+        Some(hg_cs_id) => {
+            let bcs_id = repo
+                .bonsai_hg_mapping()
+                .get_bonsai_from_hg(ctx, repo_id, hg_cs_id)
+                .await?;
+            if bcs_id != Some(cs_id) {
+                discrepancies.push(Discrepancy::HgMapsElsewhere { hg_cs_id, bcs_id });
+            }
+        }
+        None => discrepancies.push(Discrepancy::MissingHg),
+    }
+
+    let globalrev = repo
+        .bonsai_globalrev_mapping()
+        .get_globalrev_from_bonsai(ctx, repo_id, cs_id)
+        .await?;
+    match cs_id.load(ctx, repo.blobstore()).await {
+        Ok(bcs) => {
+            // Changesets without a Globalrev in their extras are fine, as long as the mapping
+            // does not have one either, which is checked below.
+            if let Ok(in_changeset) = Globalrev::from_bcs(&bcs) {
+                if globalrev != Some(in_changeset) {
+                    discrepancies.push(Discrepancy::GlobalrevMismatch {
+                        in_changeset,
+                        in_mapping: globalrev,
+                    });
+                }
+            }
+        }
+        Err(LoadableError::Missing(_)) => discrepancies.push(Discrepancy::MissingBonsai),
+        Err(err) => return Err(err.into()),
+    }
+    if let Some(globalrev) = globalrev {
+        let bcs_id = repo
+            .bonsai_globalrev_mapping()
+            .get_bonsai_from_globalrev(ctx, repo_id, globalrev)
+            .await?;
+        if bcs_id != Some(cs_id) {
+            discrepancies.push(Discrepancy::GlobalrevMapsElsewhere { globalrev, bcs_id });
+        }
+    }
+
+    Ok(Verified {
+        hg_cs_id,
+        globalrev,
+        discrepancies,
+    })
+}
+
+/// Verify a single changeset and print its mappings. Returns whether they all agree.
+async fn verify_one(ctx: &CoreContext, repo: &BlobRepo, cs_id: ChangesetId) -> Result<bool, Error> {
+    let verified = verify_changeset(ctx, repo, cs_id).await?;
+    println!("bonsai: {}", cs_id);
+    match verified.hg_cs_id {
+        Some(hg_cs_id) => println!("hg: {}", hg_cs_id),
+        None => println!("hg: none"),
+    }
+    match verified.globalrev {
+        Some(globalrev) => println!("globalrev: {}", globalrev.id()),
+        None => println!("globalrev: none"),
+    }
+    for discrepancy in &verified.discrepancies {
+        println!("MISMATCH: {}", discrepancy);
+    }
+    Ok(verified.discrepancies.is_empty())
+}
+
+/// Verify the changesets of the Globalrevs from `start` to `end`, inclusive, printing the
+/// gaps and discrepancies found and a summary of them. Returns whether there were none.
+async fn verify_range(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    start: Globalrev,
+    end: Globalrev,
+) -> Result<bool, Error> {
+    let repo_id = repo.get_repoid();
+    let mapping = repo.bonsai_globalrev_mapping();
+
+    let gaps = mapping
+        .find_globalrev_gaps(ctx, repo_id, start, end)
+        .await?;
+    let mut missing_globalrevs = 0;
+    for gap in &gaps {
+        println!(
+            "globalrevs {} to {}: no changeset",
+            gap.start.id(),
+            gap.end.id() - 1
+        );
+        missing_globalrevs += gap.end.id() - gap.start.id();
+    }
+
+    let mut checked = 0;
+    let mut mismatched = 0;
+    let mut by_kind: BTreeMap<&'static str, u64> = BTreeMap::new();
+    let mut chunk_start = start.id();
+    while chunk_start <= end.id() {
+        let chunk_end = min(chunk_start.saturating_add(RANGE_CHUNK_SIZE - 1), end.id());
+        let globalrevs = (chunk_start..=chunk_end).map(Globalrev::new).collect();
+        let mut entries = mapping
+            .get(ctx, repo_id, BonsaisOrGlobalrevs::Globalrev(globalrevs))
+            .await?;
+        entries.sort_by_key(|entry| entry.globalrev);
+
+        let results: Vec<_> = stream::iter(entries)
+            .map(|entry| async move {
+                let verified = verify_changeset(ctx, repo, entry.bcs_id).await?;
+                Ok::<_, Error>((entry, verified))
+            })
+            .buffered(CONCURRENCY)
+            .try_collect()
+            .await?;
+        for (entry, verified) in results {
+            checked += 1;
+            if verified.discrepancies.is_empty() {
+                continue;
+            }
+            mismatched += 1;
+            for discrepancy in &verified.discrepancies {
+                println!(
+                    "globalrev {} ({}): {}",
+                    entry.globalrev.id(),
+                    entry.bcs_id,
+                    discrepancy
+                );
+                *by_kind.entry(discrepancy.kind()).or_default() += 1;
+            }
+        }
+
+        chunk_start = match chunk_end.checked_add(1) {
+            Some(next) => next,
+            // The chunk ended at the largest possible Globalrev.
+            None => break,
+        };
+    }
+
+    println!(
+        "checked {} changesets: {} with discrepancies, {} gaps covering {} globalrevs",
+        checked,
+        mismatched,
+        gaps.len(),
+        missing_globalrevs
+    );
+    for (kind, count) in &by_kind {
+        println!("  {}: {}", kind, count);
+    }
+    Ok(mismatched == 0 && gaps.is_empty())
+}
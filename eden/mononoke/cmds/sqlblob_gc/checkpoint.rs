@@ -0,0 +1,64 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+
+/// A file recording the last shard that was completely swept, so that an
+/// interrupted sweep can carry on from where it left off.
+pub struct Checkpoint {
+    path: PathBuf,
+}
+
+impl Checkpoint {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Returns the last fully swept shard, or `None` if no progress has been
+    /// recorded.
+    pub fn load(&self) -> Result<Option<usize>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read checkpoint {}", self.path.display()));
+            }
+        };
+        let shard = contents.trim().parse::<usize>().with_context(|| {
+            format!(
+                "Checkpoint {} does not contain a shard number",
+                self.path.display()
+            )
+        })?;
+        Ok(Some(shard))
+    }
+
+    /// Records that `shard` has been completely swept.
+    pub fn record(&self, shard: usize) -> Result<()> {
+        atomicfile::atomic_write(&self.path, 0o644, true, |f| writeln!(f, "{}", shard))
+            .with_context(|| format!("Failed to write checkpoint {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Removes the checkpoint once the whole sweep has completed, so that
+    /// the next run starts from the beginning.
+    pub fn clear(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e)
+                .with_context(|| format!("Failed to remove checkpoint {}", self.path.display())),
+        }
+    }
+}
@@ -17,6 +17,7 @@ use blobstore_factory::make_sql_blobstore;
 use cmdlib::args::{self, MononokeClapApp};
 use metaconfig_types::{BlobConfig, BlobstoreId, ShardableRemoteDatabaseConfig};
 
+mod checkpoint;
 mod subcommand_log_size;
 mod subcommand_mark;
 
@@ -5,7 +5,15 @@
  * GNU General Public License version 2.
  */
 
-use std::{ops::Range, sync::Arc};
+use std::{
+    collections::BTreeSet,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Context, Result};
 use clap::{App, Arg, ArgMatches, SubCommand};
@@ -15,15 +23,26 @@ use futures::{
     sink::SinkExt,
     stream::{self, StreamExt, TryStreamExt},
 };
-use retry::retry;
+use retry::retry_with_max_delay;
 use slog::{info, Logger};
+use tokio::sync::Notify;
+
+use sqlblob::{GenerationCheck, Sqlblob};
 
-use sqlblob::Sqlblob;
+use crate::checkpoint::Checkpoint;
 
 pub const MARK_SAFE: &str = "mark";
 const ARG_INITIAL_GENERATION_ONLY: &str = "initial-generation-only";
 const ARG_SKIP_INITIAL_GENERATION: &str = "skip-initial-generation";
 const ARG_SKIP_INLINE_SMALL_VALUES: &str = "skip-inline-small-values";
+const ARG_RESUME_FROM_SHARD: &str = "resume-from-shard";
+const ARG_CHECKPOINT_FILE: &str = "checkpoint-file";
+const ARG_PROGRESS_INTERVAL: &str = "progress-interval";
+const ARG_RETRIES: &str = "retries";
+const ARG_RETRY_BASE_DELAY_MS: &str = "retry-base-delay-ms";
+const ARG_RETRY_MAX_DELAY_MS: &str = "retry-max-delay-ms";
+const ARG_DRY_RUN: &str = "dry-run";
+const ARG_SHARD_PARALLELISM: &str = "shard-parallelism";
 
 const BASE_RETRY_DELAY_MS: u64 = 1000;
 const RETRIES: usize = 3;
@@ -52,43 +71,295 @@ pub fn build_subcommand<'a, 'b>() -> App<'a, 'b> {
                 .required(false)
                 .help("Only set the generation, don't inline small values")
         )
+        .arg(
+            Arg::with_name(ARG_RESUME_FROM_SHARD)
+                .long(ARG_RESUME_FROM_SHARD)
+                .takes_value(true)
+                .required(false)
+                .help("Shard to resume the sweep from. Overrides any progress recorded in the checkpoint file.")
+        )
+        .arg(
+            Arg::with_name(ARG_CHECKPOINT_FILE)
+                .long(ARG_CHECKPOINT_FILE)
+                .takes_value(true)
+                .required(false)
+                .help("File recording the last fully swept shard. If present, the sweep resumes after that shard. It is removed once all shards have been swept.")
+        )
+        .arg(
+            Arg::with_name(ARG_PROGRESS_INTERVAL)
+                .long(ARG_PROGRESS_INTERVAL)
+                .takes_value(true)
+                .required(false)
+                .default_value("30")
+                .help("How often, in seconds, to log sweep progress")
+        )
+        .arg(
+            Arg::with_name(ARG_RETRIES)
+                .long(ARG_RETRIES)
+                .takes_value(true)
+                .required(false)
+                .help("Number of attempts to make for each database operation before giving up")
+        )
+        .arg(
+            Arg::with_name(ARG_RETRY_BASE_DELAY_MS)
+                .long(ARG_RETRY_BASE_DELAY_MS)
+                .takes_value(true)
+                .required(false)
+                .help("Base delay between attempts, in milliseconds. Doubles with each attempt.")
+        )
+        .arg(
+            Arg::with_name(ARG_RETRY_MAX_DELAY_MS)
+                .long(ARG_RETRY_MAX_DELAY_MS)
+                .takes_value(true)
+                .required(false)
+                .help("Maximum delay between attempts, in milliseconds")
+        )
+        .arg(
+            Arg::with_name(ARG_DRY_RUN)
+                .long(ARG_DRY_RUN)
+                .takes_value(false)
+                .required(false)
+                .help("Don't change anything, just count how many keys would be marked and how many small values would be inlined. Does not record progress in the checkpoint file.")
+        )
+        .arg(
+            Arg::with_name(ARG_SHARD_PARALLELISM)
+                .long(ARG_SHARD_PARALLELISM)
+                .takes_value(true)
+                .required(false)
+                .default_value("1")
+                .help("Number of shards to sweep at the same time. Keys from all of them share the overall key concurrency limit.")
+        )
+}
+
+#[derive(Copy, Clone)]
+struct RetryPolicy {
+    retries: usize,
+    base_delay_ms: u64,
+    max_delay_ms: Option<u64>,
+}
+
+impl RetryPolicy {
+    fn from_matches(sub_matches: &ArgMatches<'_>) -> Result<Self> {
+        let retries = match sub_matches.value_of(ARG_RETRIES) {
+            Some(retries) => retries.parse()?,
+            None => RETRIES,
+        };
+        let base_delay_ms = match sub_matches.value_of(ARG_RETRY_BASE_DELAY_MS) {
+            Some(delay) => delay.parse()?,
+            None => BASE_RETRY_DELAY_MS,
+        };
+        let max_delay_ms = sub_matches
+            .value_of(ARG_RETRY_MAX_DELAY_MS)
+            .map(str::parse)
+            .transpose()?;
+        Ok(Self {
+            retries,
+            base_delay_ms,
+            max_delay_ms,
+        })
+    }
+}
+
+/// Tracks the keys from a shard that are still being processed, so that we
+/// know when the shard has been completely swept.
+struct ShardProgress {
+    outstanding: AtomicU64,
+    drained: Notify,
+}
+
+impl ShardProgress {
+    fn new() -> Self {
+        Self {
+            outstanding: AtomicU64::new(0),
+            drained: Notify::new(),
+        }
+    }
+
+    fn start_key(&self) {
+        self.outstanding.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn finish_key(&self) {
+        if self.outstanding.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.drained.notify_one();
+        }
+    }
+
+    /// Waits until every key started so far has been successfully handled.
+    async fn wait_drained(&self) {
+        while self.outstanding.load(Ordering::SeqCst) > 0 {
+            self.drained.notified().await;
+        }
+    }
+}
+
+/// Tracks which shards have been completely swept when several shards are
+/// swept at once, so that the checkpoint only records a shard once all the
+/// shards before it have been swept too.
+struct SweptShards {
+    next: usize,
+    out_of_order: BTreeSet<usize>,
+}
+
+impl SweptShards {
+    fn new(first: usize) -> Self {
+        Self {
+            next: first,
+            out_of_order: BTreeSet::new(),
+        }
+    }
+
+    /// Marks `shard` as swept. Returns the last shard of the fully swept
+    /// prefix of the range if it has grown.
+    fn complete(&mut self, shard: usize) -> Option<usize> {
+        self.out_of_order.insert(shard);
+        let mut last_swept = None;
+        while self.out_of_order.remove(&self.next) {
+            last_swept = Some(self.next);
+            self.next += 1;
+        }
+        last_swept
+    }
+}
+
+/// Totals of what a dry run would have changed.
+#[derive(Default)]
+struct DryRunCounts {
+    keys_to_mark: AtomicU64,
+    chunks_to_mark: AtomicU64,
+    values_to_inline: AtomicU64,
+}
+
+impl DryRunCounts {
+    fn record(&self, check: GenerationCheck) {
+        if check.chunks_to_mark > 0 {
+            self.keys_to_mark.fetch_add(1, Ordering::Relaxed);
+            self.chunks_to_mark
+                .fetch_add(check.chunks_to_mark, Ordering::Relaxed);
+        }
+        if check.would_inline {
+            self.values_to_inline.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Periodically logs how many keys have been swept, until aborted.
+async fn report_progress(
+    processed: Arc<AtomicU64>,
+    start: Instant,
+    interval: Duration,
+    logger: Arc<Logger>,
+) {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    let mut last_processed = 0;
+    let mut last_report = start;
+    loop {
+        ticker.tick().await;
+        let now = Instant::now();
+        let total = processed.load(Ordering::Relaxed);
+        let rate = (total - last_processed) as f64 / now.duration_since(last_report).as_secs_f64();
+        info!(
+            logger,
+            "Processed {} keys ({:.1} keys/s) in {}s",
+            total,
+            rate,
+            now.duration_since(start).as_secs(),
+        );
+        last_processed = total;
+        last_report = now;
+    }
 }
 
 async fn handle_one_key(
     key: String,
+    progress: Arc<ShardProgress>,
+    processed: Arc<AtomicU64>,
     store: Arc<Sqlblob>,
     inline_small_values: bool,
+    retry_policy: RetryPolicy,
     logger: Arc<Logger>,
+    dry_run_counts: Option<Arc<DryRunCounts>>,
 ) -> Result<()> {
-    retry(
-        &logger,
-        |_| store.set_generation(&key, inline_small_values),
-        BASE_RETRY_DELAY_MS,
-        RETRIES,
-    )
-    .await
-    .with_context(|| anyhow!("Failed to handle {} after {} retries", &key, RETRIES))?;
+    let res = match &dry_run_counts {
+        None => retry_with_max_delay(
+            &logger,
+            |_| store.set_generation(&key, inline_small_values),
+            retry_policy.base_delay_ms,
+            retry_policy.max_delay_ms,
+            retry_policy.retries,
+        )
+        .await
+        .map(|_| ()),
+        Some(counts) => retry_with_max_delay(
+            &logger,
+            |_| store.check_generation(&key, inline_small_values),
+            retry_policy.base_delay_ms,
+            retry_policy.max_delay_ms,
+            retry_policy.retries,
+        )
+        .await
+        .map(|(check, _)| counts.record(check)),
+    };
+    res.with_context(|| {
+        anyhow!(
+            "Failed to handle {} after {} retries",
+            &key,
+            retry_policy.retries
+        )
+    })?;
+    processed.fetch_add(1, Ordering::Relaxed);
+    progress.finish_key();
     Ok(())
 }
 
-async fn handle_initial_generation(store: &Sqlblob, shard: usize, logger: &Logger) -> Result<()> {
-    retry(
+async fn handle_initial_generation(
+    store: &Sqlblob,
+    shard: usize,
+    retry_policy: RetryPolicy,
+    logger: &Logger,
+) -> Result<()> {
+    retry_with_max_delay(
         logger,
         |_| store.set_initial_generation(shard),
-        BASE_RETRY_DELAY_MS,
-        RETRIES,
+        retry_policy.base_delay_ms,
+        retry_policy.max_delay_ms,
+        retry_policy.retries,
     )
     .await
     .with_context(|| {
         anyhow!(
             "Failed to handle initial generation on shard {} after {} retries",
             &shard,
-            RETRIES
+            retry_policy.retries
         )
     })?;
     Ok(())
 }
 
+async fn count_initial_generation(
+    store: &Sqlblob,
+    shard: usize,
+    retry_policy: RetryPolicy,
+    logger: &Logger,
+) -> Result<u64> {
+    let (count, _) = retry_with_max_delay(
+        logger,
+        |_| store.count_initial_generation(shard),
+        retry_policy.base_delay_ms,
+        retry_policy.max_delay_ms,
+        retry_policy.retries,
+    )
+    .await
+    .with_context(|| {
+        anyhow!(
+            "Failed to count chunks needing initial generation on shard {} after {} retries",
+            &shard,
+            retry_policy.retries
+        )
+    })?;
+    Ok(count)
+}
+
 pub async fn subcommand_mark<'a>(
     _fb: FacebookInit,
     logger: Logger,
@@ -97,11 +368,76 @@ pub async fn subcommand_mark<'a>(
     sqlblob: Sqlblob,
     shard_range: Range<usize>,
 ) -> Result<()> {
-    if !sub_matches.is_present(ARG_SKIP_INITIAL_GENERATION) {
+    let checkpoint = sub_matches
+        .value_of(ARG_CHECKPOINT_FILE)
+        .map(Checkpoint::new);
+    let resume_from = match sub_matches.value_of(ARG_RESUME_FROM_SHARD) {
+        Some(shard) => Some(shard.parse::<usize>()?),
+        None => match &checkpoint {
+            Some(checkpoint) => checkpoint.load()?.map(|last_swept| last_swept + 1),
+            None => None,
+        },
+    };
+    let shard_range = match resume_from {
+        Some(resume_from) => {
+            let start = shard_range.start.max(resume_from);
+            info!(
+                logger,
+                "Resuming from shard {} (of shards {} to {})",
+                start,
+                shard_range.start,
+                shard_range.end - 1,
+            );
+            Range {
+                start,
+                end: shard_range.end.max(start),
+            }
+        }
+        None => shard_range,
+    };
+
+    let retry_policy = RetryPolicy::from_matches(sub_matches)?;
+    let dry_run = sub_matches.is_present(ARG_DRY_RUN);
+    // A dry run can resume from a checkpoint, but must not record progress
+    // in it, or the real run would skip shards.
+    let checkpoint = if dry_run { None } else { checkpoint };
+
+    let progress_interval_secs: u64 = sub_matches
+        .value_of(ARG_PROGRESS_INTERVAL)
+        .expect("progress interval has a default")
+        .parse()?;
+    if progress_interval_secs == 0 {
+        return Err(anyhow!("--{} must be at least 1", ARG_PROGRESS_INTERVAL));
+    }
+    let progress_interval = Duration::from_secs(progress_interval_secs);
+
+    if dry_run && !sub_matches.is_present(ARG_SKIP_INITIAL_GENERATION) {
+        info!(
+            logger,
+            "Dry run: counting chunks needing initial generation"
+        );
+        let counts: Vec<u64> = stream::iter(shard_range.clone())
+            .map(|shard| count_initial_generation(&sqlblob, shard, retry_policy, &logger))
+            .buffer_unordered(max_parallelism)
+            .try_collect()
+            .await?;
+        info!(
+            logger,
+            "Dry run: would set initial generation on {} chunks",
+            counts.into_iter().sum::<u64>(),
+        );
+    } else if !sub_matches.is_present(ARG_SKIP_INITIAL_GENERATION) {
         info!(logger, "Starting initial generation set");
         let set_initial_generation_futures: Vec<_> = shard_range
             .clone()
-            .map(|shard| Ok(handle_initial_generation(&sqlblob, shard, &logger)))
+            .map(|shard| {
+                Ok(handle_initial_generation(
+                    &sqlblob,
+                    shard,
+                    retry_policy,
+                    &logger,
+                ))
+            })
             .collect();
         stream::iter(set_initial_generation_futures.into_iter())
             .try_for_each_concurrent(max_parallelism, |fut| fut)
@@ -117,22 +453,51 @@ pub async fn subcommand_mark<'a>(
     let logger = Arc::new(logger);
 
     let inline_small_values = !sub_matches.is_present(ARG_SKIP_INLINE_SMALL_VALUES);
+    let shard_parallelism: usize = sub_matches
+        .value_of(ARG_SHARD_PARALLELISM)
+        .expect("shard parallelism has a default")
+        .parse()?;
+    if shard_parallelism == 0 {
+        return Err(anyhow!("--{} must be at least 1", ARG_SHARD_PARALLELISM));
+    }
 
     info!(logger, "Starting sweep");
+    let start = Instant::now();
+    let processed = Arc::new(AtomicU64::new(0));
+    let dry_run_counts = dry_run.then(|| Arc::new(DryRunCounts::default()));
+    let reporter = tokio::spawn(report_progress(
+        processed.clone(),
+        start,
+        progress_interval,
+        logger.clone(),
+    ));
     // Set up a task to process each key in parallel in its own task.
-    let (key_channel, processor) = {
+    let (key_channel, mut processor) = {
         let sqlblob = Arc::clone(&sqlblob);
         let logger = Arc::clone(&logger);
+        let processed = Arc::clone(&processed);
+        let dry_run_counts = dry_run_counts.clone();
         let (tx, rx) = mpsc::channel(10);
         let task = tokio::spawn(async move {
             rx.map(Ok)
                 .try_for_each_concurrent(max_parallelism, {
-                    |key| {
+                    |(key, progress)| {
                         let sqlblob = sqlblob.clone();
                         let logger = logger.clone();
+                        let processed = processed.clone();
+                        let dry_run_counts = dry_run_counts.clone();
                         async move {
-                            tokio::spawn(handle_one_key(key, sqlblob, inline_small_values, logger))
-                                .await?
+                            tokio::spawn(handle_one_key(
+                                key,
+                                progress,
+                                processed,
+                                sqlblob,
+                                inline_small_values,
+                                retry_policy,
+                                logger,
+                                dry_run_counts,
+                            ))
+                            .await?
                         }
                     }
                 })
@@ -141,25 +506,89 @@ pub async fn subcommand_mark<'a>(
         (tx, task)
     };
 
-    // Foreach shard in shard_range
-    for shard in shard_range {
-        info!(logger, "Starting sweep on data keys from shard {}", shard);
-        let res = sqlblob
-            .get_keys_from_shard(shard)
-            .forward(key_channel.clone().sink_err_into())
-            .await;
-        // Report processing errors ahead of key errors - that way, we don't lose the error if the channel goes away because of an error
-        if res.is_err() {
-            std::mem::drop(key_channel);
-            processor.await??;
-            return res;
+    // Sweep up to shard_parallelism shards at once. Keys from all of them go
+    // through the same channel, so max_parallelism still bounds the number of
+    // keys being handled.
+    let wait_for_drain = checkpoint.is_some();
+    let mut shards = stream::iter(shard_range.clone())
+        .map(|shard| {
+            let sqlblob = &sqlblob;
+            let logger = &logger;
+            let key_channel = key_channel.clone();
+            async move {
+                info!(logger, "Starting sweep on data keys from shard {}", shard);
+                let progress = Arc::new(ShardProgress::new());
+                sqlblob
+                    .get_keys_from_shard(shard)
+                    .map_ok(|key| {
+                        progress.start_key();
+                        (key, progress.clone())
+                    })
+                    .forward(key_channel.sink_err_into())
+                    .await?;
+                if wait_for_drain {
+                    // Only report the shard once all of its keys have been handled.
+                    progress.wait_drained().await;
+                }
+                anyhow::Ok(shard)
+            }
+        })
+        .buffer_unordered(shard_parallelism);
+
+    let mut swept_shards = SweptShards::new(shard_range.start);
+    loop {
+        tokio::select! {
+            next = shards.next() => match next {
+                None => break,
+                Some(Ok(shard)) => {
+                    if let Some(checkpoint) = &checkpoint {
+                        if let Some(last_swept) = swept_shards.complete(shard) {
+                            checkpoint.record(last_swept)?;
+                            info!(logger, "Recorded shards up to {} as fully swept", last_swept);
+                        }
+                    }
+                }
+                Some(Err(err)) => {
+                    // Report processing errors ahead of key errors - that way, we don't lose the error if the channel goes away because of an error
+                    reporter.abort();
+                    std::mem::drop(shards);
+                    std::mem::drop(key_channel);
+                    processor.await??;
+                    return Err(err);
+                }
+            },
+            res = &mut processor => {
+                reporter.abort();
+                res??;
+                return Err(anyhow!("Key processor exited while sweeping shards"));
+            }
         }
     }
 
-    // Drop the spare sender so that the processor task can exit
+    // Drop the spare senders so that the processor task can exit
+    std::mem::drop(shards);
     std::mem::drop(key_channel);
 
-    processor.await??;
-    info!(logger, "Completed all sweeps");
+    let res = processor.await;
+    reporter.abort();
+    res??;
+    if let Some(checkpoint) = &checkpoint {
+        checkpoint.clear()?;
+    }
+    info!(
+        logger,
+        "Completed all sweeps: processed {} keys in {}s",
+        processed.load(Ordering::Relaxed),
+        start.elapsed().as_secs(),
+    );
+    if let Some(counts) = dry_run_counts {
+        info!(
+            logger,
+            "Dry run: would mark {} chunks of {} keys, and inline {} small values",
+            counts.chunks_to_mark.load(Ordering::Relaxed),
+            counts.keys_to_mark.load(Ordering::Relaxed),
+            counts.values_to_inline.load(Ordering::Relaxed),
+        );
+    }
     Ok(())
 }
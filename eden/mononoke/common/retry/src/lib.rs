@@ -13,9 +13,26 @@ use std::time::Duration;
 pub struct RetryAttemptsCount(pub usize);
 
 pub async fn retry<V, Fut, Func, Error>(
+    logger: &Logger,
+    func: Func,
+    base_retry_delay_ms: u64,
+    retry_num: usize,
+) -> Result<(V, RetryAttemptsCount), Error>
+where
+    V: Send + 'static,
+    Fut: Future<Output = Result<V, Error>>,
+    Func: FnMut(usize) -> Fut + Send,
+{
+    retry_with_max_delay(logger, func, base_retry_delay_ms, None, retry_num).await
+}
+
+/// Like `retry`, but the exponentially growing delay between attempts is
+/// capped at `max_retry_delay_ms`, if given.
+pub async fn retry_with_max_delay<V, Fut, Func, Error>(
     logger: &Logger,
     mut func: Func,
     base_retry_delay_ms: u64,
+    max_retry_delay_ms: Option<u64>,
     retry_num: usize,
 ) -> Result<(V, RetryAttemptsCount), Error>
 where
@@ -41,7 +58,12 @@ where
                     retry_num
                 );
 
-                let delay = Duration::from_millis(base_retry_delay_ms * 2u64.pow(attempt as u32));
+                let mut delay_ms =
+                    base_retry_delay_ms.saturating_mul(2u64.saturating_pow(attempt as u32));
+                if let Some(max_retry_delay_ms) = max_retry_delay_ms {
+                    delay_ms = delay_ms.min(max_retry_delay_ms);
+                }
+                let delay = Duration::from_millis(delay_ms);
                 tokio::time::sleep(delay).await;
                 attempt += 1;
             }
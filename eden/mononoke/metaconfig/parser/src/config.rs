@@ -797,6 +797,9 @@ mod test {
             skip_dag_load_at_startup = true
             reload_dag_save_period_secs = 0
             update_to_master_bookmark_period_secs = 120
+            bookmark_prefixes_to_include = ["heads/"]
+            heads_reserve_size = 1024
+            bookmarks_to_include = ["release"]
 
             [backup_config]
             verification_enabled = false
@@ -1102,6 +1105,9 @@ mod test {
                     reload_dag_save_period: None,
                     update_to_master_bookmark_period: Some(Duration::from_secs(120)),
                     bonsai_changesets_to_include: vec![],
+                    bookmark_prefixes_to_include: vec![String::from("heads/")],
+                    heads_reserve_size: Some(1024),
+                    bookmarks_to_include: vec![String::from("release")],
                 },
                 repo_client_knobs: RepoClientKnobs {
                     allow_short_getpack_history: true,
@@ -1173,6 +1179,9 @@ mod test {
                     reload_dag_save_period: Some(Duration::from_secs(3600)),
                     update_to_master_bookmark_period: Some(Duration::from_secs(60)),
                     bonsai_changesets_to_include: vec![],
+                    bookmark_prefixes_to_include: vec![],
+                    heads_reserve_size: None,
+                    bookmarks_to_include: vec![],
                 },
                 repo_client_knobs: RepoClientKnobs::default(),
                 phabricator_callsign: Some("WWW".to_string()),
@@ -479,6 +479,9 @@ impl Convert for RawSegmentedChangelogConfig {
                 default.update_to_master_bookmark_period,
             )?,
             bonsai_changesets_to_include: bonsai_changesets_to_include?,
+            bookmark_prefixes_to_include: self.bookmark_prefixes_to_include.unwrap_or_default(),
+            heads_reserve_size: self.heads_reserve_size.map(|v| v.try_into()).transpose()?,
+            bookmarks_to_include: self.bookmarks_to_include.unwrap_or_default(),
         })
     }
 }
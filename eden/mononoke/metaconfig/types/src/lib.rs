@@ -1580,6 +1580,14 @@ pub struct SegmentedChangelogConfig {
     /// `bonsai_changesets_to_include` then every reseeding would add B and it's
     /// ancestors to the reseeded segmented changelog.
     pub bonsai_changesets_to_include: Vec<ChangesetId>,
+    /// Bookmark prefixes whose bookmarks are also used as heads during reseeding.
+    pub bookmark_prefixes_to_include: Vec<String>,
+    /// How many ids to reserve after each head during reseeding.
+    /// The segmented changelog default is used when unset.
+    pub heads_reserve_size: Option<u32>,
+    /// Additional bookmarks used as heads during reseeding. Bookmarks that don't exist are
+    /// skipped, unlike the master bookmark.
+    pub bookmarks_to_include: Vec<String>,
 }
 
 impl Default for SegmentedChangelogConfig {
@@ -1592,6 +1600,9 @@ impl Default for SegmentedChangelogConfig {
             reload_dag_save_period: Some(Duration::from_secs(3600)),
             update_to_master_bookmark_period: Some(Duration::from_secs(60)),
             bonsai_changesets_to_include: vec![],
+            bookmark_prefixes_to_include: vec![],
+            heads_reserve_size: None,
+            bookmarks_to_include: vec![],
         }
     }
 }
@@ -34,6 +34,7 @@ mod sql_types;
 mod tailer;
 pub mod types;
 mod update;
+mod verify;
 mod version_store;
 
 #[cfg(test)]
@@ -50,7 +51,11 @@ pub use crate::builder::{new_server_segmented_changelog, SegmentedChangelogSqlCo
 pub use crate::clone_hints::CloneHints;
 pub use crate::copy::copy_segmented_changelog;
 pub use crate::tailer::SegmentedChangelogTailer;
-pub use crate::update::{seedheads_from_config, SeedHead};
+pub use crate::update::{
+    changesets_from_file, seedheads_from_config, seedheads_with_vertexes, server_namedag,
+    update_namedag, ResolvedSeedHeads, SeedHead, SeedHeadKind, ServerNameDag, DEFAULT_RESERVE_SIZE,
+};
+pub use crate::verify::verify_segmented_changelog;
 
 // public for benchmarking
 pub use crate::idmap::{ConcurrentMemIdMap, IdMap};
@@ -12,7 +12,7 @@ use anyhow::{Context, Error, Result};
 use fbinit::FacebookInit;
 use futures::stream::{self, Stream, StreamExt, TryStreamExt};
 use futures_stats::TimedFutureExt;
-use slog::{debug, error, info};
+use slog::{debug, error, info, warn};
 use sql_ext::facebook::{MyAdmin, MysqlOptions};
 use sql_ext::replication::{NoReplicaLagMonitor, ReplicaLagMonitor};
 
@@ -40,7 +40,7 @@ use crate::idmap::{cs_id_from_vertex_name, CacheHandlers, IdMapFactory};
 use crate::owned::OwnedSegmentedChangelog;
 use crate::parents::FetchParents;
 use crate::types::{IdMapVersion, SegmentedChangelogVersion};
-use crate::update::{server_namedag, vertexlist_from_seedheads, SeedHead};
+use crate::update::{seedheads_with_vertexes, server_namedag, SeedHead};
 use crate::version_store::SegmentedChangelogVersionStore;
 use crate::{CloneHints, InProcessIdDag, SegmentedChangelogSqlConnections};
 
@@ -280,8 +280,30 @@ impl SegmentedChangelogTailer {
 
         let mut namedag = server_namedag(ctx.clone(), iddag, idmap)?;
 
-        let heads =
-            vertexlist_from_seedheads(&ctx, &self.seed_heads, self.bookmarks.as_ref()).await?;
+        let resolved_heads =
+            seedheads_with_vertexes(&ctx, &self.seed_heads, self.bookmarks.as_ref()).await?;
+        if !resolved_heads.skipped.is_empty() {
+            warn!(
+                ctx.logger(),
+                "skipped {} seed heads that did not resolve: {}",
+                resolved_heads.skipped.len(),
+                resolved_heads
+                    .skipped
+                    .iter()
+                    .map(|head| head.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        for (head, vertex) in &resolved_heads.vertexes {
+            info!(
+                ctx.logger(),
+                "{} resolved to {}",
+                head,
+                cs_id_from_vertex_name(vertex)
+            );
+        }
+        let heads = resolved_heads.into_vertex_list(&ctx)?;
 
         let head_commits: Vec<_> = namedag
             .heads(namedag.master_group().await?)
@@ -18,13 +18,14 @@ use once_cell::sync::Lazy;
 
 use blobrepo::BlobRepo;
 use bonsai_hg_mapping::BonsaiHgMappingArc;
-use bookmarks::{BookmarkName, Bookmarks, BookmarksArc};
+use bookmarks::{BookmarkKind, BookmarkName, BookmarkPrefix, Bookmarks, BookmarksArc};
 use bulkops::PublicChangesetBulkFetch;
 use caching_ext::{CachelibHandler, MemcacheHandler};
 use changeset_fetcher::PrefetchedChangesetsFetcher;
 use changesets::{ChangesetEntry, ChangesetsArc, ChangesetsRef};
 use context::CoreContext;
 use fixtures::{branch_even, linear, merge_even, merge_uneven, set_bookmark, unshared_merge_even};
+use metaconfig_types::SegmentedChangelogConfig;
 use mononoke_types::{ChangesetId, RepositoryId};
 use phases::{PhasesArc, PhasesRef};
 use revset::AncestorsNodeStream;
@@ -34,14 +35,21 @@ use tests_utils::{resolve_cs_id, CreateCommitContext};
 
 use crate::builder::SegmentedChangelogSqlConnections;
 use crate::iddag::IdDagSaveStore;
-use crate::idmap::{CacheHandlers, ConcurrentMemIdMap, IdMap, IdMapFactory, SqlIdMap};
+use crate::idmap::{
+    vertex_name_from_cs_id, CacheHandlers, ConcurrentMemIdMap, IdMap, IdMapFactory, SqlIdMap,
+};
 use crate::on_demand::OnDemandUpdateSegmentedChangelog;
 use crate::owned::OwnedSegmentedChangelog;
 use crate::periodic_reload::PeriodicReloadSegmentedChangelog;
 use crate::tailer::SegmentedChangelogTailer;
 use crate::types::{IdDagVersion, IdMapVersion, SegmentedChangelogVersion};
+use crate::update::vertexlist_from_seedheads;
 use crate::version_store::SegmentedChangelogVersionStore;
-use crate::{InProcessIdDag, Location, SeedHead, SegmentedChangelog};
+use crate::{
+    seedheads_from_config, seedheads_with_vertexes, server_namedag, update_namedag,
+    verify_segmented_changelog, Group, InProcessIdDag, Location, SeedHead, SeedHeadKind,
+    SegmentedChangelog, ServerNameDag, DEFAULT_RESERVE_SIZE,
+};
 
 #[async_trait::async_trait]
 trait SegmentedChangelogExt {
@@ -934,6 +942,50 @@ async fn test_seeder_tailer_and_load(fb: FacebookInit) -> Result<()> {
     Ok(())
 }
 
+#[fbinit::test]
+async fn test_verify(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = merge_uneven::getrepo(fb).await;
+    let conns = SegmentedChangelogSqlConnections::with_sqlite_in_memory()?;
+
+    let master = resolve_cs_id(&ctx, &blobrepo, "d35b1875cdd1ed2c687e86f1604b9d7e989450cb").await?;
+    seed(&ctx, &blobrepo, &conns, master).await?;
+
+    let verify = |seed_heads: Vec<SeedHead>| {
+        let ctx = &ctx;
+        let blobrepo = &blobrepo;
+        let conns = conns.clone();
+        async move {
+            verify_segmented_changelog(
+                ctx,
+                blobrepo.get_repoid(),
+                conns,
+                Arc::new(blobrepo.get_blobstore()),
+                Arc::new(NoReplicaLagMonitor()),
+                blobrepo.get_changeset_fetcher(),
+                blobrepo.bookmarks().as_ref(),
+                &seed_heads,
+                1000,
+            )
+            .await
+        }
+    };
+
+    // Every id is sampled, including the merge.
+    assert_eq!(verify(vec![master.into()]).await?, Vec::<String>::new());
+
+    // A changeset that was never added to the dag is reported, not an error.
+    let new_cs = CreateCommitContext::new(&ctx, &blobrepo, vec![master])
+        .add_file("somefile", "file")
+        .commit()
+        .await?;
+    let problems = verify(vec![master.into(), new_cs.into()]).await?;
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("not in the dag"), "{}", problems[0]);
+
+    Ok(())
+}
+
 #[fbinit::test]
 async fn test_periodic_reload(fb: FacebookInit) -> Result<()> {
     let ctx = CoreContext::test_mock(fb);
@@ -1071,3 +1123,389 @@ async fn test_seeding_with_included_bonsais(fb: FacebookInit) -> Result<()> {
 
     Ok(())
 }
+
+#[fbinit::test]
+async fn test_seed_head_bookmark_prefix(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = linear::getrepo(fb).await;
+
+    let first_hg_cs = "2d7d4ba9ce0a6ffd222de7785b249ead9c51c536";
+    let last_hg_cs = "d0a361e9022d226ae52f689667bd7d212a19cfe0";
+    set_bookmark(
+        fb,
+        blobrepo.clone(),
+        first_hg_cs,
+        BookmarkName::new("heads/first")?,
+    )
+    .await;
+    set_bookmark(
+        fb,
+        blobrepo.clone(),
+        last_hg_cs,
+        BookmarkName::new("scratch/last")?,
+    )
+    .await;
+    let first_cs_id = resolve_cs_id(&ctx, &blobrepo, first_hg_cs).await?;
+
+    let seed_head = SeedHead::from(BookmarkPrefix::new("heads/")?);
+    let vertex_list = seed_head
+        .into_vertex_list(&ctx, blobrepo.bookmarks().as_ref())
+        .await?;
+    assert_eq!(
+        vertex_list.vertexes(),
+        vec![vertex_name_from_cs_id(&first_cs_id)]
+    );
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_seed_head_bookmark_kinds(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = linear::getrepo(fb).await;
+
+    let first_hg_cs = "2d7d4ba9ce0a6ffd222de7785b249ead9c51c536";
+    let last_hg_cs = "d0a361e9022d226ae52f689667bd7d212a19cfe0";
+    set_bookmark(
+        fb,
+        blobrepo.clone(),
+        first_hg_cs,
+        BookmarkName::new("first")?,
+    )
+    .await;
+    let first_cs_id = resolve_cs_id(&ctx, &blobrepo, first_hg_cs).await?;
+    let last_cs_id = resolve_cs_id(&ctx, &blobrepo, last_hg_cs).await?;
+    let mut txn = blobrepo.bookmarks().create_transaction(ctx.clone());
+    txn.create_scratch(&BookmarkName::new("scratch/last")?, last_cs_id)?;
+    assert!(txn.commit().await?);
+
+    let vertexes_of = |kind: SeedHeadKind| {
+        let ctx = &ctx;
+        let bookmarks = blobrepo.bookmarks();
+        async move {
+            let vertex_list = SeedHead::from(kind)
+                .into_vertex_list(ctx, bookmarks.as_ref())
+                .await?;
+            Ok::<_, anyhow::Error>(vertex_list.vertexes())
+        }
+    };
+
+    // Scratch bookmarks are not included by default.
+    assert_eq!(
+        vertexes_of(SeedHeadKind::AllBookmarks).await?,
+        vec![vertex_name_from_cs_id(&first_cs_id)]
+    );
+    assert_eq!(
+        vertexes_of(SeedHeadKind::AllBookmarksOfKinds(vec![
+            BookmarkKind::Scratch
+        ]))
+        .await?,
+        vec![vertex_name_from_cs_id(&last_cs_id)]
+    );
+    let mut all = vertexes_of(SeedHeadKind::AllBookmarksOfKinds(
+        BookmarkKind::ALL.to_vec(),
+    ))
+    .await?;
+    all.sort();
+    let mut expected = vec![
+        vertex_name_from_cs_id(&first_cs_id),
+        vertex_name_from_cs_id(&last_cs_id),
+    ];
+    expected.sort();
+    assert_eq!(all, expected);
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_seedheads_with_vertexes(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = linear::getrepo(fb).await;
+
+    let first_hg_cs = "2d7d4ba9ce0a6ffd222de7785b249ead9c51c536";
+    let last_hg_cs = "d0a361e9022d226ae52f689667bd7d212a19cfe0";
+    set_bookmark(
+        fb,
+        blobrepo.clone(),
+        first_hg_cs,
+        BookmarkName::new("heads/first")?,
+    )
+    .await;
+    let first_cs_id = resolve_cs_id(&ctx, &blobrepo, first_hg_cs).await?;
+    let last_cs_id = resolve_cs_id(&ctx, &blobrepo, last_hg_cs).await?;
+
+    let seed_heads = vec![
+        SeedHead::from(BookmarkPrefix::new("heads/")?).with_reserve_size(1024),
+        SeedHead::from(last_cs_id),
+    ];
+    let resolved =
+        seedheads_with_vertexes(&ctx, &seed_heads, blobrepo.bookmarks().as_ref()).await?;
+    assert_eq!(resolved.vertexes.len(), 2);
+    assert!(resolved.skipped.is_empty());
+
+    let (head, vertex) = &resolved.vertexes[0];
+    match head.kind() {
+        SeedHeadKind::Bookmark(name) => assert_eq!(name.as_str(), "heads/first"),
+        kind => panic!("prefix resolved to unexpected seed head {:?}", kind),
+    }
+    assert_eq!(head.reserve_size(), 1024);
+    assert_eq!(*vertex, vertex_name_from_cs_id(&first_cs_id));
+
+    let (head, vertex) = &resolved.vertexes[1];
+    assert!(matches!(head.kind(), SeedHeadKind::Changeset(id) if *id == last_cs_id));
+    assert_eq!(*vertex, vertex_name_from_cs_id(&last_cs_id));
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_optional_seed_head_missing_bookmark(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = linear::getrepo(fb).await;
+    let cs_id = resolve_cs_id(&ctx, &blobrepo, "d0a361e9022d226ae52f689667bd7d212a19cfe0").await?;
+    let missing = BookmarkName::new("missing")?;
+
+    // A missing bookmark is fatal by default.
+    let seed_heads = vec![SeedHead::from(cs_id), SeedHead::from(missing.clone())];
+    assert!(
+        seedheads_with_vertexes(&ctx, &seed_heads, blobrepo.bookmarks().as_ref())
+            .await
+            .is_err()
+    );
+
+    // An optional one is skipped and reported.
+    let seed_heads = vec![
+        SeedHead::from(cs_id),
+        SeedHead::from(missing).with_optional(true),
+    ];
+    let resolved =
+        seedheads_with_vertexes(&ctx, &seed_heads, blobrepo.bookmarks().as_ref()).await?;
+    assert_eq!(resolved.vertexes.len(), 1);
+    assert_eq!(resolved.vertexes[0].1, vertex_name_from_cs_id(&cs_id));
+    assert_eq!(resolved.skipped.len(), 1);
+    assert!(matches!(
+        resolved.skipped[0].kind(),
+        SeedHeadKind::Bookmark(name) if name.as_str() == "missing"
+    ));
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_seed_head_reserve_size(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = linear::getrepo(fb).await;
+    let cs_id = resolve_cs_id(&ctx, &blobrepo, "d0a361e9022d226ae52f689667bd7d212a19cfe0").await?;
+
+    let vertex_list = SeedHead::from(cs_id)
+        .into_vertex_list(&ctx, blobrepo.bookmarks().as_ref())
+        .await?;
+    assert_eq!(
+        vertex_list.vertex_options()[0].1.reserve_size,
+        DEFAULT_RESERVE_SIZE
+    );
+
+    let vertex_list = SeedHead::from(cs_id)
+        .with_reserve_size(1024)
+        .into_vertex_list(&ctx, blobrepo.bookmarks().as_ref())
+        .await?;
+    assert_eq!(vertex_list.vertex_options()[0].1.reserve_size, 1024);
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_seed_head_changeset_file(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = linear::getrepo(fb).await;
+    let first_cs_id =
+        resolve_cs_id(&ctx, &blobrepo, "2d7d4ba9ce0a6ffd222de7785b249ead9c51c536").await?;
+    let last_cs_id =
+        resolve_cs_id(&ctx, &blobrepo, "d0a361e9022d226ae52f689667bd7d212a19cfe0").await?;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("heads");
+    std::fs::write(&path, format!("{}\n\n  {}  \n", first_cs_id, last_cs_id))?;
+    let seed_head = SeedHead::from(SeedHeadKind::ChangesetFile(path)).with_reserve_size(1024);
+    let resolved =
+        seedheads_with_vertexes(&ctx, &[seed_head.clone()], blobrepo.bookmarks().as_ref()).await?;
+    assert_eq!(resolved.vertexes.len(), 2);
+    for ((head, vertex), cs_id) in resolved.vertexes.iter().zip([first_cs_id, last_cs_id]) {
+        assert!(matches!(head.kind(), SeedHeadKind::Changeset(id) if *id == cs_id));
+        assert_eq!(head.reserve_size(), 1024);
+        assert_eq!(*vertex, vertex_name_from_cs_id(&cs_id));
+    }
+
+    let vertex_list = seed_head
+        .into_vertex_list(&ctx, blobrepo.bookmarks().as_ref())
+        .await?;
+    assert_eq!(
+        vertex_list.vertexes(),
+        vec![
+            vertex_name_from_cs_id(&first_cs_id),
+            vertex_name_from_cs_id(&last_cs_id)
+        ]
+    );
+
+    // The first malformed line is reported.
+    let path = dir.path().join("malformed");
+    std::fs::write(&path, format!("{}\nnot-a-hash\nalso-bad\n", first_cs_id))?;
+    let err = SeedHead::from(SeedHeadKind::ChangesetFile(path))
+        .into_vertex_list(&ctx, blobrepo.bookmarks().as_ref())
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("line 2: invalid changeset id 'not-a-hash'"));
+
+    // So is a missing file.
+    let path = dir.path().join("missing");
+    assert!(
+        SeedHead::from(SeedHeadKind::ChangesetFile(path))
+            .into_vertex_list(&ctx, blobrepo.bookmarks().as_ref())
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_vertexlist_from_seedheads_dedupes(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = linear::getrepo(fb).await;
+    let hg_cs = "d0a361e9022d226ae52f689667bd7d212a19cfe0";
+    let bookmark = BookmarkName::new("head")?;
+    set_bookmark(fb, blobrepo.clone(), hg_cs, bookmark.clone()).await;
+    let cs_id = resolve_cs_id(&ctx, &blobrepo, hg_cs).await?;
+
+    // The bookmark is listed twice, and the changeset it points to once more.
+    let seed_heads = vec![
+        SeedHead::from(bookmark.clone()).with_reserve_size(16),
+        SeedHead::from(cs_id).with_reserve_size(1024),
+        SeedHead::from(bookmark).with_reserve_size(64),
+    ];
+    let vertex_list =
+        vertexlist_from_seedheads(&ctx, &seed_heads, blobrepo.bookmarks().as_ref()).await?;
+    assert_eq!(vertex_list.vertexes(), vec![vertex_name_from_cs_id(&cs_id)]);
+    assert_eq!(vertex_list.vertex_options()[0].1.reserve_size, 1024);
+
+    // Nothing to build from.
+    let seed_heads = vec![SeedHead::from(BookmarkName::new("missing")?).with_optional(true)];
+    let err = vertexlist_from_seedheads(&ctx, &seed_heads, blobrepo.bookmarks().as_ref())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("missing"));
+    assert!(
+        vertexlist_from_seedheads(&ctx, &[], blobrepo.bookmarks().as_ref())
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_advance_master_over_included_changeset(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = linear::getrepo(fb).await;
+
+    // Master starts at "added 3", and "added 7", which master reaches later, is included from
+    // the config.
+    let start_hg_id = "607314ef579bd2407752361ba1b0c1729d08b281";
+    let included =
+        resolve_cs_id(&ctx, &blobrepo, "0ed509bf086fadcb8a8a5384dc3b550729b0fc17").await?;
+    let after_included =
+        resolve_cs_id(&ctx, &blobrepo, "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157").await?;
+    let new_hg_id = "79a13814c5ce7330173ec04d279bf95ab3f652fb";
+    let new_cs = resolve_cs_id(&ctx, &blobrepo, new_hg_id).await?;
+    set_bookmark(fb, blobrepo.clone(), start_hg_id, BOOKMARK_NAME.clone()).await;
+
+    let config = SegmentedChangelogConfig {
+        master_bookmark: Some(BOOKMARK_NAME.to_string()),
+        bonsai_changesets_to_include: vec![included],
+        ..Default::default()
+    };
+    let seed_heads = seedheads_from_config(&ctx, &config)?;
+    let idmap: Arc<dyn IdMap> = Arc::new(ConcurrentMemIdMap::new());
+    let mut namedag = server_namedag(ctx.clone(), InProcessIdDag::new_in_process(), idmap.clone())?;
+    assert!(
+        update_namedag(
+            &mut namedag,
+            &ctx,
+            &seed_heads,
+            blobrepo.bookmarks().as_ref(),
+            blobrepo.get_changeset_fetcher(),
+        )
+        .await?
+    );
+    let included_dag_id = idmap.get_dag_id(&ctx, included).await?;
+    assert_eq!(included_dag_id.group(), Group::MASTER);
+    assert_eq!(idmap.find_dag_id(&ctx, new_cs).await?, None);
+
+    // Master moves past the included changeset. Its id stays the same, and its descendants are
+    // assigned after it, in the master group.
+    set_bookmark(fb, blobrepo.clone(), new_hg_id, BOOKMARK_NAME.clone()).await;
+    assert!(
+        update_namedag(
+            &mut namedag,
+            &ctx,
+            &seed_heads,
+            blobrepo.bookmarks().as_ref(),
+            blobrepo.get_changeset_fetcher(),
+        )
+        .await?
+    );
+    assert_eq!(idmap.get_dag_id(&ctx, included).await?, included_dag_id);
+    for cs_id in [after_included, new_cs] {
+        let dag_id = idmap.get_dag_id(&ctx, cs_id).await?;
+        assert_eq!(dag_id.group(), Group::MASTER);
+        assert!(dag_id > included_dag_id);
+        assert!(namedag.dag().contains_id(dag_id)?);
+    }
+
+    Ok(())
+}
+
+async fn update_namedag_to(
+    ctx: &CoreContext,
+    blobrepo: &BlobRepo,
+    namedag: &mut ServerNameDag,
+    head: ChangesetId,
+) -> Result<bool> {
+    update_namedag(
+        namedag,
+        ctx,
+        &[SeedHead::from(head)],
+        blobrepo.bookmarks().as_ref(),
+        blobrepo.get_changeset_fetcher(),
+    )
+    .await
+}
+
+#[fbinit::test]
+async fn test_update_namedag(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blobrepo = linear::getrepo(fb).await;
+    let first = resolve_cs_id(&ctx, &blobrepo, "2d7d4ba9ce0a6ffd222de7785b249ead9c51c536").await?;
+    let last = resolve_cs_id(&ctx, &blobrepo, "d0a361e9022d226ae52f689667bd7d212a19cfe0").await?;
+
+    let idmap: Arc<dyn IdMap> = Arc::new(ConcurrentMemIdMap::new());
+    let mut namedag = server_namedag(ctx.clone(), InProcessIdDag::new_in_process(), idmap.clone())?;
+
+    assert!(update_namedag_to(&ctx, &blobrepo, &mut namedag, first).await?);
+    let first_dag_id = idmap.get_dag_id(&ctx, first).await?;
+    assert!(namedag.dag().contains_id(first_dag_id)?);
+    assert_eq!(idmap.find_dag_id(&ctx, last).await?, None);
+
+    // No head advanced.
+    assert!(!update_namedag_to(&ctx, &blobrepo, &mut namedag, first).await?);
+
+    assert!(update_namedag_to(&ctx, &blobrepo, &mut namedag, last).await?);
+    let last_dag_id = idmap.get_dag_id(&ctx, last).await?;
+    assert!(last_dag_id > first_dag_id);
+    assert!(namedag.dag().contains_id(last_dag_id)?);
+    assert_eq!(idmap.get_dag_id(&ctx, first).await?, first_dag_id);
+
+    assert!(!update_namedag_to(&ctx, &blobrepo, &mut namedag, last).await?);
+
+    Ok(())
+}
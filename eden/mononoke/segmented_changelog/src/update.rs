@@ -5,55 +5,127 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::{hash_map::Entry, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 
-use anyhow::{format_err, Context, Error, Result};
-use futures::future::{FutureExt, TryFutureExt};
-use futures::stream::{self, StreamExt, TryStreamExt};
-use slog::info;
+use anyhow::{format_err, Context, Result};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use slog::{info, warn};
 
 use bookmarks::{
     BookmarkKind, BookmarkName, BookmarkPagination, BookmarkPrefix, Bookmarks, Freshness,
 };
+use changeset_fetcher::ChangesetFetcher;
 use context::CoreContext;
 use metaconfig_types::SegmentedChangelogConfig;
 use mononoke_types::ChangesetId;
 
+use crate::dag::ops::DagAddHeads;
 use crate::dag::{NameDagBuilder, VertexListWithOptions, VertexName, VertexOptions};
-use crate::idmap::{vertex_name_from_cs_id, IdMap, IdMapWrapper};
+use crate::idmap::{cs_id_from_vertex_name, vertex_name_from_cs_id, IdMap, IdMapWrapper};
+use crate::parents::FetchParents;
 use crate::{Group, InProcessIdDag};
 
+/// The default number of ids to reserve after each head, so that descendants of the head can be
+/// assigned nearby ids.
+pub const DEFAULT_RESERVE_SIZE: u32 = 1 << 26;
+
 #[derive(Debug, Clone)]
-pub enum SeedHead {
+pub enum SeedHeadKind {
     Changeset(ChangesetId),
     Bookmark(BookmarkName),
+    BookmarkPrefix(BookmarkPrefix),
+    /// All publishing bookmarks, i.e. those of kind `BookmarkKind::ALL_PUBLISHING`.
     AllBookmarks,
+    /// All bookmarks of the given kinds.
+    AllBookmarksOfKinds(Vec<BookmarkKind>),
+    /// The changesets listed in a file, one hex changeset id per line. See
+    /// `changesets_from_file`.
+    ChangesetFile(PathBuf),
+}
+
+/// A head that the segmented changelog is built from, together with the options that its
+/// vertexes are added with.
+#[derive(Debug, Clone)]
+pub struct SeedHead {
+    kind: SeedHeadKind,
+    reserve_size: u32,
+    optional: bool,
+}
+
+impl SeedHead {
+    pub fn new(kind: SeedHeadKind) -> Self {
+        Self {
+            kind,
+            reserve_size: DEFAULT_RESERVE_SIZE,
+            optional: false,
+        }
+    }
+
+    /// Sets how many ids to reserve after each vertex this head resolves to.
+    pub fn with_reserve_size(mut self, reserve_size: u32) -> Self {
+        self.reserve_size = reserve_size;
+        self
+    }
+
+    /// Marks this head as optional: if it names a bookmark that doesn't exist, the head is
+    /// skipped instead of failing the whole update.
+    pub fn with_optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    pub fn kind(&self) -> &SeedHeadKind {
+        &self.kind
+    }
+
+    pub fn reserve_size(&self) -> u32 {
+        self.reserve_size
+    }
+
+    pub fn is_optional(&self) -> bool {
+        self.optional
+    }
+}
+
+impl From<SeedHeadKind> for SeedHead {
+    fn from(kind: SeedHeadKind) -> Self {
+        Self::new(kind)
+    }
 }
 
 impl From<Option<BookmarkName>> for SeedHead {
     fn from(f: Option<BookmarkName>) -> Self {
         match f {
-            None => Self::AllBookmarks,
-            Some(n) => Self::Bookmark(n),
+            None => Self::new(SeedHeadKind::AllBookmarks),
+            Some(n) => Self::new(SeedHeadKind::Bookmark(n)),
         }
     }
 }
 
 impl From<BookmarkName> for SeedHead {
     fn from(n: BookmarkName) -> Self {
-        Self::Bookmark(n)
+        Self::new(SeedHeadKind::Bookmark(n))
+    }
+}
+
+impl From<BookmarkPrefix> for SeedHead {
+    fn from(p: BookmarkPrefix) -> Self {
+        Self::new(SeedHeadKind::BookmarkPrefix(p))
     }
 }
 
 impl From<ChangesetId> for SeedHead {
     fn from(c: ChangesetId) -> Self {
-        Self::Changeset(c)
+        Self::new(SeedHeadKind::Changeset(c))
     }
 }
 
 impl From<&ChangesetId> for SeedHead {
     fn from(c: &ChangesetId) -> Self {
-        Self::Changeset(*c)
+        Self::new(SeedHeadKind::Changeset(*c))
     }
 }
 
@@ -63,35 +135,169 @@ impl SeedHead {
         ctx: &CoreContext,
         bookmarks: &dyn Bookmarks,
     ) -> Result<VertexListWithOptions> {
-        match self {
-            Self::Changeset(id) => Ok(VertexListWithOptions::from(vec![head_with_options(id)])),
-            Self::AllBookmarks => bookmark_with_options(ctx, None, bookmarks).await,
-            Self::Bookmark(name) => bookmark_with_options(ctx, Some(&name), bookmarks).await,
+        let options = self.vertex_options();
+        Ok(VertexListWithOptions::from(
+            self.resolve(ctx, bookmarks)
+                .await?
+                .iter()
+                .map(|(_head, cs_id)| head_with_options(cs_id, &options))
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Resolves this head to the changesets it currently points to. A head that expands to
+    /// several bookmarks produces one entry per bookmark, tagged with a seed head for that
+    /// bookmark with the same options as this one. An optional head naming a missing bookmark
+    /// resolves to nothing.
+    pub async fn resolve(
+        &self,
+        ctx: &CoreContext,
+        bookmarks: &dyn Bookmarks,
+    ) -> Result<Vec<(SeedHead, ChangesetId)>> {
+        match &self.kind {
+            SeedHeadKind::Changeset(id) => Ok(vec![(self.clone(), *id)]),
+            SeedHeadKind::Bookmark(name) => {
+                let cs_id = resolve_bookmark(ctx, name, self.optional, bookmarks).await?;
+                Ok(cs_id
+                    .map(|cs_id| (self.clone(), cs_id))
+                    .into_iter()
+                    .collect())
+            }
+            SeedHeadKind::AllBookmarks => {
+                self.resolve_prefix(
+                    ctx,
+                    &BookmarkPrefix::empty(),
+                    BookmarkKind::ALL_PUBLISHING,
+                    bookmarks,
+                )
+                .await
+            }
+            SeedHeadKind::AllBookmarksOfKinds(kinds) => {
+                self.resolve_prefix(ctx, &BookmarkPrefix::empty(), kinds, bookmarks)
+                    .await
+            }
+            SeedHeadKind::BookmarkPrefix(prefix) => {
+                self.resolve_prefix(ctx, prefix, BookmarkKind::ALL_PUBLISHING, bookmarks)
+                    .await
+            }
+            SeedHeadKind::ChangesetFile(path) => {
+                let cs_ids = changesets_from_file(path).await?;
+                info!(
+                    ctx.logger(),
+                    "read {} changesets from {}",
+                    cs_ids.len(),
+                    path.display()
+                );
+                Ok(cs_ids
+                    .into_iter()
+                    .map(|cs_id| {
+                        let head = SeedHead {
+                            kind: SeedHeadKind::Changeset(cs_id),
+                            ..self.clone()
+                        };
+                        (head, cs_id)
+                    })
+                    .collect())
+            }
         }
     }
+
+    async fn resolve_prefix(
+        &self,
+        ctx: &CoreContext,
+        prefix: &BookmarkPrefix,
+        kinds: &[BookmarkKind],
+        bookmarks: &dyn Bookmarks,
+    ) -> Result<Vec<(SeedHead, ChangesetId)>> {
+        list_bookmarks(ctx, prefix, kinds, bookmarks)
+            .map_ok(|(name, cs_id)| {
+                let head = SeedHead {
+                    kind: SeedHeadKind::Bookmark(name),
+                    ..self.clone()
+                };
+                (head, cs_id)
+            })
+            .try_collect()
+            .await
+    }
+
+    /// The options that the vertexes of this head are added to the graph with.
+    pub fn vertex_options(&self) -> VertexOptions {
+        let mut options = VertexOptions::default();
+        options.reserve_size = self.reserve_size;
+        options.highest_group = Group::MASTER;
+        options
+    }
 }
 
 impl std::fmt::Display for SeedHead {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Changeset(id) => write!(f, "Bonsai CS {}", id),
-            Self::Bookmark(name) => write!(f, "Bookmark {}", name),
-            Self::AllBookmarks => write!(f, "All Bookmarks"),
+        match &self.kind {
+            SeedHeadKind::Changeset(id) => write!(f, "Bonsai CS {}", id),
+            SeedHeadKind::Bookmark(name) => write!(f, "Bookmark {}", name),
+            SeedHeadKind::BookmarkPrefix(prefix) => write!(f, "Bookmarks with prefix {}", prefix),
+            SeedHeadKind::AllBookmarks => write!(f, "All Bookmarks"),
+            SeedHeadKind::AllBookmarksOfKinds(kinds) => {
+                write!(f, "All Bookmarks of kinds [")?;
+                for (i, kind) in kinds.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", kind)?;
+                }
+                write!(f, "]")
+            }
+            SeedHeadKind::ChangesetFile(path) => {
+                write!(f, "Changesets listed in {}", path.display())
+            }
         }
     }
 }
 
+/// Reads the changesets listed in the file at `path`: one hex bonsai changeset id per line.
+/// Surrounding whitespace and empty lines are ignored. Fails on the first line that is not a
+/// valid changeset id, naming its line number.
+pub async fn changesets_from_file(path: &Path) -> Result<Vec<ChangesetId>> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("reading changesets from {}", path.display()))?;
+    parse_changeset_list(&content).with_context(|| format!("in {}", path.display()))
+}
+
+fn parse_changeset_list(content: &str) -> Result<Vec<ChangesetId>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(line_number, line)| {
+            ChangesetId::from_str(line)
+                .with_context(|| format!("line {}: invalid changeset id '{}'", line_number, line))
+        })
+        .collect()
+}
+
 pub fn seedheads_from_config(
     ctx: &CoreContext,
     config: &SegmentedChangelogConfig,
 ) -> Result<Vec<SeedHead>> {
-    let head = config
+    let head: SeedHead = config
         .master_bookmark
         .as_ref()
         .map(BookmarkName::new)
         .transpose()?
         .into();
     let bonsai_changesets_to_include = &config.bonsai_changesets_to_include;
+    let bookmarks_to_include = config
+        .bookmarks_to_include
+        .iter()
+        .map(BookmarkName::new)
+        .collect::<Result<Vec<_>>>()?;
+    let bookmark_prefixes_to_include = config
+        .bookmark_prefixes_to_include
+        .iter()
+        .map(BookmarkPrefix::new)
+        .collect::<Result<Vec<_>>>()?;
 
     info!(ctx.logger(), "using '{}' for head", head);
     if bonsai_changesets_to_include.len() > 0 {
@@ -100,26 +306,136 @@ pub fn seedheads_from_config(
             "also adding {:?} to segmented changelog", bonsai_changesets_to_include
         );
     }
+    for bookmark in &bookmarks_to_include {
+        info!(
+            ctx.logger(),
+            "also adding optional bookmark '{}' to segmented changelog", bookmark
+        );
+    }
+    for prefix in &bookmark_prefixes_to_include {
+        info!(
+            ctx.logger(),
+            "also adding bookmarks with prefix '{}' to segmented changelog", prefix
+        );
+    }
 
     let mut heads = vec![head];
     heads.extend(bonsai_changesets_to_include.into_iter().map(SeedHead::from));
+    heads.extend(
+        bookmarks_to_include
+            .into_iter()
+            .map(|bookmark| SeedHead::from(bookmark).with_optional(true)),
+    );
+    heads.extend(bookmark_prefixes_to_include.into_iter().map(SeedHead::from));
+    if let Some(reserve_size) = config.heads_reserve_size {
+        info!(
+            ctx.logger(),
+            "reserving {} ids after each head", reserve_size
+        );
+        heads = heads
+            .into_iter()
+            .map(|head| head.with_reserve_size(reserve_size))
+            .collect();
+    }
     Ok(heads)
 }
 
+/// Resolves `heads` to the list of vertexes to build the graph from. See
+/// `ResolvedSeedHeads::into_vertex_list`.
 pub async fn vertexlist_from_seedheads(
     ctx: &CoreContext,
     heads: &[SeedHead],
     bookmarks: &dyn Bookmarks,
 ) -> Result<VertexListWithOptions> {
-    let heads_with_options = stream::iter(heads.into_iter().map(Result::Ok))
-        .try_fold(VertexListWithOptions::default(), {
-            move |acc, head| async move {
-                Ok::<_, Error>(acc.chain(head.into_vertex_list(ctx, bookmarks).await?))
+    seedheads_with_vertexes(ctx, heads, bookmarks)
+        .await?
+        .into_vertex_list(ctx)
+}
+
+/// Resolves every seed head to the vertexes it currently points to, keeping track of which head
+/// each vertex came from. See `SeedHead::resolve` for how heads that expand to several bookmarks
+/// are reported. Heads that resolve to nothing are logged and listed in `skipped`.
+pub async fn seedheads_with_vertexes(
+    ctx: &CoreContext,
+    heads: &[SeedHead],
+    bookmarks: &dyn Bookmarks,
+) -> Result<ResolvedSeedHeads> {
+    let mut resolved = ResolvedSeedHeads::default();
+    for head in heads {
+        let head_resolved = head.resolve(ctx, bookmarks).await?;
+        if head_resolved.is_empty() {
+            warn!(ctx.logger(), "{} did not resolve to any changeset", head);
+            resolved.skipped.push(head.clone());
+        }
+        resolved.vertexes.extend(
+            head_resolved
+                .into_iter()
+                .map(|(head, cs_id)| (head, vertex_name_from_cs_id(&cs_id))),
+        );
+    }
+    Ok(resolved)
+}
+
+/// The result of resolving a list of seed heads.
+#[derive(Debug, Default)]
+pub struct ResolvedSeedHeads {
+    /// The vertexes the heads resolved to, each with the head it came from.
+    pub vertexes: Vec<(SeedHead, VertexName)>,
+    /// Heads that did not resolve to anything, e.g. optional heads whose bookmark is missing.
+    pub skipped: Vec<SeedHead>,
+}
+
+impl ResolvedSeedHeads {
+    /// The vertexes to build the graph from, each with the options of the head it came from.
+    ///
+    /// Heads that resolve to the same vertex, e.g. a bookmark listed twice or a changeset that a
+    /// bookmark points to, are collapsed into a single entry with the largest reserve size among
+    /// them. Fails if no head resolved to anything, rather than building an empty graph.
+    pub fn into_vertex_list(self, ctx: &CoreContext) -> Result<VertexListWithOptions> {
+        if self.vertexes.is_empty() {
+            return Err(if self.skipped.is_empty() {
+                format_err!("no seed heads to build the segmented changelog from")
+            } else {
+                format_err!(
+                    "none of the seed heads resolved to a changeset: {}",
+                    self.skipped
+                        .iter()
+                        .map(|head| head.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            });
+        }
+
+        let mut index: HashMap<VertexName, usize> = HashMap::new();
+        let mut list: Vec<(VertexName, VertexOptions, SeedHead)> = Vec::new();
+        for (head, vertex) in self.vertexes {
+            let options = head.vertex_options();
+            match index.entry(vertex) {
+                Entry::Occupied(entry) => {
+                    let (vertex, merged, first) = &mut list[*entry.get()];
+                    info!(
+                        ctx.logger(),
+                        "{} and {} both resolve to {}, adding it once",
+                        first,
+                        head,
+                        cs_id_from_vertex_name(vertex)
+                    );
+                    merged.reserve_size = merged.reserve_size.max(options.reserve_size);
+                }
+                Entry::Vacant(entry) => {
+                    list.push((entry.key().clone(), options, head));
+                    entry.insert(list.len() - 1);
+                }
             }
-        })
-        .await?;
+        }
 
-    Ok(heads_with_options)
+        Ok(VertexListWithOptions::from(
+            list.into_iter()
+                .map(|(vertex, options, _head)| (vertex, options))
+                .collect::<Vec<_>>(),
+        ))
+    }
 }
 
 pub type ServerNameDag = crate::dag::namedag::AbstractNameDag<InProcessIdDag, IdMapWrapper, (), ()>;
@@ -138,59 +454,104 @@ pub fn server_namedag(
         .map_err(anyhow::Error::from)
 }
 
-fn head_with_options(head: &ChangesetId) -> (VertexName, VertexOptions) {
-    let mut options = VertexOptions::default();
-    options.reserve_size = 1 << 26;
-    options.highest_group = Group::MASTER;
-    (vertex_name_from_cs_id(head), options)
+/// Extends `dag` with the vertexes that `heads` currently resolve to, and their ancestors, then
+/// flushes the writes to the IdMap. Returns whether anything was added.
+///
+/// Unlike building from scratch, only the vertexes that are not in `dag` yet are assigned ids.
+/// When all heads are already in `dag` this returns early, without fetching any parents or
+/// writing to the IdMap.
+pub async fn update_namedag(
+    dag: &mut ServerNameDag,
+    ctx: &CoreContext,
+    heads: &[SeedHead],
+    bookmarks: &dyn Bookmarks,
+    changeset_fetcher: Arc<dyn ChangesetFetcher>,
+) -> Result<bool> {
+    let heads = vertexlist_from_seedheads(ctx, heads, bookmarks).await?;
+    if contains_all_heads(ctx, dag, &heads).await? {
+        return Ok(false);
+    }
+
+    // The heads that are already in the dag are passed in too, so that the ids reserved after
+    // them are respected.
+    let parent_fetcher = FetchParents::new(ctx.clone(), changeset_fetcher);
+    let changed = dag.add_heads(&parent_fetcher, &heads).await?;
+    dag.map().flush_writes().await?;
+    Ok(changed)
+}
+
+/// Whether all `heads` are assigned in the IdMap of `dag`, and present in its IdDag.
+async fn contains_all_heads(
+    ctx: &CoreContext,
+    dag: &ServerNameDag,
+    heads: &VertexListWithOptions,
+) -> Result<bool> {
+    let mut cs_ids: Vec<_> = heads
+        .vertexes()
+        .iter()
+        .map(cs_id_from_vertex_name)
+        .collect();
+    cs_ids.sort_unstable();
+    cs_ids.dedup();
+    let dag_ids = dag
+        .map()
+        .clone_idmap()
+        .find_many_dag_ids(ctx, cs_ids.clone())
+        .await?;
+    if dag_ids.len() != cs_ids.len() {
+        return Ok(false);
+    }
+    for dag_id in dag_ids.values() {
+        if !dag.dag().contains_id(*dag_id)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn head_with_options(head: &ChangesetId, options: &VertexOptions) -> (VertexName, VertexOptions) {
+    (vertex_name_from_cs_id(head), options.clone())
 }
 
-async fn bookmark_with_options(
+async fn resolve_bookmark(
     ctx: &CoreContext,
-    bookmark: Option<&BookmarkName>,
+    bookmark_name: &BookmarkName,
+    optional: bool,
     bookmarks: &dyn Bookmarks,
-) -> Result<VertexListWithOptions> {
-    let bm_stream = match bookmark {
-        None => bookmarks
-            .list(
-                ctx.clone(),
-                Freshness::MaybeStale,
-                &BookmarkPrefix::empty(),
-                BookmarkKind::ALL_PUBLISHING,
-                &BookmarkPagination::FromStart,
-                u64::MAX,
+) -> Result<Option<ChangesetId>> {
+    bookmarks
+        .get(ctx.clone(), bookmark_name)
+        .await
+        .and_then(|opt_cs_id| match opt_cs_id {
+            None if !optional => Err(format_err!(
+                "'{}' bookmark could not be found",
+                bookmark_name
+            )),
+            opt_cs_id => Ok(opt_cs_id),
+        })
+        .with_context(|| {
+            format!(
+                "error while fetching changeset for bookmark {}",
+                bookmark_name
             )
-            .map_ok(|(_bookmark, cs_id)| cs_id)
-            .left_stream(),
-        Some(bookmark_name) => stream::once(
-            bookmarks
-                .get(ctx.clone(), bookmark_name)
-                .and_then({
-                    let bookmark_name = bookmark_name.clone();
-                    move |opt_cs_id| async move {
-                        opt_cs_id.ok_or_else({
-                            move || format_err!("'{}' bookmark could not be found", bookmark_name)
-                        })
-                    }
-                })
-                .map({
-                    let bookmark_name = bookmark_name.clone();
-                    move |r| {
-                        r.with_context(|| {
-                            format!(
-                                "error while fetching changeset for bookmark {}",
-                                bookmark_name
-                            )
-                        })
-                    }
-                }),
+        })
+}
+
+fn list_bookmarks(
+    ctx: &CoreContext,
+    prefix: &BookmarkPrefix,
+    kinds: &[BookmarkKind],
+    bookmarks: &dyn Bookmarks,
+) -> BoxStream<'static, Result<(BookmarkName, ChangesetId)>> {
+    bookmarks
+        .list(
+            ctx.clone(),
+            Freshness::MaybeStale,
+            prefix,
+            kinds,
+            &BookmarkPagination::FromStart,
+            u64::MAX,
         )
-        .right_stream(),
-    };
-    Ok(VertexListWithOptions::from(
-        bm_stream
-            .map_ok(|cs| head_with_options(&cs))
-            .try_collect::<Vec<_>>()
-            .await?,
-    ))
+        .map_ok(|(bookmark, cs_id)| (bookmark.into_name(), cs_id))
+        .boxed()
 }
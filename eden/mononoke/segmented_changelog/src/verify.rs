@@ -0,0 +1,214 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::{format_err, Context, Result};
+use blobstore::Blobstore;
+use bookmarks::Bookmarks;
+use changeset_fetcher::ChangesetFetcher;
+use context::CoreContext;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use mononoke_types::{ChangesetId, RepositoryId};
+use sql_ext::replication::ReplicaLagMonitor;
+
+use crate::dag::ops::IdConvert;
+use crate::iddag::IdDagSaveStore;
+use crate::idmap::{cs_id_from_vertex_name, IdMap, SqlIdMap};
+use crate::update::{seedheads_with_vertexes, server_namedag, SeedHead, ServerNameDag};
+use crate::version_store::SegmentedChangelogVersionStore;
+use crate::{DagId, DagIdSet, SegmentedChangelogSqlConnections};
+
+/// How many changesets have their parents fetched concurrently.
+const PARENTS_CONCURRENCY: usize = 100;
+
+/// Check the segmented changelog that was last saved for `repo_id`, and return a description of
+/// each problem found. No problems means that all the checks passed.
+///
+/// The checks are:
+/// - each vertex that `seed_heads` resolve to is in the dag,
+/// - for `sample_size` ids spread over the dag, the IdMap maps the id to a changeset, and that
+///   changeset back to the same id,
+/// - for the same ids, the parents in the dag are the parents of the changeset.
+///
+/// Seed heads that moved since the last update are reported as missing from the dag, so check
+/// right after an update to avoid false positives. Failing to load the dag or to query the
+/// databases is an error, not a problem.
+pub async fn verify_segmented_changelog(
+    ctx: &CoreContext,
+    repo_id: RepositoryId,
+    connections: SegmentedChangelogSqlConnections,
+    blobstore: Arc<dyn Blobstore>,
+    replica_lag_monitor: Arc<dyn ReplicaLagMonitor>,
+    changeset_fetcher: Arc<dyn ChangesetFetcher>,
+    bookmarks: &dyn Bookmarks,
+    seed_heads: &[SeedHead],
+    sample_size: u64,
+) -> Result<Vec<String>> {
+    let sc_version_store = SegmentedChangelogVersionStore::new(connections.0.clone(), repo_id);
+    let sc_version = sc_version_store
+        .get(&ctx)
+        .await
+        .with_context(|| {
+            format!(
+                "repo {}: error loading segmented changelog version",
+                repo_id
+            )
+        })?
+        .ok_or_else(|| {
+            format_err!(
+                "repo {}: segmented changelog metadata not found, maybe repo is not seeded",
+                repo_id
+            )
+        })?;
+    let iddag = IdDagSaveStore::new(repo_id, blobstore)
+        .load(&ctx, sc_version.iddag_version)
+        .await
+        .with_context(|| format!("repo {}: failed to load iddag", repo_id))?;
+    let idmap: Arc<dyn IdMap> = Arc::new(SqlIdMap::new(
+        connections.0,
+        replica_lag_monitor,
+        repo_id,
+        sc_version.idmap_version,
+    ));
+    let namedag = server_namedag(ctx.clone(), iddag, Arc::clone(&idmap))?;
+    let all_ids = namedag.dag().all()?;
+
+    let mut problems = check_seed_heads(ctx, &namedag, &all_ids, seed_heads, bookmarks).await?;
+    let sample = sample_ids(&all_ids, sample_size);
+    problems.extend(check_ids(ctx, &namedag, idmap.as_ref(), changeset_fetcher, sample).await?);
+    Ok(problems)
+}
+
+async fn check_seed_heads(
+    ctx: &CoreContext,
+    namedag: &ServerNameDag,
+    all_ids: &DagIdSet,
+    seed_heads: &[SeedHead],
+    bookmarks: &dyn Bookmarks,
+) -> Result<Vec<String>> {
+    let mut problems = vec![];
+    let resolved = seedheads_with_vertexes(ctx, seed_heads, bookmarks).await?;
+    for head in resolved.skipped {
+        // Optional heads are expected to be missing at times.
+        if !head.is_optional() {
+            problems.push(format!("{} did not resolve to any changeset", head));
+        }
+    }
+    for (head, vertex) in &resolved.vertexes {
+        let cs_id = cs_id_from_vertex_name(vertex);
+        match namedag.vertex_id_optional(vertex).await? {
+            None => problems.push(format!(
+                "{} resolved to {}, which is not in the dag",
+                head, cs_id
+            )),
+            Some(id) if !all_ids.contains(id) => problems.push(format!(
+                "{} resolved to {}, which has id {} in the IdMap but is not in the IdDag",
+                head, cs_id, id
+            )),
+            Some(_) => {}
+        }
+    }
+    Ok(problems)
+}
+
+/// Up to `sample_size` ids of `all_ids`, evenly spread from the lowest one.
+fn sample_ids(all_ids: &DagIdSet, sample_size: u64) -> Vec<DagId> {
+    let count = all_ids.count();
+    if count == 0 || sample_size == 0 {
+        return vec![];
+    }
+    let step = std::cmp::max(count / sample_size, 1);
+    all_ids
+        .iter_asc()
+        .step_by(step as usize)
+        .take(sample_size as usize)
+        .collect()
+}
+
+/// Check that each of `ids` round-trips through the IdMap, and that its parents in the dag are
+/// the parents of its changeset.
+async fn check_ids(
+    ctx: &CoreContext,
+    namedag: &ServerNameDag,
+    idmap: &dyn IdMap,
+    changeset_fetcher: Arc<dyn ChangesetFetcher>,
+    ids: Vec<DagId>,
+) -> Result<Vec<String>> {
+    let mut problems = vec![];
+
+    let with_parents = ids
+        .into_iter()
+        .map(|id| Ok((id, namedag.dag().parent_ids(id)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let to_look_up: HashSet<DagId> = with_parents
+        .iter()
+        .flat_map(|(id, parents)| std::iter::once(*id).chain(parents.iter().copied()))
+        .collect();
+    let cs_ids = idmap
+        .find_many_changeset_ids(ctx, to_look_up.into_iter().collect())
+        .await?;
+    let dag_ids = idmap
+        .find_many_dag_ids(ctx, cs_ids.values().copied().collect())
+        .await?;
+
+    let mut to_fetch: Vec<(DagId, ChangesetId, Vec<ChangesetId>)> = vec![];
+    for (id, parents) in with_parents {
+        let cs_id = match cs_ids.get(&id) {
+            Some(cs_id) => *cs_id,
+            None => {
+                problems.push(format!("id {} is in the IdDag but not in the IdMap", id));
+                continue;
+            }
+        };
+        match dag_ids.get(&cs_id) {
+            Some(back) if *back == id => {}
+            Some(back) => problems.push(format!(
+                "id {} maps to {}, which maps back to id {}",
+                id, cs_id, back
+            )),
+            None => problems.push(format!(
+                "id {} maps to {}, which does not map back to any id",
+                id, cs_id
+            )),
+        }
+        let parents: Option<Vec<ChangesetId>> = parents
+            .iter()
+            .map(|parent| cs_ids.get(parent).copied())
+            .collect();
+        match parents {
+            Some(parents) => to_fetch.push((id, cs_id, parents)),
+            None => problems.push(format!(
+                "id {} ({}) has parents in the IdDag that are not in the IdMap",
+                id, cs_id
+            )),
+        }
+    }
+
+    let fetched: Vec<_> = stream::iter(to_fetch)
+        .map(|(id, cs_id, dag_parents)| {
+            let changeset_fetcher = &changeset_fetcher;
+            async move {
+                let parents = changeset_fetcher.get_parents(ctx.clone(), cs_id).await?;
+                Ok::<_, anyhow::Error>((id, cs_id, dag_parents, parents))
+            }
+        })
+        .buffered(PARENTS_CONCURRENCY)
+        .try_collect()
+        .await?;
+    for (id, cs_id, dag_parents, parents) in fetched {
+        if dag_parents != parents {
+            problems.push(format!(
+                "id {} ({}) has parents {:?} in the dag, but the changeset has parents {:?}",
+                id, cs_id, dag_parents, parents
+            ));
+        }
+    }
+
+    Ok(problems)
+}
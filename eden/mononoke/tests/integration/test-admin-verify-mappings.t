@@ -0,0 +1,67 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+  $ hg init repo-hg --config format.usefncache=False
+
+  $ cd repo-hg
+  $ cat >> .hg/hgrc <<EOF
+  > [extensions]
+  > commitextras=
+  > treemanifest=!
+  > treemanifestserver=
+  > [treemanifest]
+  > server=True
+  > EOF
+
+  $ touch file1
+  $ hg add
+  adding file1
+  $ hg commit -m "adding extra globalrev" --extra global_rev=9999999991
+
+  $ touch file2
+  $ hg add
+  adding file2
+  $ hg commit -m "adding extra convert_revision" --extra convert_revision=svn:uuid/path@9999999992
+
+  $ touch file3
+  $ hg add
+  adding file3
+  $ hg commit -m "skip a globalrev" --extra convert_revision=svn:uuid/path@9999999994
+
+  $ setup_mononoke_config
+  $ cd $TESTTMP
+  $ blobimport repo-hg/.hg repo --has-globalrev
+  $ get_bonsai_globalrev_mapping
+  ADB923EB43189CF56394F61995A9E1FA5CD003CED7167B6FDDB03E94229DB10F|9999999991
+  BEA29B6994B07C79FD1B641BC5EFDDFC8955C43E673BE83242D0A1C55D026AC2|9999999992
+  *|9999999994 (glob)
+
+Verify a single changeset whose mappings agree
+  $ mononoke_admin verify-mappings adb923eb43189cf56394f61995a9e1fa5cd003ced7167b6fddb03e94229db10f 2> /dev/null
+  bonsai: adb923eb43189cf56394f61995a9e1fa5cd003ced7167b6fddb03e94229db10f
+  hg: * (glob)
+  globalrev: 9999999991
+
+Verify a range without gaps
+  $ mononoke_admin verify-mappings --range 9999999991 9999999992 2> /dev/null
+  checked 2 changesets: 0 with discrepancies, 0 gaps covering 0 globalrevs
+
+A gap in the range is reported, and makes the command fail
+  $ mononoke_admin verify-mappings --range 9999999991 9999999994 2> /dev/null
+  globalrevs 9999999993 to 9999999993: no changeset
+  checked 3 changesets: 0 with discrepancies, 1 gaps covering 1 globalrevs
+  [1]
+
+A mapping that disagrees with the changeset is reported
+  $ sqlite3 "$TESTTMP/monsql/sqlite_dbs" "update bonsai_globalrev_mapping set globalrev = 9999999993 where globalrev = 9999999992"
+  $ mononoke_admin verify-mappings bea29b6994b07c79fd1b641bc5efddfc8955c43e673be83242d0a1c55d026ac2 2> /dev/null
+  bonsai: bea29b6994b07c79fd1b641bc5efddfc8955c43e673be83242d0a1c55d026ac2
+  hg: * (glob)
+  globalrev: 9999999993
+  MISMATCH: the changeset has globalrev 9999999992, but the mapping has 9999999993
+  [1]
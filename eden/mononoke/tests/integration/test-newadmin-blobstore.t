@@ -29,12 +29,42 @@ Check we can upload and fetch an arbitrary blob.
   $ mononoke_newadmin blobstore -R repo fetch -q somekey -o "$TESTTMP/fetched_value"
   $ diff "$TESTTMP/value" "$TESTTMP/fetched_value"
 
+Check that a dry run doesn't write anything.
+  $ mononoke_newadmin blobstore -R repo upload --key otherkey --value-file "$TESTTMP/value" --dry-run
+  Dry run: would write 6 bytes to blobstore key otherkey
+  $ mononoke_newadmin blobstore -R repo fetch -q otherkey
+  No blob exists for otherkey
+
+Check the blob can be fetched as JSON.
+  $ mononoke_newadmin --output json blobstore -R repo fetch somekey
+  {"compressed_size":*,"ctime":*,"decode_error":null,"decoded":null,"exists":true,"key":"somekey","raw":"76616c75650a","size":6} (glob)
+  $ mononoke_newadmin --output json blobstore -R repo fetch nosuchkey
+  {"exists":false,"key":"nosuchkey"}
+
+Check whether blobs exist.
+  $ mononoke_newadmin blobstore-exists -R repo somekey
+  somekey: present (6 bytes)
+  $ mononoke_newadmin blobstore-exists -R repo somekey otherkey
+  somekey: present (6 bytes)
+  otherkey: absent
+  Error: 1 of 2 keys are absent
+  [1]
+  $ printf "somekey\n\nnosuchkey\n" | mononoke_newadmin blobstore-exists -R repo --stdin
+  somekey: present (6 bytes)
+  nosuchkey: absent
+  Error: 1 of 2 keys are absent
+  [1]
+  $ echo somekey | mononoke_newadmin --output json blobstore-exists -R repo --stdin
+  {"exists":true,"key":"somekey","size":6}
+
 Test we can unlink a blob
 
 NOTE: The blobstore-unlink command currently only works for sqlblob, and
 doesn't construct the blobstore in the usual way, so we need to give the full
 key.
 
+  $ mononoke_newadmin blobstore-unlink -R repo repo0000.somekey --dry-run
+  Dry run: would unlink key repo0000.somekey
   $ mononoke_newadmin blobstore-unlink -R repo repo0000.somekey
   Unlinking key repo0000.somekey
   $ mononoke_newadmin blobstore -R repo fetch -q somekey -o "$TESTTMP/fetched_value_unlinked"
@@ -0,0 +1,60 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+#require slow
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+setup configuration
+  $ setup_common_config "blob_sqlite"
+  $ mononoke_testtool drawdag -R repo --derive-all <<'EOF'
+  > A-B-C
+  > # bookmark: C main
+  > # extra: A example_extra "123\xff"
+  > EOF
+  *] Reloading redacted config from configerator (glob)
+  A=c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba
+  B=749add4e33cf83fda6cce6f4fb4e3037a171dd8068acef09b336fd8ae027bf6f
+  C=93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+
+Create a new bookmark
+  $ mononoke_newadmin bookmark -R repo set feature -i 749add4e33cf83fda6cce6f4fb4e3037a171dd8068acef09b336fd8ae027bf6f
+  *] Reloading redacted config from configerator (glob)
+  Going to create bookmark feature at 749add4e33cf83fda6cce6f4fb4e3037a171dd8068acef09b336fd8ae027bf6f
+
+Move it forward
+  $ mononoke_newadmin bookmark -R repo set feature -i 93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+  *] Reloading redacted config from configerator (glob)
+  Going to move bookmark feature from 749add4e33cf83fda6cce6f4fb4e3037a171dd8068acef09b336fd8ae027bf6f to 93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+  $ mononoke_newadmin fetch -R repo -B feature --json 2> /dev/null | jq -r .changeset_id
+  93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+
+Moving a publishing bookmark backwards is refused
+  $ mononoke_newadmin bookmark -R repo set main -i c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba
+  *] Reloading redacted config from configerator (glob)
+  Error: Refusing to move publishing bookmark main from 93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd to c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba, which is not a fast-forward move (use --force to move it anyway)
+  [1]
+  $ mononoke_newadmin fetch -R repo -B main --json 2> /dev/null | jq -r .changeset_id
+  93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+
+A dry run reports the move but leaves the bookmark unchanged
+  $ mononoke_newadmin bookmark -R repo set main --force -i c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba --dry-run
+  *] Reloading redacted config from configerator (glob)
+  Dry run: would move bookmark main from 93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd to c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba
+  $ mononoke_newadmin fetch -R repo -B main --json 2> /dev/null | jq -r .changeset_id
+  93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+
+The move can be forced
+  $ mononoke_newadmin bookmark -R repo set main --force -i c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba
+  *] Reloading redacted config from configerator (glob)
+  Going to move bookmark main from 93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd to c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba
+  $ mononoke_newadmin fetch -R repo -B main --json 2> /dev/null | jq -r .changeset_id
+  c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba
+
+Unknown target changesets are reported as errors
+  $ mononoke_newadmin bookmark -R repo set main -i 0000000000000000000000000000000000000000000000000000000000000000
+  *] Reloading redacted config from configerator (glob)
+  Error: Changeset not found: 0000000000000000000000000000000000000000000000000000000000000000
+  [1]
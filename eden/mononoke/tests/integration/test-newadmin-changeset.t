@@ -0,0 +1,50 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+#require slow
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+setup configuration
+  $ setup_common_config "blob_sqlite"
+  $ mononoke_testtool drawdag -R repo --derive-all <<'EOF'
+  > A-B-C
+  > # bookmark: C main
+  > # extra: A example_extra "123\xff"
+  > EOF
+  *] Reloading redacted config from configerator (glob)
+  A=c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba
+  B=749add4e33cf83fda6cce6f4fb4e3037a171dd8068acef09b336fd8ae027bf6f
+  C=93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+
+Look up a changeset by its bonsai id
+  $ mononoke_newadmin changeset -R repo -i 93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+  *] Reloading redacted config from configerator (glob)
+  BonsaiChangesetId: 93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+  Parent: 749add4e33cf83fda6cce6f4fb4e3037a171dd8068acef09b336fd8ae027bf6f
+  Author: author
+  Message: C
+  FileChanges:
+  	 ADDED/MODIFIED: C 896ad5879a5df0403bfc93fc96507ad9c93b31b11f3d0fa05445da7918241e5d
+  
+
+Look up a changeset by its hg id
+  $ HG_ID=$(mononoke_newadmin convert -R repo -f bonsai -t hg 749add4e33cf83fda6cce6f4fb4e3037a171dd8068acef09b336fd8ae027bf6f 2> /dev/null)
+  $ mononoke_newadmin --output json changeset -R repo --hg-id "$HG_ID" | jq -S '.changeset_id, .parents'
+  *] Reloading redacted config from configerator (glob)
+  "749add4e33cf83fda6cce6f4fb4e3037a171dd8068acef09b336fd8ae027bf6f"
+  [
+    "c1c5eb4a15a4c71edae31c84f8b23ec5008ad16be07fba5b872fe010184b16ba"
+  ]
+
+Missing changesets are reported as errors
+  $ mononoke_newadmin changeset -R repo --globalrev 1234
+  *] Reloading redacted config from configerator (glob)
+  Error: Changeset not found
+  [1]
+  $ mononoke_newadmin changeset -R repo -i 0000000000000000000000000000000000000000000000000000000000000000
+  *] Reloading redacted config from configerator (glob)
+  Error: Changeset not found: 0000000000000000000000000000000000000000000000000000000000000000
+  [1]
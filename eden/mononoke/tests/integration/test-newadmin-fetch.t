@@ -22,6 +22,7 @@ setup configuration
   $ mononoke_newadmin fetch -R repo -i 93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
   *] Reloading redacted config from configerator (glob)
   BonsaiChangesetId: 93cd0903625ea3162047e2699c2ea20d531b634df84180dbeeeb4b62f8afa8cd
+  Parent: 749add4e33cf83fda6cce6f4fb4e3037a171dd8068acef09b336fd8ae027bf6f
   Author: author
   Message: C
   FileChanges:
@@ -7,7 +7,10 @@
 
 mononoke_app::subcommands! {
     mod blobstore;
+    mod blobstore_exists;
     mod blobstore_unlink;
+    mod bookmark;
+    mod changeset;
     mod convert;
     mod fetch;
     mod list_repos;
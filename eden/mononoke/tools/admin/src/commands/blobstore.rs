@@ -13,6 +13,7 @@ use clap::{Parser, Subcommand};
 use mononoke_app::args::RepoBlobstoreArgs;
 use mononoke_app::MononokeApp;
 
+use crate::output::OutputFormat;
 use fetch::BlobstoreFetchArgs;
 use upload::BlobstoreUploadArgs;
 
@@ -36,6 +37,7 @@ pub enum BlobstoreSubcommand {
 
 pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
     let ctx = app.new_context();
+    let output = OutputFormat::from_app(&app)?;
 
     let blobstore = app
         .open_blobstore(&args.repo_blobstore_args)
@@ -44,10 +46,10 @@ pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
 
     match args.subcommand {
         BlobstoreSubcommand::Fetch(fetch_args) => {
-            fetch::fetch(&ctx, &blobstore, fetch_args).await?
+            fetch::fetch(&ctx, &blobstore, fetch_args, output).await?
         }
         BlobstoreSubcommand::Upload(upload_args) => {
-            upload::upload(&ctx, &blobstore, upload_args).await?
+            upload::upload(&ctx, &blobstore, upload_args, app.dry_run()).await?
         }
     }
 
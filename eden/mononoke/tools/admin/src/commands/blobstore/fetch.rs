@@ -20,9 +20,12 @@ use mercurial_types::{HgChangesetEnvelope, HgFileEnvelope, HgManifestEnvelope};
 use mononoke_types::fsnode::Fsnode;
 use mononoke_types::skeleton_manifest::SkeletonManifest;
 use mononoke_types::{BonsaiChangeset, ContentChunk, FileContents};
+use serde_json::json;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
+use crate::output::OutputFormat;
+
 #[derive(Args)]
 pub struct BlobstoreFetchArgs {
     /// Write raw blob bytes to the given filename instead of
@@ -138,16 +141,76 @@ fn decode(key: &str, data: BlobstoreGetData, mut decode_as: DecodeAs) -> Decoded
     }
 }
 
+fn hex_string(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn print_json(
+    key: &str,
+    value: Option<BlobstoreGetData>,
+    decode_as: DecodeAs,
+    include_value: bool,
+) -> Result<()> {
+    let json = match value {
+        None => json!({
+            "key": key,
+            "exists": false,
+        }),
+        Some(value) => {
+            let mut json = json!({
+                "key": key,
+                "exists": true,
+                "ctime": value.as_meta().ctime(),
+                "size": value.len(),
+                "compressed_size": value
+                    .as_meta()
+                    .sizes()
+                    .map(|sizes| sizes.unique_compressed_size),
+            });
+            if include_value {
+                let raw = hex_string(value.as_raw_bytes());
+                let (decoded, error) = match decode(key, value, decode_as) {
+                    Decoded::Display(decoded) => (Some(decoded), None),
+                    Decoded::Hexdump(data) => (Some(hex_string(&data)), None),
+                    Decoded::Fail(err) => (None, Some(err)),
+                    Decoded::None => (None, None),
+                };
+                json["raw"] = json!(raw);
+                json["decoded"] = json!(decoded);
+                json["decode_error"] = json!(error);
+            }
+            json
+        }
+    };
+    writeln!(std::io::stdout(), "{}", json)?;
+    Ok(())
+}
+
 pub async fn fetch(
     ctx: &CoreContext,
     blobstore: &dyn Blobstore,
     fetch_args: BlobstoreFetchArgs,
+    output: OutputFormat,
 ) -> Result<()> {
     let value = blobstore
         .get(ctx, &fetch_args.key)
         .await
         .context("Failed to fetch blob")?;
 
+    if output.is_json() {
+        if let (Some(path), Some(value)) = (&fetch_args.output, &value) {
+            let mut file = File::create(path)
+                .await
+                .context("Failed to create output file")?;
+            file.write_all(value.as_raw_bytes())
+                .await
+                .context("Failed to write to output file")?;
+            file.flush().await?;
+        }
+        let include_value = fetch_args.output.is_none();
+        return print_json(&fetch_args.key, value, fetch_args.decode_as, include_value);
+    }
+
     match value {
         None => {
             writeln!(std::io::stderr(), "No blob exists for {}", fetch_args.key)?;
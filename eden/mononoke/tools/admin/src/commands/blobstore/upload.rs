@@ -28,11 +28,22 @@ pub async fn upload(
     ctx: &CoreContext,
     blobstore: &dyn Blobstore,
     upload_args: BlobstoreUploadArgs,
+    dry_run: bool,
 ) -> Result<()> {
     let data = tokio::fs::read(upload_args.value_file)
         .await
         .context("Failed to read value file")?;
 
+    if dry_run {
+        writeln!(
+            std::io::stdout(),
+            "Dry run: would write {} bytes to blobstore key {}",
+            data.len(),
+            upload_args.key
+        )?;
+        return Ok(());
+    }
+
     writeln!(
         std::io::stdout(),
         "Writing {} bytes to blobstore key {}",
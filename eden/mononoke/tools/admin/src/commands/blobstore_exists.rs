@@ -0,0 +1,114 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::io::Write;
+
+use anyhow::{anyhow, Context, Result};
+use blobstore::Blobstore;
+use clap::Parser;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use mononoke_app::args::RepoBlobstoreArgs;
+use mononoke_app::MononokeApp;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::output::OutputFormat;
+
+/// Number of keys looked up concurrently.
+const CONCURRENCY: usize = 100;
+
+/// Check whether blobstore keys exist
+///
+/// Prints whether each key is present and, if it is, the size of its
+/// value.  Exits with an error if any of the keys is absent.
+#[derive(Parser)]
+pub struct CommandArgs {
+    #[clap(flatten)]
+    repo_blobstore_args: RepoBlobstoreArgs,
+
+    /// Also read keys from stdin, one per line
+    #[clap(long)]
+    stdin: bool,
+
+    /// Blobstore keys to check
+    #[clap(required_unless_present = "stdin")]
+    keys: Vec<String>,
+}
+
+async fn read_keys_from_stdin(keys: &mut Vec<String>) -> Result<()> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        let key = line.trim();
+        if !key.is_empty() {
+            keys.push(key.to_string());
+        }
+    }
+    Ok(())
+}
+
+pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
+    let ctx = app.new_context();
+    let output = OutputFormat::from_app(&app)?;
+
+    let mut keys = args.keys;
+    if args.stdin {
+        read_keys_from_stdin(&mut keys)
+            .await
+            .context("Failed to read keys from stdin")?;
+    }
+
+    let blobstore = app
+        .open_blobstore(&args.repo_blobstore_args)
+        .await
+        .context("Failed to open blobstore")?;
+
+    let mut missing = 0;
+    let mut sizes = stream::iter(keys.iter())
+        .map(|key| {
+            let ctx = &ctx;
+            let blobstore = &blobstore;
+            async move {
+                let value = blobstore
+                    .get(ctx, key)
+                    .await
+                    .with_context(|| format!("Failed to fetch blob {}", key))?;
+                anyhow::Ok((key, value.map(|value| value.len())))
+            }
+        })
+        .buffered(CONCURRENCY);
+
+    while let Some((key, size)) = sizes.try_next().await? {
+        if size.is_none() {
+            missing += 1;
+        }
+        if output.is_json() {
+            let json = match size {
+                None => json!({
+                    "key": key,
+                    "exists": false,
+                }),
+                Some(size) => json!({
+                    "key": key,
+                    "exists": true,
+                    "size": size,
+                }),
+            };
+            writeln!(std::io::stdout(), "{}", json)?;
+        } else {
+            match size {
+                None => writeln!(std::io::stdout(), "{}: absent", key)?,
+                Some(size) => writeln!(std::io::stdout(), "{}: present ({} bytes)", key, size)?,
+            }
+        }
+    }
+
+    if missing > 0 {
+        return Err(anyhow!("{} of {} keys are absent", missing, keys.len()));
+    }
+
+    Ok(())
+}
@@ -105,6 +105,11 @@ pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
     )
     .await?;
 
+    if app.dry_run() {
+        writeln!(std::io::stdout(), "Dry run: would unlink key {}", args.key)?;
+        return Ok(());
+    }
+
     writeln!(std::io::stdout(), "Unlinking key {}", args.key)?;
 
     blobstore
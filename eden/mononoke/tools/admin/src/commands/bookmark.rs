@@ -0,0 +1,77 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+mod set;
+
+use anyhow::{Context, Result};
+use bonsai_globalrev_mapping::BonsaiGlobalrevMapping;
+use bonsai_hg_mapping::BonsaiHgMapping;
+use bookmarks::Bookmarks;
+use changeset_fetcher::ChangesetFetcher;
+use changesets::Changesets;
+use clap::{Parser, Subcommand};
+use mononoke_app::args::RepoArgs;
+use mononoke_app::MononokeApp;
+use repo_identity::RepoIdentity;
+use skiplist::SkiplistIndex;
+
+use set::BookmarkSetArgs;
+
+/// Manage bookmarks
+#[derive(Parser)]
+pub struct CommandArgs {
+    #[clap(flatten)]
+    repo_args: RepoArgs,
+
+    #[clap(subcommand)]
+    subcommand: BookmarkSubcommand,
+}
+
+#[derive(Subcommand)]
+pub enum BookmarkSubcommand {
+    /// Move a bookmark, or create it if it doesn't exist
+    Set(BookmarkSetArgs),
+}
+
+#[facet::container]
+pub struct Repo {
+    #[facet]
+    repo_identity: RepoIdentity,
+
+    #[facet]
+    bookmarks: dyn Bookmarks,
+
+    #[facet]
+    bonsai_hg_mapping: dyn BonsaiHgMapping,
+
+    #[facet]
+    bonsai_globalrev_mapping: dyn BonsaiGlobalrevMapping,
+
+    #[facet]
+    changesets: dyn Changesets,
+
+    #[facet]
+    changeset_fetcher: dyn ChangesetFetcher,
+
+    #[facet]
+    skiplist_index: SkiplistIndex,
+}
+
+pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
+    let ctx = app.new_context();
+
+    let repo: Repo = app
+        .open_repo(&args.repo_args)
+        .await
+        .context("Failed to open repo")?;
+
+    match args.subcommand {
+        BookmarkSubcommand::Set(set_args) => set::set(&ctx, &repo, set_args, app.dry_run()).await?,
+    }
+
+    Ok(())
+}
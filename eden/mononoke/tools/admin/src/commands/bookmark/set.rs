@@ -0,0 +1,147 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::io::Write;
+
+use anyhow::{anyhow, Context, Result};
+use bookmarks::{
+    BookmarkKind, BookmarkName, BookmarkPagination, BookmarkPrefix, BookmarkUpdateReason,
+    BookmarksRef, Freshness,
+};
+use changeset_fetcher::ChangesetFetcherArc;
+use changesets::ChangesetsRef;
+use clap::Args;
+use context::CoreContext;
+use futures::stream::TryStreamExt;
+use mononoke_app::args::ChangesetArgs;
+use mononoke_types::ChangesetId;
+use reachabilityindex::LeastCommonAncestorsHint;
+use skiplist::SkiplistIndexRef;
+
+use super::Repo;
+
+#[derive(Args)]
+pub struct BookmarkSetArgs {
+    /// Move a publishing bookmark even if the move is not a fast-forward
+    #[clap(long)]
+    force: bool,
+
+    /// Name of the bookmark to set
+    name: String,
+
+    #[clap(flatten)]
+    changeset_args: ChangesetArgs,
+}
+
+/// Returns true if the bookmark is publishing, in which case moving it
+/// backwards or sideways would rewrite public history.
+async fn is_publishing(ctx: &CoreContext, repo: &Repo, bookmark: &BookmarkName) -> Result<bool> {
+    // A bookmark name sorts before any other name that has it as a prefix,
+    // so if the bookmark is publishing it will be the first one listed.
+    let first = repo
+        .bookmarks()
+        .list(
+            ctx.clone(),
+            Freshness::MostRecent,
+            &BookmarkPrefix::new(bookmark.as_str())?,
+            BookmarkKind::ALL_PUBLISHING,
+            &BookmarkPagination::FromStart,
+            1,
+        )
+        .try_next()
+        .await?;
+    Ok(matches!(first, Some((found, _)) if found.name() == bookmark))
+}
+
+async fn is_fast_forward(
+    ctx: &CoreContext,
+    repo: &Repo,
+    old: ChangesetId,
+    new: ChangesetId,
+) -> Result<bool> {
+    if old == new {
+        return Ok(true);
+    }
+    repo.skiplist_index()
+        .is_ancestor(ctx, &repo.changeset_fetcher_arc(), old, new)
+        .await
+}
+
+pub async fn set(
+    ctx: &CoreContext,
+    repo: &Repo,
+    set_args: BookmarkSetArgs,
+    dry_run: bool,
+) -> Result<()> {
+    let bookmark = BookmarkName::new(&set_args.name)
+        .with_context(|| format!("Invalid bookmark name: {}", set_args.name))?;
+
+    let new = set_args
+        .changeset_args
+        .resolve_changeset(ctx, repo)
+        .await
+        .context("Failed to resolve changeset")?
+        .ok_or_else(|| anyhow!("Changeset not found"))?;
+    if !repo
+        .changesets()
+        .exists(ctx, new)
+        .await
+        .with_context(|| format!("Failed to check whether changeset {} exists", new))?
+    {
+        return Err(anyhow!("Changeset not found: {}", new));
+    }
+
+    let old = repo
+        .bookmarks()
+        .get(ctx.clone(), &bookmark)
+        .await
+        .with_context(|| format!("Failed to resolve bookmark {}", bookmark))?;
+
+    if let Some(old) = old {
+        if !set_args.force
+            && is_publishing(ctx, repo, &bookmark).await?
+            && !is_fast_forward(ctx, repo, old, new).await?
+        {
+            return Err(anyhow!(
+                "Refusing to move publishing bookmark {} from {} to {}, which is not a fast-forward move (use --force to move it anyway)",
+                bookmark,
+                old,
+                new,
+            ));
+        }
+    }
+
+    let description = match old {
+        Some(old) => format!("move bookmark {} from {} to {}", bookmark, old, new),
+        None => format!("create bookmark {} at {}", bookmark, new),
+    };
+    if dry_run {
+        writeln!(std::io::stdout(), "Dry run: would {}", description)?;
+        return Ok(());
+    }
+    writeln!(std::io::stdout(), "Going to {}", description)?;
+
+    let mut transaction = repo.bookmarks().create_transaction(ctx.clone());
+    match old {
+        Some(old) => {
+            transaction.update(&bookmark, new, old, BookmarkUpdateReason::ManualMove, None)?
+        }
+        None => transaction.create(&bookmark, new, BookmarkUpdateReason::ManualMove, None)?,
+    }
+    if !transaction
+        .commit()
+        .await
+        .context("Failed to commit bookmark transaction")?
+    {
+        return Err(anyhow!(
+            "Failed to {}: the bookmark was changed concurrently",
+            description
+        ));
+    }
+
+    Ok(())
+}
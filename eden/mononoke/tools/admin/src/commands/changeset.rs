@@ -0,0 +1,85 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::{anyhow, Context, Result};
+use blobstore::{Loadable, LoadableError};
+use bonsai_globalrev_mapping::BonsaiGlobalrevMapping;
+use bonsai_hg_mapping::BonsaiHgMapping;
+use bookmarks::Bookmarks;
+use clap::Parser;
+use cmdlib_displaying::DisplayChangeset;
+use mononoke_app::args::{ChangesetArgs, RepoArgs};
+use mononoke_app::MononokeApp;
+use repo_blobstore::{RepoBlobstore, RepoBlobstoreRef};
+use repo_identity::RepoIdentity;
+
+use crate::output::OutputFormat;
+
+/// Show a bonsai changeset
+#[derive(Parser)]
+pub struct CommandArgs {
+    #[clap(flatten)]
+    repo_args: RepoArgs,
+
+    #[clap(flatten)]
+    changeset_args: ChangesetArgs,
+
+    /// Format as JSON.  Equivalent to the global `--output=json`.
+    #[clap(long)]
+    json: bool,
+}
+
+#[facet::container]
+struct Repo {
+    #[facet]
+    repo_identity: RepoIdentity,
+    #[facet]
+    bookmarks: dyn Bookmarks,
+    #[facet]
+    bonsai_hg_mapping: dyn BonsaiHgMapping,
+    #[facet]
+    bonsai_globalrev_mapping: dyn BonsaiGlobalrevMapping,
+    #[facet]
+    repo_blobstore: RepoBlobstore,
+}
+
+pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
+    let ctx = app.new_context();
+    let json = args.json || OutputFormat::from_app(&app)?.is_json();
+
+    let repo: Repo = app
+        .open_repo(&args.repo_args)
+        .await
+        .context("Failed to open repo")?;
+
+    let changeset_id = args
+        .changeset_args
+        .resolve_changeset(&ctx, &repo)
+        .await
+        .context("Failed to resolve changeset")?
+        .ok_or_else(|| anyhow!("Changeset not found"))?;
+
+    let cs = match changeset_id.load(&ctx, repo.repo_blobstore()).await {
+        Ok(cs) => cs,
+        Err(LoadableError::Missing(_)) => {
+            return Err(anyhow!("Changeset not found: {}", changeset_id));
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to load changeset {}", changeset_id));
+        }
+    };
+    let display_cs = DisplayChangeset::try_from(&cs).context("Failed to display changeset")?;
+
+    if json {
+        let json_cs = serde_json::to_string(&display_cs).context("Failed to convert to JSON")?;
+        println!("{}", json_cs);
+    } else {
+        println!("{}", display_cs);
+    }
+
+    Ok(())
+}
@@ -10,6 +10,7 @@ use std::num::NonZeroU64;
 
 use anyhow::{anyhow, Context, Result};
 use blobstore::Loadable;
+use bonsai_globalrev_mapping::BonsaiGlobalrevMapping;
 use bonsai_hg_mapping::{BonsaiHgMapping, BonsaiHgMappingRef};
 use bookmarks::Bookmarks;
 use clap::{ArgEnum, Parser};
@@ -23,6 +24,8 @@ use mononoke_types::{ChangesetId, MPath};
 use repo_blobstore::{RepoBlobstore, RepoBlobstoreRef};
 use repo_identity::{RepoIdentity, RepoIdentityRef};
 
+use crate::output::OutputFormat;
+
 /// Fetch commit, tree or file data.
 #[derive(Parser)]
 pub struct CommandArgs {
@@ -41,6 +44,7 @@ pub struct CommandArgs {
     path: Option<String>,
 
     /// Format as JSON. Currently works only for changesets.
+    /// Equivalent to the global `--output=json`.
     #[clap(long)]
     json: bool,
 
@@ -62,6 +66,8 @@ struct Repo {
     #[facet]
     bonsai_hg_mapping: dyn BonsaiHgMapping,
     #[facet]
+    bonsai_globalrev_mapping: dyn BonsaiGlobalrevMapping,
+    #[facet]
     bookmarks: dyn Bookmarks,
     #[facet]
     repo_blobstore: RepoBlobstore,
@@ -71,6 +77,7 @@ struct Repo {
 
 pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
     let ctx = app.new_context();
+    let json = args.json || OutputFormat::from_app(&app)?.is_json();
 
     let repo: Repo = app
         .open_repo(&args.repo_args)
@@ -105,7 +112,7 @@ pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
             let display_cs =
                 DisplayChangeset::try_from(&cs).context("Failed to display changeset")?;
 
-            if args.json {
+            if json {
                 let json_cs =
                     serde_json::to_string(&display_cs).context("Failed to convert to JSON")?;
                 println!("{}", json_cs);
@@ -9,6 +9,9 @@ use anyhow::{Context, Result};
 use clap::Parser;
 use mononoke_app::MononokeApp;
 use regex::Regex;
+use serde_json::json;
+
+use crate::output::OutputFormat;
 
 /// List configured repositories.
 #[derive(Parser)]
@@ -18,6 +21,7 @@ pub struct CommandArgs {
 }
 
 pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
+    let output = OutputFormat::from_app(&app)?;
     let pattern = args
         .pattern
         .as_deref()
@@ -34,7 +38,15 @@ pub async fn run(app: MononokeApp, args: CommandArgs) -> Result<()> {
                 continue;
             }
         }
-        println!("{} {}", repo_config.repoid, repo_name);
+        if output.is_json() {
+            let json = json!({
+                "repo_id": repo_config.repoid.id(),
+                "repo_name": repo_name,
+            });
+            println!("{}", json);
+        } else {
+            println!("{} {}", repo_config.repoid, repo_name);
+        }
     }
 
     Ok(())
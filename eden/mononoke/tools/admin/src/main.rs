@@ -11,17 +11,26 @@ use cmdlib_scrubbing::ScrubArgExtension;
 use fbinit::FacebookInit;
 use mononoke_app::{MononokeApp, MononokeAppBuilder};
 
+use crate::output::OutputFormat;
+
 mod commands;
+mod output;
 
 /// Administrate Mononoke
 #[derive(Parser)]
-struct AdminArgs {}
+struct AdminArgs {
+    /// Output format for subcommands that support structured output.
+    /// Must be given before the subcommand name.
+    #[clap(long, arg_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
 
 #[fbinit::main]
 fn main(fb: FacebookInit) -> Result<()> {
     let subcommands = commands::subcommands();
     let app = MononokeAppBuilder::new(fb)
         .with_arg_extension(ScrubArgExtension::new())
+        .with_dry_run_support()
         .build_with_subcommands::<AdminArgs>(subcommands)?;
     app.run(async_main)
 }
@@ -0,0 +1,33 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Result;
+use clap::ArgEnum;
+use mononoke_app::MononokeApp;
+
+use crate::AdminArgs;
+
+/// Format used by subcommands when printing their results.
+#[derive(ArgEnum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Text,
+
+    /// Structured JSON, one value per line
+    Json,
+}
+
+impl OutputFormat {
+    /// Returns the output format selected on the command line.
+    pub fn from_app(app: &MononokeApp) -> Result<Self> {
+        Ok(app.args::<AdminArgs>()?.output)
+    }
+
+    pub fn is_json(self) -> bool {
+        self == OutputFormat::Json
+    }
+}
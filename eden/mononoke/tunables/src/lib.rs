@@ -171,6 +171,10 @@ pub struct MononokeTunables {
 
     bookmarks_cache_ttl_ms: AtomicI64,
 
+    // How long a globalrev found to have no bonsai is remembered as missing, in milliseconds.
+    // 0 means the default, and negative disables negative caching.
+    bonsai_globalrev_mapping_negative_cache_ttl_ms: AtomicI64,
+
     // Disable running SaveMappingPushrebaseHook on every Pushrebase
     disable_save_mapping_pushrebase_hook: AtomicBool,
 
@@ -5,15 +5,25 @@
  * GNU General Public License version 2.
  */
 
-use std::{fs, fs::File, io, path::Path};
+use std::{
+    error::Error,
+    fmt, fs,
+    fs::File,
+    hash::Hasher,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 #[cfg(unix)]
 use std::{fs::Permissions, os::unix::fs::PermissionsExt};
 
+use twox_hash::XxHash;
+
 /// Create a temp file and then rename it into the specified path to
 /// achieve atomicity. The temp file is created in the same directory
 /// as path to ensure the rename is not cross filesystem. If fysnc is
 /// true, the file will be fsynced before and after renaming, and the
-/// directory will by fsynced after renaming.
+/// directory will by fsynced after renaming, as described in
+/// [`FsyncMode::BestEffort`].
 ///
 /// mode_perms is required but does nothing on windows. mode_perms is
 /// not automatically umasked.
@@ -21,54 +31,395 @@ use std::{fs::Permissions, os::unix::fs::PermissionsExt};
 /// The renamed file is returned. Any further data written to the file
 /// will not be atomic since the file is already visibile to readers.
 ///
-/// Note that the rename operation will fail on windows if the
-/// destination file exists and is open.
+/// On Windows, an existing destination is replaced with `ReplaceFileW`,
+/// which works even if the destination is open for reading and keeps its
+/// attributes and ACLs. If that fails, the temp file is renamed instead,
+/// which is retried a few times as it fails if the destination is open.
+///
+/// Errors keep their original kind, but their message is extended with
+/// the temp file and destination paths. The temp file is removed when an
+/// error is returned.
 pub fn atomic_write<P: AsRef<Path>>(
     path: P,
-    #[allow(dead_code)] mode_perms: u32,
+    mode_perms: u32,
+    fsync: bool,
+    op: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<File> {
+    atomic_write_impl(path, None, mode_perms, fsync.into(), op, || Ok(())).map(|(file, ())| file)
+}
+
+/// Like [`atomic_write`], but `op` can return a value, for example a digest
+/// of the content computed while writing it. The value is returned with the
+/// renamed file if the write succeeds.
+pub fn atomic_write_with_result<P: AsRef<Path>, R>(
+    path: P,
+    mode_perms: u32,
+    fsync: bool,
+    op: impl FnOnce(&mut File) -> io::Result<R>,
+) -> io::Result<(File, R)> {
+    atomic_write_impl(path, None, mode_perms, fsync.into(), op, || Ok(()))
+}
+
+/// How [`atomic_write`] and friends sync the written file to disk, and so
+/// what survives a power loss or OS crash. Without a crash, every mode
+/// leaves either the old or the new content at the destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncMode {
+    /// Never sync (`fsync: false`). After a crash, the destination can have
+    /// its old content, its new content, or, on filesystems that delay
+    /// allocating blocks (ext4 without `auto_da_alloc`, XFS, btrfs), be
+    /// empty or partially written even though the rename happened.
+    Off,
+    /// Sync the temp file's data before renaming it, then sync the file and
+    /// its directory after renaming (`fsync: true`). After a crash, the
+    /// destination has either its old or its complete new content. The
+    /// directory sync is best effort: if it fails, it is only logged, so the
+    /// rename itself can be lost and the old content come back after a crash
+    /// even though the write succeeded.
+    BestEffort,
+    /// Write, sync the temp file (data and metadata), rename, then sync the
+    /// directory, in that order. After a crash, the destination has either
+    /// its old or its complete new content, and once the write returns `Ok`,
+    /// it has the new content. A failure to sync the directory is returned
+    /// as an error, but the destination has already been replaced by then.
+    ///
+    /// Windows cannot sync a directory, so the renamed file is synced again
+    /// instead.
+    Strict,
+}
+
+impl From<bool> for FsyncMode {
+    fn from(fsync: bool) -> Self {
+        if fsync {
+            FsyncMode::BestEffort
+        } else {
+            FsyncMode::Off
+        }
+    }
+}
+
+/// Like [`atomic_write`], but with a choice of [`FsyncMode`] instead of a
+/// `fsync` flag.
+pub fn atomic_write_with_fsync_mode<P: AsRef<Path>>(
+    path: P,
+    mode_perms: u32,
+    fsync: FsyncMode,
+    op: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<File> {
+    atomic_write_impl(path, None, mode_perms, fsync, op, || Ok(())).map(|(file, ())| file)
+}
+
+/// Error returned by [`atomic_write_in`] when the temp directory is not on
+/// the same filesystem as the destination, so the temp file could not be
+/// renamed into place atomically. It is wrapped in an [`io::Error`].
+#[derive(Debug)]
+pub struct TempDirOnOtherFilesystem {
+    pub temp_dir: PathBuf,
+    pub path: PathBuf,
+}
+
+impl fmt::Display for TempDirOnOtherFilesystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "temp directory {} is not on the same filesystem as {}",
+            self.temp_dir.display(),
+            self.path.display()
+        )
+    }
+}
+
+impl Error for TempDirOnOtherFilesystem {}
+
+/// Like [`atomic_write`], but create the temp file in `temp_dir` instead
+/// of the directory containing `path`. This is useful when a faster
+/// directory is available on the same filesystem.
+///
+/// `temp_dir` must be on the same filesystem as `path`, otherwise the
+/// rename would not be atomic. On Unix, this is checked up front by
+/// comparing device ids, and a [`TempDirOnOtherFilesystem`] error is
+/// returned if they differ. Elsewhere, the rename itself fails.
+pub fn atomic_write_in<P: AsRef<Path>, D: AsRef<Path>>(
+    path: P,
+    temp_dir: D,
+    mode_perms: u32,
     fsync: bool,
     op: impl FnOnce(&mut File) -> io::Result<()>,
 ) -> io::Result<File> {
+    atomic_write_impl(
+        path,
+        Some(temp_dir.as_ref()),
+        mode_perms,
+        fsync.into(),
+        op,
+        || Ok(()),
+    )
+    .map(|(file, ())| file)
+}
+
+/// Check that `temp_dir` is on the same device as `dir`.
+#[cfg(unix)]
+fn check_same_filesystem(temp_dir: &Path, dir: &Path, path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    // `parent()` of a relative path without directories is empty.
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let metadata = |dir: &Path| {
+        fs::metadata(dir).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("{} (checking filesystem of {})", e, dir.display()),
+            )
+        })
+    };
+    if metadata(temp_dir)?.dev() != metadata(dir)?.dev() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            TempDirOnOtherFilesystem {
+                temp_dir: temp_dir.to_path_buf(),
+                path: path.to_path_buf(),
+            },
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_same_filesystem(_temp_dir: &Path, _dir: &Path, _path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// What [`atomic_write_with_backup`] does when it cannot back up the file
+/// it is about to replace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackupFailure {
+    /// Fail the write, leaving the destination untouched.
+    Fatal,
+    /// Log a warning and replace the destination anyway.
+    BestEffort,
+}
+
+/// Like [`atomic_write`], but just before renaming the temp file into
+/// place, atomically copies the current content of `path`, if any, to
+/// `path.with_extension("bak")`. This keeps one generation of backup to
+/// recover from a bad write. An existing backup is replaced.
+///
+/// `on_backup_failure` decides whether a failure to take the backup fails
+/// the write. No backup is taken if `path` does not exist.
+pub fn atomic_write_with_backup<P: AsRef<Path>>(
+    path: P,
+    mode_perms: u32,
+    fsync: bool,
+    on_backup_failure: BackupFailure,
+    op: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<File> {
+    let path = path.as_ref();
+    atomic_write_impl(path, None, mode_perms, fsync.into(), op, || {
+        let backup_path = path.with_extension("bak");
+        match backup(path, &backup_path, mode_perms, fsync) {
+            Ok(()) => Ok(()),
+            Err(e) if on_backup_failure == BackupFailure::BestEffort => {
+                tracing::warn!(
+                    name = "atomic_write failed to back up file. Replacing it anyway.",
+                    path = AsRef::<str>::as_ref(&path.display().to_string()),
+                    error = AsRef::<str>::as_ref(&e.to_string()),
+                );
+                Ok(())
+            }
+            Err(e) => Err(io::Error::new(
+                e.kind(),
+                format!(
+                    "{} (backing up {} to {})",
+                    e,
+                    path.display(),
+                    backup_path.display()
+                ),
+            )),
+        }
+    })
+    .map(|(file, ())| file)
+}
+
+/// Atomically copy `path` to `backup_path`, if `path` exists.
+fn backup(path: &Path, backup_path: &Path, mode_perms: u32, fsync: bool) -> io::Result<()> {
+    let mut original = match File::open(path) {
+        Ok(original) => original,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    atomic_write(backup_path, mode_perms, fsync, |f| {
+        io::copy(&mut original, f)?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Implementation of [`atomic_write`] that creates the temp file in
+/// `temp_dir`, or next to `path` if it is `None`, and calls `before_rename`
+/// after the temp file is written, just before it is renamed into place. An
+/// error from `before_rename` is returned as is, after removing the temp file.
+/// The value returned by `op` is returned with the renamed file.
+fn atomic_write_impl<P: AsRef<Path>, R>(
+    path: P,
+    temp_dir: Option<&Path>,
+    #[allow(dead_code)] mode_perms: u32,
+    fsync: FsyncMode,
+    op: impl FnOnce(&mut File) -> io::Result<R>,
+    before_rename: impl FnOnce() -> io::Result<()>,
+) -> io::Result<(File, R)> {
     let dir = match path.as_ref().parent() {
         Some(dir) => dir,
         None => return Err(io::ErrorKind::InvalidInput.into()),
     };
+    let temp_dir = match temp_dir {
+        Some(temp_dir) => {
+            check_same_filesystem(temp_dir, dir, path.as_ref())?;
+            temp_dir
+        }
+        None => dir,
+    };
 
-    let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+    let mut temp = tempfile::NamedTempFile::new_in(temp_dir).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "{} (creating temp file in {} for {})",
+                e,
+                temp_dir.display(),
+                path.as_ref().display()
+            ),
+        )
+    })?;
+    let temp_path = temp.path().to_path_buf();
+    let annotate = |e: io::Error, action: &str| annotate_temp(e, action, &temp_path, path.as_ref());
     let f = temp.as_file_mut();
 
     #[cfg(unix)]
-    f.set_permissions(Permissions::from_mode(mode_perms))?;
+    f.set_permissions(Permissions::from_mode(mode_perms))
+        .map_err(|e| annotate(e, "setting permissions on"))?;
+
+    let result = op(f).map_err(|e| annotate(e, "writing"))?;
+    step("write");
+
+    sync_temp(f, fsync).map_err(|e| annotate(e, "syncing"))?;
+
+    before_rename()?;
+
+    let persisted = persist(temp, path.as_ref(), dir, fsync)?;
+    Ok((persisted, result))
+}
 
-    op(f)?;
+/// Rename a temp file that was created and written by the caller into
+/// `path`, and sync it as [`atomic_write`] does if `fsync` is true. This is
+/// for callers that need to write the file in their own way, for example
+/// with `sendfile`.
+///
+/// `temp` must be on the same filesystem as `path`, ideally in the same
+/// directory, otherwise the rename would not be atomic. On Unix, this is
+/// checked up front, and a [`TempDirOnOtherFilesystem`] error is returned
+/// if they differ. The permissions of `temp` are left as they are.
+///
+/// Existing destinations are replaced, and errors are reported, as by
+/// [`atomic_write`]. The temp file is removed when an error is returned.
+pub fn atomic_persist<P: AsRef<Path>>(
+    temp: tempfile::NamedTempFile,
+    path: P,
+    fsync: bool,
+) -> io::Result<File> {
+    let path = path.as_ref();
+    let dir = match path.parent() {
+        Some(dir) => dir,
+        None => return Err(io::ErrorKind::InvalidInput.into()),
+    };
+    if let Some(temp_dir) = temp.path().parent().filter(|d| !d.as_os_str().is_empty()) {
+        check_same_filesystem(temp_dir, dir, path)?;
+    }
+    let fsync = FsyncMode::from(fsync);
+    sync_temp(temp.as_file(), fsync).map_err(|e| annotate_temp(e, "syncing", temp.path(), path))?;
+    persist(temp, path, dir, fsync)
+}
+
+/// Add the temp file and destination paths to the message of `e`, which
+/// happened while doing `action` to the temp file.
+fn annotate_temp(e: io::Error, action: &str, temp_path: &Path, path: &Path) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!(
+            "{} ({} temp file {} for {})",
+            e,
+            action,
+            temp_path.display(),
+            path.display()
+        ),
+    )
+}
+
+/// Sync the written temp file before it is renamed, as required by `fsync`.
+fn sync_temp(f: &File, fsync: FsyncMode) -> io::Result<()> {
+    match fsync {
+        FsyncMode::Off => {}
+        FsyncMode::BestEffort => {
+            f.sync_data()?;
+            step("sync temp file");
+        }
+        // Also sync the permissions, as the file is not synced again after
+        // the rename.
+        FsyncMode::Strict => {
+            f.sync_all()?;
+            step("sync temp file");
+        }
+    }
+    Ok(())
+}
 
-    if fsync {
-        f.sync_data()?;
+/// Rename `temp` into `path`, which is in `dir`, then sync the renamed file
+/// and `dir` as required by `fsync`.
+fn persist(
+    mut temp: tempfile::NamedTempFile,
+    path: &Path,
+    dir: &Path,
+    fsync: FsyncMode,
+) -> io::Result<File> {
+    let temp_path = temp.path().to_path_buf();
+    let finish = |persisted: File| {
+        step("rename");
+        let sync_file = match fsync {
+            FsyncMode::Off => false,
+            FsyncMode::BestEffort => true,
+            FsyncMode::Strict => cfg!(windows),
+        };
+        if sync_file {
+            persisted.sync_all().map_err(|e| {
+                io::Error::new(e.kind(), format!("{} (syncing {})", e, path.display()))
+            })?;
+            step("sync file");
+        }
+        if fsync != FsyncMode::Off {
+            sync_dir(dir, path, fsync == FsyncMode::Strict)?;
+        }
+        Ok(persisted)
+    };
+
+    #[cfg(windows)]
+    {
+        temp = match replace_file(path, temp) {
+            Ok(persisted) => return finish(persisted),
+            Err(temp) => temp,
+        };
     }
 
     let max_retries = if cfg!(windows) { 5u16 } else { 0 };
     let mut retry = 0;
     loop {
-        match temp.persist(&path) {
-            Ok(persisted) => {
-                if fsync {
-                    persisted.sync_all()?;
-
-                    // Also sync the directory on Unix.
-                    // Windows does not support syncing a directory.
-                    #[cfg(unix)]
-                    {
-                        if let Ok(opened) = fs::OpenOptions::new().read(true).open(dir) {
-                            let _ = opened.sync_all();
-                        }
-                    }
-                }
-
-                break Ok(persisted);
-            }
+        match temp.persist(path) {
+            Ok(persisted) => break finish(persisted),
             Err(e) => {
                 if retry == max_retries || e.error.kind() != io::ErrorKind::PermissionDenied {
-                    break Err(e.error);
+                    break Err(annotate_temp(e.error, "renaming", &temp_path, path));
                 }
 
                 // Windows fails with "Access Denied" if destination file is open.
@@ -76,7 +427,7 @@ pub fn atomic_write<P: AsRef<Path>>(
                 tracing::info!(
                     name = "atomic_write rename failed with EPERM. Will retry.",
                     retry = retry,
-                    path = AsRef::<str>::as_ref(&path.as_ref().display().to_string()),
+                    path = AsRef::<str>::as_ref(&path.display().to_string()),
                 );
                 std::thread::sleep(std::time::Duration::from_millis(1 << retry));
                 temp = e.file;
@@ -87,6 +438,233 @@ pub fn atomic_write<P: AsRef<Path>>(
     }
 }
 
+/// Sync `dir` after `path` was renamed into it, so that the rename survives
+/// a crash. Errors are only returned if `strict`, otherwise they are logged.
+/// Windows does not support syncing a directory.
+#[cfg(unix)]
+fn sync_dir(dir: &Path, path: &Path, strict: bool) -> io::Result<()> {
+    // `parent()` of a relative path without directories is empty.
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    match File::open(dir).and_then(|opened| opened.sync_all()) {
+        Ok(()) => step("sync dir"),
+        Err(e) if strict => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!(
+                    "{} (syncing directory {} after renaming to {})",
+                    e,
+                    dir.display(),
+                    path.display()
+                ),
+            ));
+        }
+        Err(e) => tracing::warn!(
+            name = "atomic_write failed to sync directory.",
+            path = AsRef::<str>::as_ref(&path.display().to_string()),
+            error = AsRef::<str>::as_ref(&e.to_string()),
+        ),
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path, _path: &Path, _strict: bool) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+thread_local! {
+    static STEPS: std::cell::RefCell<Vec<&'static str>> = Default::default();
+}
+
+/// Record a step of [`atomic_write_impl`], so tests can check their order.
+fn step(name: &'static str) {
+    #[cfg(test)]
+    STEPS.with(|steps| steps.borrow_mut().push(name));
+    #[cfg(not(test))]
+    let _ = name;
+}
+
+/// Replace the existing file at `path` with `temp` using `ReplaceFileW`.
+/// Unlike a rename, this works if `path` is open for reading. `temp` is
+/// handed back if `path` does not exist or the replace fails, so the caller
+/// can fall back to renaming it.
+#[cfg(windows)]
+fn replace_file(
+    path: &Path,
+    temp: tempfile::NamedTempFile,
+) -> Result<File, tempfile::NamedTempFile> {
+    use std::ffi::c_void;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+
+    // The binding in winapi 0.3 is missing the return value.
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn ReplaceFileW(
+            replaced: *const u16,
+            replacement: *const u16,
+            backup: *const u16,
+            flags: u32,
+            exclude: *mut c_void,
+            reserved: *mut c_void,
+        ) -> i32;
+    }
+    const REPLACEFILE_IGNORE_MERGE_ERRORS: u32 = 0x00000002;
+
+    if !path.is_file() {
+        return Err(temp);
+    }
+    let wide =
+        |path: &Path| -> Vec<u16> { path.as_os_str().encode_wide().chain(Some(0)).collect() };
+    let replaced = wide(path);
+    let replacement = wide(temp.path());
+    let ok = unsafe {
+        ReplaceFileW(
+            replaced.as_ptr(),
+            replacement.as_ptr(),
+            ptr::null(),
+            REPLACEFILE_IGNORE_MERGE_ERRORS,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        tracing::info!(
+            name = "atomic_write ReplaceFileW failed. Will rename instead.",
+            error = AsRef::<str>::as_ref(&io::Error::last_os_error().to_string()),
+            path = AsRef::<str>::as_ref(&path.display().to_string()),
+        );
+        return Err(temp);
+    }
+
+    // The temp path no longer exists, so removing it is a no-op. The handle
+    // now refers to the file at `path`.
+    Ok(temp.into_file())
+}
+
+/// Size of the checksum appended by `atomic_write_checksummed`.
+const CHECKSUM_SIZE: usize = 8;
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut xx = XxHash::default();
+    xx.write(data);
+    xx.finish()
+}
+
+/// Error returned by [`read_verified`] when the content of a file does not
+/// match its checksum. It is wrapped in an [`io::Error`] of kind
+/// `InvalidData`.
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub path: PathBuf,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "checksum mismatch in {} (expected {:016x}, got {:016x})",
+            self.path.display(),
+            self.expected,
+            self.actual
+        )
+    }
+}
+
+impl Error for ChecksumMismatch {}
+
+/// Atomically write `data` to `path`, followed by a checksum of it, so that
+/// [`read_verified`] can detect corruption. See [`atomic_write`] for the
+/// meaning of the other arguments.
+///
+/// The checksum is written to the same file as the data, so the two are
+/// replaced (and, with `fsync`, made durable) together. It is an 8-byte
+/// xxhash: it detects accidental corruption, not tampering.
+pub fn atomic_write_checksummed<P: AsRef<Path>>(
+    path: P,
+    mode_perms: u32,
+    fsync: bool,
+    data: &[u8],
+) -> io::Result<File> {
+    atomic_write(path, mode_perms, fsync, |f| {
+        f.write_all(data)?;
+        f.write_all(&checksum(data).to_be_bytes())
+    })
+}
+
+/// Read a file written by [`atomic_write_checksummed`] and return its data,
+/// without the checksum. Fails with a [`ChecksumMismatch`] if the data does
+/// not match the checksum.
+pub fn read_verified<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+    let mut data = fs::read(path)?;
+    if data.len() < CHECKSUM_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is too short to contain a checksum", path.display()),
+        ));
+    }
+
+    let data_len = data.len() - CHECKSUM_SIZE;
+    let mut expected = [0; CHECKSUM_SIZE];
+    expected.copy_from_slice(&data[data_len..]);
+    let expected = u64::from_be_bytes(expected);
+    data.truncate(data_len);
+
+    let actual = checksum(&data);
+    if actual != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            ChecksumMismatch {
+                path: path.to_path_buf(),
+                expected,
+                actual,
+            },
+        ));
+    }
+    Ok(data)
+}
+
+/// Digest of `data` as compared by [`atomic_write_if_changed`]. It is an
+/// 8-byte xxhash, so it detects accidental changes, not tampering.
+pub fn content_digest(data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    checksum(data).to_be_bytes()
+}
+
+/// Like [`atomic_write`], but skip running `op` and writing altogether if
+/// the content of `path` already has the digest `expected_current`.
+///
+/// This is for callers that regenerate a file from inputs they can cheaply
+/// tell are unchanged, for example by keeping the [`content_digest`] of the
+/// last content they produced. `op` may be expensive and is not called when
+/// the file is up to date. If `expected_current` is `None`, or `path` cannot
+/// be read, the file is always written.
+///
+/// Returns `None` if nothing was written.
+pub fn atomic_write_if_changed<P: AsRef<Path>>(
+    path: P,
+    mode_perms: u32,
+    fsync: bool,
+    expected_current: Option<&[u8]>,
+    op: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<Option<File>> {
+    if let Some(expected) = expected_current {
+        if let Ok(current) = fs::read(path.as_ref()) {
+            if content_digest(&current) == expected {
+                return Ok(None);
+            }
+        }
+    }
+    atomic_write(path, mode_perms, fsync, op).map(Some)
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Write;
@@ -120,4 +698,308 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_atomic_write_with_result() -> io::Result<()> {
+        let td = tempdir()?;
+        let foo_path = td.path().join("foo");
+
+        let (_file, digest) = atomic_write_with_result(&foo_path, 0o640, false, |f| {
+            f.write_all(b"sushi")?;
+            Ok(content_digest(b"sushi"))
+        })?;
+        assert_eq!(digest, content_digest(&std::fs::read(&foo_path)?));
+
+        // The value is dropped if the write fails.
+        let err = atomic_write_with_result(&foo_path, 0o640, false, |f| {
+            f.write_all(b"ramen")?;
+            Err::<u32, _>(io::Error::new(io::ErrorKind::Other, "failed"))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!("sushi", std::fs::read_to_string(&foo_path)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsync_order() -> io::Result<()> {
+        let td = tempdir()?;
+        let foo_path = td.path().join("foo");
+        let steps = |fsync| -> io::Result<Vec<&'static str>> {
+            STEPS.with(|steps| steps.borrow_mut().clear());
+            atomic_write_with_fsync_mode(&foo_path, 0o640, fsync, |f| f.write_all(b"sushi"))?;
+            assert_eq!("sushi", std::fs::read_to_string(&foo_path)?);
+            Ok(STEPS.with(|steps| steps.take()))
+        };
+
+        assert_eq!(steps(FsyncMode::Off)?, ["write", "rename"]);
+        #[cfg(unix)]
+        {
+            assert_eq!(
+                steps(FsyncMode::BestEffort)?,
+                ["write", "sync temp file", "rename", "sync file", "sync dir"]
+            );
+            assert_eq!(
+                steps(FsyncMode::Strict)?,
+                ["write", "sync temp file", "rename", "sync dir"]
+            );
+        }
+        #[cfg(windows)]
+        assert_eq!(
+            steps(FsyncMode::Strict)?,
+            ["write", "sync temp file", "rename", "sync file"]
+        );
+
+        // The empty parent of a relative path like "foo" is the current
+        // directory, which is synced too.
+        #[cfg(unix)]
+        {
+            STEPS.with(|steps| steps.borrow_mut().clear());
+            sync_dir(Path::new(""), Path::new("foo"), true)?;
+            assert_eq!(STEPS.with(|steps| steps.take()), ["sync dir"]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_persist() -> io::Result<()> {
+        let td = tempdir()?;
+        let foo_path = td.path().join("foo");
+
+        let mut temp = tempfile::NamedTempFile::new_in(td.path())?;
+        temp.write_all(b"sushi")?;
+        STEPS.with(|steps| steps.borrow_mut().clear());
+        atomic_persist(temp, &foo_path, true)?;
+        #[cfg(unix)]
+        assert_eq!(
+            STEPS.with(|steps| steps.take()),
+            ["sync temp file", "rename", "sync file", "sync dir"]
+        );
+        assert_eq!("sushi", std::fs::read_to_string(&foo_path)?);
+        assert_eq!(1, std::fs::read_dir(td.path())?.count());
+
+        // An existing file is replaced.
+        let mut temp = tempfile::NamedTempFile::new_in(td.path())?;
+        temp.write_all(b"ramen")?;
+        atomic_persist(temp, &foo_path, false)?;
+        assert_eq!("ramen", std::fs::read_to_string(&foo_path)?);
+
+        // The temp file is removed if the rename fails.
+        let dir_path = td.path().join("dir");
+        std::fs::create_dir(&dir_path)?;
+        std::fs::write(dir_path.join("file"), b"")?;
+        let temp = tempfile::NamedTempFile::new_in(td.path())?;
+        let temp_path = temp.path().to_path_buf();
+        let err = atomic_persist(temp, &dir_path, false).unwrap_err();
+        assert!(err.to_string().contains("renaming temp file"));
+        assert!(!temp_path.exists());
+        assert_eq!(2, std::fs::read_dir(td.path())?.count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_write_error_paths() -> io::Result<()> {
+        let td = tempdir()?;
+
+        let foo_path = td.path().join("foo");
+        let err = atomic_write(&foo_path, 0o640, false, |_| {
+            Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"))
+        })
+        .unwrap_err();
+
+        // The original error kind and message are kept, with the paths added.
+        assert_eq!(io::ErrorKind::WriteZero, err.kind());
+        let message = err.to_string();
+        assert!(message.starts_with("disk full (writing temp file "));
+        assert!(message.contains(&td.path().display().to_string()));
+        assert!(message.ends_with(&format!("for {})", foo_path.display())));
+
+        // The temp file was cleaned up and the destination never created.
+        assert_eq!(0, std::fs::read_dir(td.path())?.count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_write_with_backup() -> io::Result<()> {
+        let td = tempdir()?;
+
+        let foo_path = td.path().join("foo.conf");
+        let bak_path = td.path().join("foo.bak");
+        let write = |data: &'static [u8], on_failure| {
+            atomic_write_with_backup(&foo_path, 0o640, false, on_failure, |f| f.write_all(data))
+        };
+
+        // No backup is taken if there is nothing to back up.
+        write(b"sushi", BackupFailure::Fatal)?;
+        assert!(!bak_path.exists());
+
+        write(b"ramen", BackupFailure::Fatal)?;
+        assert_eq!("ramen", std::fs::read_to_string(&foo_path)?);
+        assert_eq!("sushi", std::fs::read_to_string(&bak_path)?);
+
+        write(b"udon", BackupFailure::Fatal)?;
+        assert_eq!("udon", std::fs::read_to_string(&foo_path)?);
+        assert_eq!("ramen", std::fs::read_to_string(&bak_path)?);
+        assert_eq!(2, std::fs::read_dir(td.path())?.count());
+
+        // Make the backup fail by putting a non-empty directory in its way.
+        std::fs::remove_file(&bak_path)?;
+        std::fs::create_dir(&bak_path)?;
+        std::fs::write(bak_path.join("x"), b"")?;
+
+        let err = write(b"soba", BackupFailure::Fatal).unwrap_err();
+        assert!(err.to_string().contains("(backing up "));
+        assert_eq!("udon", std::fs::read_to_string(&foo_path)?);
+        // The temp file for the destination was cleaned up.
+        assert_eq!(2, std::fs::read_dir(td.path())?.count());
+
+        write(b"soba", BackupFailure::BestEffort)?;
+        assert_eq!("soba", std::fs::read_to_string(&foo_path)?);
+
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_atomic_write_open_destination() -> io::Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = td.path().join("foo");
+        fs::write(&path, b"old")?;
+
+        let mut reader = File::open(&path)?;
+        atomic_write(&path, 0o644, false, |f| f.write_all(b"new"))?;
+        assert_eq!(fs::read(&path)?, b"new");
+
+        // The open handle still reads the old file.
+        let mut old = Vec::new();
+        io::Read::read_to_end(&mut reader, &mut old)?;
+        assert_eq!(old, b"old");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_write_in() -> io::Result<()> {
+        let td = tempdir()?;
+        let temp_dir = td.path().join("tmp");
+        std::fs::create_dir(&temp_dir)?;
+
+        let foo_path = td.path().join("foo");
+        atomic_write_in(&foo_path, &temp_dir, 0o640, false, |f| {
+            f.write_all(b"sushi")
+        })?;
+        assert_eq!("sushi", std::fs::read_to_string(&foo_path)?);
+        assert_eq!(0, std::fs::read_dir(&temp_dir)?.count());
+
+        // Errors mention the temp directory.
+        let err = atomic_write_in(&foo_path, &temp_dir, 0o640, false, |_| {
+            Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"))
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains(&temp_dir.display().to_string()));
+        assert_eq!(0, std::fs::read_dir(&temp_dir)?.count());
+        assert_eq!("sushi", std::fs::read_to_string(&foo_path)?);
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_atomic_write_in_other_filesystem() -> io::Result<()> {
+        // /dev/shm is usually a tmpfs. Skip if it is on the same device as the
+        // temporary directory.
+        let td = tempdir()?;
+        let other = match tempfile::tempdir_in("/dev/shm") {
+            Ok(other) => other,
+            Err(_) => return Ok(()),
+        };
+        if td.path().metadata()?.dev() == other.path().metadata()?.dev() {
+            return Ok(());
+        }
+
+        let foo_path = td.path().join("foo");
+        let err = atomic_write_in(&foo_path, other.path(), 0o640, false, |f| {
+            f.write_all(b"sushi")
+        })
+        .unwrap_err();
+        let other_fs = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<TempDirOnOtherFilesystem>())
+            .expect("TempDirOnOtherFilesystem");
+        assert_eq!(other.path(), other_fs.temp_dir);
+        assert_eq!(foo_path, other_fs.path);
+        assert!(!foo_path.exists());
+        assert_eq!(0, std::fs::read_dir(other.path())?.count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_write_if_changed() -> io::Result<()> {
+        let td = tempdir()?;
+
+        let foo_path = td.path().join("foo");
+        let sushi = content_digest(b"sushi");
+
+        // Nothing to compare against yet.
+        let written = atomic_write_if_changed(&foo_path, 0o640, false, Some(&sushi), |f| {
+            f.write_all(b"sushi")
+        })?;
+        assert!(written.is_some());
+        assert_eq!("sushi", std::fs::read_to_string(&foo_path)?);
+
+        // Up to date: `op` is not even called.
+        let written = atomic_write_if_changed(&foo_path, 0o640, false, Some(&sushi), |_| {
+            panic!("op should not run")
+        })?;
+        assert!(written.is_none());
+
+        // The file changed behind our back, so it is rewritten.
+        std::fs::write(&foo_path, b"ramen")?;
+        let written = atomic_write_if_changed(&foo_path, 0o640, false, Some(&sushi), |f| {
+            f.write_all(b"sushi")
+        })?;
+        assert!(written.is_some());
+        assert_eq!("sushi", std::fs::read_to_string(&foo_path)?);
+
+        // Without a digest, the file is always written.
+        let written =
+            atomic_write_if_changed(&foo_path, 0o640, false, None, |f| f.write_all(b"sushi"))?;
+        assert!(written.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksummed() -> io::Result<()> {
+        let td = tempdir()?;
+
+        let foo_path = td.path().join("foo");
+        atomic_write_checksummed(&foo_path, 0o640, false, b"sushi")?;
+        assert_eq!(b"sushi".to_vec(), read_verified(&foo_path)?);
+        assert_eq!(5 + CHECKSUM_SIZE as u64, foo_path.metadata()?.len());
+
+        // Flip a bit in the data.
+        let mut corrupted = std::fs::read(&foo_path)?;
+        corrupted[0] ^= 1;
+        std::fs::write(&foo_path, &corrupted)?;
+        let err = read_verified(&foo_path).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let mismatch = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<ChecksumMismatch>())
+            .expect("ChecksumMismatch");
+        assert_eq!(foo_path, mismatch.path);
+
+        // Too short to have a checksum at all.
+        std::fs::write(&foo_path, b"sushi")?;
+        let err = read_verified(&foo_path).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        Ok(())
+    }
 }
@@ -5,6 +5,7 @@
  * GNU General Public License version 2.
  */
 #include "ConfigParser.h"
+#include <folly/Conv.h>
 using namespace facebook::eden;
 
 // The following functions are exported from this rust library:
@@ -15,13 +16,29 @@ struct BytesData {
   const uint8_t* ptr;
   size_t len;
 };
+struct BytesDataArray {
+  const BytesData* ptr;
+  size_t len;
+};
 } // namespace
 
 extern "C" HgRcConfigSetStruct* hgrc_configset_new(void) noexcept;
+extern "C" HgRcConfigSetStruct* hgrc_configset_clone_ref(
+    HgRcConfigSetStruct* configset) noexcept;
 extern "C" void hgrc_configset_free(HgRcConfigSetStruct* configset) noexcept;
 extern "C" HgRcBytesStruct* hgrc_configset_load_path(
     HgRcConfigSetStruct* ptr,
     const char* path) noexcept;
+extern "C" HgRcBytesStruct* hgrc_configset_load_path_with_code(
+    HgRcConfigSetStruct* ptr,
+    const char* path,
+    int32_t* code) noexcept;
+extern "C" HgRcBytesStruct* hgrc_configset_load_bytes(
+    HgRcConfigSetStruct* ptr,
+    const uint8_t* data,
+    size_t len,
+    const uint8_t* source_name,
+    size_t source_name_len) noexcept;
 extern "C" HgRcBytesStruct* hgrc_configset_load_system(
     HgRcConfigSetStruct* ptr) noexcept;
 extern "C" HgRcBytesStruct* hgrc_configset_load_user(
@@ -32,9 +49,43 @@ extern "C" HgRcBytesStruct* hgrc_configset_get(
     size_t section_len,
     const uint8_t* name,
     size_t name_len) noexcept;
+extern "C" int32_t hgrc_configset_get_bool(
+    HgRcConfigSetStruct* ptr,
+    const uint8_t* section,
+    size_t section_len,
+    const uint8_t* name,
+    size_t name_len,
+    bool* out) noexcept;
+extern "C" int32_t hgrc_configset_get_int(
+    HgRcConfigSetStruct* ptr,
+    const uint8_t* section,
+    size_t section_len,
+    const uint8_t* name,
+    size_t name_len,
+    int64_t* out) noexcept;
+extern "C" HgRcBytesStruct* hgrc_configset_set(
+    HgRcConfigSetStruct* ptr,
+    const uint8_t* section,
+    size_t section_len,
+    const uint8_t* name,
+    size_t name_len,
+    const uint8_t* value,
+    size_t value_len) noexcept;
+extern "C" HgRcBytesStruct* hgrc_configset_to_string(
+    HgRcConfigSetStruct* ptr,
+    bool include_sources) noexcept;
+extern "C" HgRcBytesListStruct* hgrc_configset_sections(
+    HgRcConfigSetStruct* ptr) noexcept;
+extern "C" HgRcBytesListStruct* hgrc_configset_keys(
+    HgRcConfigSetStruct* ptr,
+    const uint8_t* section,
+    size_t section_len) noexcept;
 
 extern "C" void hgrc_bytes_free(HgRcBytesStruct* bytes) noexcept;
 extern "C" BytesData hgrc_bytes_data(HgRcBytesStruct* bytes) noexcept;
+extern "C" void hgrc_bytes_list_free(HgRcBytesListStruct* list) noexcept;
+extern "C" BytesDataArray hgrc_bytes_list_data(
+    HgRcBytesListStruct* list) noexcept;
 
 namespace facebook {
 namespace eden {
@@ -50,14 +101,53 @@ folly::ByteRange HgRcBytes::bytes() const {
   return folly::ByteRange(data.ptr, data.len);
 }
 
+HgRcBytesList::HgRcBytesList(HgRcBytesListStruct* ptr) : ptr_(ptr) {}
+
+void HgRcBytesList::Deleter::operator()(HgRcBytesListStruct* ptr) const {
+  hgrc_bytes_list_free(ptr);
+}
+
+size_t HgRcBytesList::size() const {
+  return hgrc_bytes_list_data(ptr_.get()).len;
+}
+
+folly::ByteRange HgRcBytesList::operator[](size_t index) const {
+  auto data = hgrc_bytes_list_data(ptr_.get());
+  return folly::ByteRange(data.ptr[index].ptr, data.ptr[index].len);
+}
+
 HgRcConfigSet::HgRcConfigSet() : ptr_(hgrc_configset_new()) {}
 
+HgRcConfigSet::HgRcConfigSet(HgRcConfigSetStruct* ptr) : ptr_(ptr) {}
+
+HgRcConfigSet HgRcConfigSet::cloneRef() const noexcept {
+  return HgRcConfigSet(hgrc_configset_clone_ref(ptr_.get()));
+}
+
 void HgRcConfigSet::Deleter::operator()(HgRcConfigSetStruct* ptr) const {
   hgrc_configset_free(ptr);
 }
 
 void HgRcConfigSet::loadPath(const char* path) {
-  auto result = hgrc_configset_load_path(ptr_.get(), path);
+  int32_t code = 0;
+  auto result = hgrc_configset_load_path_with_code(ptr_.get(), path, &code);
+  if (!result) {
+    return;
+  }
+  HgRcBytes errorText(result);
+  throw HgRcConfigError(
+      errorText.stringPiece().str(), static_cast<HgRcConfigError::Code>(code));
+}
+
+void HgRcConfigSet::loadBytes(
+    folly::ByteRange data,
+    folly::StringPiece sourceName) {
+  auto result = hgrc_configset_load_bytes(
+      ptr_.get(),
+      data.data(),
+      data.size(),
+      reinterpret_cast<const uint8_t*>(sourceName.data()),
+      sourceName.size());
   if (!result) {
     return;
   }
@@ -83,6 +173,25 @@ void HgRcConfigSet::loadUser() {
   throw HgRcConfigError(errorText.stringPiece().str());
 }
 
+void HgRcConfigSet::set(
+    folly::ByteRange section,
+    folly::ByteRange name,
+    folly::ByteRange value) {
+  auto result = hgrc_configset_set(
+      ptr_.get(),
+      section.data(),
+      section.size(),
+      name.data(),
+      name.size(),
+      value.data(),
+      value.size());
+  if (!result) {
+    return;
+  }
+  HgRcBytes errorText(result);
+  throw HgRcConfigError(errorText.stringPiece().str());
+}
+
 folly::Optional<HgRcBytes> HgRcConfigSet::get(
     folly::ByteRange section,
     folly::ByteRange name) const noexcept {
@@ -94,5 +203,72 @@ folly::Optional<HgRcBytes> HgRcConfigSet::get(
   return folly::none;
 }
 
+namespace {
+template <typename T>
+folly::Optional<T> checkGetStatus(
+    int32_t status,
+    T value,
+    folly::ByteRange section,
+    folly::ByteRange name,
+    const char* type) {
+  switch (static_cast<HgRcConfigSet::GetStatus>(status)) {
+    case HgRcConfigSet::GetStatus::Ok:
+      return value;
+    case HgRcConfigSet::GetStatus::NotSet:
+      return folly::none;
+    case HgRcConfigSet::GetStatus::Invalid:
+      break;
+  }
+  throw HgRcConfigError(folly::to<std::string>(
+      "config ",
+      folly::StringPiece{section},
+      ".",
+      folly::StringPiece{name},
+      " is not a valid ",
+      type));
+}
+} // namespace
+
+folly::Optional<bool> HgRcConfigSet::getBool(
+    folly::ByteRange section,
+    folly::ByteRange name) const {
+  bool value = false;
+  auto status = hgrc_configset_get_bool(
+      ptr_.get(),
+      section.data(),
+      section.size(),
+      name.data(),
+      name.size(),
+      &value);
+  return checkGetStatus(status, value, section, name, "boolean");
+}
+
+folly::Optional<int64_t> HgRcConfigSet::getInt(
+    folly::ByteRange section,
+    folly::ByteRange name) const {
+  int64_t value = 0;
+  auto status = hgrc_configset_get_int(
+      ptr_.get(),
+      section.data(),
+      section.size(),
+      name.data(),
+      name.size(),
+      &value);
+  return checkGetStatus(status, value, section, name, "integer");
+}
+
+HgRcBytes HgRcConfigSet::toString(bool includeSources) const noexcept {
+  return HgRcBytes(hgrc_configset_to_string(ptr_.get(), includeSources));
+}
+
+HgRcBytesList HgRcConfigSet::sections() const noexcept {
+  return HgRcBytesList(hgrc_configset_sections(ptr_.get()));
+}
+
+HgRcBytesList HgRcConfigSet::keys(folly::ByteRange section) const noexcept {
+  return HgRcBytesList(
+      hgrc_configset_keys(ptr_.get(), section.data(), section.size()));
+}
+
 } // namespace eden
 } // namespace facebook
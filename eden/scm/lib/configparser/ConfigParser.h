@@ -39,23 +39,87 @@ class HgRcBytes {
   std::unique_ptr<HgRcBytesStruct, Deleter> ptr_;
 };
 
+struct HgRcBytesListStruct;
+
+/** Encapsulates a list of rust Bytes objects returned from the configparser
+ * library.  Each item can be referenced as a folly::ByteRange while the
+ * list is alive. */
+class HgRcBytesList {
+ public:
+  explicit HgRcBytesList(HgRcBytesListStruct* ptr);
+
+  size_t size() const;
+
+  folly::ByteRange operator[](size_t index) const;
+
+ private:
+  struct Deleter {
+    void operator()(HgRcBytesListStruct*) const;
+  };
+  std::unique_ptr<HgRcBytesListStruct, Deleter> ptr_;
+};
+
 class HgRcConfigError : public std::runtime_error {
  public:
-  using std::runtime_error::runtime_error;
+  /** Stable error codes reported by the configparser library.  These match
+   * the HGRC_* constants in c_api.rs and must not be renumbered:
+   *   0  Ok                no error
+   *   1  NotFound          the config file passed in could not be found
+   *   2  PermissionDenied  a config file could not be read due to permissions
+   *   3  Io                a config file could not be read for another reason
+   *   4  Parse             a config file has a syntax error
+   *   5  InvalidUtf8       a config file, or its path, is not valid UTF-8
+   *   6  Other             any other error
+   * If there were several errors, the code is that of the first one. */
+  enum class Code : int32_t {
+    Ok = 0,
+    NotFound = 1,
+    PermissionDenied = 2,
+    Io = 3,
+    Parse = 4,
+    InvalidUtf8 = 5,
+    Other = 6,
+  };
+
+  explicit HgRcConfigError(const std::string& what, Code code = Code::Other)
+      : std::runtime_error(what), code_(code) {}
+
+  Code code() const noexcept {
+    return code_;
+  }
+
+ private:
+  Code code_;
 };
 
 /** Encapsulates a ConfigSet instance from the configparser library.
  * It is initially empty but can have multiple configuration files
  * loaed into it via loadPath().
+ *
+ * The underlying ConfigSet is reference counted and can be shared between
+ * threads via cloneRef().  The const methods may be called concurrently.
+ * The load and set methods require exclusive access: they throw
+ * HgRcConfigError once the ConfigSet has been shared, and must not race with
+ * any other call on the same instance.
  */
 class HgRcConfigSet {
  public:
   HgRcConfigSet();
 
+  // Return another reference to the same ConfigSet.  The ConfigSet cannot
+  // be modified while more than one reference to it exists.
+  HgRcConfigSet cloneRef() const noexcept;
+
   // Attempt to load configuration from path.
-  // Throws HgRcConfigError if there were error(s)
+  // Throws HgRcConfigError if there were error(s); its code() says what
+  // kind of error it was
   void loadPath(const char* path);
 
+  // Attempt to parse data as the contents of a configuration file,
+  // recording sourceName as the source of the values.
+  // Throws HgRcConfigError if there were error(s)
+  void loadBytes(folly::ByteRange data, folly::StringPiece sourceName);
+
   // Attempt to load the system configuration files
   // Throws HgRcConfigError if there were error(s)
   void loadSystem();
@@ -64,6 +128,23 @@ class HgRcConfigSet {
   // Throws HgRcConfigError if there were error(s)
   void loadUser();
 
+  // Set the configuration value for the specified section/name.
+  // A later load may override it, as with any other config source.
+  // Throws HgRcConfigError if there were error(s)
+  void set(
+      folly::ByteRange section,
+      folly::ByteRange name,
+      folly::ByteRange value);
+
+  void set(
+      folly::StringPiece section,
+      folly::StringPiece name,
+      folly::StringPiece value) {
+    set(folly::ByteRange{section},
+        folly::ByteRange{name},
+        folly::ByteRange{value});
+  }
+
   // Return the configuration value for the specified section/name
   folly::Optional<HgRcBytes> get(
       folly::ByteRange section,
@@ -76,11 +157,62 @@ class HgRcConfigSet {
     return get(folly::ByteRange{section}, folly::ByteRange{name});
   }
 
+  /** Status codes of the typed getters.  These match the HGRC_GET_*
+   * constants in c_api.rs and must not be renumbered. */
+  enum class GetStatus : int32_t {
+    Ok = 0,
+    NotSet = 1,
+    Invalid = 2,
+  };
+
+  // Return the configuration value for the specified section/name as a
+  // boolean, parsed with the same rules as the Rust ConfigSet, or none if
+  // it is not set.
+  // Throws HgRcConfigError if the value is not a valid boolean
+  folly::Optional<bool> getBool(
+      folly::ByteRange section,
+      folly::ByteRange name) const;
+
+  folly::Optional<bool> getBool(
+      folly::StringPiece section,
+      folly::StringPiece name) const {
+    return getBool(folly::ByteRange{section}, folly::ByteRange{name});
+  }
+
+  // Like getBool(), but for a signed 64-bit integer.
+  // Throws HgRcConfigError if the value is not a valid integer
+  folly::Optional<int64_t> getInt(
+      folly::ByteRange section,
+      folly::ByteRange name) const;
+
+  folly::Optional<int64_t> getInt(
+      folly::StringPiece section,
+      folly::StringPiece name) const {
+    return getInt(folly::ByteRange{section}, folly::ByteRange{name});
+  }
+
+  // Render the merged configuration in hgrc syntax.  If includeSources is
+  // true, each value is preceded by a comment saying where it came from.
+  HgRcBytes toString(bool includeSources = false) const noexcept;
+
+  // Return the names of all sections, in definition order
+  HgRcBytesList sections() const noexcept;
+
+  // Return the names of all items in the specified section, in definition
+  // order
+  HgRcBytesList keys(folly::ByteRange section) const noexcept;
+
+  HgRcBytesList keys(folly::StringPiece section) const noexcept {
+    return keys(folly::ByteRange{section});
+  }
+
  private:
   struct Deleter {
     void operator()(HgRcConfigSetStruct*) const;
   };
   std::unique_ptr<HgRcConfigSetStruct, Deleter> ptr_;
+
+  explicit HgRcConfigSet(HgRcConfigSetStruct* ptr);
 };
 
 } // namespace eden
@@ -6,35 +6,73 @@
  */
 
 //! This module exports some symbols to allow calling the config parser from C/C++
+//!
+//! A ConfigSet handle is reference counted.  Read operations (get, sections,
+//! keys, to_string) may be called concurrently from any number of threads,
+//! through the same handle or through handles shared with
+//! hgrc_configset_clone_ref().  Mutating operations (load_*, set) require
+//! exclusive access: they fail if the handle has been shared, and must not
+//! race with any other call on the same handle.
 use std::ffi::CStr;
+use std::io::ErrorKind;
+use std::mem::ManuallyDrop;
 use std::os::raw::c_char;
 use std::path::Path;
 use std::ptr;
 use std::slice;
+use std::sync::Arc;
 
 use minibytes::Text;
 
 use crate::config::ConfigSet;
 use crate::config::Options;
+use crate::convert::FromConfigValue;
 use crate::error::Error;
 use crate::hg::ConfigSetHgExt;
 use crate::hg::OptionsHgExt;
 
-/// Create and return a new, empty ConfigSet
+/// Create and return a new, empty ConfigSet with a reference count of one
 #[no_mangle]
 pub extern "C" fn hgrc_configset_new() -> *mut ConfigSet {
-    Box::into_raw(Box::new(ConfigSet::new()))
+    Arc::into_raw(Arc::new(ConfigSet::new())) as *mut ConfigSet
 }
 
-/// Free a ConfigSet instance created via hgrc_configset_new().
-/// Releases all associated resources.
+/// Add a reference to a ConfigSet and return it.  The returned handle is the
+/// same pointer; each reference must be released with hgrc_configset_free().
+/// A shared ConfigSet can no longer be modified.
+#[no_mangle]
+pub extern "C" fn hgrc_configset_clone_ref(cfg: *const ConfigSet) -> *mut ConfigSet {
+    debug_assert!(!cfg.is_null());
+    unsafe { Arc::increment_strong_count(cfg) };
+    cfg as *mut ConfigSet
+}
+
+/// Release a reference to a ConfigSet created via hgrc_configset_new() or
+/// hgrc_configset_clone_ref().  Releases all associated resources once the
+/// last reference is released.
 #[no_mangle]
 pub extern "C" fn hgrc_configset_free(cfg: *mut ConfigSet) {
     debug_assert!(!cfg.is_null());
-    let cfg = unsafe { Box::from_raw(cfg) };
+    let cfg = unsafe { Arc::from_raw(cfg) };
     drop(cfg);
 }
 
+/// Run `f` on the ConfigSet behind `cfg`, if this is its only reference.
+fn with_config_mut(
+    cfg: *mut ConfigSet,
+    f: impl FnOnce(&mut ConfigSet) -> Vec<Error>,
+) -> Vec<Error> {
+    debug_assert!(!cfg.is_null());
+    // The reference is borrowed from the caller, so it must not be dropped.
+    let mut cfg = ManuallyDrop::new(unsafe { Arc::from_raw(cfg as *const ConfigSet) });
+    match Arc::get_mut(&mut cfg) {
+        Some(cfg) => f(cfg),
+        None => vec![Error::General(
+            "cannot modify a ConfigSet shared via hgrc_configset_clone_ref()".to_string(),
+        )],
+    }
+}
+
 fn errors_to_bytes(errors: Vec<Error>) -> *mut Text {
     if errors.is_empty() {
         // Success!
@@ -53,52 +91,139 @@ fn errors_to_bytes(errors: Vec<Error>) -> *mut Text {
     Box::into_raw(Box::new(error_text.into()))
 }
 
-fn load_path(cfg: &mut ConfigSet, path: &Path) -> *mut Text {
-    let errors = cfg.load_path(path, &Options::new().process_hgplain());
+/// Stable error codes reported by hgrc_configset_load_path_with_code().
+/// These values are part of the C API and must not be renumbered; they are
+/// mirrored by HgRcConfigError::Code in ConfigParser.h.
+pub const HGRC_OK: i32 = 0;
+/// The config file passed in could not be found.
+pub const HGRC_ERROR_NOT_FOUND: i32 = 1;
+/// A config file could not be read due to insufficient permissions.
+pub const HGRC_ERROR_PERMISSION_DENIED: i32 = 2;
+/// A config file could not be read for some other IO reason.
+pub const HGRC_ERROR_IO: i32 = 3;
+/// A config file has a syntax error.
+pub const HGRC_ERROR_PARSE: i32 = 4;
+/// A config file, or the path passed in, is not valid UTF-8.
+pub const HGRC_ERROR_INVALID_UTF8: i32 = 5;
+/// Any other error.
+pub const HGRC_ERROR_OTHER: i32 = 6;
+
+fn error_code(error: &Error) -> i32 {
+    match error {
+        Error::Io(_, e) => match e.kind() {
+            ErrorKind::NotFound => HGRC_ERROR_NOT_FOUND,
+            ErrorKind::PermissionDenied => HGRC_ERROR_PERMISSION_DENIED,
+            _ => HGRC_ERROR_IO,
+        },
+        Error::Parse(..) => HGRC_ERROR_PARSE,
+        Error::Utf8(..) | Error::Utf8Path(..) => HGRC_ERROR_INVALID_UTF8,
+        Error::Convert(_) | Error::ParseInt(_) | Error::ParseFloat(_) | Error::General(_) => {
+            HGRC_ERROR_OTHER
+        }
+    }
+}
 
+/// Like errors_to_bytes(), but also stores the code of the first error (or
+/// HGRC_OK if there were none) in `code`.
+fn errors_to_bytes_with_code(errors: Vec<Error>, code: *mut i32) -> *mut Text {
+    let first_code = errors.first().map_or(HGRC_OK, error_code);
+    unsafe { *code = first_code };
     errors_to_bytes(errors)
 }
 
-/// Attempt to load and parse the config file at the specified path.
-/// If successful, returns a nullptr.
-/// Returns a Text object containing the error reason on failure; the
-/// error object is UTF-8 encoded text, and errors can span multiple lines.
-#[no_mangle]
-pub extern "C" fn hgrc_configset_load_path(cfg: *mut ConfigSet, path: *const c_char) -> *mut Text {
+fn load_path(cfg: &mut ConfigSet, path: &Path) -> Vec<Error> {
+    cfg.load_path(path, &Options::new().process_hgplain())
+}
+
+fn load_path_from_c(cfg: *mut ConfigSet, path: *const c_char) -> Vec<Error> {
     debug_assert!(!path.is_null());
     debug_assert!(!cfg.is_null());
 
     let path_cstr = unsafe { CStr::from_ptr(path) };
     let path_str = match path_cstr.to_str() {
         Ok(path) => path,
-        Err(e) => return errors_to_bytes(vec![Error::Utf8Path(path_cstr.to_owned(), e)]),
+        Err(e) => return vec![Error::Utf8Path(path_cstr.to_owned(), e)],
     };
     let path = Path::new(path_str);
 
-    let cfg = unsafe { &mut *cfg };
+    // ConfigSet::load_path() silently skips files that do not exist, which
+    // is right for %include, but not for the file the caller asked for.
+    if let Err(e) = path.metadata() {
+        return vec![Error::Io(path.to_path_buf(), e)];
+    }
 
-    load_path(cfg, path)
+    with_config_mut(cfg, |cfg| load_path(cfg, path))
 }
 
-/// Load system config files
+/// Attempt to load and parse the config file at the specified path.
+/// It is an error for that file not to exist; missing %include files are
+/// skipped.
+/// If successful, returns a nullptr.
+/// Returns a Text object containing the error reason on failure; the
+/// error object is UTF-8 encoded text, and errors can span multiple lines.
+/// Fails without loading anything if the ConfigSet is shared.
 #[no_mangle]
-pub extern "C" fn hgrc_configset_load_system(cfg: *mut ConfigSet) -> *mut Text {
+pub extern "C" fn hgrc_configset_load_path(cfg: *mut ConfigSet, path: *const c_char) -> *mut Text {
+    errors_to_bytes(load_path_from_c(cfg, path))
+}
+
+/// Like hgrc_configset_load_path(), but also stores one of the HGRC_*
+/// error codes in `code`, so callers can tell the kind of failure apart
+/// without inspecting the text.  If there are several errors, the code is
+/// that of the first one; the returned text still describes all of them.
+#[no_mangle]
+pub extern "C" fn hgrc_configset_load_path_with_code(
+    cfg: *mut ConfigSet,
+    path: *const c_char,
+    code: *mut i32,
+) -> *mut Text {
+    debug_assert!(!code.is_null());
+    errors_to_bytes_with_code(load_path_from_c(cfg, path), code)
+}
+
+/// Attempt to parse the given buffer as the contents of a config file.
+/// source_name is recorded as the source of the loaded values.
+/// Returns errors in the same way as hgrc_configset_load_path().
+#[no_mangle]
+pub extern "C" fn hgrc_configset_load_bytes(
+    cfg: *mut ConfigSet,
+    data: *const u8,
+    len: usize,
+    source_name: *const u8,
+    source_name_len: usize,
+) -> *mut Text {
+    debug_assert!(!data.is_null());
+    debug_assert!(!source_name.is_null());
     debug_assert!(!cfg.is_null());
-    let cfg = unsafe { &mut *cfg };
 
+    let parsed =
+        str_from_raw_parts("source name", source_name, source_name_len).and_then(|source_name| {
+            let data = str_from_raw_parts("config data", data, len)?;
+            Ok((source_name, data))
+        });
+    let (source_name, data) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return errors_to_bytes(vec![e]),
+    };
+    let opts = Options::new().process_hgplain().source(source_name);
+    errors_to_bytes(with_config_mut(cfg, |cfg| {
+        cfg.parse(Text::copy_from_slice(data), &opts)
+    }))
+}
+
+/// Load system config files
+#[no_mangle]
+pub extern "C" fn hgrc_configset_load_system(cfg: *mut ConfigSet) -> *mut Text {
     // Forces datapath to be the empty string as it doesn't
     // appear to play a useful role in simply resolving config
     // settings for Eden.
-    errors_to_bytes(cfg.load_system(Options::new()))
+    errors_to_bytes(with_config_mut(cfg, |cfg| cfg.load_system(Options::new())))
 }
 
 /// Load user config files
 #[no_mangle]
 pub extern "C" fn hgrc_configset_load_user(cfg: *mut ConfigSet) -> *mut Text {
-    debug_assert!(!cfg.is_null());
-    let cfg = unsafe { &mut *cfg };
-
-    errors_to_bytes(cfg.load_user(Options::new()))
+    errors_to_bytes(with_config_mut(cfg, |cfg| cfg.load_user(Options::new())))
 }
 
 /// Returns a Text object holding the configuration value for the corresponding
@@ -126,6 +251,184 @@ pub extern "C" fn hgrc_configset_get(
     }
 }
 
+/// Stable status codes returned by hgrc_configset_get_bool() and
+/// hgrc_configset_get_int().  These values are part of the C API and must
+/// not be renumbered; they are mirrored by HgRcConfigSet::GetStatus in
+/// ConfigParser.h.
+pub const HGRC_GET_OK: i32 = 0;
+/// The config item is not set.
+pub const HGRC_GET_NOT_SET: i32 = 1;
+/// The config item is set, but its value cannot be converted to the
+/// requested type.
+pub const HGRC_GET_INVALID: i32 = 2;
+
+/// Look up a config item and convert it with the same rules as
+/// ConfigSet::get_opt().  `out` is only written on success.
+fn get_typed<T: FromConfigValue>(
+    cfg: *const ConfigSet,
+    section: *const u8,
+    section_len: usize,
+    name: *const u8,
+    name_len: usize,
+    out: *mut T,
+) -> i32 {
+    debug_assert!(!section.is_null());
+    debug_assert!(!name.is_null());
+    debug_assert!(!out.is_null());
+    debug_assert!(!cfg.is_null());
+
+    let section =
+        unsafe { std::str::from_utf8_unchecked(slice::from_raw_parts(section, section_len)) };
+    let name = unsafe { std::str::from_utf8_unchecked(slice::from_raw_parts(name, name_len)) };
+    let cfg = unsafe { &*cfg };
+
+    match cfg.get_opt::<T>(section, name) {
+        Ok(Some(value)) => {
+            unsafe { *out = value };
+            HGRC_GET_OK
+        }
+        Ok(None) => HGRC_GET_NOT_SET,
+        Err(_) => HGRC_GET_INVALID,
+    }
+}
+
+/// Look up the configuration value for the corresponding section name and
+/// key, and store it in `out` as a boolean, parsed the same way as on the
+/// Rust side ("1", "yes", "true", "on" and "always" are true, case
+/// insensitively).  Returns one of the HGRC_GET_* status codes; `out` is
+/// left untouched unless the status is HGRC_GET_OK.
+#[no_mangle]
+pub extern "C" fn hgrc_configset_get_bool(
+    cfg: *const ConfigSet,
+    section: *const u8,
+    section_len: usize,
+    name: *const u8,
+    name_len: usize,
+    out: *mut bool,
+) -> i32 {
+    get_typed(cfg, section, section_len, name, name_len, out)
+}
+
+/// Like hgrc_configset_get_bool(), but parses the value as a signed 64-bit
+/// integer.
+#[no_mangle]
+pub extern "C" fn hgrc_configset_get_int(
+    cfg: *const ConfigSet,
+    section: *const u8,
+    section_len: usize,
+    name: *const u8,
+    name_len: usize,
+    out: *mut i64,
+) -> i32 {
+    get_typed(cfg, section, section_len, name, name_len, out)
+}
+
+fn str_from_raw_parts<'a>(what: &str, ptr: *const u8, len: usize) -> Result<&'a str, Error> {
+    let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    std::str::from_utf8(bytes)
+        .map_err(|e| Error::General(format!("{} is not valid UTF-8: {}", what, e)))
+}
+
+/// Set the configuration value for the corresponding section name and key.
+/// The value is recorded with the source "c_api".
+/// If successful, returns a nullptr.  Returns a Text object containing the
+/// error reason on failure, including when the ConfigSet is shared.
+///
+/// Values set this way are not special: a later load_* call may override
+/// them, as with any other config source.
+#[no_mangle]
+pub extern "C" fn hgrc_configset_set(
+    cfg: *mut ConfigSet,
+    section: *const u8,
+    section_len: usize,
+    name: *const u8,
+    name_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> *mut Text {
+    debug_assert!(!section.is_null());
+    debug_assert!(!name.is_null());
+    debug_assert!(!value.is_null());
+    debug_assert!(!cfg.is_null());
+
+    let parsed = str_from_raw_parts("section", section, section_len).and_then(|section| {
+        let name = str_from_raw_parts("name", name, name_len)?;
+        let value = str_from_raw_parts("value", value, value_len)?;
+        Ok((section, name, value))
+    });
+    let (section, name, value) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return errors_to_bytes(vec![e]),
+    };
+    errors_to_bytes(with_config_mut(cfg, |cfg| {
+        cfg.set(section, name, Some(value), &Options::new().source("c_api"));
+        Vec::new()
+    }))
+}
+
+/// Render every set config item in hgrc syntax, optionally preceded by a
+/// comment saying where the value came from.
+fn render_config(cfg: &ConfigSet, include_sources: bool) -> String {
+    let mut out = String::new();
+    for section in cfg.sections() {
+        let mut header_written = false;
+        for name in cfg.keys(&section) {
+            let sources = cfg.get_sources(&section, &name);
+            let source = match sources.last() {
+                Some(source) => source,
+                None => continue,
+            };
+            let value = match source.value() {
+                Some(value) => value,
+                None => continue,
+            };
+            if !header_written {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                out.push_str(&format!("[{}]\n", section.as_ref()));
+                header_written = true;
+            }
+            if include_sources {
+                match (source.location(), source.file_content()) {
+                    (Some((path, range)), Some(content)) => {
+                        let line = content[..range.start].matches('\n').count() + 1;
+                        // Config parsed from memory has no path, only a source name.
+                        let origin = if path.as_os_str().is_empty() {
+                            source.source().to_string()
+                        } else {
+                            path.display().to_string()
+                        };
+                        out.push_str(&format!("# from {}:{}\n", origin, line));
+                    }
+                    _ => out.push_str(&format!("# from {}\n", source.source().as_ref())),
+                }
+            }
+            // Continuation lines of multi-line values must be indented.
+            out.push_str(&format!(
+                "{} = {}\n",
+                name.as_ref(),
+                value.replace('\n', "\n  ")
+            ));
+        }
+    }
+    out
+}
+
+/// Returns a Text object holding every config item in hgrc syntax.
+/// If include_sources is true, each item is preceded by a comment saying
+/// where its value came from.
+#[no_mangle]
+pub extern "C" fn hgrc_configset_to_string(
+    cfg: *const ConfigSet,
+    include_sources: bool,
+) -> *mut Text {
+    debug_assert!(!cfg.is_null());
+    let cfg = unsafe { &*cfg };
+
+    Box::into_raw(Box::new(render_config(cfg, include_sources).into()))
+}
+
 #[repr(C)]
 pub struct ByteData {
     ptr: *const u8,
@@ -151,3 +454,249 @@ pub extern "C" fn hgrc_bytes_free(bytes: *mut Text) {
     let bytes = unsafe { Box::from_raw(bytes) };
     drop(bytes);
 }
+
+/// A list of Text objects returned by hgrc_configset_sections() and
+/// hgrc_configset_keys().
+pub struct TextList {
+    texts: Vec<Text>,
+    data: Vec<ByteData>,
+}
+
+impl TextList {
+    fn new(texts: Vec<Text>) -> Self {
+        let data = texts
+            .iter()
+            .map(|text| ByteData {
+                ptr: text.as_ptr(),
+                len: text.len(),
+            })
+            .collect();
+        Self { texts, data }
+    }
+}
+
+#[repr(C)]
+pub struct ByteDataArray {
+    ptr: *const ByteData,
+    len: usize,
+}
+
+/// Returns the names of all config sections, in the order they were first
+/// defined. The list must be released with hgrc_bytes_list_free().
+#[no_mangle]
+pub extern "C" fn hgrc_configset_sections(cfg: *const ConfigSet) -> *mut TextList {
+    debug_assert!(!cfg.is_null());
+    let cfg = unsafe { &*cfg };
+
+    Box::into_raw(Box::new(TextList::new(cfg.sections())))
+}
+
+/// Returns the names of all config items in the given section, in the order
+/// they were first defined. The list is empty if the section does not exist.
+/// The list must be released with hgrc_bytes_list_free().
+#[no_mangle]
+pub extern "C" fn hgrc_configset_keys(
+    cfg: *const ConfigSet,
+    section: *const u8,
+    section_len: usize,
+) -> *mut TextList {
+    debug_assert!(!section.is_null());
+    debug_assert!(!cfg.is_null());
+
+    let section =
+        unsafe { std::str::from_utf8_unchecked(slice::from_raw_parts(section, section_len)) };
+    let cfg = unsafe { &*cfg };
+
+    Box::into_raw(Box::new(TextList::new(cfg.keys(section))))
+}
+
+/// Returns an array holding the data pointer and length of each item in a
+/// list.  The array is only valid until the list is freed.
+#[no_mangle]
+pub extern "C" fn hgrc_bytes_list_data(list: *const TextList) -> ByteDataArray {
+    debug_assert!(!list.is_null());
+    let list = unsafe { &*list };
+    debug_assert_eq!(list.texts.len(), list.data.len());
+    ByteDataArray {
+        ptr: list.data.as_ptr(),
+        len: list.data.len(),
+    }
+}
+
+/// Frees a list returned by hgrc_configset_sections() or
+/// hgrc_configset_keys(), releasing any associated resources
+#[no_mangle]
+pub extern "C" fn hgrc_bytes_list_free(list: *mut TextList) {
+    debug_assert!(!list.is_null());
+    let list = unsafe { Box::from_raw(list) };
+    drop(list);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::fs;
+    use std::io;
+
+    use super::*;
+
+    fn load_path_code(cfg: *mut ConfigSet, path: &Path) -> (i32, Option<String>) {
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        let mut code = -1;
+        let text = hgrc_configset_load_path_with_code(cfg, path.as_ptr(), &mut code);
+        if text.is_null() {
+            return (code, None);
+        }
+        let text = unsafe { Box::from_raw(text) };
+        (code, Some(text.to_string()))
+    }
+
+    #[test]
+    fn test_load_path_with_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = hgrc_configset_new();
+
+        let good = dir.path().join("good.rc");
+        fs::write(&good, "[a]\nx = 1\n").unwrap();
+        assert_eq!(load_path_code(cfg, &good), (HGRC_OK, None));
+        assert_eq!(unsafe { &*cfg }.get("a", "x"), Some("1".into()));
+
+        let bad = dir.path().join("bad.rc");
+        fs::write(&bad, "[a\n").unwrap();
+        let (code, text) = load_path_code(cfg, &bad);
+        assert_eq!(code, HGRC_ERROR_PARSE);
+        assert!(text.unwrap().contains("bad.rc"));
+
+        let missing = dir.path().join("missing.rc");
+        let (code, text) = load_path_code(cfg, &missing);
+        assert_eq!(code, HGRC_ERROR_NOT_FOUND);
+        assert!(text.unwrap().contains("missing.rc"));
+
+        hgrc_configset_free(cfg);
+    }
+
+    /// Set a.x = 1 through the C API, returning whether it succeeded.
+    fn set_a_x(cfg: *mut ConfigSet) -> bool {
+        let text = hgrc_configset_set(cfg, b"a".as_ptr(), 1, b"x".as_ptr(), 1, b"1".as_ptr(), 1);
+        if text.is_null() {
+            return true;
+        }
+        hgrc_bytes_free(text);
+        false
+    }
+
+    #[test]
+    fn test_clone_ref() {
+        let cfg = hgrc_configset_new();
+        assert!(set_a_x(cfg));
+
+        // A shared ConfigSet cannot be modified.
+        let shared = hgrc_configset_clone_ref(cfg);
+        assert_eq!(shared, cfg);
+        assert!(!set_a_x(cfg));
+
+        // Handles can be sent across threads and read concurrently.
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let cfg = hgrc_configset_clone_ref(shared) as usize;
+                std::thread::spawn(move || {
+                    let cfg = cfg as *mut ConfigSet;
+                    let text = hgrc_configset_get(cfg, b"a".as_ptr(), 1, b"x".as_ptr(), 1);
+                    let value = unsafe { Box::from_raw(text) };
+                    hgrc_configset_free(cfg);
+                    value.to_string()
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), "1");
+        }
+
+        // Once the other references are released, it can be modified again.
+        hgrc_configset_free(shared);
+        assert!(set_a_x(cfg));
+        hgrc_configset_free(cfg);
+    }
+
+    #[test]
+    fn test_get_typed() {
+        let cfg = hgrc_configset_new();
+        unsafe { &mut *cfg }.parse("[a]\nb = On\ni = -42\nx = maybe\n", &"test".into());
+
+        let get_bool = |name: &str| {
+            let mut value = false;
+            let status = hgrc_configset_get_bool(
+                cfg,
+                b"a".as_ptr(),
+                1,
+                name.as_ptr(),
+                name.len(),
+                &mut value,
+            );
+            (status, value)
+        };
+        assert_eq!(get_bool("b"), (HGRC_GET_OK, true));
+        assert_eq!(get_bool("missing"), (HGRC_GET_NOT_SET, false));
+        assert_eq!(get_bool("x"), (HGRC_GET_INVALID, false));
+        assert_eq!(get_bool("i"), (HGRC_GET_INVALID, false));
+
+        let get_int = |name: &str| {
+            let mut value = 7;
+            let status = hgrc_configset_get_int(
+                cfg,
+                b"a".as_ptr(),
+                1,
+                name.as_ptr(),
+                name.len(),
+                &mut value,
+            );
+            (status, value)
+        };
+        assert_eq!(get_int("i"), (HGRC_GET_OK, -42));
+        assert_eq!(get_int("missing"), (HGRC_GET_NOT_SET, 7));
+        assert_eq!(get_int("b"), (HGRC_GET_INVALID, 7));
+
+        hgrc_configset_free(cfg);
+    }
+
+    #[test]
+    fn test_error_code() {
+        let io_error = |kind| Error::Io("x".into(), io::Error::new(kind, "x"));
+        assert_eq!(
+            error_code(&io_error(ErrorKind::NotFound)),
+            HGRC_ERROR_NOT_FOUND
+        );
+        assert_eq!(
+            error_code(&io_error(ErrorKind::PermissionDenied)),
+            HGRC_ERROR_PERMISSION_DENIED
+        );
+        assert_eq!(error_code(&io_error(ErrorKind::Other)), HGRC_ERROR_IO);
+        assert_eq!(
+            error_code(&Error::Parse("x".into(), "x".into())),
+            HGRC_ERROR_PARSE
+        );
+        assert_eq!(error_code(&Error::General("x".into())), HGRC_ERROR_OTHER);
+    }
+
+    #[test]
+    fn test_render_config() {
+        let mut cfg = ConfigSet::new();
+        cfg.parse(
+            "[a]\nx = 1\ny = 2\n  3\n[b]\nz = 4\n%unset z\n[c]\nw = 5\n",
+            &"test".into(),
+        );
+        cfg.set("a", "x", Some("6"), &"set".into());
+
+        let rendered = render_config(&cfg, false);
+        assert_eq!(rendered, "[a]\nx = 6\ny = 2\n  3\n\n[c]\nw = 5\n");
+
+        let mut reparsed = ConfigSet::new();
+        assert!(reparsed.parse(rendered, &"test".into()).is_empty());
+        assert_eq!(reparsed.get("a", "y"), Some("2\n3".into()));
+
+        assert_eq!(
+            render_config(&cfg, true),
+            "[a]\n# from set\nx = 6\n# from test:3\ny = 2\n  3\n\n[c]\n# from test:9\nw = 5\n"
+        );
+    }
+}
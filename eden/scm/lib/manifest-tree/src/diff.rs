@@ -717,6 +717,27 @@ mod tests {
         assert_eq!(entries, expected);
     }
 
+    #[test]
+    fn test_diff_store_error() {
+        let store = Arc::new(TestStore::new());
+        let mut ltree = make_tree_manifest(
+            store.clone(),
+            &[("d1/changed", "1"), ("d2/changed", "1"), ("same", "1")],
+        );
+        let mut rtree = make_tree_manifest(
+            store.clone(),
+            &[("d1/changed", "2"), ("d2/changed", "2"), ("same", "1")],
+        );
+        let ltree = TreeManifest::durable(store.clone(), ltree.flush().unwrap());
+        let rtree = TreeManifest::durable(store.clone(), rtree.flush().unwrap());
+
+        // Both roots load, but the diff fails partway through the subdirectories.
+        store.fail_after_gets(3);
+        let matcher = AlwaysMatcher::new();
+        let diff = Diff::new(&ltree, &rtree, &matcher).unwrap();
+        assert!(diff.collect::<Result<Vec<_>>>().is_err());
+    }
+
     #[test]
     fn test_diff_matcher() {
         let store = Arc::new(TestStore::new());
@@ -860,6 +881,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_diff_only_descends_into_changed_subtrees() {
+        let store = Arc::new(TestStore::new());
+        let mut tree = make_tree_manifest(
+            store.clone(),
+            &[("a/f", "1"), ("b/c/f", "1"), ("b/f", "1"), ("d/f", "1")],
+        );
+        let root = tree.flush().unwrap();
+
+        let left = TreeManifest::durable(store.clone(), root);
+        // Only the `b/c` path becomes ephemeral; `a`, `b/f` and `d` stay durable.
+        let mut right = TreeManifest::durable(store.clone(), root);
+        right
+            .insert(repo_path_buf("b/c/f"), make_meta("2"))
+            .unwrap();
+        right
+            .insert(repo_path_buf("b/c/g"), make_meta("1"))
+            .unwrap();
+        right.remove(repo_path("d/f")).unwrap();
+
+        let matcher = AlwaysMatcher::new();
+        let entries = Diff::new(&left, &right, &matcher)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                DiffEntry::new(repo_path_buf("d/f"), DiffType::LeftOnly(make_meta("1"))),
+                DiffEntry::new(
+                    repo_path_buf("b/c/f"),
+                    DiffType::Changed(make_meta("1"), make_meta("2"))
+                ),
+                DiffEntry::new(repo_path_buf("b/c/g"), DiffType::RightOnly(make_meta("1"))),
+            ]
+        );
+
+        // The unchanged `a` subtree is never fetched.
+        let fetched = store
+            .fetches()
+            .into_iter()
+            .flatten()
+            .map(|key| key.path)
+            .collect::<Vec<_>>();
+        assert!(!fetched.contains(&repo_path_buf("a")));
+    }
+
     #[test]
     fn test_diff_one_file_one_directory() {
         let mut left = TreeManifest::ephemeral(Arc::new(TestStore::new()));
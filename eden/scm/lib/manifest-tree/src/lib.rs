@@ -18,6 +18,7 @@ use std::collections::BTreeMap;
 use std::fmt;
 use std::sync::Arc;
 
+use anyhow::bail;
 use anyhow::Result;
 use bytes::Bytes;
 use manifest::DiffEntry;
@@ -25,6 +26,7 @@ use manifest::DirDiffEntry;
 use manifest::Directory;
 use manifest::File;
 use manifest::FileMetadata;
+use manifest::FsNodeKind;
 use manifest::FsNodeMetadata;
 use manifest::List;
 use manifest::Manifest;
@@ -83,6 +85,14 @@ impl InsertError {
     }
 }
 
+#[derive(Error, Debug)]
+#[error("'{0}' is a file, not a directory")]
+pub struct NotADirectoryError(pub RepoPathBuf);
+
+#[derive(Error, Debug)]
+#[error("'{0}' is a directory, not a file")]
+pub struct IsADirectoryError(pub RepoPathBuf);
+
 #[derive(thiserror::Error, Debug)]
 pub enum InsertErrorCause {
     #[error("'{0}' is already a file")]
@@ -202,6 +212,8 @@ impl Manifest for TreeManifest {
         Ok(())
     }
 
+    /// Removes the file at `path`, pruning any directories left empty, and returns its metadata.
+    /// Returns `None` if `path` does not exist and an `IsADirectoryError` if it is a directory.
     fn remove(&mut self, path: &RepoPath) -> Result<Option<FileMetadata>> {
         // The return value lets us know if there are no more files in the subtree and we should be
         // removing it.
@@ -233,15 +245,17 @@ impl Manifest for TreeManifest {
                 }
             }
         }
-        if let Some(file_metadata) = self.get_file(path)? {
-            do_remove(
-                &self.store,
-                &mut self.root,
-                &mut path.parents().zip(path.components()),
-            )?;
-            Ok(Some(file_metadata))
-        } else {
-            Ok(None)
+        match self.get(path)? {
+            None => Ok(None),
+            Some(FsNodeMetadata::Directory(_)) => Err(IsADirectoryError(path.to_owned()).into()),
+            Some(FsNodeMetadata::File(file_metadata)) => {
+                do_remove(
+                    &self.store,
+                    &mut self.root,
+                    &mut path.parents().zip(path.components()),
+                )?;
+                Ok(Some(file_metadata))
+            }
         }
     }
 
@@ -405,6 +419,9 @@ impl fmt::Debug for TreeManifest {
     }
 }
 
+/// A tree produced by `finalize`, in hg format: (path, id, text, p1, p2).
+type FinalizedTree = (RepoPathBuf, HgId, Bytes, HgId, HgId);
+
 impl TreeManifest {
     /// Produces new trees to write in hg format (path, id, text, p1, p2).
     /// Does not write to the tree store directly.
@@ -412,6 +429,32 @@ impl TreeManifest {
         &mut self,
         parent_trees: Vec<&TreeManifest>,
     ) -> Result<impl Iterator<Item = (RepoPathBuf, HgId, Bytes, HgId, HgId)>> {
+        Ok(self.finalize_impl(parent_trees, false)?.into_iter())
+    }
+
+    /// Converts ephemeral trees to durable trees in hg format and writes them to the tree store.
+    /// Returns the path and id of every written tree, with the root last. Trees that are already
+    /// durable are not rewritten, so the result is empty when there are no changes.
+    ///
+    /// A tree only becomes durable once it is written, so if a write fails, the trees that were
+    /// not written stay ephemeral.
+    pub fn finalize_to_store(
+        &mut self,
+        parent_trees: Vec<&TreeManifest>,
+    ) -> Result<impl Iterator<Item = (RepoPathBuf, HgId)>> {
+        Ok(self
+            .finalize_impl(parent_trees, true)?
+            .into_iter()
+            .map(|(path, hgid, ..)| (path, hgid)))
+    }
+
+    /// With `keep_durable`, trees that are already durable are not converted again, and
+    /// converted trees are written to the store.
+    fn finalize_impl(
+        &mut self,
+        parent_trees: Vec<&TreeManifest>,
+        keep_durable: bool,
+    ) -> Result<Vec<FinalizedTree>> {
         fn compute_hgid<C: AsRef<[u8]>>(parent_tree_nodes: &[HgId], content: C) -> HgId {
             let mut hasher = Sha1::new();
             debug_assert!(parent_tree_nodes.len() <= 2);
@@ -433,19 +476,22 @@ impl TreeManifest {
         struct Executor<'a> {
             store: &'a InnerStore,
             path: RepoPathBuf,
-            converted_nodes: Vec<(RepoPathBuf, HgId, Bytes, HgId, HgId)>,
+            converted_nodes: Vec<FinalizedTree>,
             parent_trees: Vec<DfsCursor<'a>>,
+            keep_durable: bool,
         }
         impl<'a> Executor<'a> {
             fn new(
                 store: &'a InnerStore,
                 parent_trees: &[&'a TreeManifest],
+                keep_durable: bool,
             ) -> Result<Executor<'a>> {
                 let mut executor = Executor {
                     store,
                     path: RepoPathBuf::new(),
                     converted_nodes: Vec::new(),
                     parent_trees: parent_trees.iter().map(|v| v.root_cursor()).collect(),
+                    keep_durable,
                 };
                 // The first node after step is the root directory. `work()` expects cursors to
                 // be pointing to the underlying link.
@@ -512,7 +558,7 @@ impl TreeManifest {
             ) -> Result<(HgId, store::Flag)> {
                 let parent_tree_nodes = self.active_parent_tree_nodes(&active_parents)?;
                 if let Durable(entry) = link.as_ref() {
-                    if parent_tree_nodes.contains(&entry.hgid) {
+                    if self.keep_durable || parent_tree_nodes.contains(&entry.hgid) {
                         return Ok((entry.hgid, store::Flag::Directory));
                     }
                 }
@@ -540,6 +586,15 @@ impl TreeManifest {
                 }
                 let entry = entry.freeze();
                 let hgid = compute_hgid(&parent_tree_nodes, &entry);
+                let data = entry.to_bytes();
+                if self.keep_durable {
+                    // Write the tree before the link claims that it is durable.
+                    self.store.insert_entry(
+                        &self.path,
+                        hgid,
+                        store::Entry(data.clone(), format),
+                    )?;
+                }
 
                 let cell = OnceCell::new();
                 // TODO: remove clone
@@ -552,7 +607,7 @@ impl TreeManifest {
                 self.converted_nodes.push((
                     self.path.clone(),
                     hgid,
-                    entry.to_bytes(),
+                    data,
                     parent_hgid(0),
                     parent_hgid(1),
                 ));
@@ -565,9 +620,210 @@ impl TreeManifest {
             TreeFormat::Hg,
             "finalize() can only be used for hg store, use flush() instead"
         );
-        let mut executor = Executor::new(&self.store, &parent_trees)?;
+        let mut executor = Executor::new(&self.store, &parent_trees, keep_durable)?;
         executor.work(&mut self.root, (0..parent_trees.len()).collect())?;
-        Ok(executor.converted_nodes.into_iter())
+        Ok(executor.converted_nodes)
+    }
+
+    /// Inserts many files at once. The result is the same as calling `insert` for each file, but
+    /// the files are sorted first so that each directory is only descended into once.
+    ///
+    /// If the same path is given more than once, the last metadata wins. On error the manifest
+    /// may be partially updated.
+    pub fn insert_many(
+        &mut self,
+        files: impl IntoIterator<Item = (RepoPathBuf, FileMetadata)>,
+    ) -> Result<()> {
+        fn do_insert(
+            store: &InnerStore,
+            cursor: &mut Link,
+            path: &mut RepoPathBuf,
+            depth: usize,
+            files: &[(RepoPathBuf, FileMetadata)],
+        ) -> Result<()> {
+            let links = cursor.mut_ephemeral_links(store, path)?;
+            let mut remaining = files;
+            while let Some((first_path, _)) = remaining.first() {
+                // All files share the first `depth` components, and sorting by components keeps
+                // files under the same child contiguous, with the child itself first.
+                let component = first_path.components().nth(depth).unwrap();
+                let len = remaining
+                    .iter()
+                    .take_while(|(p, _)| p.components().nth(depth) == Some(component))
+                    .count();
+                let (group, rest) = remaining.split_at(len);
+                remaining = rest;
+
+                path.push(component);
+                let mut group = group;
+                if let Some(((file_path, file_metadata), under_file)) = group
+                    .split_first()
+                    .filter(|((p, _), _)| p.components().count() == depth + 1)
+                {
+                    match links.entry(component.to_owned()) {
+                        Entry::Vacant(entry) => {
+                            entry.insert(Link::leaf(*file_metadata));
+                        }
+                        Entry::Occupied(mut entry) => match entry.get_mut().as_mut_ref()? {
+                            Leaf(ref mut store_ref) => *store_ref = *file_metadata,
+                            Ephemeral(_) | Durable(_) => Err(InsertError::new(
+                                file_path.clone(),
+                                *file_metadata,
+                                InsertErrorCause::DirectoryExistsForPath,
+                            ))?,
+                        },
+                    }
+                    if let Some((under_path, under_metadata)) = under_file.first() {
+                        Err(InsertError::new(
+                            under_path.clone(),
+                            *under_metadata,
+                            InsertErrorCause::ParentFileExists(path.clone()),
+                        ))?;
+                    }
+                    group = &[];
+                }
+                if let Some((first_path, first_metadata)) = group.first() {
+                    let link = links
+                        .entry(component.to_owned())
+                        .or_insert_with(Link::ephemeral);
+                    if let Leaf(_) = link.as_ref() {
+                        Err(InsertError::new(
+                            first_path.clone(),
+                            *first_metadata,
+                            InsertErrorCause::ParentFileExists(path.clone()),
+                        ))?;
+                    }
+                    do_insert(store, link, path, depth + 1, group)?;
+                }
+                path.pop();
+            }
+            Ok(())
+        }
+
+        let mut files: Vec<_> = files.into_iter().collect();
+        // Stable sort, so that the last of several entries for the same path can be kept.
+        files.sort_by(|(a, _), (b, _)| a.components().cmp(b.components()));
+        let mut deduped: Vec<(RepoPathBuf, FileMetadata)> = Vec::with_capacity(files.len());
+        for (path, metadata) in files {
+            match deduped.last_mut() {
+                Some(last) if last.0 == path => last.1 = metadata,
+                _ => deduped.push((path, metadata)),
+            }
+        }
+        if deduped.is_empty() {
+            return Ok(());
+        }
+
+        let mut path = RepoPathBuf::new();
+        do_insert(&self.store, &mut self.root, &mut path, 0, &deduped)
+    }
+
+    /// Lists the immediate children of the directory at `path`, without descending further.
+    /// Returns `None` if `path` does not exist and a `NotADirectoryError` if it is a file.
+    pub fn list_dir(
+        &self,
+        path: &RepoPath,
+    ) -> Result<Option<Vec<(PathComponentBuf, FsNodeKind)>>> {
+        match self.list(path)? {
+            List::NotFound => Ok(None),
+            List::File => Err(NotADirectoryError(path.to_owned()).into()),
+            List::Directory(entries) => Ok(Some(
+                entries
+                    .into_iter()
+                    .map(|(component, metadata)| (component, metadata.kind()))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Returns whether `path` is a file or a directory, or `None` if it does not exist.
+    ///
+    /// Only the directories leading to `path` are loaded from the store. If `path` is a durable
+    /// directory, its own entries are not loaded.
+    pub fn get_kind(&self, path: &RepoPath) -> Result<Option<FsNodeKind>> {
+        Ok(self.get_link(path)?.map(|link| link.kind()))
+    }
+
+    /// Copies the file or directory at `other_path` in `other` to `path` in this manifest,
+    /// creating the directories leading to `path` as needed. If `path` is already a directory,
+    /// the copied directory is overlaid on it: files that are in both are replaced by the ones
+    /// from `other`, and the others are kept.
+    ///
+    /// Trees are stored by path, so durable directories of `other` can only be shared when
+    /// `path` is `other_path`. They are then loaded from this manifest's store when needed, so
+    /// both manifests should use the same store. Otherwise they are loaded from `other`'s store
+    /// and copied.
+    ///
+    /// Copying a file behaves like `insert`. Copying a directory fails with a
+    /// `NotADirectoryError` if `path`, or a directory leading to it, is a file. A conflict
+    /// between a file and a directory further down is only found while copying, and leaves the
+    /// manifest partially updated.
+    pub fn graft(
+        &mut self,
+        path: &RepoPath,
+        other: &TreeManifest,
+        other_path: &RepoPath,
+    ) -> Result<()> {
+        let source = match other.get_link(other_path)? {
+            Some(link) => link,
+            None => bail!(
+                "'{}' does not exist in the manifest to graft from",
+                other_path
+            ),
+        };
+        if let Leaf(file_metadata) = source.as_ref() {
+            if path.is_empty() {
+                return Err(IsADirectoryError(path.to_owned()).into());
+            }
+            return self.insert(path.to_owned(), *file_metadata);
+        }
+
+        // Check for files in the way before changing anything.
+        let mut cursor = &self.root;
+        for (parent, component) in path.parents().zip(path.components()) {
+            let links = match cursor.as_ref() {
+                Leaf(_) => return Err(NotADirectoryError(parent.to_owned()).into()),
+                Ephemeral(links) => links,
+                Durable(entry) => entry.materialize_links(&self.store, parent)?,
+            };
+            match links.get(component) {
+                None => break,
+                Some(link) => cursor = link,
+            }
+        }
+        if let Leaf(_) = cursor.as_ref() {
+            return Err(NotADirectoryError(path.to_owned()).into());
+        }
+
+        let mut cursor = &mut self.root;
+        for (parent, component) in path.parents().zip(path.components()) {
+            cursor = cursor
+                .mut_ephemeral_links(&self.store, parent)?
+                .entry(component.to_owned())
+                .or_insert_with(Link::ephemeral);
+        }
+        overlay(&self.store, cursor, path, &other.store, source, other_path)
+    }
+
+    /// Returns an iterator over all the files in the tree, depth first, with the entries of each
+    /// directory in sorted order. Durable subtrees are loaded from the store as they are reached;
+    /// a failure to load one is yielded as an `Err` item and ends the iteration.
+    ///
+    /// Unlike `Manifest::files`, whose order is unspecified, the order is deterministic, which
+    /// makes the output suitable for serializing or comparing manifests.
+    pub fn ordered_files(&self) -> impl Iterator<Item = Result<File>> + '_ {
+        let mut cursor = self.root_cursor();
+        std::iter::from_fn(move || loop {
+            match cursor.step() {
+                Step::Success => {
+                    if let Leaf(file_metadata) = cursor.link().as_ref() {
+                        return Some(Ok(File::new(cursor.path().to_owned(), *file_metadata)));
+                    }
+                }
+                Step::End => return None,
+                Step::Err(err) => return Some(Err(err)),
+            }
+        })
     }
 
     fn get_link(&self, path: &RepoPath) -> Result<Option<&Link>> {
@@ -590,6 +846,107 @@ impl TreeManifest {
     }
 }
 
+/// Overlays the directory `source` at `source_path`, loaded from `source_store`, on the
+/// directory `target` at `target_path`. See `TreeManifest::graft`.
+fn overlay(
+    store: &InnerStore,
+    target: &mut Link,
+    target_path: &RepoPath,
+    source_store: &InnerStore,
+    source: &Link,
+    source_path: &RepoPath,
+) -> Result<()> {
+    match (target.as_ref(), source.as_ref()) {
+        // Nothing to keep from `target`, so `source` can be copied as a whole.
+        (Ephemeral(links), _) if links.is_empty() => {
+            *target = copy_link(source_store, source, source_path, target_path)?;
+            return Ok(());
+        }
+        (Durable(target_entry), Durable(source_entry))
+            if target_entry.hgid == source_entry.hgid =>
+        {
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let source_links = match source.as_ref() {
+        Leaf(_) => unreachable!("graft only overlays directories"),
+        Ephemeral(links) => links,
+        Durable(entry) => entry.materialize_links(source_store, source_path)?,
+    };
+    let target_links = target.mut_ephemeral_links(store, target_path)?;
+    for (component, source_link) in source_links {
+        let mut child_path = target_path.to_owned();
+        child_path.push(component.as_ref());
+        let mut source_child_path = source_path.to_owned();
+        source_child_path.push(component.as_ref());
+        match target_links.entry(component.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(copy_link(
+                    source_store,
+                    source_link,
+                    &source_child_path,
+                    &child_path,
+                )?);
+            }
+            Entry::Occupied(mut entry) => match (entry.get().as_ref(), source_link.as_ref()) {
+                (Leaf(_), Leaf(_)) => {
+                    entry.insert(source_link.clone());
+                }
+                (Leaf(_), _) => return Err(NotADirectoryError(child_path).into()),
+                (_, Leaf(_)) => return Err(IsADirectoryError(child_path).into()),
+                _ => {
+                    overlay(
+                        store,
+                        entry.get_mut(),
+                        &child_path,
+                        source_store,
+                        source_link,
+                        &source_child_path,
+                    )?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Copies `source` at `source_path`, loaded from `source_store`, to be placed at `target_path`.
+/// Durable directories are shared if the paths are the same. Otherwise they are loaded and
+/// copied as ephemeral ones, since trees are stored by path and would not be found at the new
+/// one.
+fn copy_link(
+    source_store: &InnerStore,
+    source: &Link,
+    source_path: &RepoPath,
+    target_path: &RepoPath,
+) -> Result<Link> {
+    let source_links = match source.as_ref() {
+        Leaf(_) => return Ok(source.clone()),
+        _ if source_path == target_path => return Ok(source.clone()),
+        Ephemeral(links) => links,
+        Durable(entry) => entry.materialize_links(source_store, source_path)?,
+    };
+    let mut links = BTreeMap::new();
+    for (component, source_link) in source_links {
+        let mut source_child_path = source_path.to_owned();
+        source_child_path.push(component.as_ref());
+        let mut target_child_path = target_path.to_owned();
+        target_child_path.push(component.as_ref());
+        links.insert(
+            component.clone(),
+            copy_link(
+                source_store,
+                source_link,
+                &source_child_path,
+                &target_child_path,
+            )?,
+        );
+    }
+    Ok(Link::new(Ephemeral(links)))
+}
+
 /// The purpose of this function is to provide compatible behavior with the C++ implementation
 /// of the treemanifest. This function is problematic because it goes through abstraction
 /// boundaries and is built with the assumption that the storage format is the same as the
@@ -729,6 +1086,7 @@ pub fn prefetch(
 mod tests {
     use manifest::testutil::*;
     use manifest::FileType;
+    use pathmatcher::AlwaysMatcher;
     use store::Element;
     use types::hgid::NULL_ID;
     use types::testutil::*;
@@ -746,10 +1104,10 @@ mod tests {
     }
 
     fn get_hgid(tree: &TreeManifest, path: &RepoPath) -> HgId {
-        match tree.get_link(path).unwrap().unwrap().as_ref() {
-            Leaf(file_metadata) => file_metadata.hgid,
-            Durable(ref entry) => entry.hgid,
-            Ephemeral(_) => {
+        match tree.get(path).unwrap().unwrap() {
+            FsNodeMetadata::File(file_metadata) => file_metadata.hgid,
+            FsNodeMetadata::Directory(Some(hgid)) => hgid,
+            FsNodeMetadata::Directory(None) => {
                 panic!("Asked for hgid on path {} but found ephemeral hgid.", path)
             }
         }
@@ -909,6 +1267,168 @@ mod tests {
         assert_eq!(tree.get(repo_path("foo/bar/baz")).unwrap(), None);
     }
 
+    #[test]
+    fn test_get_file() {
+        let store = Arc::new(TestStore::new());
+        let mut tree = make_tree_manifest(store.clone(), &[("a1/b1", "10"), ("a2", "20")]);
+
+        assert_eq!(
+            tree.get_file(repo_path("a1/b1")).unwrap(),
+            Some(make_meta("10"))
+        );
+        assert_eq!(
+            tree.get_file(repo_path("a2")).unwrap(),
+            Some(make_meta("20"))
+        );
+        // Directories and missing paths have no file metadata.
+        assert_eq!(tree.get_file(repo_path("a1")).unwrap(), None);
+        assert_eq!(tree.get_file(RepoPath::empty()).unwrap(), None);
+        assert_eq!(tree.get_file(repo_path("a3")).unwrap(), None);
+        assert_eq!(tree.get_file(repo_path("a2/b2")).unwrap(), None);
+
+        let hgid = tree.flush().unwrap();
+        let tree = TreeManifest::durable(store, hgid);
+        assert_eq!(
+            tree.get_file(repo_path("a1/b1")).unwrap(),
+            Some(make_meta("10"))
+        );
+        assert_eq!(tree.get_file(repo_path("a1")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_kind() {
+        let store = Arc::new(TestStore::new());
+        let mut tree = make_tree_manifest(store.clone(), &[("a1/b1/c1", "10"), ("a2", "20")]);
+
+        assert_eq!(
+            tree.get_kind(repo_path("a1/b1/c1")).unwrap(),
+            Some(FsNodeKind::File)
+        );
+        assert_eq!(
+            tree.get_kind(repo_path("a1/b1")).unwrap(),
+            Some(FsNodeKind::Directory)
+        );
+        assert_eq!(
+            tree.get_kind(RepoPath::empty()).unwrap(),
+            Some(FsNodeKind::Directory)
+        );
+        assert_eq!(tree.get_kind(repo_path("a3")).unwrap(), None);
+        assert_eq!(tree.get_kind(repo_path("a2/b2")).unwrap(), None);
+
+        let hgid = tree.flush().unwrap();
+        let b1_hgid = get_hgid(&tree, repo_path("a1/b1"));
+        let tree = TreeManifest::durable(store.clone(), hgid);
+        // The entries of the directory itself are not needed.
+        store.fail_on_key(Key::new(repo_path_buf("a1/b1"), b1_hgid));
+        assert_eq!(
+            tree.get_kind(repo_path("a1/b1")).unwrap(),
+            Some(FsNodeKind::Directory)
+        );
+        assert_eq!(
+            tree.get_kind(repo_path("a2")).unwrap(),
+            Some(FsNodeKind::File)
+        );
+        assert!(tree.get_kind(repo_path("a1/b1/c1")).is_err());
+    }
+
+    #[test]
+    fn test_graft() {
+        let store = Arc::new(TestStore::new());
+        let mut other = make_tree_manifest(
+            store.clone(),
+            &[
+                ("x/y/z1", "10"),
+                ("x/y/z2", "20"),
+                ("x/w", "30"),
+                ("v", "40"),
+            ],
+        );
+        let other_hgid = other.flush().unwrap();
+        let y_hgid = get_hgid(&other, repo_path("x/y"));
+        let other = TreeManifest::durable(store.clone(), other_hgid);
+
+        let finalized_hgid = |tree: &mut TreeManifest| {
+            let changed: Vec<_> = tree.finalize(vec![]).unwrap().collect();
+            changed.last().unwrap().1
+        };
+
+        // At the same path, the durable subtree is shared.
+        let mut tree = make_tree_manifest(store.clone(), &[("a/b", "50")]);
+        tree.graft(repo_path("x/y"), &other, repo_path("x/y"))
+            .unwrap();
+        assert_eq!(get_hgid(&tree, repo_path("x/y")), y_hgid);
+        let mut expected = make_tree_manifest(
+            store.clone(),
+            &[("a/b", "50"), ("x/y/z1", "10"), ("x/y/z2", "20")],
+        );
+        assert_eq!(finalized_hgid(&mut tree), finalized_hgid(&mut expected));
+
+        // Into a new directory elsewhere.
+        let mut tree = make_tree_manifest(store.clone(), &[("a/b", "50")]);
+        tree.graft(repo_path("a/c/d"), &other, repo_path("x/y"))
+            .unwrap();
+        let mut expected = make_tree_manifest(
+            store.clone(),
+            &[("a/b", "50"), ("a/c/d/z1", "10"), ("a/c/d/z2", "20")],
+        );
+        assert_eq!(finalized_hgid(&mut tree), finalized_hgid(&mut expected));
+
+        // Over an existing directory, with the files of `other` winning.
+        let mut tree = make_tree_manifest(store.clone(), &[("a/z1", "60"), ("a/z3", "70")]);
+        tree.graft(repo_path("a"), &other, repo_path("x/y"))
+            .unwrap();
+        let mut expected = make_tree_manifest(
+            store.clone(),
+            &[("a/z1", "10"), ("a/z2", "20"), ("a/z3", "70")],
+        );
+        assert_eq!(finalized_hgid(&mut tree), finalized_hgid(&mut expected));
+
+        // The root of `other` onto the root.
+        let mut tree = make_tree_manifest(store.clone(), &[("u", "80")]);
+        tree.graft(RepoPath::empty(), &other, RepoPath::empty())
+            .unwrap();
+        let mut expected = make_tree_manifest(
+            store.clone(),
+            &[
+                ("u", "80"),
+                ("v", "40"),
+                ("x/w", "30"),
+                ("x/y/z1", "10"),
+                ("x/y/z2", "20"),
+            ],
+        );
+        assert_eq!(finalized_hgid(&mut tree), finalized_hgid(&mut expected));
+
+        // A single file.
+        let mut tree = make_tree_manifest(store.clone(), &[("a/b", "50")]);
+        tree.graft(repo_path("a/c"), &other, repo_path("x/y/z2"))
+            .unwrap();
+        let mut expected = make_tree_manifest(store.clone(), &[("a/b", "50"), ("a/c", "20")]);
+        assert_eq!(finalized_hgid(&mut tree), finalized_hgid(&mut expected));
+
+        // Files in the way.
+        let mut tree = make_tree_manifest(store.clone(), &[("a/b", "50")]);
+        for path in ["a/b", "a/b/c"] {
+            let err = tree
+                .graft(repo_path(path), &other, repo_path("x"))
+                .unwrap_err();
+            assert!(err.is::<NotADirectoryError>());
+        }
+        assert!(
+            tree.graft(repo_path("a"), &other, repo_path("v"))
+                .unwrap_err()
+                .is::<InsertError>()
+        );
+        assert!(
+            tree.graft(repo_path("a"), &other, repo_path("missing"))
+                .is_err()
+        );
+        assert_eq!(
+            tree.get_file(repo_path("a/b")).unwrap(),
+            Some(make_meta("50"))
+        );
+    }
+
     #[test]
     fn test_remove_from_ephemeral() {
         let mut tree = TreeManifest::ephemeral(Arc::new(TestStore::new()));
@@ -919,8 +1439,8 @@ mod tests {
         tree.insert(repo_path_buf("a2/b2/c2"), make_meta("30"))
             .unwrap();
 
-        assert_eq!(tree.remove(repo_path("a1")).unwrap(), None);
-        assert_eq!(tree.remove(repo_path("a1/b1")).unwrap(), None);
+        assert!(tree.remove(repo_path("a1")).is_err());
+        assert!(tree.remove(repo_path("a1/b1")).is_err());
         assert_eq!(tree.remove(repo_path("a1/b1/c1/d1/e1")).unwrap(), None);
         assert_eq!(
             tree.remove(repo_path("a1/b1/c1/d1")).unwrap(),
@@ -930,7 +1450,7 @@ mod tests {
         assert_eq!(tree.remove(repo_path("a1/b3")).unwrap(), None);
         assert_eq!(tree.remove(repo_path("a1/b1/c1/d2")).unwrap(), None);
         assert_eq!(tree.remove(repo_path("a1/b1/c1/d1/e1")).unwrap(), None);
-        assert_eq!(tree.remove(RepoPath::empty()).unwrap(), None);
+        assert!(tree.remove(RepoPath::empty()).is_err());
         assert_eq!(tree.get(repo_path("a1/b1/c1/d1")).unwrap(), None);
         assert_eq!(tree.get(repo_path("a1/b1/c1")).unwrap(), None);
         assert_eq!(
@@ -983,7 +1503,8 @@ mod tests {
             tree.get(RepoPath::empty()).unwrap(),
             Some(FsNodeMetadata::Directory(Some(tree_hgid)))
         );
-        assert_eq!(tree.remove(repo_path("a1")).unwrap(), None);
+        let err = tree.remove(repo_path("a1")).unwrap_err();
+        assert!(err.downcast_ref::<IsADirectoryError>().is_some());
         assert_eq!(
             tree.remove(repo_path("a1/b1")).unwrap(),
             Some(make_meta("11"))
@@ -1013,6 +1534,113 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ordered_files() {
+        let store = Arc::new(TestStore::new());
+        let mut tree = make_tree_manifest(
+            store.clone(),
+            &[("b", "10"), ("a/c", "20"), ("a.txt", "30"), ("a/b/d", "40")],
+        );
+        let files = |tree: &TreeManifest| {
+            tree.ordered_files()
+                .map(|file| {
+                    let file = file.unwrap();
+                    (file.path.to_string(), file.meta)
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            ("a/b/d".to_string(), make_meta("40")),
+            ("a/c".to_string(), make_meta("20")),
+            ("a.txt".to_string(), make_meta("30")),
+            ("b".to_string(), make_meta("10")),
+        ];
+        assert_eq!(files(&tree), expected);
+
+        let hgid = tree.flush().unwrap();
+        let tree = TreeManifest::durable(store, hgid);
+        assert_eq!(files(&tree), expected);
+    }
+
+    #[test]
+    fn test_ordered_files_store_error() {
+        let tree = TreeManifest::durable(Arc::new(TestStore::new()), hgid("1"));
+        let files = tree.ordered_files().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].is_err());
+    }
+
+    #[test]
+    fn test_files_and_finalize_store_error() {
+        let store = Arc::new(TestStore::new());
+        let mut tree = make_tree_manifest(
+            store.clone(),
+            &[("a1/b1", "10"), ("a1/b2", "20"), ("a2", "30")],
+        );
+        let hgid = tree.flush().unwrap();
+        let tree = TreeManifest::durable(store.clone(), hgid);
+        let a1 = get_hgid(&tree, repo_path("a1"));
+        store.fail_on_key(Key::new(repo_path_buf("a1"), a1));
+
+        let files = tree.files(AlwaysMatcher::new()).collect::<Vec<_>>();
+        assert_eq!(files.iter().filter(|file| file.is_ok()).count(), 1);
+        assert_eq!(files.iter().filter(|file| file.is_err()).count(), 1);
+
+        let mut child = make_tree_manifest(
+            store.clone(),
+            &[("a1/b1", "11"), ("a1/b2", "20"), ("a2", "30")],
+        );
+        assert!(child.finalize(vec![&tree]).is_err());
+
+        // Load failures are cached, so check with a fresh tree.
+        store.clear_faults();
+        let tree = TreeManifest::durable(store, hgid);
+        let files = tree
+            .files(AlwaysMatcher::new())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(files.len(), 3);
+    }
+
+    #[test]
+    fn test_store_fail_after_gets() {
+        let store = Arc::new(TestStore::new());
+        let mut tree = make_tree_manifest(store.clone(), &[("a1/b1/c1", "10"), ("a2", "20")]);
+        let hgid = tree.flush().unwrap();
+
+        // The root and a1 load, a1/b1 does not.
+        store.fail_after_gets(2);
+        let tree = TreeManifest::durable(store, hgid);
+        let files = tree.files(AlwaysMatcher::new()).collect::<Vec<_>>();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().any(|file| file.is_err()));
+    }
+
+    #[test]
+    fn test_remove_matches_tree_without_file() {
+        let store = Arc::new(TestStore::new());
+        let mut tree = make_tree_manifest(
+            store.clone(),
+            &[("a1/b1/c1", "10"), ("a1/b2", "20"), ("a2", "30")],
+        );
+        let mut expected = make_tree_manifest(store, &[("a1/b2", "20"), ("a2", "30")]);
+
+        assert_eq!(
+            tree.remove(repo_path("a1/b1/c1")).unwrap(),
+            Some(make_meta("10"))
+        );
+        assert_eq!(tree.get(repo_path("a1/b1")).unwrap(), None);
+
+        let root_hgid = |tree: &mut TreeManifest| {
+            tree.finalize(vec![])
+                .unwrap()
+                .find(|(path, ..)| path.is_empty())
+                .unwrap()
+                .1
+        };
+        assert_eq!(root_hgid(&mut tree), root_hgid(&mut expected));
+    }
+
     #[test]
     fn test_flush() {
         let store = Arc::new(TestStore::new());
@@ -1042,6 +1670,109 @@ mod tests {
         assert_eq!(tree.get(repo_path("a2/b1")).unwrap(), None);
     }
 
+    #[test]
+    fn test_insert_many() {
+        let files = vec![
+            (repo_path_buf("a1/b1/c1/d1"), make_meta("10")),
+            (repo_path_buf("a2/b2/c2"), make_meta("30")),
+            (repo_path_buf("a1/b2"), make_meta("20")),
+            (repo_path_buf("a1.b"), make_meta("40")),
+            (repo_path_buf("a1/b1/c2"), make_meta("50")),
+            (repo_path_buf("a1/b2"), make_meta("60")),
+            (repo_path_buf("a3"), make_meta("70")),
+        ];
+
+        let mut sequential = TreeManifest::ephemeral(Arc::new(TestStore::new()));
+        for (path, meta) in files.iter() {
+            sequential.insert(path.clone(), *meta).unwrap();
+        }
+        let mut bulk = TreeManifest::ephemeral(Arc::new(TestStore::new()));
+        bulk.insert_many(files).unwrap();
+
+        assert_eq!(
+            bulk.get_file(repo_path("a1/b2")).unwrap(),
+            Some(make_meta("60"))
+        );
+        let sequential_changed: Vec<_> = sequential.finalize(vec![]).unwrap().collect();
+        let bulk_changed: Vec<_> = bulk.finalize(vec![]).unwrap().collect();
+        assert_eq!(bulk_changed, sequential_changed);
+
+        // Inserting into a durable tree only touches the affected directories.
+        let store = Arc::new(TestStore::new());
+        for (path, hgid, raw, _, _) in bulk_changed.iter() {
+            store.insert(path, *hgid, raw.clone()).unwrap();
+        }
+        let root = bulk_changed.last().unwrap().1;
+        let mut sequential = TreeManifest::durable(store.clone(), root);
+        sequential
+            .insert(repo_path_buf("a2/b3"), make_meta("80"))
+            .unwrap();
+        let mut bulk = TreeManifest::durable(store, root);
+        bulk.insert_many(vec![(repo_path_buf("a2/b3"), make_meta("80"))])
+            .unwrap();
+        assert_eq!(
+            bulk.finalize(vec![]).unwrap().collect::<Vec<_>>(),
+            sequential.finalize(vec![]).unwrap().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_insert_many_conflicts() {
+        let mut tree = TreeManifest::ephemeral(Arc::new(TestStore::new()));
+        let err = tree
+            .insert_many(vec![
+                (repo_path_buf("a1/b1"), make_meta("20")),
+                (repo_path_buf("a1"), make_meta("10")),
+            ])
+            .unwrap_err();
+        let err = err.downcast_ref::<InsertError>().unwrap();
+        assert_eq!(err.path, repo_path_buf("a1/b1"));
+        assert!(matches!(
+            err.source,
+            InsertErrorCause::ParentFileExists(ref p) if *p == repo_path_buf("a1")
+        ));
+
+        let mut tree = TreeManifest::ephemeral(Arc::new(TestStore::new()));
+        tree.insert(repo_path_buf("a1/b1"), make_meta("10"))
+            .unwrap();
+        let err = tree
+            .insert_many(vec![(repo_path_buf("a1"), make_meta("20"))])
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<InsertError>().unwrap().source,
+            InsertErrorCause::DirectoryExistsForPath
+        ));
+    }
+
+    #[test]
+    fn test_list_dir() {
+        let tree = make_tree_manifest(
+            Arc::new(TestStore::new()),
+            &[("a1/b1/c1", "10"), ("a1/b2", "20"), ("a2", "30")],
+        );
+        assert_eq!(
+            tree.list_dir(RepoPath::empty()).unwrap(),
+            Some(vec![
+                (path_component_buf("a1"), FsNodeKind::Directory),
+                (path_component_buf("a2"), FsNodeKind::File),
+            ])
+        );
+        assert_eq!(
+            tree.list_dir(repo_path("a1")).unwrap(),
+            Some(vec![
+                (path_component_buf("b1"), FsNodeKind::Directory),
+                (path_component_buf("b2"), FsNodeKind::File),
+            ])
+        );
+        assert_eq!(tree.list_dir(repo_path("a3")).unwrap(), None);
+        assert_eq!(tree.list_dir(repo_path("a2/b1")).unwrap(), None);
+        let err = tree.list_dir(repo_path("a1/b2")).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<NotADirectoryError>().unwrap().0,
+            repo_path_buf("a1/b2")
+        );
+    }
+
     #[test]
     fn test_finalize_with_zero_and_one_parents() {
         let store = Arc::new(TestStore::new());
@@ -1093,6 +1824,79 @@ mod tests {
         assert_eq!(update_changed[2].4, NULL_ID);
     }
 
+    #[test]
+    fn test_finalize_to_store() {
+        let store = Arc::new(TestStore::new());
+        let mut tree = TreeManifest::ephemeral(store.clone());
+        tree.insert(repo_path_buf("a1/b1/c1/d1"), make_meta("10"))
+            .unwrap();
+        tree.insert(repo_path_buf("a1/b2"), make_meta("20"))
+            .unwrap();
+        tree.insert(repo_path_buf("a2/b2/c2"), make_meta("30"))
+            .unwrap();
+        let expected: Vec<_> = tree
+            .clone()
+            .finalize(vec![])
+            .unwrap()
+            .map(|(path, hgid, _, _, _)| (path, hgid))
+            .collect();
+        let written: Vec<_> = tree.finalize_to_store(vec![]).unwrap().collect();
+        assert_eq!(written, expected);
+        let root = written.last().unwrap().1;
+        assert_eq!(get_hgid(&tree, RepoPath::empty()), root);
+
+        // Everything written can be read back from the store.
+        let reloaded = TreeManifest::durable(store.clone(), root);
+        assert_eq!(
+            reloaded.get_file(repo_path("a2/b2/c2")).unwrap(),
+            Some(make_meta("30"))
+        );
+
+        // Nothing is rewritten when there are no changes.
+        assert_eq!(tree.finalize_to_store(vec![]).unwrap().count(), 0);
+
+        // Only the changed directories are written.
+        let mut update = TreeManifest::durable(store, root);
+        update
+            .insert(repo_path_buf("a1/b2"), make_meta("40"))
+            .unwrap();
+        let paths: Vec<_> = update
+            .finalize_to_store(vec![&reloaded])
+            .unwrap()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, vec![repo_path_buf("a1"), RepoPathBuf::new()]);
+        assert_eq!(
+            update.get_file(repo_path("a1/b1/c1/d1")).unwrap(),
+            Some(make_meta("10"))
+        );
+    }
+
+    #[test]
+    fn test_finalize_to_store_failed_insert() {
+        let store = Arc::new(TestStore::new());
+        let mut tree = make_tree_manifest(store.clone(), &[("a1/b1/c1", "10"), ("a2", "20")]);
+        store.fail_on_insert(repo_path_buf("a1"));
+        assert!(tree.finalize_to_store(vec![]).is_err());
+
+        // The trees below "a1" were written, "a1" and the root were not, and stay ephemeral, so
+        // they are written once the store works again.
+        store.clear_faults();
+        let paths: Vec<_> = tree
+            .finalize_to_store(vec![])
+            .unwrap()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, vec![repo_path_buf("a1"), RepoPathBuf::new()]);
+        let root = get_hgid(&tree, RepoPath::empty());
+        assert_eq!(
+            TreeManifest::durable(store, root)
+                .get_file(repo_path("a1/b1/c1"))
+                .unwrap(),
+            Some(make_meta("10"))
+        );
+    }
+
     #[test]
     fn test_finalize_merge() {
         let store = Arc::new(TestStore::new());
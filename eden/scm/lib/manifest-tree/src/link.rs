@@ -17,6 +17,7 @@ use anyhow::Context;
 use anyhow::Result;
 use manifest::File;
 use manifest::FileMetadata;
+use manifest::FsNodeKind;
 use manifest::FsNodeMetadata;
 use once_cell::sync::OnceCell;
 use pathmatcher::DirectoryMatch;
@@ -128,6 +129,13 @@ impl Link {
         }
     }
 
+    pub fn kind(&self) -> FsNodeKind {
+        match self.as_ref() {
+            Leaf(_) => FsNodeKind::File,
+            Ephemeral(_) | Durable(_) => FsNodeKind::Directory,
+        }
+    }
+
     /// Create a file record for a `Link`, failing if the link
     /// refers to a directory rather than a file.
     pub fn to_file(&self, path: RepoPathBuf) -> Option<File> {
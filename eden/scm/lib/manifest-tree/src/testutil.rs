@@ -6,6 +6,7 @@
  */
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use anyhow::format_err;
@@ -50,10 +51,21 @@ pub fn make_tree_manifest_from_meta(
 }
 
 /// An in memory `Store` implementation backed by HashMaps. Primarily intended for tests.
+///
+/// The store never fails by default. Use `fail_on_key` or `fail_after_gets` to inject `get`
+/// errors, and `fail_on_insert` to inject `insert` errors, for testing error paths.
 pub struct TestStore {
     entries: RwLock<HashMap<RepoPathBuf, HashMap<HgId, Bytes>>>,
     pub prefetched: Mutex<Vec<Vec<Key>>>,
     format: TreeFormat,
+    faults: Mutex<Faults>,
+}
+
+#[derive(Default)]
+struct Faults {
+    keys: HashSet<Key>,
+    gets_until_failure: Option<usize>,
+    insert_paths: HashSet<RepoPathBuf>,
 }
 
 impl TestStore {
@@ -62,6 +74,7 @@ impl TestStore {
             entries: RwLock::new(HashMap::new()),
             prefetched: Mutex::new(Vec::new()),
             format: TreeFormat::Hg,
+            faults: Mutex::new(Faults::default()),
         }
     }
 
@@ -74,10 +87,53 @@ impl TestStore {
     pub fn fetches(&self) -> Vec<Vec<Key>> {
         self.prefetched.lock().clone()
     }
+
+    /// Make every `get` of `key` fail, whether or not the store has an entry for it.
+    pub fn fail_on_key(&self, key: Key) {
+        self.faults.lock().keys.insert(key);
+    }
+
+    /// Allow `count` more `get` calls to succeed, then make all subsequent ones fail.
+    pub fn fail_after_gets(&self, count: usize) {
+        self.faults.lock().gets_until_failure = Some(count);
+    }
+
+    /// Make every `insert` of an entry for `path` fail.
+    pub fn fail_on_insert(&self, path: RepoPathBuf) {
+        self.faults.lock().insert_paths.insert(path);
+    }
+
+    /// Remove all injected failures.
+    pub fn clear_faults(&self) {
+        *self.faults.lock() = Faults::default();
+    }
+
+    fn check_faults(&self, path: &RepoPath, hgid: HgId) -> Result<()> {
+        let mut faults = self.faults.lock();
+        if faults.keys.contains(&Key::new(path.to_owned(), hgid)) {
+            return Err(format_err!(
+                "Injected failure for manifest entry ({}, {})",
+                path,
+                hgid
+            ));
+        }
+        if let Some(remaining) = faults.gets_until_failure.as_mut() {
+            if *remaining == 0 {
+                return Err(format_err!(
+                    "Injected failure for manifest entry ({}, {})",
+                    path,
+                    hgid
+                ));
+            }
+            *remaining -= 1;
+        }
+        Ok(())
+    }
 }
 
 impl TreeStore for TestStore {
     fn get(&self, path: &RepoPath, hgid: HgId) -> Result<Bytes> {
+        self.check_faults(path, hgid)?;
         let underlying = self.entries.read();
         let result = underlying
             .get(path)
@@ -87,6 +143,13 @@ impl TreeStore for TestStore {
     }
 
     fn insert(&self, path: &RepoPath, hgid: HgId, data: Bytes) -> Result<()> {
+        if self.faults.lock().insert_paths.contains(path) {
+            return Err(format_err!(
+                "Injected failure inserting manifest entry ({}, {})",
+                path,
+                hgid
+            ));
+        }
         let mut underlying = self.entries.write();
         underlying
             .entry(path.to_owned())
@@ -60,6 +60,8 @@ pub trait Manifest {
 
     /// Removes a file from the manifest (equivalent to removing it from the repository).
     /// A call with a file path that does not exist in the manifest is a no-op.
+    /// A call with a path that points to a directory, including the root, is an error, and
+    /// leaves the manifest unchanged.
     fn remove(&mut self, file_path: &RepoPath) -> Result<Option<FileMetadata>>;
 
     /// Persists the manifest so that it can be retrieved at a later time. Returns a note
@@ -139,6 +141,23 @@ pub enum FsNodeMetadata {
     Directory(Option<HgId>),
 }
 
+impl FsNodeMetadata {
+    /// Returns whether this node is a file or a directory.
+    pub fn kind(&self) -> FsNodeKind {
+        match self {
+            FsNodeMetadata::File(_) => FsNodeKind::File,
+            FsNodeMetadata::Directory(_) => FsNodeKind::Directory,
+        }
+    }
+}
+
+/// The kind of a file system node, without any of its metadata.
+#[derive(Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum FsNodeKind {
+    File,
+    Directory,
+}
+
 /// A directory entry in a manifest.
 ///
 /// Consists of the full path to the directory. Directories may or may not be assigned
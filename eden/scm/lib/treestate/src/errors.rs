@@ -23,6 +23,10 @@ pub enum ErrorKind {
     ReadOnlyStore,
     #[error("treedirstate is corrupt")]
     CorruptTree,
+    #[error("treedirstate is corrupt: invalid block {block_id} for directory '{path}'")]
+    CorruptTreeBlock { block_id: u64, path: String },
     #[error("callback error: {0}")]
     CallbackError(String),
+    #[error("cannot add file '{0}': it is a directory, or is inside a file")]
+    FileConflict(String),
 }
@@ -27,6 +27,7 @@ use byteorder::WriteBytesExt;
 
 use crate::errors::ErrorKind;
 use crate::store::BlockId;
+use crate::store::BlockStats;
 use crate::store::Store;
 use crate::store::StoreView;
 
@@ -149,6 +150,18 @@ impl FileStore {
     pub fn position(&self) -> u64 {
         self.position
     }
+
+    /// Count all blocks in the store, reachable or not.
+    pub fn stats(&self) -> Result<BlockStats> {
+        let mut stats = BlockStats::default();
+        let mut id = HEADER_LEN;
+        while id < self.position {
+            let size = self.read(BlockId(id))?.len();
+            stats.add_block(size);
+            id += 4 + size as u64;
+        }
+        Ok(stats)
+    }
 }
 
 impl Store for FileStore {
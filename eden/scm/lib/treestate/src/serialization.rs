@@ -155,29 +155,14 @@ where
         b'f' => {
             // File entry.
             let data = T::deserialize(r)?;
-            let name_len = r.read_vlq()?;
-            let mut name = Vec::with_capacity(name_len);
-            unsafe {
-                // Safe as we've just allocated the buffer and are about to read into it.
-                name.set_len(name_len);
-            }
-            r.read_exact(name.as_mut_slice())?;
-            Ok((name.into_boxed_slice(), NodeEntry::File(data)))
+            let name = Key::deserialize(r)?;
+            Ok((name, NodeEntry::File(data)))
         }
         b'd' => {
             // Directory entry.
             let id = r.read_vlq()?;
-            let name_len = r.read_vlq()?;
-            let mut name = Vec::with_capacity(name_len);
-            unsafe {
-                // Safe as we've just allocated the buffer and are about to read into it.
-                name.set_len(name_len);
-            }
-            r.read_exact(name.as_mut_slice())?;
-            Ok((
-                name.into_boxed_slice(),
-                NodeEntry::Directory(Node::open(BlockId(id))),
-            ))
+            let name = Key::deserialize(r)?;
+            Ok((name, NodeEntry::Directory(Node::open(BlockId(id)))))
         }
         _ => {
             bail!(ErrorKind::CorruptTree);
@@ -17,6 +17,30 @@ use crate::errors::ErrorKind;
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
 pub struct BlockId(pub u64);
 
+/// Number and total size of a set of blocks.  Sizes count the data of the blocks only, not any
+/// framing the store adds around them.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct BlockStats {
+    pub blocks: u64,
+    pub bytes: u64,
+}
+
+impl BlockStats {
+    pub(crate) fn add_block(&mut self, size: usize) {
+        self.blocks += 1;
+        self.bytes += size as u64;
+    }
+}
+
+/// Block-level statistics for a store.  Blocks are never removed from an append-only store, so
+/// `total` keeps growing as the tree is rewritten, while `live` only counts the blocks that are
+/// still reachable from the current root.  The difference is what a compaction would reclaim.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct StoreStats {
+    pub total: BlockStats,
+    pub live: BlockStats,
+}
+
 /// Append-only storage.  Blocks of data may be stored in an instance of a Store.  Once written,
 /// blocks are immutable.
 pub trait Store {
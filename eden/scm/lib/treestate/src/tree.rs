@@ -12,15 +12,20 @@ use std::collections::Bound;
 use std::io::Cursor;
 use std::io::Read;
 use std::io::Write;
+use std::sync::Arc;
+use std::sync::RwLock;
 
+use anyhow::bail;
 use anyhow::Result;
 use types::RepoPath;
 
+use crate::errors::ErrorKind;
 use crate::filestate::FileState;
 use crate::filestate::FileStateV2;
 use crate::filestate::StateFlags;
 use crate::serialization::Serializable;
 use crate::store::BlockId;
+use crate::store::BlockStats;
 use crate::store::Store;
 use crate::store::StoreView;
 use crate::vecmap::VecMap;
@@ -48,6 +53,9 @@ pub enum VisitorResult {
 /// Store the node entries in an ordered map from name to node entry.
 pub(crate) type NodeEntryMap<T> = VecMap<Key, NodeEntry<T>>;
 
+/// The entries of a `ReadOnlyNode`, shared by all readers once loaded.
+type ReadOnlyNodeEntryMap<T> = Arc<VecMap<Key, ReadOnlyNodeEntry<T>>>;
+
 /// The aggregated state. Useful for fast decision about whether to visit a directory recursively
 /// or not.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -91,6 +99,43 @@ impl Default for AggregatedState {
     }
 }
 
+/// The result of `Tree::check`.
+#[derive(Debug, Default, PartialEq)]
+pub struct CheckReport {
+    /// The number of files found in the tree.  Files in directories whose block could not be
+    /// read are not included.
+    pub files: u64,
+
+    /// The problems found, in the order they were found.
+    pub problems: Vec<CheckProblem>,
+}
+
+impl CheckReport {
+    /// Returns true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// A problem found by `Tree::check`.  Paths of directories include the trailing slash, and the
+/// path of the root directory is empty.
+#[derive(Debug, PartialEq)]
+pub enum CheckProblem {
+    /// The file count of the tree does not match the number of files found in it.
+    FileCountMismatch { recorded: u32, found: u64 },
+
+    /// A directory other than the root has no entries.  Directories are removed along with
+    /// their last file, so this should never happen.
+    EmptyDirectory { path: Key },
+
+    /// The block of a directory could not be read from the store or deserialized.
+    UnreadableBlock {
+        block_id: u64,
+        path: Key,
+        error: String,
+    },
+}
+
 /// The contents of a directory.
 #[derive(Debug)]
 pub struct Node<T> {
@@ -132,6 +177,30 @@ pub struct Tree<T> {
     file_count: u32,
 }
 
+/// A read-only view of a tree that has been written to a store.
+///
+/// `Tree` loads nodes lazily and caches them in place, so even lookups need `&mut self`.  This
+/// type instead caches loaded nodes behind `RwLock`s, so lookups only need `&self` and a single
+/// tree can be shared between threads.  The tradeoff is that files are returned by value rather
+/// than by reference, every directory visited takes a lock, and the tree cannot be modified.
+/// Writers should keep using `Tree`.
+pub struct ReadOnlyTree<T> {
+    root: ReadOnlyNode<T>,
+    file_count: u32,
+}
+
+/// The contents of a directory in a `ReadOnlyTree`.  The entries are loaded from the store the
+/// first time they are needed, and shared by all readers after that.
+struct ReadOnlyNode<T> {
+    id: BlockId,
+    entries: RwLock<Option<ReadOnlyNodeEntryMap<T>>>,
+}
+
+enum ReadOnlyNodeEntry<T> {
+    Directory(ReadOnlyNode<T>),
+    File(T),
+}
+
 /// Utility enum for recursing through trees.
 enum PathRecurse<'name, 'node, T: 'node> {
     Directory(KeyRef<'name>, KeyRef<'name>, &'node mut Node<T>),
@@ -241,9 +310,10 @@ impl CompatExt<FileStateV2> for Node<FileStateV2> {
     ) -> Result<AggregatedState> {
         self.load_aggregated_state(store)?;
         if self.aggregated_state.get().is_none() {
-            for (_name, entry) in self.load_entries(store)?.iter_mut() {
+            for (name, entry) in self.load_entries(store)?.iter_mut() {
                 if let &mut NodeEntry::Directory(ref mut node) = entry {
-                    node.calculate_aggregated_state_recursive(store)?;
+                    node.calculate_aggregated_state_recursive(store)
+                        .map_err(|e| in_subdir(e, name))?;
                 }
             }
         }
@@ -289,6 +359,36 @@ impl<T: Serializable + Clone> Node<T> {
     pub fn is_changed(&self) -> bool {
         self.id.is_none()
     }
+
+    /// Copy the node for `Tree::snapshot`.  Nodes that are in the store are shared by their ID,
+    /// and their entries are loaded again when the copy needs them, so only the nodes that were
+    /// changed in memory are copied.
+    fn snapshot(&self) -> Node<T> {
+        let (id, entries) = match self.id {
+            Some(id) => (Some(id), None),
+            None => {
+                let entries = self
+                    .entries
+                    .as_ref()
+                    .expect("Node should have entries populated if it was modified.");
+                let mut copy = NodeEntryMap::with_capacity(entries.len());
+                for (name, entry) in entries.iter() {
+                    let entry = match entry {
+                        NodeEntry::Directory(node) => NodeEntry::Directory(node.snapshot()),
+                        NodeEntry::File(file) => NodeEntry::File(file.clone()),
+                    };
+                    copy.insert_hint_end(name.clone(), entry);
+                }
+                (None, Some(copy))
+            }
+        };
+        Node {
+            id,
+            entries,
+            aggregated_state: Cell::new(self.aggregated_state.get()),
+            filtered_keys: None,
+        }
+    }
 }
 
 impl<T: Serializable + Clone> Node<T>
@@ -305,7 +405,8 @@ where
         let data = store.read(id)?;
         let mut cur = Cursor::new(data);
         self.load_ext(&mut cur)?;
-        self.entries = Some(NodeEntryMap::<T>::deserialize(&mut cur)?);
+        let entries = NodeEntryMap::<T>::deserialize(&mut cur).map_err(|e| corrupt_block(e, id))?;
+        self.entries = Some(entries);
         Ok(())
     }
 
@@ -358,9 +459,10 @@ where
     /// loaded from the old_store before being written back to the new store.
     fn write_full(&mut self, store: &mut dyn Store, old_store: &dyn StoreView) -> Result<()> {
         // Write out all the child nodes.
-        for (_name, entry) in self.load_entries(old_store)?.iter_mut() {
+        for (name, entry) in self.load_entries(old_store)?.iter_mut() {
             if let &mut NodeEntry::Directory(ref mut node) = entry {
-                node.write_full(store, old_store)?;
+                node.write_full(store, old_store)
+                    .map_err(|e| in_subdir(e, name))?;
             }
         }
         // Write out this node.
@@ -400,6 +502,74 @@ where
         }
     }
 
+    /// Add the block of the node stored at `id`, and the blocks of all of its descendants, to
+    /// `stats`.
+    fn add_block_stats(id: BlockId, store: &dyn StoreView, stats: &mut BlockStats) -> Result<()> {
+        let data = store.read(id)?;
+        stats.add_block(data.len());
+        let node = Node::<T>::open(id);
+        let mut cur = Cursor::new(data);
+        node.load_ext(&mut cur)?;
+        let entries = NodeEntryMap::<T>::deserialize(&mut cur).map_err(|e| corrupt_block(e, id))?;
+        for (name, entry) in entries.iter() {
+            if let NodeEntry::Directory(child) = entry {
+                let child_id = child.id.expect("Loaded directories must have an ID");
+                Self::add_block_stats(child_id, store, stats).map_err(|e| in_subdir(e, name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the entries of the node stored at `id`, without caching them.
+    fn read_entries(id: BlockId, store: &dyn StoreView) -> Result<NodeEntryMap<T>> {
+        let data = store.read(id)?;
+        let node = Node::<T>::open(id);
+        let mut cur = Cursor::new(data);
+        node.load_ext(&mut cur)?;
+        NodeEntryMap::<T>::deserialize(&mut cur).map_err(|e| corrupt_block(e, id))
+    }
+
+    /// Check the directory at `path` and all of its descendants for `Tree::check`, adding the
+    /// files and problems found to `report`.  Entries that have not been loaded are read from
+    /// the store, but are not cached in the tree.
+    fn check(&self, store: &dyn StoreView, path: &mut Vec<u8>, report: &mut CheckReport) {
+        let read_entries;
+        let entries = match (&self.entries, self.id) {
+            (Some(entries), _) => entries,
+            (None, Some(id)) => match Self::read_entries(id, store) {
+                Ok(entries) => {
+                    read_entries = entries;
+                    &read_entries
+                }
+                Err(e) => {
+                    report.problems.push(CheckProblem::UnreadableBlock {
+                        block_id: id.0,
+                        path: path.clone().into_boxed_slice(),
+                        error: e.to_string(),
+                    });
+                    return;
+                }
+            },
+            (None, None) => panic!("Node must have either entries or a valid ID"),
+        };
+        if entries.is_empty() && !path.is_empty() {
+            report.problems.push(CheckProblem::EmptyDirectory {
+                path: path.clone().into_boxed_slice(),
+            });
+        }
+        for (name, entry) in entries.iter() {
+            match entry {
+                NodeEntry::File(_) => report.files += 1,
+                NodeEntry::Directory(node) => {
+                    let len = path.len();
+                    path.extend_from_slice(name);
+                    node.check(store, path, report);
+                    path.truncate(len);
+                }
+            }
+        }
+    }
+
     /// Visit all of the files in under this node, by calling the visitor function on each one.
     ///
     /// `visit_dir` will be called to test if a directory is worth visiting or not.
@@ -432,7 +602,8 @@ where
         let mut result = VisitorResult::NotChanged;
 
         let entries: &mut NodeEntryMap<T> = {
-            self.load_entries(store)?;
+            self.load_entries(store)
+                .map_err(|e| in_subdir(e, &path.as_ref().concat()))?;
             self.entries.as_mut().unwrap()
         };
 
@@ -472,7 +643,9 @@ where
         for (name, entry) in self.load_entries(store)?.iter_mut() {
             match entry {
                 &mut NodeEntry::Directory(ref mut node) => {
-                    if let Some((mut next_name, next_file)) = node.get_first(store)? {
+                    if let Some((mut next_name, next_file)) =
+                        node.get_first(store).map_err(|e| in_subdir(e, name))?
+                    {
                         next_name.push(name);
                         return Ok(Some((next_name, next_file)));
                     }
@@ -514,11 +687,12 @@ where
                     }
                     let next = if let Some(path) = path {
                         // Find the next file after the given subpath.
-                        node.get_next(store, path)?
+                        node.get_next(store, path)
                     } else {
                         // Find the first file in this subtree.
-                        node.get_first(store)?
-                    };
+                        node.get_first(store)
+                    }
+                    .map_err(|e| in_subdir(e, entry_name))?;
                     if let Some((mut next_name, next_file)) = next {
                         next_name.push(entry_name);
                         return Ok(Some((next_name, next_file)));
@@ -574,7 +748,9 @@ where
         name: KeyRef,
     ) -> Result<Option<&'node T>> {
         match self.path_recurse(store, name)? {
-            PathRecurse::Directory(_dir, path, node) => node.get(store, path),
+            PathRecurse::Directory(dir, path, node) => {
+                node.get(store, path).map_err(|e| in_subdir(e, dir))
+            }
             PathRecurse::ExactDirectory(_dir, _node) => Ok(None),
             PathRecurse::MissingDirectory(_dir, _path) => Ok(None),
             PathRecurse::File(_name, file) => Ok(Some(file)),
@@ -590,7 +766,9 @@ where
             return Ok(true);
         }
         match self.path_recurse(store, name)? {
-            PathRecurse::Directory(_dir, path, node) => node.has_dir(store, path),
+            PathRecurse::Directory(dir, path, node) => {
+                node.has_dir(store, path).map_err(|e| in_subdir(e, dir))
+            }
             PathRecurse::ExactDirectory(_dir, _node) => Ok(true),
             PathRecurse::MissingDirectory(_dir, _path) => Ok(false),
             PathRecurse::File(_name, _file) => Ok(false),
@@ -606,8 +784,12 @@ where
         }
 
         match self.path_recurse(store, name)? {
-            PathRecurse::Directory(_dir, path, node) => node.get_dir(store, path),
-            PathRecurse::ExactDirectory(_dir, node) => node.get_dir(store, b"/"),
+            PathRecurse::Directory(dir, path, node) => {
+                node.get_dir(store, path).map_err(|e| in_subdir(e, dir))
+            }
+            PathRecurse::ExactDirectory(dir, node) => {
+                node.get_dir(store, b"/").map_err(|e| in_subdir(e, dir))
+            }
             PathRecurse::MissingDirectory(_dir, _path) => Ok(None),
             PathRecurse::File(_name, _file) => Ok(None),
             PathRecurse::MissingFile(_name) => Ok(None),
@@ -617,13 +799,24 @@ where
 
     /// Add a file to the node.  The name may contain a path, in which case sufficient
     /// subdirectories are updated to add or update the file.
-    fn add(&mut self, store: &dyn StoreView, name: KeyRef, info: &T) -> Result<bool> {
+    ///
+    /// Returns a pair of booleans (file_added, changed) indicating whether the file was
+    /// added, and whether anything changed.  Updating a file to an identical state changes
+    /// nothing.
+    fn add(&mut self, store: &dyn StoreView, name: KeyRef, info: &T) -> Result<(bool, bool)>
+    where
+        T: PartialEq,
+    {
         // Construct a RepoPath so we match the core path validation logic.
         let _ = RepoPath::from_utf8(name)?;
         let (new_entry, file_added) = match self.path_recurse(store, name)? {
-            PathRecurse::Directory(_dir, path, node) => {
+            PathRecurse::Directory(dir, path, node) => {
                 // The file is in a subdirectory.  Add it to the subdirectory.
-                let file_added = node.add(store, path, info)?;
+                let (file_added, changed) =
+                    node.add(store, path, info).map_err(|e| in_subdir(e, dir))?;
+                if !changed {
+                    return Ok((false, false));
+                }
                 (None, file_added)
             }
             PathRecurse::ExactDirectory(_dir, _node) => {
@@ -632,7 +825,7 @@ where
             PathRecurse::MissingDirectory(dir, path) => {
                 // The file is in a new subdirectory.  Create the directory and add the file.
                 let mut node = Node::new();
-                let file_added = node.add(store, path, info)?;
+                let (file_added, _) = node.add(store, path, info)?;
                 (
                     Some((dir.to_vec().into_boxed_slice(), NodeEntry::Directory(node))),
                     file_added,
@@ -640,6 +833,9 @@ where
             }
             PathRecurse::File(_name, file) => {
                 // The file is in this directory.  Update it.
+                if file == info {
+                    return Ok((false, false));
+                }
                 file.clone_from(info);
                 (None, false)
             }
@@ -667,36 +863,37 @@ where
         // Reset aggregated_state so it needs recalculation.
         self.aggregated_state.set(None);
         self.id = None;
-        Ok(file_added)
+        Ok((file_added, true))
     }
 
     /// Remove a file from the node.  The name may contain a path, in which case sufficient
     /// subdirectories are updated to remove the file.
     ///
-    /// Returns a pair of booleans (file_removed, now_empty) indicating whether the file
-    /// was removed, and whether the diectory is now empty.
-    fn remove(&mut self, store: &dyn StoreView, name: KeyRef) -> Result<(bool, bool)> {
-        let (file_removed, remove_entry) = match self.path_recurse(store, name)? {
+    /// Returns a pair (removed_file, now_empty) with the state of the file that was removed,
+    /// if any, and whether the diectory is now empty.
+    fn remove(&mut self, store: &dyn StoreView, name: KeyRef) -> Result<(Option<T>, bool)> {
+        let (removed_file, remove_entry) = match self.path_recurse(store, name)? {
             PathRecurse::Directory(dir, path, node) => {
-                let (file_removed, now_empty) = node.remove(store, path)?;
-                (file_removed, if now_empty { Some(dir) } else { None })
+                let (removed_file, now_empty) =
+                    node.remove(store, path).map_err(|e| in_subdir(e, dir))?;
+                (removed_file, if now_empty { Some(dir) } else { None })
             }
-            PathRecurse::ExactDirectory(_dir, _node) => (false, None),
-            PathRecurse::MissingDirectory(_dir, _path) => (false, None),
-            PathRecurse::File(name, _file) => (true, Some(name)),
-            PathRecurse::MissingFile(_name) => (false, None),
-            PathRecurse::ConflictingFile(_name, _path, _file) => (false, None),
+            PathRecurse::ExactDirectory(_dir, _node) => (None, None),
+            PathRecurse::MissingDirectory(_dir, _path) => (None, None),
+            PathRecurse::File(name, file) => (Some(file.clone()), Some(name)),
+            PathRecurse::MissingFile(_name) => (None, None),
+            PathRecurse::ConflictingFile(_name, _path, _file) => (None, None),
         };
         if let Some(entry) = remove_entry {
             self.load_entries(store)?.remove(entry);
             self.filtered_keys = None;
             self.id = None;
         }
-        if file_removed {
+        if removed_file.is_some() {
             self.aggregated_state.set(None);
             self.id = None;
         }
-        Ok((file_removed, self.load_entries(store)?.is_empty()))
+        Ok((removed_file, self.load_entries(store)?.is_empty()))
     }
 
     /// Performs a key lookup using filtered keys.
@@ -756,8 +953,9 @@ where
                     if let Some(&mut NodeEntry::Directory(ref mut node)) =
                         entries.get_mut(mapped_elem)
                     {
-                        for mut mapped_path in
-                            node.get_filtered_key(store, path, filter, filter_id)?
+                        for mut mapped_path in node
+                            .get_filtered_key(store, path, filter, filter_id)
+                            .map_err(|e| in_subdir(e, mapped_elem))?
                         {
                             mapped_path.push(mapped_elem.clone());
                             result.push(mapped_path);
@@ -787,10 +985,13 @@ where
     where
         FA: Fn(&T) -> bool,
     {
-        for (_name, entry) in self.load_entries(store)?.iter_mut() {
+        for (name, entry) in self.load_entries(store)?.iter_mut() {
             match entry {
                 &mut NodeEntry::Directory(ref mut node) => {
-                    if node.path_complete_check(store, acceptable)? {
+                    if node
+                        .path_complete_check(store, acceptable)
+                        .map_err(|e| in_subdir(e, name))?
+                    {
                         return Ok(true);
                     }
                 }
@@ -827,7 +1028,10 @@ where
         let (elem, subpath) = split_key_exact(prefix);
         if let Some(subpath) = subpath {
             // Prefix part is for a directory, so look for that directory.
-            let entry = self.load_entries(store)?.get_mut(elem);
+            let entry = self
+                .load_entries(store)
+                .map_err(|e| in_subdir(e, &path.as_ref().concat()))?
+                .get_mut(elem);
             if let Some(&mut NodeEntry::Directory(ref mut node)) = entry {
                 let mut path = path.push(elem);
                 node.path_complete(store, &mut path, subpath, full_paths, acceptable, visitor)?;
@@ -835,7 +1039,8 @@ where
         } else {
             // Prefix part is for a entry in this directory.  Iterate across all matching entries.
             for (entry_name, entry) in self
-                .load_entries(store)?
+                .load_entries(store)
+                .map_err(|e| in_subdir(e, &path.as_ref().concat()))?
                 .range_mut((Bound::Included(elem), Bound::Unbounded))
             {
                 if entry_name.len() < elem.len() || &entry_name[..elem.len()] != elem {
@@ -865,8 +1070,11 @@ where
                             // The entry is a directory, and the caller has asked for matching
                             // directories.  Check there is an acceptable file under the
                             // directory.
-                            if node.path_complete_check(store, acceptable)? {
-                                let path = path.push(entry_name);
+                            let path = path.push(entry_name);
+                            if node
+                                .path_complete_check(store, acceptable)
+                                .map_err(|e| in_subdir(e, &path.as_ref().concat()))?
+                            {
                                 visitor(path.as_ref())?;
                             }
                         }
@@ -915,10 +1123,47 @@ where
         self.root.id
     }
 
+    /// Returns true if the tree has changed since it was last written or opened, i.e. if
+    /// `write_delta` would write anything.  A new tree is dirty until it is written.
+    pub fn is_dirty(&self) -> bool {
+        self.root.is_changed()
+    }
+
     pub fn file_count(&self) -> u32 {
         self.file_count
     }
 
+    /// Create a tree with the same contents that can be changed independently of this one.
+    ///
+    /// This is much cheaper than copying the whole tree: only the directories changed since the
+    /// tree was last written or opened are copied.  The other directories are shared by their
+    /// `BlockId`, and loaded again from the store when the snapshot needs them, so the snapshot
+    /// must be used with the same store.  As the store is append-only, changing or writing
+    /// either tree does not affect the other.
+    pub fn snapshot(&self) -> Tree<T> {
+        Tree {
+            root: self.root.snapshot(),
+            file_count: self.file_count,
+        }
+    }
+
+    /// Apply the changes made by `f` to the tree all at once: if `f` fails, the tree is restored
+    /// to what it was before `f` was called, and the error is returned.
+    ///
+    /// The tree is restored from a `snapshot`, so nothing needs to be undone in the store, but
+    /// directories that were loaded before `f` was called may need to be loaded again.
+    pub fn with_transaction<R, F>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Tree<T>) -> Result<R>,
+    {
+        let saved = self.snapshot();
+        let result = f(self);
+        if result.is_err() {
+            *self = saved;
+        }
+        result
+    }
+
     pub fn write_full(
         &mut self,
         store: &mut dyn Store,
@@ -933,6 +1178,34 @@ where
         Ok(self.root.id.unwrap())
     }
 
+    /// Count the blocks of the tree whose root node was written to `store` as `root_id`.  These
+    /// are the blocks that are still live while that root is current.  This takes a root ID
+    /// rather than a loaded tree, as any unwritten changes to a tree are not in the store.
+    pub fn live_block_stats(root_id: BlockId, store: &dyn StoreView) -> Result<BlockStats> {
+        let mut stats = BlockStats::default();
+        Node::<T>::add_block_stats(root_id, store, &mut stats)?;
+        Ok(stats)
+    }
+
+    /// Audit the tree: check that every directory other than the root is non-empty, that the
+    /// blocks of all directories can be read from `store`, and that the file count matches the
+    /// number of files in the tree.  All problems are collected in the report, rather than
+    /// failing on the first one.
+    ///
+    /// This walks the whole tree, including changes that have not been written yet, and does
+    /// not load any nodes into the tree.
+    pub fn check(&self, store: &dyn StoreView) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+        self.root.check(store, &mut Vec::new(), &mut report);
+        if report.files != self.file_count as u64 {
+            report.problems.push(CheckProblem::FileCountMismatch {
+                recorded: self.file_count,
+                found: report.files,
+            });
+        }
+        Ok(report)
+    }
+
     pub fn get<'a>(&'a mut self, store: &dyn StoreView, name: KeyRef) -> Result<Option<&'a T>> {
         Ok(self.root.get(store, name)?)
     }
@@ -975,6 +1248,56 @@ where
         )
     }
 
+    /// Returns the paths of all files whose state satisfies `matches`, in tree order.
+    pub fn visit_filtered<F>(&mut self, store: &dyn StoreView, matches: F) -> Result<Vec<Key>>
+    where
+        F: Fn(&T) -> bool,
+    {
+        let mut paths = Vec::new();
+        self.visit_advanced(
+            store,
+            &mut |path, _| {
+                paths.push(path.concat().into_boxed_slice());
+                Ok(VisitorResult::NotChanged)
+            },
+            &|_, _| true,
+            &|_, file| matches(file),
+        )?;
+        Ok(paths)
+    }
+
+    /// Export all files in the tree as a flat list of paths and file states, sorted by path.
+    /// The order of the tree is the lexicographic order of the full paths, so exports of trees
+    /// with the same content are identical.
+    ///
+    /// This is lossy: only the file states in this tree are included.  Anything that is stored
+    /// alongside the tree, such as the copymap, the parents, or the other trees of a dirstate,
+    /// is not.
+    pub fn export(&mut self, store: &dyn StoreView) -> Result<Vec<(Key, T)>> {
+        let mut entries = Vec::with_capacity(self.file_count as usize);
+        self.visit(store, &mut |path, file| {
+            entries.push((path.concat().into_boxed_slice(), file.clone()));
+            Ok(VisitorResult::NotChanged)
+        })?;
+        Ok(entries)
+    }
+
+    /// Add the files of a list produced by `export`, as if by calling `add` for each of them.
+    /// The entries do not need to be sorted.
+    pub fn import(
+        &mut self,
+        store: &dyn StoreView,
+        entries: impl IntoIterator<Item = (Key, T)>,
+    ) -> Result<()>
+    where
+        T: PartialEq,
+    {
+        for (name, file) in entries {
+            self.add(store, &name, &file)?;
+        }
+        Ok(())
+    }
+
     pub fn get_first<'a>(&'a mut self, store: &dyn StoreView) -> Result<Option<(Key, &'a T)>> {
         Ok(self.root.get_first(store)?.map(|(mut path, file)| {
             path.reverse();
@@ -1005,15 +1328,18 @@ where
         Ok(self.root.get_dir(store, name)?)
     }
 
-    pub fn add(&mut self, store: &dyn StoreView, name: KeyRef, file: &T) -> Result<()> {
-        if self.root.add(store, name, file)? {
+    pub fn add(&mut self, store: &dyn StoreView, name: KeyRef, file: &T) -> Result<()>
+    where
+        T: PartialEq,
+    {
+        if self.root.add(store, name, file)?.0 {
             self.file_count += 1;
         }
         Ok(())
     }
 
     pub fn remove(&mut self, store: &dyn StoreView, name: KeyRef) -> Result<bool> {
-        let removed = self.root.remove(store, name)?.0;
+        let removed = self.root.remove(store, name)?.0.is_some();
         if removed {
             assert!(self.file_count > 0);
             self.file_count -= 1;
@@ -1021,6 +1347,42 @@ where
         Ok(removed)
     }
 
+    /// Move the state of the file `from` to `to`.  Directories are created for `to` as needed,
+    /// and directories that only contained `from` are removed.  If `to` is already a file, its
+    /// state is replaced.
+    ///
+    /// Returns `false`, and changes nothing, if `from` is not a file.  Fails, and changes
+    /// nothing, if `to` is a directory, or is inside a file.
+    pub fn rename(&mut self, store: &dyn StoreView, from: KeyRef, to: KeyRef) -> Result<bool>
+    where
+        T: PartialEq,
+    {
+        // Check `to` before anything is removed.
+        let _ = RepoPath::from_utf8(to)?;
+        if from == to {
+            return Ok(self.get(store, from)?.is_some());
+        }
+        // `to` must not be a directory, or be inside a file.
+        let mut conflict = self.root.has_dir(store, &[to, b"/"].concat())?;
+        for (index, _) in to.iter().enumerate().filter(|&(_, &byte)| byte == b'/') {
+            conflict |= self.root.get(store, &to[..index])?.is_some();
+        }
+        if conflict {
+            bail!(ErrorKind::FileConflict(
+                String::from_utf8_lossy(to).into_owned()
+            ));
+        }
+        let file = match self.root.remove(store, from)?.0 {
+            Some(file) => file,
+            None => return Ok(false),
+        };
+        if !self.root.add(store, to, &file)?.0 {
+            // `to` was a file, and has been replaced.
+            self.file_count -= 1;
+        }
+        Ok(true)
+    }
+
     pub fn get_filtered_key<F>(
         &mut self,
         store: &dyn StoreView,
@@ -1061,6 +1423,133 @@ where
     }
 }
 
+impl<T: Serializable + Clone> ReadOnlyNode<T>
+where
+    Node<T>: CompatExt<T>,
+{
+    fn open(id: BlockId) -> ReadOnlyNode<T> {
+        ReadOnlyNode {
+            id,
+            entries: RwLock::new(None),
+        }
+    }
+
+    /// Get access to the node entries, loading them from the store if no reader has done so yet.
+    fn load_entries(&self, store: &dyn StoreView) -> Result<ReadOnlyNodeEntryMap<T>> {
+        if let Some(entries) = self.entries.read().unwrap().as_ref() {
+            return Ok(entries.clone());
+        }
+
+        // Load the node without holding the lock.  If another reader loads it at the same time,
+        // whichever finishes first wins and the other copy is dropped.
+        let mut node = Node::<T>::open(self.id);
+        node.load(store)?;
+        let loaded = node
+            .entries
+            .expect("Entries should have been populated by loading");
+        let mut entries = VecMap::with_capacity(loaded.len());
+        for (name, entry) in loaded.iter() {
+            let entry = match entry {
+                NodeEntry::Directory(node) => ReadOnlyNodeEntry::Directory(ReadOnlyNode::open(
+                    node.id.expect("Loaded directories must have an ID"),
+                )),
+                NodeEntry::File(file) => ReadOnlyNodeEntry::File(file.clone()),
+            };
+            entries.insert_hint_end(name.clone(), entry);
+        }
+
+        let mut guard = self.entries.write().unwrap();
+        Ok(guard.get_or_insert_with(|| Arc::new(entries)).clone())
+    }
+
+    /// Get a file's state.
+    fn get(&self, store: &dyn StoreView, name: KeyRef) -> Result<Option<T>> {
+        let (elem, path) = split_key(name);
+        let entries = self.load_entries(store)?;
+        match (entries.get(elem), path) {
+            (Some(ReadOnlyNodeEntry::Directory(node)), Some(path)) => {
+                node.get(store, path).map_err(|e| in_subdir(e, elem))
+            }
+            (Some(ReadOnlyNodeEntry::File(file)), None) => Ok(Some(file.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns true if the given path is a directory.
+    fn has_dir(&self, store: &dyn StoreView, name: KeyRef) -> Result<bool> {
+        // This directory exists, without checking entries.
+        if name == b"/" {
+            return Ok(true);
+        }
+        let (elem, path) = split_key(name);
+        let entries = self.load_entries(store)?;
+        match (entries.get(elem), path) {
+            (Some(ReadOnlyNodeEntry::Directory(node)), Some(path)) => {
+                node.has_dir(store, path).map_err(|e| in_subdir(e, elem))
+            }
+            (Some(ReadOnlyNodeEntry::Directory(_)), None) => Ok(true),
+            _ => Ok(false),
+        }
+    }
+}
+
+impl<T: Serializable + Clone> ReadOnlyTree<T>
+where
+    Node<T>: CompatExt<T>,
+{
+    /// Create a read-only tree that references an existing root node.
+    pub fn open(root_id: BlockId, file_count: u32) -> ReadOnlyTree<T> {
+        ReadOnlyTree {
+            root: ReadOnlyNode::open(root_id),
+            file_count,
+        }
+    }
+
+    pub fn root_id(&self) -> BlockId {
+        self.root.id
+    }
+
+    pub fn file_count(&self) -> u32 {
+        self.file_count
+    }
+
+    pub fn get(&self, store: &dyn StoreView, name: KeyRef) -> Result<Option<T>> {
+        self.root.get(store, name)
+    }
+
+    pub fn has_dir(&self, store: &dyn StoreView, name: KeyRef) -> Result<bool> {
+        self.root.has_dir(store, name)
+    }
+}
+
+/// Converts a `CorruptTree` error from deserializing the entries of block `id` into a
+/// `CorruptTreeBlock` error for that block.  The path starts out empty, and is filled in by
+/// `in_subdir` as the error is returned through the parent directories.
+fn corrupt_block(err: anyhow::Error, id: BlockId) -> anyhow::Error {
+    match err.downcast::<ErrorKind>() {
+        Ok(ErrorKind::CorruptTree) => ErrorKind::CorruptTreeBlock {
+            block_id: id.0,
+            path: String::new(),
+        }
+        .into(),
+        Ok(err) => err.into(),
+        Err(err) => err,
+    }
+}
+
+/// Prefixes the path of a `CorruptTreeBlock` error returned from the subdirectory `dir`.
+fn in_subdir(err: anyhow::Error, dir: KeyRef) -> anyhow::Error {
+    match err.downcast::<ErrorKind>() {
+        Ok(ErrorKind::CorruptTreeBlock { block_id, path }) => ErrorKind::CorruptTreeBlock {
+            block_id,
+            path: format!("{}{}", String::from_utf8_lossy(dir), path),
+        }
+        .into(),
+        Ok(err) => err.into(),
+        Err(err) => err,
+    }
+}
+
 fn trim_separator(path: &[u8]) -> &[u8] {
     // Strip trailing slashes
     if path.last() == Some(&b'/') {
@@ -1132,6 +1621,77 @@ mod tests {
         assert_eq!(t.get(&ms, b"dirB/subdirb/file9").expect("can get"), None);
     }
 
+    #[test]
+    fn rename() {
+        let mut ms = MapStore::new();
+        let mut t = Tree::new();
+        populate(&mut t, &ms);
+        t.write_delta(&mut ms).expect("can write delta");
+
+        // Move the only file of a nested directory into a new nested directory elsewhere.
+        assert!(
+            t.rename(&ms, b"dirB/subdira/subsubdiry/file6", b"dirC/new/deeper/file6")
+                .expect("can rename")
+        );
+        assert_eq!(t.file_count(), 16);
+        assert_eq!(
+            t.get(&ms, b"dirB/subdira/subsubdiry/file6")
+                .expect("can get"),
+            None
+        );
+        assert_eq!(
+            t.get(&ms, b"dirC/new/deeper/file6").expect("can get"),
+            Some(&FileState::new(b'n', 0o644, 6, 10006))
+        );
+        assert!(
+            !t.has_dir(&ms, b"dirB/subdira/subsubdiry/")
+                .expect("can check has_dir")
+        );
+        assert!(t.has_dir(&ms, b"dirB/subdira/").expect("can check has_dir"));
+        assert!(t.has_dir(&ms, b"dirC/new/deeper/").expect("can check has_dir"));
+
+        // Renaming a missing file, or a directory, changes nothing.
+        assert!(!t.rename(&ms, b"dirA/missing", b"dirA/file").expect("can rename"));
+        assert!(!t.rename(&ms, b"dirC/new", b"dirC/old").expect("can rename"));
+        assert!(!t.has_dir(&ms, b"dirC/old/").expect("can check has_dir"));
+        assert_eq!(t.file_count(), 16);
+
+        // Renaming over another file replaces it.
+        assert!(t.rename(&ms, b"file16", b"dirC/file11").expect("can rename"));
+        assert_eq!(t.file_count(), 15);
+        assert_eq!(
+            t.get(&ms, b"dirC/file11").expect("can get"),
+            Some(&FileState::new(b'n', 0o644, 16, 10016))
+        );
+
+        // Renaming to a directory, or inside a file, fails and changes nothing.
+        for to in [
+            &b"dirC/new"[..],
+            b"dirC/file11/file15",
+            b"dirC/file11/sub/file15",
+        ] {
+            assert!(t.rename(&ms, b"dirC/file15", to).is_err());
+            assert_eq!(
+                t.get(&ms, b"dirC/file15").expect("can get"),
+                Some(&FileState::new(b'n', 0o644, 15, 10015))
+            );
+            assert_eq!(
+                t.get(&ms, b"dirC/file11").expect("can get"),
+                Some(&FileState::new(b'n', 0o644, 16, 10016))
+            );
+            assert!(t.has_dir(&ms, b"dirC/new/deeper/").expect("can check has_dir"));
+            assert_eq!(t.file_count(), 15);
+        }
+
+        // A file can be renamed to itself.
+        assert!(t.rename(&ms, b"dirC/file11", b"dirC/file11").expect("can rename"));
+        assert_eq!(t.file_count(), 15);
+
+        let report = t.check(&ms).expect("can check");
+        assert!(report.is_ok());
+        assert_eq!(report.files, 15);
+    }
+
     #[test]
     fn iterate() {
         let ms = MapStore::new();
@@ -1233,6 +1793,349 @@ mod tests {
         );
     }
 
+    #[test]
+    fn is_dirty() {
+        let mut ms = MapStore::new();
+        let mut t = Tree::new();
+        assert!(t.is_dirty());
+        populate(&mut t, &ms);
+        t.write_delta(&mut ms).expect("can write delta");
+        assert!(!t.is_dirty());
+
+        // Adding an identical file does not make the tree dirty.
+        let file7 = FileState::new(b'n', 0o755, 7, 10007);
+        t.add(&ms, b"dirB/subdira/subsubdirz/file7", &file7)
+            .expect("can add file");
+        assert!(!t.is_dirty());
+
+        // Neither does removing a file that does not exist.
+        assert!(!t.remove(&ms, b"dirB/file_missing").expect("can remove"));
+        assert!(!t.is_dirty());
+
+        t.add(
+            &ms,
+            b"dirB/subdira/subsubdirz/file7",
+            &FileState::new(b'n', 0o644, 7, 10007),
+        )
+        .expect("can add file");
+        assert!(t.is_dirty());
+        t.write_delta(&mut ms).expect("can write delta");
+        assert!(!t.is_dirty());
+
+        // A tree opened from the store is clean.
+        let t2 = Tree::<FileState>::open(t.root_id().unwrap(), t.file_count());
+        assert!(!t2.is_dirty());
+    }
+
+    #[test]
+    fn export_and_import() {
+        let mut ms = MapStore::new();
+        let mut t = Tree::new();
+        assert_eq!(t.export(&ms).expect("can export"), vec![]);
+        populate(&mut t, &ms);
+        t.add(&ms, b"dirA.txt", &FileState::new(b'a', 0o644, 17, 10017))
+            .expect("can add file");
+        t.write_delta(&mut ms).expect("can write delta");
+
+        let mut t = Tree::<FileState>::open(t.root_id().unwrap(), t.file_count());
+        let exported = t.export(&ms).expect("can export");
+        assert_eq!(exported.len(), 17);
+        let mut expected = exported.clone();
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(exported, expected);
+        // "." sorts before "/", so this comes before the files in "dirA".
+        assert_eq!(
+            exported[0],
+            (
+                b"dirA.txt".to_vec().into_boxed_slice(),
+                FileState::new(b'a', 0o644, 17, 10017)
+            )
+        );
+
+        // Importing in any order gives back the same tree.
+        let mut t2 = Tree::new();
+        t2.import(&ms, exported.iter().rev().cloned())
+            .expect("can import");
+        assert_eq!(t2.file_count(), 17);
+        assert_eq!(t2.export(&ms).expect("can export"), exported);
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut ms = MapStore::new();
+        let mut t = Tree::new();
+        populate(&mut t, &ms);
+        t.write_delta(&mut ms).expect("can write delta");
+        let mut t = Tree::<FileState>::open(t.root_id().unwrap(), t.file_count());
+        t.add(
+            &ms,
+            b"dirA/subdira/file17",
+            &FileState::new(b'a', 0o644, 17, 10017),
+        )
+        .expect("can add file");
+        let before = t.export(&ms).expect("can export");
+
+        // The snapshot has the changes that were not written yet.
+        let mut snapshot = t.snapshot();
+        assert!(snapshot.is_dirty());
+        assert_eq!(snapshot.file_count(), 17);
+        assert_eq!(snapshot.export(&ms).expect("can export"), before);
+
+        // Changes to either tree are not seen by the other.
+        snapshot
+            .remove(&ms, b"dirB/subdira/file4")
+            .expect("can remove");
+        snapshot
+            .add(
+                &ms,
+                b"dirA/subdira/file17",
+                &FileState::new(b'm', 0o644, 18, 10018),
+            )
+            .expect("can add file");
+        t.remove(&ms, b"dirC/file11").expect("can remove");
+        assert_eq!(snapshot.file_count(), 16);
+        assert_eq!(t.file_count(), 16);
+        assert!(t.get(&ms, b"dirB/subdira/file4").unwrap().is_some());
+        assert_eq!(
+            t.get(&ms, b"dirA/subdira/file17").unwrap(),
+            Some(&FileState::new(b'a', 0o644, 17, 10017))
+        );
+        assert!(snapshot.get(&ms, b"dirC/file11").unwrap().is_some());
+
+        // Writing one tree does not affect the other.
+        let snapshot_id = snapshot.write_delta(&mut ms).expect("can write delta");
+        let mut reopened = Tree::<FileState>::open(snapshot_id, snapshot.file_count());
+        assert_eq!(
+            reopened.export(&ms).expect("can export"),
+            snapshot.export(&ms).expect("can export")
+        );
+        t.add(&ms, b"dirC/file11", &FileState::new(b'n', 0o644, 11, 10011))
+            .expect("can add file");
+        assert_eq!(t.export(&ms).expect("can export"), before);
+
+        // A snapshot of a clean tree shares its root.
+        let clean = reopened.snapshot();
+        assert!(!clean.is_dirty());
+        assert_eq!(clean.root_id(), Some(snapshot_id));
+    }
+
+    #[test]
+    fn with_transaction() {
+        let mut ms = MapStore::new();
+        let mut t = Tree::new();
+        populate(&mut t, &ms);
+        t.write_delta(&mut ms).expect("can write delta");
+        t.add(&ms, b"dirC/file17", &FileState::new(b'a', 0o644, 17, 10017))
+            .expect("can add file");
+        let before = t.export(&ms).expect("can export");
+
+        // A batch that fails part way leaves the tree unchanged.
+        t.with_transaction(|t| {
+            t.remove(&ms, b"dirA/subdira/file1")?;
+            t.remove(&ms, b"dirC/file17")?;
+            t.add(&ms, b"dirD/file18", &FileState::new(b'a', 0o644, 18, 10018))?;
+            t.rename(&ms, b"file16", b"dirD//file16")
+        })
+        .expect_err("rename to an invalid path fails");
+        assert_eq!(t.file_count(), 17);
+        assert_eq!(t.export(&ms).expect("can export"), before);
+        assert!(!t.has_dir(&ms, b"dirD/").expect("can check has_dir"));
+
+        // A batch that succeeds is kept.
+        let removed = t
+            .with_transaction(|t| {
+                t.add(&ms, b"dirD/file18", &FileState::new(b'a', 0o644, 18, 10018))?;
+                t.remove(&ms, b"dirC/file17")
+            })
+            .expect("can apply transaction");
+        assert!(removed);
+        assert_eq!(t.file_count(), 17);
+        assert!(t.get(&ms, b"dirC/file17").unwrap().is_none());
+        assert!(t.get(&ms, b"dirD/file18").unwrap().is_some());
+        assert!(t.check(&ms).expect("can check").is_ok());
+    }
+
+    #[test]
+    fn check() {
+        let mut ms = MapStore::new();
+        let mut t = Tree::new();
+        let empty = t.check(&ms).expect("can check");
+        assert!(empty.is_ok());
+        assert_eq!(empty.files, 0);
+
+        populate(&mut t, &ms);
+        let report = t.check(&ms).expect("can check");
+        assert!(report.is_ok());
+        assert_eq!(report.files, 16);
+
+        t.write_delta(&mut ms).expect("can write delta");
+        let root_id = t.root_id().unwrap();
+        assert!(
+            Tree::<FileState>::open(root_id, 16)
+                .check(&ms)
+                .expect("can check")
+                .is_ok()
+        );
+
+        // A tree whose file count is out of sync.
+        let report = Tree::<FileState>::open(root_id, 15)
+            .check(&ms)
+            .expect("can check");
+        assert_eq!(report.files, 16);
+        assert_eq!(
+            report.problems,
+            vec![CheckProblem::FileCountMismatch {
+                recorded: 15,
+                found: 16,
+            }]
+        );
+    }
+
+    #[test]
+    fn check_bad_blocks() {
+        let mut ms = MapStore::new();
+        let bad_id = ms.append(&[1, b'x']).expect("can append");
+        let mut data = Vec::new();
+        NodeEntryMap::<FileState>::new()
+            .serialize(&mut data)
+            .expect("can serialize");
+        let empty_id = ms.append(&data).expect("can append");
+        let mut root_entries = NodeEntryMap::<FileState>::new();
+        root_entries.insert(
+            b"bad/".to_vec().into_boxed_slice(),
+            NodeEntry::Directory(Node::open(bad_id)),
+        );
+        root_entries.insert(
+            b"empty/".to_vec().into_boxed_slice(),
+            NodeEntry::Directory(Node::open(empty_id)),
+        );
+        root_entries.insert(
+            b"file".to_vec().into_boxed_slice(),
+            NodeEntry::File(FileState::new(b'n', 0o644, 1, 1)),
+        );
+        let mut data = Vec::new();
+        root_entries.serialize(&mut data).expect("can serialize");
+        let root_id = ms.append(&data).expect("can append");
+
+        // All problems are reported, rather than just the first one.
+        let report = Tree::<FileState>::open(root_id, 1)
+            .check(&ms)
+            .expect("can check");
+        assert_eq!(report.files, 1);
+        assert_eq!(report.problems.len(), 2);
+        match &report.problems[0] {
+            CheckProblem::UnreadableBlock { block_id, path, .. } => {
+                assert_eq!(*block_id, bad_id.0);
+                assert_eq!(path.as_ref(), b"bad/");
+            }
+            problem => panic!("unexpected problem {:?}", problem),
+        }
+        assert_eq!(
+            report.problems[1],
+            CheckProblem::EmptyDirectory {
+                path: b"empty/".to_vec().into_boxed_slice()
+            }
+        );
+
+        // The root directory itself cannot be read.
+        let report = Tree::<FileState>::open(bad_id, 0)
+            .check(&ms)
+            .expect("can check");
+        assert_eq!(report.files, 0);
+        assert_eq!(report.problems.len(), 1);
+    }
+
+    #[test]
+    fn read_only() {
+        let ns = NullStore::new();
+        let mut ms = MapStore::new();
+        let mut t = Tree::new();
+        populate(&mut t, &ms);
+        t.write_full(&mut ms, &ns).expect("can write full");
+        let t_root = t.root_id().unwrap();
+        let t_count = t.file_count();
+
+        let t2 = Arc::new(ReadOnlyTree::<FileState>::open(t_root, t_count));
+        let ms = Arc::new(ms);
+        assert_eq!(t2.file_count(), 16);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let t2 = t2.clone();
+                let ms = ms.clone();
+                std::thread::spawn(move || {
+                    for &(name, mode, size, mtime) in TEST_FILES.iter() {
+                        assert_eq!(
+                            t2.get(&*ms, name).expect("can get"),
+                            Some(FileState::new(b'n', mode, size, mtime))
+                        );
+                    }
+                    assert_eq!(t2.get(&*ms, b"dirB/subdira/").expect("can get"), None);
+                    assert_eq!(t2.get(&*ms, b"dirB/missing").expect("can get"), None);
+                    assert!(
+                        t2.has_dir(&*ms, b"dirB/subdira/")
+                            .expect("can check has_dir")
+                    );
+                    assert!(
+                        !t2.has_dir(&*ms, b"dirB/subdira/subsubdirz/file7/")
+                            .expect("can check has_dir")
+                    );
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("reader thread succeeds");
+        }
+    }
+
+    #[test]
+    fn corrupt_block() {
+        let mut ms = MapStore::new();
+        // A directory block with a single entry of an unknown type.
+        let bad_id = ms.append(&[1, b'x']).expect("can append");
+        let mut sub_entries = NodeEntryMap::<FileState>::new();
+        sub_entries.insert(
+            b"sub/".to_vec().into_boxed_slice(),
+            NodeEntry::Directory(Node::open(bad_id)),
+        );
+        let mut data = Vec::new();
+        sub_entries.serialize(&mut data).expect("can serialize");
+        let dir_id = ms.append(&data).expect("can append");
+        let mut root_entries = NodeEntryMap::<FileState>::new();
+        root_entries.insert(
+            b"dir/".to_vec().into_boxed_slice(),
+            NodeEntry::Directory(Node::open(dir_id)),
+        );
+        let mut data = Vec::new();
+        root_entries.serialize(&mut data).expect("can serialize");
+        let root_id = ms.append(&data).expect("can append");
+
+        let expected = format!(
+            "treedirstate is corrupt: invalid block {} for directory 'dir/sub/'",
+            bad_id.0
+        );
+        let mut t = Tree::<FileState>::open(root_id, 1);
+        let err = t.get(&ms, b"dir/sub/file").expect_err("block is corrupt");
+        assert_eq!(err.to_string(), expected);
+        let mut v = |_: &Vec<KeyRef>, _: &mut FileState| Ok(VisitorResult::NotChanged);
+        let err = t.visit(&ms, &mut v).expect_err("block is corrupt");
+        assert_eq!(err.to_string(), expected);
+
+        let t = ReadOnlyTree::<FileState>::open(root_id, 1);
+        let err = t.get(&ms, b"dir/sub/file").expect_err("block is corrupt");
+        assert_eq!(err.to_string(), expected);
+
+        // The root block has an empty path.
+        let mut t = Tree::<FileState>::open(bad_id, 1);
+        let err = t.get(&ms, b"file").expect_err("block is corrupt");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "treedirstate is corrupt: invalid block {} for directory ''",
+                bad_id.0
+            )
+        );
+    }
+
     #[test]
     fn visit() {
         let mut ms = MapStore::new();
@@ -1290,6 +2193,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn visit_filtered() {
+        let ms = MapStore::new();
+        let mut t = Tree::new();
+        populate(&mut t, &ms);
+        t.add(
+            &ms,
+            b"dirB/subdirb/file9",
+            &FileState::new(b'm', 0o644, 9, 10009),
+        )
+        .expect("can add");
+
+        let paths = t
+            .visit_filtered(&ms, |fs| fs.mode == 0o755)
+            .expect("can visit_filtered");
+        assert_eq!(
+            paths,
+            vec![
+                b"dirB/subdira/subsubdirz/file7".to_vec().into_boxed_slice(),
+                b"dirB/subdira/subsubdirz/file8".to_vec().into_boxed_slice(),
+            ]
+        );
+
+        let paths = t
+            .visit_filtered(&ms, |fs| fs.state == b'm')
+            .expect("can visit_filtered");
+        assert_eq!(
+            paths,
+            vec![b"dirB/subdirb/file9".to_vec().into_boxed_slice()]
+        );
+
+        let paths = t
+            .visit_filtered(&ms, |_| false)
+            .expect("can visit_filtered");
+        assert!(paths.is_empty());
+    }
+
     #[test]
     fn filtered_keys() {
         let ms = MapStore::new();
@@ -143,8 +143,12 @@ impl TreeDirstate {
         self.write_root()
     }
 
-    /// Write updated entries in the treedirstate to the store.
+    /// Write updated entries in the treedirstate to the store.  Nothing is written if the
+    /// treedirstate has not changed since it was last written or opened.
     pub fn write_delta(&mut self) -> Result<()> {
+        if self.root_id.is_some() && !self.is_dirty() {
+            return Ok(());
+        }
         {
             match self.store {
                 Backend::Empty(ref mut store) => {
@@ -171,6 +175,12 @@ impl TreeDirstate {
         self.root_id
     }
 
+    /// Returns true if any entries have changed since the treedirstate was last written or
+    /// opened.
+    pub fn is_dirty(&self) -> bool {
+        self.tracked.is_dirty() || self.removed.is_dirty()
+    }
+
     /// Returns the current append offset for the file store.
     pub fn store_offset(&self) -> Option<u64> {
         self.store.offset()
@@ -394,4 +404,35 @@ mod tests {
         assert_eq!(ds2.tracked_count(), 0);
         assert_eq!(ds2.removed_count(), 0);
     }
+
+    #[test]
+    fn write_delta_skips_clean() {
+        let dir = TempDir::new("dirstate_test").expect("create temp dir");
+        let p = dir.path().join("store");
+        let mut ds = TreeDirstate::new();
+        ds.write_full(&p)
+            .expect("can write full empty treedirstate");
+        ds.add_file(b"dirA/file1", &make_state(b'n'))
+            .expect("can add");
+        assert!(ds.is_dirty());
+        ds.write_delta().expect("can write delta");
+        assert!(!ds.is_dirty());
+
+        // Re-adding an identical entry, or dropping a missing one, changes nothing.
+        let offset = ds.store_offset();
+        let root_id = ds.root_id();
+        ds.add_file(b"dirA/file1", &make_state(b'n'))
+            .expect("can add");
+        ds.drop_file(b"dirA/file2").expect("can drop");
+        assert!(!ds.is_dirty());
+        ds.write_delta().expect("can write delta");
+        assert_eq!(ds.store_offset(), offset);
+        assert_eq!(ds.root_id(), root_id);
+
+        ds.add_file(b"dirA/file1", &make_state(b'm'))
+            .expect("can add");
+        assert!(ds.is_dirty());
+        ds.write_delta().expect("can write delta");
+        assert_ne!(ds.store_offset(), offset);
+    }
 }
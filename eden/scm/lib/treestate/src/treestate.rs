@@ -16,6 +16,7 @@ use crate::filestore::FileStore;
 use crate::serialization::Serializable;
 use crate::store::BlockId;
 use crate::store::Store;
+use crate::store::StoreStats;
 use crate::store::StoreView;
 use crate::tree::AggregatedState;
 use crate::tree::Key;
@@ -32,6 +33,8 @@ pub struct TreeState {
     store: FileStore,
     tree: Tree<FileStateV2>,
     root: TreeStateRoot,
+    /// The block `root` was last read from or written to, if any.
+    root_id: Option<BlockId>,
 }
 
 /// `TreeStateRoot` contains block id to the root `Tree`, and other metadata.
@@ -54,13 +57,23 @@ impl TreeState {
                     TreeStateRoot::deserialize(&mut root_buf)?
                 };
                 let tree = Tree::open(root.tree_block_id, root.file_count);
-                Ok(TreeState { store, tree, root })
+                Ok(TreeState {
+                    store,
+                    tree,
+                    root,
+                    root_id: Some(root_id),
+                })
             }
             None => {
                 let store = FileStore::create(path)?;
                 let root = TreeStateRoot::default();
                 let tree = Tree::new();
-                Ok(TreeState { store, tree, root })
+                Ok(TreeState {
+                    store,
+                    tree,
+                    root,
+                    root_id: None,
+                })
             }
         }
     }
@@ -88,9 +101,25 @@ impl TreeState {
         self.root.serialize(&mut root_buf)?;
         let result = self.store.append(&root_buf)?;
         self.store.flush()?;
+        self.root_id = Some(result);
         Ok(result)
     }
 
+    /// Block-level statistics for the backing store.  Blocks written by earlier flushes that
+    /// the last flushed root no longer references count towards `total` but not `live`.
+    pub fn store_stats(&self) -> Result<StoreStats> {
+        let mut stats = StoreStats {
+            total: self.store.stats()?,
+            live: Default::default(),
+        };
+        if let Some(root_id) = self.root_id {
+            stats.live =
+                Tree::<FileStateV2>::live_block_stats(self.root.tree_block_id, &self.store)?;
+            stats.live.add_block(self.store.read(root_id)?.len());
+        }
+        Ok(stats)
+    }
+
     /// Create or replace the existing entry.
     pub fn insert<K: AsRef<[u8]>>(&mut self, path: K, state: &FileStateV2) -> Result<()> {
         self.tree.add(&self.store, path.as_ref(), state)
@@ -435,6 +464,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_store_stats() {
+        let dir = TempDir::new("treestate").expect("tempdir");
+        let mut state = new_treestate(dir.path().join("1"));
+        assert_eq!(state.store_stats().expect("stats"), StoreStats::default());
+
+        state.flush().expect("flush");
+        let stats = state.store_stats().expect("stats");
+        // Nothing has been overwritten yet, so every block is live.
+        assert!(stats.total.blocks > 1);
+        assert_eq!(stats.live, stats.total);
+
+        // Each flush after a change rewrites the changed directories up to the root, leaving the
+        // old copies behind.
+        let mut rng = ChaChaRng::from_seed([1; 32]);
+        let mut previous = stats;
+        for _ in 0..10 {
+            for path in &SAMPLE_PATHS {
+                let file: FileStateV2 = rng.gen();
+                state.insert(path, &file).expect("insert");
+            }
+            state.flush().expect("flush");
+            let stats = state.store_stats().expect("stats");
+            assert_eq!(stats.live.blocks, previous.live.blocks);
+            assert_eq!(
+                stats.total.blocks,
+                previous.total.blocks + stats.live.blocks
+            );
+            previous = stats;
+        }
+
+        // A full write only keeps the live blocks.  Their sizes can change slightly, as the IDs
+        // of child blocks are encoded as varints.
+        let root_id = state.write_as(dir.path().join("2")).expect("write_as");
+        let stats = state.store_stats().expect("stats");
+        assert_eq!(stats.live, stats.total);
+        assert_eq!(stats.live.blocks, previous.live.blocks);
+        let state = TreeState::open(dir.path().join("2"), root_id.into()).expect("open");
+        assert_eq!(state.store_stats().expect("stats"), stats);
+    }
+
     #[test]
     fn test_get_keys_ignorecase() {
         let dir = TempDir::new("treestate").expect("tempdir");
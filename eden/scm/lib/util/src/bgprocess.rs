@@ -5,10 +5,12 @@
  * GNU General Public License version 2.
  */
 
+use std::io;
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
 use std::process::Child;
 use std::process::Command;
+use std::process::ExitStatus;
 #[cfg(unix)]
 use std::process::Stdio;
 
@@ -18,19 +20,63 @@ use winapi::um::winbase::CREATE_NEW_PROCESS_GROUP;
 #[cfg(windows)]
 use winapi::um::winbase::CREATE_NO_WINDOW;
 
-pub fn run_background(mut command: Command) -> Result<Child> {
+/// A process started by [`run_background`].
+///
+/// Dropping it neither waits for nor terminates the process, which keeps
+/// running on its own.
+pub struct BackgroundProcess {
+    child: Child,
+}
+
+impl BackgroundProcess {
+    /// The OS process id.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Wait for the process to exit.
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait()
+    }
+
+    /// Ask the process to exit: send `SIGTERM` on Unix, and call
+    /// `TerminateProcess` on Windows. Does nothing if it already exited.
+    pub fn terminate(&mut self) -> io::Result<()> {
+        // Once the process is reaped its id can be reused, so check first.
+        if self.child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        #[cfg(unix)]
+        {
+            if unsafe { libc::kill(self.child.id() as libc::pid_t, libc::SIGTERM) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+        #[cfg(windows)]
+        {
+            // Child::kill uses TerminateProcess on Windows.
+            self.child.kill()
+        }
+    }
+}
+
+/// Start `command` detached from the current process: without a console
+/// window on Windows, and with null stdio on Unix. Returns a handle that
+/// can be used to wait for or terminate the process.
+pub fn run_background(mut command: Command) -> Result<BackgroundProcess> {
     #[cfg(windows)]
     {
         command.creation_flags(CREATE_NO_WINDOW | CREATE_NEW_PROCESS_GROUP);
-        command.spawn().map_err(|e| e.into())
     }
     #[cfg(unix)]
     {
         command.stderr(Stdio::null());
         command.stdout(Stdio::null());
         command.stdin(Stdio::null());
-        command.spawn().map_err(|e| e.into())
     }
+    let child = command.spawn()?;
+    Ok(BackgroundProcess { child })
 }
 
 #[cfg(test)]
@@ -59,9 +105,38 @@ mod tests {
             cmd
         };
 
-        let mut child = run_background(cmd).unwrap();
-        child.wait().unwrap();
+        let mut process = run_background(cmd).unwrap();
+        process.wait().unwrap();
 
         assert!(file_path.exists());
+        // Terminating a process that already exited is fine.
+        process.terminate().unwrap();
+    }
+
+    #[test]
+    fn test_terminate() {
+        #[cfg(unix)]
+        let cmd = {
+            let mut cmd = Command::new("/bin/sh");
+            cmd.arg("-c").arg("sleep 60");
+            cmd
+        };
+        #[cfg(windows)]
+        let cmd = {
+            let mut cmd = Command::new("cmd.exe");
+            cmd.arg("/c").arg("ping -n 60 127.0.0.1 > nul");
+            cmd
+        };
+
+        let mut process = run_background(cmd).unwrap();
+        assert!(process.id() > 0);
+        process.terminate().unwrap();
+        let status = process.wait().unwrap();
+        assert!(!status.success());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            assert_eq!(status.signal(), Some(libc::SIGTERM));
+        }
     }
 }
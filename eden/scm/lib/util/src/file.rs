@@ -5,6 +5,20 @@
  * GNU General Public License version 2.
  */
 
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
 #[cfg(unix)]
 use once_cell::sync::Lazy;
 
@@ -22,3 +36,506 @@ pub fn apply_umask(mode: u32) -> u32 {
 }
 
 pub use atomicfile::atomic_write;
+
+/// Error returned by [`rename_checked`] when `src` and `dst` are on different
+/// filesystems. It is wrapped in an [`io::Error`], and can be recovered with
+/// `err.get_ref().and_then(|e| e.downcast_ref::<CrossDeviceError>())`.
+#[derive(Debug)]
+pub struct CrossDeviceError {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+}
+
+impl fmt::Display for CrossDeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cannot rename {} to {}: they are on different filesystems",
+            self.src.display(),
+            self.dst.display()
+        )
+    }
+}
+
+impl Error for CrossDeviceError {}
+
+/// Rename `src` to `dst`, reporting a [`CrossDeviceError`] naming both paths
+/// instead of the bare `EXDEV` error from [`fs::rename`] when they are on
+/// different filesystems.
+///
+/// On Unix the device ids of `src` and of the directory containing `dst` are
+/// compared up front. Elsewhere, the cross-device error from the OS is
+/// translated after the rename fails.
+///
+/// If `copy_fallback` is set, a cross-device rename of a regular file is done
+/// by copying `src` to `dst` and then removing `src`. Unlike a rename, this is
+/// not atomic: `dst` can be observed partially written.
+pub fn rename_checked(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    copy_fallback: bool,
+) -> io::Result<()> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+    if same_device(src, dst) == Some(false) {
+        return rename_cross_device(src, dst, copy_fallback);
+    }
+    match fs::rename(src, dst) {
+        Err(e) if is_cross_device(&e) => rename_cross_device(src, dst, copy_fallback),
+        result => result,
+    }
+}
+
+fn rename_cross_device(src: &Path, dst: &Path, copy_fallback: bool) -> io::Result<()> {
+    if copy_fallback && fs::symlink_metadata(src)?.is_file() {
+        fs::copy(src, dst)?;
+        fs::remove_file(src)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            CrossDeviceError {
+                src: src.to_path_buf(),
+                dst: dst.to_path_buf(),
+            },
+        ))
+    }
+}
+
+/// Whether `src` and the directory `dst` would be created in are on the same
+/// device. `None` if that cannot be determined, in which case the rename is
+/// attempted and reports its own error.
+#[cfg(unix)]
+fn same_device(src: &Path, dst: &Path) -> Option<bool> {
+    let dst_dir = match dst.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let src_dev = fs::symlink_metadata(src).ok()?.dev();
+    let dst_dev = fs::metadata(dst_dir).ok()?.dev();
+    Some(src_dev == dst_dev)
+}
+
+#[cfg(not(unix))]
+fn same_device(_src: &Path, _dst: &Path) -> Option<bool> {
+    None
+}
+
+fn is_cross_device(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    return err.raw_os_error() == Some(libc::EXDEV);
+    // ERROR_NOT_SAME_DEVICE
+    #[cfg(windows)]
+    return err.raw_os_error() == Some(17);
+    #[cfg(all(not(unix), not(windows)))]
+    return false;
+}
+
+/// Read the whole file at `path` like [`fs::read`], retrying a few times if
+/// it fails because a concurrent [`atomic_write`] is replacing it.
+///
+/// On Windows, opening a file while another file is being renamed over it
+/// can fail with a sharing violation, access denied or, for a moment, not
+/// found. Those errors are retried with the same backoff as the rename
+/// retries in [`atomic_write`]. This only papers over the short window in
+/// which the file is replaced: a file that is really missing or locked still
+/// fails, after a few milliseconds. Elsewhere, a rename does not affect
+/// readers, and this is a plain [`fs::read`].
+pub fn read_atomic(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    read_atomic_impl(path.as_ref())
+}
+
+#[cfg(windows)]
+fn read_atomic_impl(path: &Path) -> io::Result<Vec<u8>> {
+    const MAX_RETRIES: u16 = 5;
+    // ERROR_ACCESS_DENIED, ERROR_SHARING_VIOLATION
+    let is_replace_race = |err: &io::Error| {
+        err.kind() == io::ErrorKind::NotFound || matches!(err.raw_os_error(), Some(5) | Some(32))
+    };
+
+    let mut retry = 0;
+    loop {
+        match fs::read(path) {
+            Err(e) if retry < MAX_RETRIES && is_replace_race(&e) => {
+                std::thread::sleep(std::time::Duration::from_millis(1 << retry));
+                retry += 1;
+            }
+            result => break result,
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn read_atomic_impl(path: &Path) -> io::Result<Vec<u8>> {
+    fs::read(path)
+}
+
+/// Make `dst` a copy-on-write clone of the regular file `src`, without
+/// copying its content. `dst` must not exist yet.
+///
+/// This uses the `FICLONE` ioctl on Linux (btrfs, XFS) and `clonefile` on
+/// macOS (APFS). Returns `Ok(false)`, leaving no `dst` behind, if the
+/// filesystem or platform does not support it, including when `src` and
+/// `dst` are on different filesystems. Callers can then fall back to a normal
+/// copy. Always returns `Ok(false)` on Windows.
+pub fn clone_file(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<bool> {
+    clone_file_impl(src.as_ref(), dst.as_ref())
+}
+
+#[cfg(target_os = "linux")]
+fn clone_file_impl(src: &Path, dst: &Path) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // _IOW(0x94, 9, int) from linux/fs.h.
+    #[cfg(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc64"
+    ))]
+    const FICLONE: u32 = 0x80049409;
+    #[cfg(not(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc64"
+    )))]
+    const FICLONE: u32 = 0x40049409;
+
+    let src_file = fs::File::open(src)?;
+    let metadata = src_file.metadata()?;
+    if !metadata.is_file() {
+        return Ok(false);
+    }
+    let dst_file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dst)?;
+    let ret = unsafe { libc::ioctl(dst_file.as_raw_fd(), FICLONE as _, src_file.as_raw_fd()) };
+    let result = if ret == 0 {
+        dst_file
+            .set_permissions(metadata.permissions())
+            .map(|_| true)
+    } else {
+        let err = io::Error::last_os_error();
+        if is_clone_unsupported(&err) {
+            Ok(false)
+        } else {
+            Err(err)
+        }
+    };
+    if !matches!(result, Ok(true)) {
+        drop(dst_file);
+        let _ = fs::remove_file(dst);
+    }
+    result
+}
+
+#[cfg(target_os = "macos")]
+fn clone_file_impl(src: &Path, dst: &Path) -> io::Result<bool> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    extern "C" {
+        fn clonefile(src: *const libc::c_char, dst: *const libc::c_char, flags: u32) -> i32;
+    }
+    // Clone a symlink itself instead of following it.
+    const CLONE_NOFOLLOW: u32 = 1;
+
+    if !fs::symlink_metadata(src)?.is_file() {
+        return Ok(false);
+    }
+    let src = CString::new(src.as_os_str().as_bytes())?;
+    let dst = CString::new(dst.as_os_str().as_bytes())?;
+    if unsafe { clonefile(src.as_ptr(), dst.as_ptr(), CLONE_NOFOLLOW) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if is_clone_unsupported(&err) {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn clone_file_impl(_src: &Path, _dst: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Whether a failed clone means the filesystem cannot clone `src` to `dst`,
+/// as opposed to a real error like a missing permission.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn is_clone_unsupported(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(libc::EXDEV) | Some(libc::ENOTSUP) | Some(libc::ENOSYS) | Some(libc::ENOTTY) => true,
+        // Linux reports EINVAL if the filesystem supports cloning, but not for
+        // these files.
+        #[cfg(target_os = "linux")]
+        Some(libc::EINVAL) => true,
+        _ => false,
+    }
+}
+
+/// Number of threads that [`sync_tree`] issues fsyncs from.
+const SYNC_TREE_THREADS: usize = 16;
+
+/// Make `root` and all the regular files and directories under it durable,
+/// by fsyncing each of them. Use this after writing a whole tree, e.g. when
+/// extracting an archive, instead of syncing every file as it is written.
+///
+/// The tree is listed first, then the fsyncs are issued from a few threads so
+/// that the filesystem can work on them concurrently. Symlinks are not
+/// followed. They, and other special files, are not synced themselves: their
+/// entries become durable when the directory containing them is synced.
+///
+/// Directories cannot be synced on Windows, so only files are synced there.
+/// New directory entries are then only as durable as the filesystem makes
+/// them.
+///
+/// Fails with the first error found, naming the path it is about. Paths not
+/// synced yet are skipped after an error.
+pub fn sync_tree(root: impl AsRef<Path>) -> io::Result<()> {
+    let mut paths = Vec::new();
+    list_tree(root.as_ref(), &mut paths)?;
+
+    let paths = Arc::new(paths);
+    let next = Arc::new(AtomicUsize::new(0));
+    let failed = Arc::new(AtomicBool::new(false));
+    let threads: Vec<_> = (0..SYNC_TREE_THREADS.min(paths.len()))
+        .map(|_| {
+            let paths = paths.clone();
+            let next = next.clone();
+            let failed = failed.clone();
+            thread::spawn(move || -> io::Result<()> {
+                while !failed.load(Ordering::Relaxed) {
+                    let (path, is_dir) = match paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        Some(entry) => entry,
+                        None => break,
+                    };
+                    if let Err(err) = sync_path(path, *is_dir) {
+                        failed.store(true, Ordering::Relaxed);
+                        return Err(io::Error::new(
+                            err.kind(),
+                            format!("cannot sync {}: {}", path.display(), err),
+                        ));
+                    }
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    let mut result = Ok(());
+    for thread in threads {
+        let thread_result = thread.join().expect("sync_tree thread panicked");
+        if result.is_ok() {
+            result = thread_result;
+        }
+    }
+    result
+}
+
+/// Add `path` and, if it is a directory, the regular files and directories
+/// under it to `paths`, each with whether it is a directory.
+fn list_tree(path: &Path, paths: &mut Vec<(PathBuf, bool)>) -> io::Result<()> {
+    let file_type = fs::metadata(path)?.file_type();
+    if file_type.is_file() {
+        paths.push((path.to_path_buf(), false));
+    } else if file_type.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_file() || file_type.is_dir() {
+                list_tree(&entry.path(), paths)?;
+            }
+        }
+        if cfg!(not(windows)) {
+            paths.push((path.to_path_buf(), true));
+        }
+    }
+    Ok(())
+}
+
+fn sync_path(path: &Path, is_dir: bool) -> io::Result<()> {
+    // FlushFileBuffers on Windows needs a handle with write access.
+    let file = if cfg!(windows) && !is_dir {
+        fs::OpenOptions::new().write(true).open(path)?
+    } else {
+        fs::File::open(path)?
+    };
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_rename_checked() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::write(&src, b"data")?;
+        rename_checked(&src, &dst, false)?;
+        assert!(!src.exists());
+        assert_eq!(fs::read(&dst)?, b"data");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_rename_checked_cross_device() -> io::Result<()> {
+        // /dev/shm is usually a tmpfs. Skip if it is on the same device as the
+        // temporary directory.
+        let shm = Path::new("/dev/shm");
+        let dir = TempDir::new()?;
+        let other = match tempfile::tempdir_in(shm) {
+            Ok(other) => other,
+            Err(_) => return Ok(()),
+        };
+        if same_device(dir.path(), &other.path().join("x")) != Some(false) {
+            return Ok(());
+        }
+
+        let src = dir.path().join("src");
+        let dst = other.path().join("dst");
+        fs::write(&src, b"data")?;
+
+        let err = rename_checked(&src, &dst, false).unwrap_err();
+        let cross_device = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<CrossDeviceError>())
+            .unwrap();
+        assert_eq!(cross_device.src, src);
+        assert_eq!(cross_device.dst, dst);
+        assert!(err.to_string().contains(&*dst.to_string_lossy()));
+        assert!(src.exists());
+
+        rename_checked(&src, &dst, true)?;
+        assert!(!src.exists());
+        assert_eq!(fs::read(&dst)?, b"data");
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_atomic() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("file");
+        assert_eq!(
+            read_atomic(&path).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        fs::write(&path, b"old")?;
+        assert_eq!(read_atomic(&path)?, b"old");
+
+        // Reads racing with atomic writes see either content in full.
+        let writer = {
+            let path = path.clone();
+            std::thread::spawn(move || -> io::Result<()> {
+                for _ in 0..100 {
+                    atomic_write(&path, 0o644, false, |f| {
+                        io::Write::write_all(f, b"new content")
+                    })?;
+                }
+                Ok(())
+            })
+        };
+        for _ in 0..100 {
+            let data = read_atomic(&path)?;
+            assert!(data == b"old" || data == b"new content");
+        }
+        writer.join().unwrap()?;
+        assert_eq!(read_atomic(&path)?, b"new content");
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_file() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::write(&src, b"data")?;
+
+        // Whether the temporary directory supports cloning depends on the
+        // filesystem. Either way, there must be no partial `dst` left behind.
+        if clone_file(&src, &dst)? {
+            assert_eq!(fs::read(&dst)?, b"data");
+            // The clone is independent of the original.
+            fs::write(&dst, b"changed")?;
+            assert_eq!(fs::read(&src)?, b"data");
+            // An existing `dst` is not overwritten.
+            assert!(clone_file(&src, &dst).is_err());
+        } else {
+            assert!(!dst.exists());
+            fs::copy(&src, &dst)?;
+            assert_eq!(fs::read(&dst)?, b"data");
+        }
+
+        assert!(clone_file(dir.path().join("missing"), dir.path().join("x")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_tree() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join("a/b"))?;
+        fs::create_dir(root.join("empty"))?;
+        for name in ["x", "a/y", "a/b/z"] {
+            fs::write(root.join(name), name)?;
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("missing", root.join("a/link"))?;
+
+        sync_tree(&root)?;
+        sync_tree(root.join("x"))?;
+
+        let mut paths = Vec::new();
+        list_tree(&root, &mut paths)?;
+        paths.sort();
+        let relative: Vec<_> = paths
+            .iter()
+            .map(|(path, is_dir)| (path.strip_prefix(&root).unwrap().to_path_buf(), *is_dir))
+            .collect();
+        let mut expected = vec![
+            (PathBuf::from("a/b/z"), false),
+            (PathBuf::from("a/y"), false),
+            (PathBuf::from("x"), false),
+        ];
+        if cfg!(not(windows)) {
+            expected.extend([
+                (PathBuf::from(""), true),
+                (PathBuf::from("a"), true),
+                (PathBuf::from("a/b"), true),
+                (PathBuf::from("empty"), true),
+            ]);
+        }
+        expected.sort();
+        assert_eq!(relative, expected);
+
+        let err = sync_tree(root.join("missing")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_clone_file_unsupported() -> io::Result<()> {
+        // tmpfs does not support cloning.
+        let dir = match tempfile::tempdir_in("/dev/shm") {
+            Ok(dir) => dir,
+            Err(_) => return Ok(()),
+        };
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::write(&src, b"data")?;
+        assert!(!clone_file(&src, &dst)?);
+        assert!(!dst.exists());
+        Ok(())
+    }
+}
@@ -20,3 +20,4 @@ pub mod lock;
 pub mod path;
 
 pub use bgprocess::run_background;
+pub use bgprocess::BackgroundProcess;
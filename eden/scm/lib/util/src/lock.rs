@@ -8,40 +8,199 @@
 use std::fs;
 use std::fs::File;
 use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
+use std::path::PathBuf;
 
 use fs2::FileExt;
 
 /// RAII lock on a filesystem path.
+///
+/// The lock is released when this is dropped. Use [`PathLock::leak`] to keep
+/// it held beyond that.
 #[derive(Debug)]
 pub struct PathLock {
-    file: File,
+    // Always `Some`, except after `leak` or `drop` took it.
+    file: Option<File>,
+    path: PathBuf,
+    remove_on_drop: bool,
+    shared: bool,
 }
 
 impl PathLock {
     /// Take an exclusive lock on `path`. The lock file will be created on
     /// demand.
     pub fn exclusive<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let file = fs::OpenOptions::new().write(true).create(true).open(path)?;
-        file.lock_exclusive()?;
-        Ok(PathLock { file })
+        Self::lock(path.as_ref(), false)
+    }
+
+    /// Take a shared lock on `path`. The lock file will be created on
+    /// demand.
+    ///
+    /// Any number of shared locks can be held at the same time, but they
+    /// exclude exclusive locks. This suits readers that only need to keep
+    /// writers out.
+    ///
+    /// Upgrading a shared lock to an exclusive one, or downgrading, is not
+    /// supported: drop the lock and take the other kind. Other lockers can
+    /// get in between.
+    pub fn shared<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::lock(path.as_ref(), true)
+    }
+
+    fn lock(path: &Path, shared: bool) -> io::Result<Self> {
+        loop {
+            let file = fs::OpenOptions::new().write(true).create(true).open(path)?;
+            if shared {
+                file.lock_shared()?;
+            } else {
+                file.lock_exclusive()?;
+            }
+            // The previous holder might have removed the lock file (see
+            // `remove_on_drop`) after we opened it. Holding a lock on the
+            // removed file would not exclude anyone opening `path` from now
+            // on, so start over with the new file in that case.
+            if is_locked_file(&file, path)? {
+                return Ok(PathLock {
+                    file: Some(file),
+                    path: path.to_path_buf(),
+                    remove_on_drop: false,
+                    shared,
+                });
+            }
+        }
+    }
+
+    /// Remove the lock file when the lock is released, instead of leaving it
+    /// behind for the next locker.
+    ///
+    /// This has no effect on shared locks, since other holders of the shared
+    /// lock would no longer exclude new lockers.
+    pub fn remove_on_drop(mut self) -> Self {
+        self.remove_on_drop = true;
+        self
     }
 
     pub fn as_file(&self) -> &File {
-        &self.file
+        self.file.as_ref().expect("file is present until dropped")
+    }
+
+    /// Give up the guard without releasing the lock. The lock is held until
+    /// the returned file is closed, and the lock file is not removed.
+    pub fn leak(mut self) -> File {
+        self.file.take().expect("file is present until dropped")
     }
 }
 
 impl Drop for PathLock {
     fn drop(&mut self) {
-        self.file.unlock().expect("unlock");
+        if let Some(file) = self.file.take() {
+            if self.remove_on_drop && !self.shared {
+                // Remove while still holding the lock, so whoever takes it
+                // next notices the file is gone and recreates it.
+                let _ = fs::remove_file(&self.path);
+            }
+            file.unlock().expect("unlock");
+        }
     }
 }
 
+/// A directory of lock files, one per named resource, to lock resources
+/// such as bookmarks or shards individually rather than all at once.
+///
+/// Names are escaped into file names directly in the directory, so any
+/// name is safe to use, including ones with slashes or dots. Distinct names
+/// always map to distinct lock files, even on case-insensitive filesystems.
+#[derive(Clone, Debug)]
+pub struct LockDir {
+    dir: PathBuf,
+}
+
+impl LockDir {
+    /// Lock files are created in `dir`. It is created on demand.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        LockDir {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Take an exclusive lock on the resource `name`. See
+    /// [`PathLock::exclusive`].
+    pub fn lock(&self, name: &str) -> io::Result<PathLock> {
+        fs::create_dir_all(&self.dir)?;
+        PathLock::exclusive(self.path(name))
+    }
+
+    /// Take a shared lock on the resource `name`. See [`PathLock::shared`].
+    pub fn lock_shared(&self, name: &str) -> io::Result<PathLock> {
+        fs::create_dir_all(&self.dir)?;
+        PathLock::shared(self.path(name))
+    }
+
+    /// The path of the lock file for the resource `name`.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(lock_file_name(name))
+    }
+}
+
+/// Escape `name` into a file name that is valid on every platform.
+///
+/// Lowercase ASCII letters, digits, `-` and `_` are kept, and every other
+/// byte becomes `%XX`. Uppercase letters are escaped so that names that only
+/// differ in case do not collide on case-insensitive filesystems. Names that
+/// Windows reserves for devices get their first letter escaped, as those
+/// names are reserved whatever the extension.
+fn lock_file_name(name: &str) -> String {
+    let reserved = is_reserved_on_windows(name);
+    let mut file_name = String::with_capacity(name.len() + 5);
+    for (i, b) in name.bytes().enumerate() {
+        match b {
+            b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' if !(reserved && i == 0) => {
+                file_name.push(b as char)
+            }
+            _ => file_name.push_str(&format!("%{:02x}", b)),
+        }
+    }
+    file_name.push_str(".lock");
+    file_name
+}
+
+fn is_reserved_on_windows(name: &str) -> bool {
+    matches!(
+        name.as_bytes(),
+        b"con"
+            | b"prn"
+            | b"aux"
+            | b"nul"
+            | [b'c', b'o', b'm', b'1'..=b'9']
+            | [b'l', b'p', b't', b'1'..=b'9']
+    )
+}
+
+/// Whether `file` is still the file at `path`.
+#[cfg(unix)]
+fn is_locked_file(file: &File, path: &Path) -> io::Result<bool> {
+    let locked = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(locked.dev() == current.dev() && locked.ino() == current.ino()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `file` is still the file at `path`. Files cannot be removed while
+/// they are open on Windows, so it always is.
+#[cfg(not(unix))]
+fn is_locked_file(_file: &File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::mpsc::channel;
     use std::thread;
+    use std::time::Duration;
 
     use super::*;
 
@@ -77,4 +236,134 @@ mod tests {
 
         Ok(())
     }
+
+    fn is_locked_by_other(path: &Path) -> io::Result<bool> {
+        let other = File::open(path)?;
+        match other.try_lock_exclusive() {
+            Ok(()) => {
+                other.unlock()?;
+                Ok(false)
+            }
+            Err(_) => Ok(true),
+        }
+    }
+
+    #[test]
+    fn test_path_lock_released_on_drop() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a");
+
+        let locked = PathLock::exclusive(&path)?;
+        assert!(is_locked_by_other(&path)?);
+        drop(locked);
+        assert!(!is_locked_by_other(&path)?);
+
+        // Still usable for locking again.
+        let _locked = PathLock::exclusive(&path)?;
+        assert!(is_locked_by_other(&path)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_lock_shared() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a");
+
+        // Shared locks coexist.
+        let shared1 = PathLock::shared(&path)?;
+        let shared2 = PathLock::shared(&path)?;
+        assert!(is_locked_by_other(&path)?);
+
+        // An exclusive lock waits until all shared locks are released.
+        let (tx, rx) = channel();
+        let exclusive = thread::spawn({
+            let path = path.clone();
+            move || {
+                let _locked = PathLock::exclusive(&path).unwrap();
+                tx.send(()).unwrap();
+            }
+        });
+        let timeout = Duration::from_millis(100);
+        assert!(rx.recv_timeout(timeout).is_err());
+        drop(shared1);
+        assert!(rx.recv_timeout(timeout).is_err());
+        drop(shared2);
+        rx.recv().unwrap();
+        exclusive.join().expect("joined");
+
+        // Shared locks do not remove the lock file other holders are using.
+        let shared1 = PathLock::shared(&path)?.remove_on_drop();
+        let _shared2 = PathLock::shared(&path)?;
+        drop(shared1);
+        assert!(path.exists());
+        assert!(is_locked_by_other(&path)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_lock_leak() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a");
+
+        let file = PathLock::exclusive(&path)?.leak();
+        assert!(is_locked_by_other(&path)?);
+        drop(file);
+        assert!(!is_locked_by_other(&path)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_lock_remove_on_drop() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a");
+
+        let locked = PathLock::exclusive(&path)?.remove_on_drop();
+        assert!(path.exists());
+        drop(locked);
+        assert!(!path.exists());
+
+        let _locked = PathLock::exclusive(&path)?;
+        assert!(path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_file_name() {
+        assert_eq!(lock_file_name("main"), "main.lock");
+        assert_eq!(lock_file_name("shard_1-a"), "shard_1-a.lock");
+        assert_eq!(lock_file_name("remote/main"), "remote%2fmain.lock");
+        assert_eq!(lock_file_name(".."), "%2e%2e.lock");
+        assert_eq!(lock_file_name("Main"), "%4dain.lock");
+        assert_eq!(lock_file_name("100%"), "100%25.lock");
+        assert_eq!(lock_file_name("nul"), "%6eul.lock");
+        assert_eq!(lock_file_name("com1"), "%63om1.lock");
+        assert_eq!(lock_file_name("com10"), "com10.lock");
+        assert_eq!(lock_file_name(""), ".lock");
+        assert_eq!(lock_file_name("\u{e9}"), "%c3%a9.lock");
+    }
+
+    #[test]
+    fn test_lock_dir() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let lock_dir = LockDir::new(dir.path().join("locks"));
+
+        // Names with slashes stay directly in the lock directory.
+        let path = lock_dir.path("remote/main");
+        assert_eq!(path.parent(), Some(dir.path().join("locks").as_path()));
+
+        let locked = lock_dir.lock("remote/main")?;
+        assert!(is_locked_by_other(&path)?);
+
+        // Other names are locked independently.
+        let other = lock_dir.path("remote/Main");
+        assert_ne!(path, other);
+        let _other_locked = lock_dir.lock("remote/Main")?;
+        assert!(is_locked_by_other(&other)?);
+
+        drop(locked);
+        assert!(!is_locked_by_other(&path)?);
+        let _shared = lock_dir.lock_shared("remote/main")?;
+        assert!(is_locked_by_other(&path)?);
+        Ok(())
+    }
 }
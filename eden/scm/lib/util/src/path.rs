@@ -171,6 +171,72 @@ pub fn absolute(path: impl AsRef<Path>) -> io::Result<PathBuf> {
     Ok(result)
 }
 
+/// Join `relative` under `root`, failing if the result would not be inside `root`.
+///
+/// `relative` must be a relative path. `.` components are dropped and `..` removes the
+/// previous component. A `..` that would climb above `root` is an error, and so is an
+/// absolute `relative` (including, on Windows, one with a drive prefix).
+///
+/// This is a lexical check only. Like [`absolute`], it does not access the filesystem,
+/// so a symlink inside `root` can still point outside of it. It is not a security
+/// boundary on its own: combine it with `O_NOFOLLOW` or with checking the canonicalized
+/// result when `root` may contain untrusted symlinks.
+pub fn join_within(root: impl AsRef<Path>, relative: impl AsRef<Path>) -> io::Result<PathBuf> {
+    let root = root.as_ref();
+    let relative = relative.as_ref();
+
+    let mut components = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => components.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if components.pop().is_none() {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{:?} escapes {:?}", relative, root),
+                    ));
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{:?} is not a relative path", relative),
+                ));
+            }
+        }
+    }
+
+    let mut result = root.to_path_buf();
+    result.extend(components);
+    Ok(result)
+}
+
+/// Return the longest path that is a prefix of all of `paths`, comparing whole components, so
+/// `/a/bc` and `/a/bd` share `/a`, not `/a/b`.
+///
+/// The result is empty if `paths` is empty, or if the paths have nothing in common, which is
+/// always the case for a mix of absolute and relative paths. A single path, or several copies of
+/// the same path, is its own prefix. Like [`absolute`], this does not access the filesystem:
+/// `.` and `..` components are not resolved, and components are compared exactly, so the
+/// comparison is case sensitive even on Windows.
+pub fn common_prefix(paths: &[&Path]) -> PathBuf {
+    let (first, rest) = match paths.split_first() {
+        Some(split) => split,
+        None => return PathBuf::new(),
+    };
+    let mut len = first.components().count();
+    for path in rest {
+        len = first
+            .components()
+            .zip(path.components())
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count();
+    }
+    first.components().take(len).collect()
+}
+
 /// Remove the file pointed by `path`.
 pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
     let path = path.as_ref();
@@ -337,16 +403,29 @@ pub fn create_shared_dir(path: impl AsRef<Path>) -> io::Result<()> {
 ///   string starting with a tilde (`~`), the tilde will be expanded into the
 ///   user's home directory.
 ///
+/// - Windows-style `%VAR%` references are expanded on all platforms, in
+///   addition to `$VAR` and `${VAR}`.
+///
+/// - A leading `~user` is expanded into the home directory of `user` on Unix,
+///   by looking it up in the password database. On other platforms, or if the
+///   user does not exist, it is left unchanged.
+///
 pub fn expand_path(path: impl AsRef<str>) -> PathBuf {
-    expand_path_impl(path.as_ref(), |k| env::var(k).ok(), dirs::home_dir)
+    expand_path_impl(
+        path.as_ref(),
+        |k| env::var(k).ok(),
+        dirs::home_dir,
+        user_home_dir,
+    )
 }
 
 /// Same as `expand_path` but explicitly takes closures for environment variable
 /// and home directory lookup for the sake of testability.
-fn expand_path_impl<E, H>(path: &str, getenv: E, homedir: H) -> PathBuf
+fn expand_path_impl<E, H, U>(path: &str, getenv: E, homedir: H, userdir: U) -> PathBuf
 where
     E: FnMut(&str) -> Option<String>,
     H: FnOnce() -> Option<PathBuf>,
+    U: FnOnce(&str) -> Option<PathBuf>,
 {
     // The shellexpand crate does not expand Windows environment variables
     // like `%PROGRAMDATA%`. We'd like to expand them too. So let's do some
@@ -383,11 +462,70 @@ where
     };
 
     let path = shellexpand::env_with_context_no_errors(&path, getenv);
+    if let Some(path) = expand_user_tilde(&path, userdir) {
+        return path;
+    }
     shellexpand::tilde_with_context(&path, homedir)
         .as_ref()
         .into()
 }
 
+/// Expand a leading `~user` into the home directory of `user`. Return `None` if
+/// the path does not start with `~user`, or if the user is unknown.
+fn expand_user_tilde<U>(path: &str, userdir: U) -> Option<PathBuf>
+where
+    U: FnOnce(&str) -> Option<PathBuf>,
+{
+    let rest = path.strip_prefix('~')?;
+    let end = rest.find(std::path::is_separator).unwrap_or(rest.len());
+    let (user, rest) = rest.split_at(end);
+    if user.is_empty() {
+        return None;
+    }
+    let mut path = userdir(user)?.into_os_string();
+    path.push(rest);
+    Some(path.into())
+}
+
+/// Look up the home directory of `user` in the password database.
+#[cfg(unix)]
+fn user_home_dir(user: &str) -> Option<PathBuf> {
+    use std::ffi::CStr;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = CString::new(user).ok()?;
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        // The buffer is too small for the entry.
+        if rc == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 || result.is_null() || passwd.pw_dir.is_null() {
+            return None;
+        }
+        let dir = unsafe { CStr::from_ptr(passwd.pw_dir) };
+        return Some(PathBuf::from(OsStr::from_bytes(dir.to_bytes())));
+    }
+}
+
+#[cfg(not(unix))]
+fn user_home_dir(_user: &str) -> Option<PathBuf> {
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
@@ -590,6 +728,48 @@ mod tests {
         assert_eq!(err.kind(), ErrorKind::NotFound);
     }
 
+    #[test]
+    fn test_join_within() {
+        let root = Path::new("root");
+        assert_eq!(join_within(root, "a/b").unwrap(), root.join("a").join("b"));
+        assert_eq!(join_within(root, "./a/../b/.").unwrap(), root.join("b"));
+        assert_eq!(join_within(root, "a/..").unwrap(), root);
+        assert_eq!(join_within(root, "").unwrap(), root);
+
+        for escape in ["..", "../root/a", "a/../../b", "a/b/../../.."] {
+            let err = join_within(root, escape).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", escape);
+        }
+
+        let absolute = if cfg!(windows) { "C:\\a" } else { "/a" };
+        let err = join_within(root, absolute).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        let common = |paths: &[&str]| {
+            let paths: Vec<&Path> = paths.iter().map(Path::new).collect();
+            common_prefix(&paths)
+        };
+        assert_eq!(common(&[]), Path::new(""));
+        assert_eq!(common(&["a/b/c"]), Path::new("a/b/c"));
+        assert_eq!(common(&["a/b/c", "a/b/c/"]), Path::new("a/b/c"));
+        assert_eq!(common(&["a/b/c", "a/b/d", "a/b"]), Path::new("a/b"));
+        assert_eq!(common(&["a/bc", "a/bd"]), Path::new("a"));
+        assert_eq!(common(&["a/b", "c/d"]), Path::new(""));
+        assert_eq!(common(&["a/b", "a/b/c", "d"]), Path::new(""));
+
+        let root = if cfg!(windows) { "C:\\" } else { "/" };
+        let abs = |path: &str| format!("{}{}", root, path);
+        assert_eq!(
+            common(&[&abs("a/b/c"), &abs("a/b/d")]),
+            Path::new(&abs("a/b"))
+        );
+        assert_eq!(common(&[&abs("a"), &abs("b")]), Path::new(root));
+        assert_eq!(common(&[&abs("a/b"), "a/b"]), Path::new(""));
+    }
+
     #[test]
     fn test_path_expansion() {
         fn getenv(key: &str) -> Option<String> {
@@ -604,9 +784,38 @@ mod tests {
             Some(PathBuf::from("/home/user"))
         }
 
+        fn userdir(user: &str) -> Option<PathBuf> {
+            match user {
+                "other" => Some(PathBuf::from("/home/other")),
+                _ => None,
+            }
+        }
+
         let path = "$foo/${bar}/$baz";
         let expected = PathBuf::from("/home/user/a/b/$baz");
 
-        assert_eq!(expand_path_impl(&path, getenv, homedir), expected);
+        assert_eq!(expand_path_impl(&path, getenv, homedir, userdir), expected);
+
+        let sep = std::path::MAIN_SEPARATOR;
+        let path = format!("~other{}${{bar}}", sep);
+        let expected = PathBuf::from(format!("/home/other{}b", sep));
+        assert_eq!(expand_path_impl(&path, getenv, homedir, userdir), expected);
+
+        assert_eq!(
+            expand_path_impl("~other", getenv, homedir, userdir),
+            PathBuf::from("/home/other")
+        );
+        let path = format!("~missing{}a", sep);
+        assert_eq!(
+            expand_path_impl(&path, getenv, homedir, userdir),
+            PathBuf::from(&path)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_user_home_dir() {
+        assert!(user_home_dir("root").is_some());
+        assert_eq!(user_home_dir("no-such-user-for-util-tests"), None);
     }
 }
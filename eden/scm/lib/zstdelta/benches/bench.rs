@@ -0,0 +1,118 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use minibench::bench;
+use minibench::elapsed;
+use rand::RngCore;
+use rand::SeedableRng;
+use zstdelta::ZstdDeltaDecoder;
+use zstdelta::ZstdDeltaEncoder;
+
+fn main() {
+    // Many small, similar files. This is where creating a context per call hurts the most.
+    let mut rng = rand_chacha::ChaChaRng::seed_from_u64(0);
+    let pairs: Vec<(Vec<u8>, Vec<u8>)> = (0..10000)
+        .map(|_| {
+            let mut base = vec![0u8; 1000];
+            rng.fill_bytes(&mut base);
+            let mut data = base.clone();
+            data[500] ^= 1;
+            (base, data)
+        })
+        .collect();
+    let deltas: Vec<Vec<u8>> = pairs
+        .iter()
+        .map(|(base, data)| zstdelta::diff(base, data).unwrap())
+        .collect();
+
+    bench("diff (10k x 1KB, new encoder each)", || {
+        elapsed(|| {
+            for (base, data) in &pairs {
+                ZstdDeltaEncoder::new().unwrap().diff(base, data).unwrap();
+            }
+        })
+    });
+
+    bench("diff (10k x 1KB, reused encoder)", || {
+        let mut encoder = ZstdDeltaEncoder::new().unwrap();
+        elapsed(|| {
+            for (base, data) in &pairs {
+                encoder.diff(base, data).unwrap();
+            }
+        })
+    });
+
+    for level in [1, 3, 6] {
+        bench(format!("diff_with_level {} (10k x 1KB)", level), || {
+            let mut encoder = ZstdDeltaEncoder::new().unwrap();
+            elapsed(|| {
+                for (base, data) in &pairs {
+                    encoder.diff_with_level(base, data, level).unwrap();
+                }
+            })
+        });
+    }
+
+    bench("apply (10k x 1KB, new decoder each)", || {
+        elapsed(|| {
+            for ((base, _), delta) in pairs.iter().zip(&deltas) {
+                ZstdDeltaDecoder::new().unwrap().apply(base, delta).unwrap();
+            }
+        })
+    });
+
+    bench("apply (10k x 1KB, reused decoder)", || {
+        let mut decoder = ZstdDeltaDecoder::new().unwrap();
+        elapsed(|| {
+            for ((base, _), delta) in pairs.iter().zip(&deltas) {
+                decoder.apply(base, delta).unwrap();
+            }
+        })
+    });
+
+    // A large file with a few changes.
+    let mut base = vec![0u8; 10000000];
+    rng.fill_bytes(&mut base);
+    let mut data = base.clone();
+    rng.fill_bytes(&mut data[5000000..5100000]);
+
+    bench("diff (10MB)", || {
+        elapsed(|| {
+            zstdelta::diff(&base, &data).unwrap();
+        })
+    });
+
+    for level in [1, 3, 6, 9] {
+        bench(format!("diff_with_level {} (10MB)", level), || {
+            elapsed(|| {
+                zstdelta::diff_with_level(&base, &data, level).unwrap();
+            })
+        });
+    }
+
+    bench("estimate_ratio (10MB)", || {
+        elapsed(|| {
+            zstdelta::estimate_ratio(&base, &data);
+        })
+    });
+
+    // A large file that is not similar at all.
+    let mut unrelated = vec![0u8; 10000000];
+    rng.fill_bytes(&mut unrelated);
+
+    bench("diff (10MB, unrelated)", || {
+        elapsed(|| {
+            zstdelta::diff(&base, &unrelated).unwrap();
+        })
+    });
+
+    bench("estimate_ratio (10MB, unrelated)", || {
+        elapsed(|| {
+            zstdelta::estimate_ratio(&base, &unrelated);
+        })
+    });
+}
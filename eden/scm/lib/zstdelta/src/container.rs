@@ -0,0 +1,214 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! A container for several labeled deltas, so they can be shipped as a single artifact.
+//!
+//! The format is:
+//!
+//! ```plain,ignore
+//! CONTAINER := MAGIC (4B, "ZDPK") + VERSION (1B, 1) + COUNT (u64) + ENTRY * COUNT
+//! ENTRY     := LABEL_LEN (u64) + LABEL (UTF-8) + DELTA_LEN (u64) + DELTA
+//! ```
+//!
+//! Integers are big-endian. Nothing may follow the last entry.
+
+use std::cmp;
+use std::io;
+
+const MAGIC: &[u8] = b"ZDPK";
+const VERSION: u8 = 1;
+
+/// Size of the magic, the version and the entry count.
+const HEADER_SIZE: usize = 4 + 1 + 8;
+
+/// Size of the smallest possible entry: an empty label and an empty delta.
+const MIN_ENTRY_SIZE: usize = 16;
+
+/// Pack labeled deltas into a single buffer that `unpack_deltas` can split again.
+///
+/// The deltas are stored as they are. Their order is kept, and labels do not need to be
+/// unique.
+pub fn pack_deltas<L: AsRef<str>, D: AsRef<[u8]>>(entries: &[(L, D)]) -> Vec<u8> {
+    let size = entries.iter().fold(HEADER_SIZE, |size, (label, delta)| {
+        size + MIN_ENTRY_SIZE + label.as_ref().len() + delta.as_ref().len()
+    });
+    let mut buf = Vec::with_capacity(size);
+    buf.extend_from_slice(MAGIC);
+    buf.push(VERSION);
+    buf.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+    for (label, delta) in entries {
+        for field in [label.as_ref().as_bytes(), delta.as_ref()] {
+            buf.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            buf.extend_from_slice(field);
+        }
+    }
+    buf
+}
+
+/// Split a buffer written by `pack_deltas` into its labeled deltas, in the order they were
+/// packed.
+///
+/// The input is not trusted: lengths are checked against the remaining input before anything
+/// is allocated for them. Fails with an `InvalidData` error if the input is truncated, has
+/// trailing data, has a label that is not UTF-8, or is not a container of a supported version.
+pub fn unpack_deltas(buf: &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut reader = Reader { buf };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(invalid_data("not a delta container"));
+    }
+    let version = reader.take(1)?[0];
+    if version != VERSION {
+        return Err(invalid_data(format!(
+            "unsupported delta container version {}",
+            version
+        )));
+    }
+
+    let count = reader.read_u64()?;
+    // Do not trust `count` for the allocation: it cannot be more than what fits in the input.
+    let capacity = cmp::min(count, (reader.buf.len() / MIN_ENTRY_SIZE) as u64);
+    let mut entries = Vec::with_capacity(capacity as usize);
+    for _ in 0..count {
+        let label = reader.read_field()?;
+        let label = std::str::from_utf8(label)
+            .map_err(|_| invalid_data("delta container label is not UTF-8"))?;
+        let delta = reader.read_field()?;
+        entries.push((label.to_string(), delta.to_vec()));
+    }
+
+    if !reader.buf.is_empty() {
+        return Err(invalid_data(format!(
+            "{} bytes of trailing data after the delta container",
+            reader.buf.len()
+        )));
+    }
+    Ok(entries)
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads the fields of a container, failing instead of reading past its end.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.buf.len() {
+            return Err(invalid_data("delta container is truncated"));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Read a length-prefixed field.
+    fn read_field(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.read_u64()?;
+        // A length that does not fit in `usize` is larger than the input anyway.
+        let len = usize::try_from(len).unwrap_or(usize::MAX);
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickcheck::quickcheck;
+
+    use super::*;
+    use crate::zstdelta::apply;
+    use crate::zstdelta::diff;
+
+    fn owned(entries: &[(&str, &[u8])]) -> Vec<(String, Vec<u8>)> {
+        entries
+            .iter()
+            .map(|(label, delta)| (label.to_string(), delta.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let base = b"base content".repeat(10);
+        let v1 = diff(&base, b"first target").unwrap();
+        let v2 = diff(&base, &b"second target".repeat(5)).unwrap();
+        let entries: Vec<(&str, &[u8])> = vec![("v1", &v1), ("", b""), ("v2", &v2), ("v1", b"x")];
+
+        let packed = pack_deltas(&entries);
+        let unpacked = unpack_deltas(&packed).unwrap();
+        assert_eq!(unpacked, owned(&entries));
+        assert_eq!(apply(&base, &unpacked[0].1).unwrap(), b"first target");
+        assert_eq!(
+            apply(&base, &unpacked[2].1).unwrap(),
+            b"second target".repeat(5)
+        );
+
+        let empty: &[(&str, &[u8])] = &[];
+        assert_eq!(unpack_deltas(&pack_deltas(empty)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let packed = pack_deltas(&[("v1", b"delta one"), ("v2", b"delta two")]);
+        for len in 0..packed.len() {
+            let err = unpack_deltas(&packed[..len]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let packed = pack_deltas(&[("v1", b"delta")]);
+
+        let mut bad_magic = packed.clone();
+        bad_magic[0] = b'X';
+        assert!(unpack_deltas(&bad_magic).is_err());
+
+        let mut bad_version = packed.clone();
+        bad_version[MAGIC.len()] = 2;
+        let err = unpack_deltas(&bad_version).unwrap_err();
+        assert!(err.to_string().contains("version 2"));
+
+        let mut trailing = packed.clone();
+        trailing.push(0);
+        let err = unpack_deltas(&trailing).unwrap_err();
+        assert!(err.to_string().contains("trailing"));
+
+        // The label starts after the header and its own length.
+        let mut not_utf8 = packed.clone();
+        not_utf8[HEADER_SIZE + 8] = 0xff;
+        assert!(unpack_deltas(&not_utf8).is_err());
+
+        // Huge counts and lengths fail without allocating for them.
+        let mut huge_count = packed.clone();
+        huge_count[HEADER_SIZE - 8..HEADER_SIZE].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(unpack_deltas(&huge_count).is_err());
+        let mut huge_len = packed;
+        huge_len[HEADER_SIZE..HEADER_SIZE + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(unpack_deltas(&huge_len).is_err());
+    }
+
+    quickcheck! {
+        fn test_round_trip_quickcheck(entries: Vec<(String, Vec<u8>)>) -> bool {
+            unpack_deltas(&pack_deltas(&entries)).unwrap() == entries
+        }
+
+        fn test_unpack_arbitrary_quickcheck(buf: Vec<u8>) -> bool {
+            // Arbitrary input must fail cleanly, or be a valid container.
+            match unpack_deltas(&buf) {
+                Ok(entries) => pack_deltas(&entries) == buf,
+                Err(err) => err.kind() == io::ErrorKind::InvalidData,
+            }
+        }
+    }
+}
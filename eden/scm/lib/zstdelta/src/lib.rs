@@ -5,7 +5,22 @@
  * GNU General Public License version 2.
  */
 
+mod container;
 mod zstdelta;
 
+pub use crate::container::pack_deltas;
+pub use crate::container::unpack_deltas;
+pub use crate::zstdelta::adaptive_level;
 pub use crate::zstdelta::apply;
+pub use crate::zstdelta::apply_to_writer;
 pub use crate::zstdelta::diff;
+pub use crate::zstdelta::diff_adaptive;
+pub use crate::zstdelta::diff_with_level;
+pub use crate::zstdelta::diff_with_stats;
+pub use crate::zstdelta::estimate_ratio;
+pub use crate::zstdelta::is_identity_delta;
+pub use crate::zstdelta::DeltaStats;
+pub use crate::zstdelta::InputTooLarge;
+pub use crate::zstdelta::ZstdDeltaDecoder;
+pub use crate::zstdelta::ZstdDeltaEncoder;
+pub use crate::zstdelta::DEFAULT_MAX_INPUT_SIZE;
@@ -5,8 +5,6 @@
  * GNU General Public License version 2.
  */
 
-mod zstdelta;
-
 use std::env::args;
 use std::fs::File;
 use std::io::Read;
@@ -16,8 +14,10 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
 
-use crate::zstdelta::apply;
-use crate::zstdelta::diff;
+use zstdelta::apply;
+use zstdelta::apply_to_writer;
+use zstdelta::diff;
+use zstdelta::diff_with_stats;
 
 fn read(path: &Path) -> Vec<u8> {
     let mut buf = Vec::new();
@@ -28,19 +28,92 @@ fn read(path: &Path) -> Vec<u8> {
     buf
 }
 
-fn main() {
-    let args: Vec<_> = args().skip(1).collect();
-    if args.len() < 3 {
-        println!("Usage: zstdelta -c base data > delta\n       zstdelta -d base delta > data\n");
+/// Like `read`, but `-` reads from stdin.
+fn read_or_stdin(path: &str) -> Vec<u8> {
+    if path == "-" {
+        let mut buf = Vec::new();
+        io::stdin().lock().read_to_end(&mut buf).expect("read");
+        buf
+    } else {
+        read(&PathBuf::from(path))
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: zstdelta -c base data > delta\n       zstdelta -d base delta > data\n       zstdelta -v base data\n       zstdelta -s base data\n\nUse - as data or delta to read it from stdin.\n"
+    );
+    exit(1);
+}
+
+/// Check that `data` survives a round trip through `diff` and `apply`. Exit with an error and
+/// the offset of the first mismatching byte if it does not.
+fn verify(base: &[u8], data: &[u8]) {
+    let delta = diff(base, data).expect("diff");
+    let reconstructed = apply(base, &delta).expect("apply");
+    if reconstructed != data {
+        let offset = reconstructed
+            .iter()
+            .zip(data)
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| reconstructed.len().min(data.len()));
+        eprintln!(
+            "mismatch at byte {} (expected {} bytes, got {})",
+            offset,
+            data.len(),
+            reconstructed.len()
+        );
         exit(1);
     }
-    let base = read(&PathBuf::from(&args[1]));
-    let data = read(&PathBuf::from(&args[2]));
-    let out = if args[0] == "-c" {
-        diff(&base, &data).expect("diff")
+    let ratio = if data.is_empty() {
+        0.0
     } else {
-        apply(&base, &data).expect("apply")
+        delta.len() as f64 / data.len() as f64
     };
+    println!(
+        "ok: delta is {} bytes for {} bytes of data (ratio {:.4})",
+        delta.len(),
+        data.len(),
+        ratio
+    );
+}
 
+/// Print how `data` is encoded in a delta against `base`.
+fn stats(base: &[u8], data: &[u8]) {
+    let (delta, stats) = diff_with_stats(base, data).expect("diff");
+    println!(
+        "delta: {} bytes\nliterals: {} bytes\ncopies: {} ({} bytes)",
+        delta.len(),
+        stats.literal_bytes,
+        stats.copies,
+        stats.copied_bytes
+    );
+}
+
+/// Write the delta of `data` against `base` to stdout.
+fn compress(base: &[u8], data: &[u8]) {
+    let out = diff(base, data).expect("diff");
     io::stdout().write_all(&out).expect("write");
 }
+
+/// Write the data reconstructed from `base` and `delta` to stdout.
+fn decompress(base: &[u8], delta: &[u8]) {
+    apply_to_writer(base, delta, &mut io::stdout().lock()).expect("apply");
+}
+
+fn main() {
+    let args: Vec<_> = args().skip(1).collect();
+    if args.len() < 3 {
+        usage();
+    }
+    let run: fn(&[u8], &[u8]) = match args[0].as_str() {
+        "-c" => compress,
+        "-d" => decompress,
+        "-v" => verify,
+        "-s" => stats,
+        _ => usage(),
+    };
+    let base = read(&PathBuf::from(&args[1]));
+    let data = read_or_stdin(&args[2]);
+    run(&base, &data);
+}
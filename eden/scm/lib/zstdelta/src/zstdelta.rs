@@ -5,30 +5,60 @@
  * GNU General Public License version 2.
  */
 
+use std::cell::RefCell;
 use std::cmp;
+use std::collections::HashSet;
+use std::error::Error;
 use std::ffi::CStr;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
 use std::io;
+use std::io::Write;
+use std::mem;
 
 use libc::c_void;
+use zstd_sys::ZSTD_CCtx;
+use zstd_sys::ZSTD_CCtxParams_init_advanced;
+use zstd_sys::ZSTD_CCtx_loadDictionary;
+use zstd_sys::ZSTD_CCtx_setParametersUsingCCtxParams;
+use zstd_sys::ZSTD_DCtx;
+use zstd_sys::ZSTD_DCtx_refPrefix;
+use zstd_sys::ZSTD_DCtx_reset;
 use zstd_sys::ZSTD_DCtx_setMaxWindowSize;
+use zstd_sys::ZSTD_DStreamOutSize;
+use zstd_sys::ZSTD_ResetDirective;
+use zstd_sys::ZSTD_Sequence;
 use zstd_sys::ZSTD_compressBound;
 use zstd_sys::ZSTD_compress_advanced;
 use zstd_sys::ZSTD_compressionParameters;
 use zstd_sys::ZSTD_createCCtx;
+use zstd_sys::ZSTD_createCCtxParams;
 use zstd_sys::ZSTD_createDCtx;
+use zstd_sys::ZSTD_decompressStream;
 use zstd_sys::ZSTD_decompress_usingDict;
 use zstd_sys::ZSTD_findDecompressedSize;
+use zstd_sys::ZSTD_frameHeader;
 use zstd_sys::ZSTD_frameParameters;
+use zstd_sys::ZSTD_frameType_e;
 use zstd_sys::ZSTD_freeCCtx;
+use zstd_sys::ZSTD_freeCCtxParams;
 use zstd_sys::ZSTD_freeDCtx;
+use zstd_sys::ZSTD_generateSequences;
+use zstd_sys::ZSTD_getCParams;
 use zstd_sys::ZSTD_getErrorName;
+use zstd_sys::ZSTD_getFrameHeader;
+use zstd_sys::ZSTD_inBuffer;
 use zstd_sys::ZSTD_isError;
+use zstd_sys::ZSTD_outBuffer;
 use zstd_sys::ZSTD_parameters;
 use zstd_sys::ZSTD_strategy;
+use zstd_sys::ZSTD_BLOCKSIZE_MAX;
 use zstd_sys::ZSTD_CHAINLOG_MIN;
 use zstd_sys::ZSTD_CONTENTSIZE_ERROR;
 use zstd_sys::ZSTD_CONTENTSIZE_UNKNOWN;
 use zstd_sys::ZSTD_HASHLOG_MIN;
+use zstd_sys::ZSTD_MINMATCH_MIN;
 use zstd_sys::ZSTD_SEARCHLOG_MIN;
 use zstd_sys::ZSTD_WINDOWLOG_MIN;
 
@@ -36,6 +66,136 @@ use zstd_sys::ZSTD_WINDOWLOG_MIN;
 const ZSTD_WINDOWLOG_MAX: u32 = 30;
 const ZSTD_HASHLOG_MAX: u32 = 30;
 
+/// Default limit for the size of each input (and of the reconstructed data in `apply`): 1 GiB.
+///
+/// Larger inputs are rejected with an `InputTooLarge` error instead of attempting to allocate
+/// buffers for them. Use `ZstdDeltaEncoder::with_max_input_size` or
+/// `ZstdDeltaDecoder::with_max_input_size` to raise the limit.
+pub const DEFAULT_MAX_INPUT_SIZE: usize = 1 << 30;
+
+/// The zstd compression levels `diff_adaptive` uses, by the size of `data`: data smaller than
+/// the size of an entry, and not smaller than that of the entry before, uses its level. Larger
+/// data uses `ADAPTIVE_LEVEL_LARGE`.
+///
+/// For small data, a higher level barely improves the ratio but costs relatively much time,
+/// so the cheapest level is used. For large data, the better ratio is worth the time. See the
+/// `diff_with_level` benchmarks.
+const ADAPTIVE_LEVELS: &[(usize, i32)] = &[(64 << 10, 1), (1 << 20, 3), (32 << 20, 6)];
+const ADAPTIVE_LEVEL_LARGE: i32 = 9;
+
+/// How many times larger than itself a delta may claim the data it reconstructs to be, not
+/// counting what is copied from `base`. zstd can compress long runs much better than this, so
+/// a delta is only rejected if it also claims more than `MIN_SIZE_CEILING` bytes.
+const MAX_EXPANSION: u64 = 1000;
+
+/// A delta may always claim to reconstruct up to this many bytes, see `MAX_EXPANSION`.
+const MIN_SIZE_CEILING: u64 = 64 << 20;
+
+/// The delta that `ZstdDeltaEncoder::with_identity_delta` makes `diff` return when `data` is the
+/// same as `base`, which `apply` turns back into `base` without running zstd. It is an empty zstd
+/// skippable frame (magic `0x184D2A50`, size 0), so it cannot be mistaken for a delta of
+/// compressed data, which always starts with the zstd frame magic.
+const IDENTITY_DELTA: [u8; 8] = [0x50, 0x2a, 0x4d, 0x18, 0, 0, 0, 0];
+
+/// Error returned when an input to `diff` or `apply`, or the data a delta claims to
+/// reconstruct, is larger than the configured limit. It is wrapped in an [`io::Error`] of
+/// kind `InvalidInput`.
+#[derive(Debug)]
+pub struct InputTooLarge {
+    pub size: u64,
+    pub limit: usize,
+}
+
+impl fmt::Display for InputTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "input of {} bytes exceeds the limit of {} bytes",
+            self.size, self.limit
+        )
+    }
+}
+
+impl Error for InputTooLarge {}
+
+/// Fail with `InputTooLarge` if `size` exceeds `limit`.
+fn check_size(size: u64, limit: usize) -> io::Result<()> {
+    if size > limit as u64 {
+        let err = InputTooLarge { size, limit };
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+    }
+    Ok(())
+}
+
+/// The most data that a delta of `delta_len` bytes against a base of `base_len` bytes may claim
+/// to reconstruct. See `MAX_EXPANSION`.
+fn size_ceiling(base_len: usize, delta_len: usize) -> u64 {
+    let expanded = (delta_len as u64).saturating_mul(MAX_EXPANSION);
+    cmp::max(MIN_SIZE_CEILING, expanded.saturating_add(base_len as u64))
+}
+
+/// Fail with an `InvalidData` error if the `what` of `size` bytes, which a delta of `delta_len`
+/// bytes against a base of `base_len` bytes claims to need, is implausibly large.
+fn check_declared_size(
+    what: &str,
+    size: u64,
+    ceiling: u64,
+    base_len: usize,
+    delta_len: usize,
+) -> io::Result<()> {
+    if size > ceiling {
+        let msg = format!(
+            "delta of {} bytes against {} bytes of base declares a {} of {} bytes (limit {})",
+            delta_len, base_len, what, size, ceiling
+        );
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+    Ok(())
+}
+
+/// Check the header of the first frame of `delta` before anything is allocated for it, so that
+/// a corrupt or malicious delta cannot make the decoder allocate much more than `base` and
+/// `delta` would justify.
+fn check_frame_header(base: &[u8], delta: &[u8]) -> io::Result<()> {
+    let header = unsafe {
+        let mut header: ZSTD_frameHeader = mem::zeroed();
+        let code = ZSTD_getFrameHeader(&mut header, delta.as_ptr() as *const c_void, delta.len());
+        if ZSTD_isError(code) != 0 {
+            let msg = format!("invalid delta ({})", explain_error(code));
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        if code != 0 {
+            // `code` more bytes are needed to read the header.
+            let msg = "truncated delta";
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+        }
+        header
+    };
+    // Nothing is decompressed from a skippable frame, whatever size it has.
+    if header.frameType != ZSTD_frameType_e::ZSTD_frame {
+        return Ok(());
+    }
+
+    let ceiling = size_ceiling(base.len(), delta.len());
+    if header.frameContentSize != ZSTD_CONTENTSIZE_UNKNOWN as u64 {
+        let size = header.frameContentSize;
+        check_declared_size("content size", size, ceiling, base.len(), delta.len())?;
+    }
+    // The window has to cover `base` and the reconstructed data, and `diff` rounds it up to a
+    // power of two.
+    let window_ceiling = (base.len() as u64)
+        .saturating_add(ceiling)
+        .checked_next_power_of_two()
+        .unwrap_or(u64::MAX);
+    check_declared_size(
+        "window size",
+        header.windowSize,
+        window_ceiling,
+        base.len(),
+        delta.len(),
+    )
+}
+
 /// Return `y` so `1 << y` is greater than `x`.
 /// Note: `1 << y` might be greater than `u64::MAX`.
 fn log_base2(x: u64) -> u32 {
@@ -57,8 +217,8 @@ fn explain_error(code: usize) -> &'static str {
     }
 }
 
-/// Create a "zstd delta". Compress `data` using dictionary `base`.
-pub fn diff(base: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+/// Compression parameters used by `diff`.
+fn parameters(base: &[u8], data: &[u8]) -> ZSTD_parameters {
     // Customized wlog, hlog to let zstd do better at delta-ing. Use "fast" strategy, which is
     // good enough assuming the primary space saving is caused by "delta-ing".
     let log = log_base2((data.len() + base.len() + 1) as u64);
@@ -78,85 +238,614 @@ pub fn diff(base: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
         checksumFlag: 0,    // checksum is done at another level
         noDictIDFlag: 1,    // dictionary is fixed, not reused
     };
-    let params = ZSTD_parameters {
+    ZSTD_parameters {
         cParams: cparams,
         fParams: fparams,
+    }
+}
+
+/// Compression parameters used by `diff_with_level`: the ones zstd chooses for `level`, but with
+/// the window of `diff`, so that all of `base` can be referred to.
+fn level_parameters(base: &[u8], data: &[u8], level: i32) -> ZSTD_parameters {
+    let mut params = parameters(base, data);
+    let cparams = unsafe { ZSTD_getCParams(level, data.len() as u64, base.len()) };
+    params.cParams = ZSTD_compressionParameters {
+        windowLog: params.cParams.windowLog,
+        ..cparams
     };
+    params
+}
 
-    unsafe {
-        let cctx = ZSTD_createCCtx();
+/// Create a "zstd delta". Compress `data` using dictionary `base`.
+///
+/// Either input may be empty. An empty `base` means `data` is compressed without a
+/// dictionary, and an empty `data` still produces a (small) non-empty delta that `apply`
+/// turns back into an empty buffer.
+///
+/// Inputs larger than `DEFAULT_MAX_INPUT_SIZE` are rejected with an `InputTooLarge` error.
+///
+/// This uses a compression context that is kept per thread, see `ZstdDeltaEncoder`.
+pub fn diff(base: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+    with_thread_encoder(|encoder| encoder.diff(base, data))
+}
+
+/// Like `diff`, but compress with the zstd compression level `level` instead of the fixed
+/// parameters of `diff`, which are tuned for speed. Levels follow zstd: `0` is zstd's default
+/// level, and levels above the maximum are treated as the maximum. The delta is applied with
+/// `apply` like any other.
+pub fn diff_with_level(base: &[u8], data: &[u8], level: i32) -> io::Result<Vec<u8>> {
+    with_thread_encoder(|encoder| encoder.diff_with_level(base, data, level))
+}
+
+/// Like `diff_with_level`, with the level `adaptive_level` picks for the size of `data`.
+pub fn diff_adaptive(base: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+    diff_with_level(base, data, adaptive_level(data.len()))
+}
+
+/// Whether `delta` is the identity delta that an encoder created with
+/// `ZstdDeltaEncoder::with_identity_delta` creates when `data` is the same as `base`. Applying it
+/// returns `base` unchanged, so callers can skip applying it.
+pub fn is_identity_delta(delta: &[u8]) -> bool {
+    delta == IDENTITY_DELTA
+}
+
+/// The zstd compression level `diff_adaptive` uses for data of `data_len` bytes:
+///
+/// | `data_len`       | level |
+/// |------------------|-------|
+/// | below 64 KiB     | 1     |
+/// | below 1 MiB      | 3     |
+/// | below 32 MiB     | 6     |
+/// | 32 MiB and above | 9     |
+pub fn adaptive_level(data_len: usize) -> i32 {
+    ADAPTIVE_LEVELS
+        .iter()
+        .find(|(size, _)| data_len < *size)
+        .map_or(ADAPTIVE_LEVEL_LARGE, |(_, level)| *level)
+}
+
+thread_local! {
+    // Reused by `diff`, `apply` and `apply_to_writer` so they do not allocate a context
+    // (and its workspace) for every call. The contexts keep their memory until the thread
+    // exits.
+    static THREAD_ENCODER: RefCell<Option<ZstdDeltaEncoder>> = const { RefCell::new(None) };
+    static THREAD_DECODER: RefCell<Option<ZstdDeltaDecoder>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with the encoder of the current thread, or with a new encoder if it is in use.
+fn with_thread_encoder<T>(f: impl FnOnce(&mut ZstdDeltaEncoder) -> io::Result<T>) -> io::Result<T> {
+    THREAD_ENCODER.with(|cell| match cell.try_borrow_mut() {
+        Ok(mut encoder) => {
+            if encoder.is_none() {
+                *encoder = Some(ZstdDeltaEncoder::new()?);
+            }
+            f(encoder.as_mut().unwrap())
+        }
+        Err(_) => f(&mut ZstdDeltaEncoder::new()?),
+    })
+}
+
+/// Run `f` with the decoder of the current thread. Fall back to a new decoder if the
+/// thread's decoder is in use, which can happen if the writer of `apply_to_writer` calls
+/// back into this module.
+fn with_thread_decoder<T>(f: impl FnOnce(&mut ZstdDeltaDecoder) -> io::Result<T>) -> io::Result<T> {
+    THREAD_DECODER.with(|cell| match cell.try_borrow_mut() {
+        Ok(mut decoder) => {
+            if decoder.is_none() {
+                *decoder = Some(ZstdDeltaDecoder::new()?);
+            }
+            f(decoder.as_mut().unwrap())
+        }
+        Err(_) => f(&mut ZstdDeltaDecoder::new()?),
+    })
+}
+
+/// Creates zstd deltas like `diff`, reusing one compression context for all of them.
+///
+/// Creating a context allocates its workspace, which is a significant part of the cost of
+/// diffing small inputs. Keep an encoder around when creating many deltas.
+///
+/// An encoder can be sent to another thread, but it is not `Sync`: use one per thread.
+pub struct ZstdDeltaEncoder {
+    cctx: *mut ZSTD_CCtx,
+    max_input_size: usize,
+    identity_delta: bool,
+}
+
+// The context is only accessed through `&mut self`.
+unsafe impl Send for ZstdDeltaEncoder {}
+
+impl ZstdDeltaEncoder {
+    pub fn new() -> io::Result<Self> {
+        let cctx = unsafe { ZSTD_createCCtx() };
         if cctx.is_null() {
             return Err(io::Error::new(io::ErrorKind::Other, "cannot create CCtx"));
         }
+        Ok(Self {
+            cctx,
+            max_input_size: DEFAULT_MAX_INPUT_SIZE,
+            identity_delta: false,
+        })
+    }
 
-        let max_outsize = ZSTD_compressBound(data.len());
-        let mut buf: Vec<u8> = Vec::with_capacity(max_outsize);
+    /// Set the limit for the size of `base` and `data` in `diff`, which defaults to
+    /// `DEFAULT_MAX_INPUT_SIZE`.
+    pub fn with_max_input_size(mut self, max_input_size: usize) -> Self {
+        self.max_input_size = max_input_size;
+        self
+    }
 
-        buf.set_len(max_outsize);
-        let outsize = ZSTD_compress_advanced(
-            cctx,
-            buf.as_mut_ptr() as *mut c_void,
-            buf.len(),
-            data.as_ptr() as *const c_void,
-            data.len(),
-            base.as_ptr() as *const c_void,
-            base.len(),
-            params,
-        );
+    /// If `identity_delta` is set, `diff` returns a fixed "identity delta" without compressing
+    /// anything when `data` is the same as `base`, see `is_identity_delta`. `apply` recognizes it,
+    /// but readers built before it was introduced take it for an empty delta and silently return
+    /// empty data, so it is off by default. Only turn it on for deltas that are never read by
+    /// such readers, e.g. deltas that are not persisted.
+    pub fn with_identity_delta(mut self, identity_delta: bool) -> Self {
+        self.identity_delta = identity_delta;
+        self
+    }
+
+    /// Create a "zstd delta". See `diff`.
+    pub fn diff(&mut self, base: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+        self.compress(base, data, parameters(base, data))
+    }
+
+    /// Create a "zstd delta" with the zstd compression level `level`. See `diff_with_level`.
+    pub fn diff_with_level(&mut self, base: &[u8], data: &[u8], level: i32) -> io::Result<Vec<u8>> {
+        self.compress(base, data, level_parameters(base, data, level))
+    }
+
+    fn compress(
+        &mut self,
+        base: &[u8],
+        data: &[u8],
+        params: ZSTD_parameters,
+    ) -> io::Result<Vec<u8>> {
+        check_size(base.len() as u64, self.max_input_size)?;
+        check_size(data.len() as u64, self.max_input_size)?;
+        if self.identity_delta && base == data {
+            return Ok(IDENTITY_DELTA.to_vec());
+        }
+
+        unsafe {
+            let max_outsize = ZSTD_compressBound(data.len());
+            let mut buf: Vec<u8> = Vec::with_capacity(max_outsize);
+
+            buf.set_len(max_outsize);
+            let outsize = ZSTD_compress_advanced(
+                self.cctx,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+                data.as_ptr() as *const c_void,
+                data.len(),
+                base.as_ptr() as *const c_void,
+                base.len(),
+                params,
+            );
+
+            if ZSTD_isError(outsize) != 0 {
+                let msg = format!("cannot compress ({})", explain_error(outsize));
+                Err(io::Error::new(io::ErrorKind::Other, msg))
+            } else {
+                buf.set_len(outsize);
+                Ok(buf)
+            }
+        }
+    }
+}
+
+impl Drop for ZstdDeltaEncoder {
+    fn drop(&mut self) {
+        unsafe { ZSTD_freeCCtx(self.cctx) };
+    }
+}
+
+/// How the data in a delta created by `diff` is encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeltaStats {
+    /// Bytes stored as literals in the delta.
+    pub literal_bytes: u64,
+
+    /// Bytes copied from `base`, or from earlier in the data.
+    pub copied_bytes: u64,
+
+    /// Number of copy operations.
+    pub copies: u64,
+}
 
+/// Like `diff`, but also report how much of `data` is encoded as literals and how much is
+/// copied.
+///
+/// The statistics are collected by compressing `data` a second time, so this is meant for
+/// analysis and is much slower than `diff`.
+pub fn diff_with_stats(base: &[u8], data: &[u8]) -> io::Result<(Vec<u8>, DeltaStats)> {
+    let delta = diff(base, data)?;
+    let stats = unsafe {
+        let cctx = ZSTD_createCCtx();
+        if cctx.is_null() {
+            return Err(io::Error::new(io::ErrorKind::Other, "cannot create CCtx"));
+        }
+        let result = collect_stats(cctx, base, data);
         ZSTD_freeCCtx(cctx);
+        result?
+    };
+    Ok((delta, stats))
+}
+
+/// Find the sequences zstd uses to compress `data` with the parameters and dictionary of
+/// `diff`, and summarize them.
+unsafe fn collect_stats(cctx: *mut ZSTD_CCtx, base: &[u8], data: &[u8]) -> io::Result<DeltaStats> {
+    let cctx_params = ZSTD_createCCtxParams();
+    if cctx_params.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "cannot create CCtxParams",
+        ));
+    }
+    let mut code = ZSTD_CCtxParams_init_advanced(cctx_params, parameters(base, data));
+    if ZSTD_isError(code) == 0 {
+        code = ZSTD_CCtx_setParametersUsingCCtxParams(cctx, cctx_params);
+    }
+    ZSTD_freeCCtxParams(cctx_params);
+    if ZSTD_isError(code) == 0 {
+        code = ZSTD_CCtx_loadDictionary(cctx, base.as_ptr() as *const c_void, base.len());
+    }
+    if ZSTD_isError(code) != 0 {
+        let msg = format!("cannot set parameters ({})", explain_error(code));
+        return Err(io::Error::new(io::ErrorKind::Other, msg));
+    }
 
-        if ZSTD_isError(outsize) != 0 {
-            let msg = format!("cannot compress ({})", explain_error(outsize));
-            Err(io::Error::new(io::ErrorKind::Other, msg))
-        } else {
-            buf.set_len(outsize);
-            Ok(buf)
+    // zstd does not check the size of the output buffer. Every sequence covers at least
+    // ZSTD_MINMATCH_MIN bytes, and each block ends with an extra delimiter sequence.
+    let max_sequences =
+        data.len() / ZSTD_MINMATCH_MIN as usize + data.len() / ZSTD_BLOCKSIZE_MAX as usize + 2;
+    let mut sequences: Vec<ZSTD_Sequence> = Vec::with_capacity(max_sequences);
+    let count = ZSTD_generateSequences(
+        cctx,
+        sequences.as_mut_ptr(),
+        max_sequences,
+        data.as_ptr() as *const c_void,
+        data.len(),
+    );
+    if ZSTD_isError(count) != 0 {
+        let msg = format!("cannot compress ({})", explain_error(count));
+        return Err(io::Error::new(io::ErrorKind::Other, msg));
+    }
+    sequences.set_len(count);
+
+    let mut stats = DeltaStats::default();
+    for sequence in sequences.iter().filter(|s| s.matchLength > 0) {
+        stats.copies += 1;
+        stats.copied_bytes += sequence.matchLength as u64;
+    }
+    // Blocks that zstd stores uncompressed produce no sequences at all, so count everything
+    // that is not copied as literals instead of summing up literal lengths.
+    stats.literal_bytes = data.len() as u64 - stats.copied_bytes;
+    Ok(stats)
+}
+
+/// Random values for the gear rolling hash used by `estimate_ratio`, generated with splitmix64.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// `estimate_ratio` samples the positions whose hash has this many leading zero bits, i.e. one
+/// in 32 positions on average.
+const SAMPLE_BITS: u32 = 5;
+
+/// Hashes of sampled positions are already well distributed, so they do not need to be hashed
+/// again when inserting them into a `HashSet`.
+#[derive(Default)]
+struct IdentityHasher(u64);
+
+impl Hasher for IdentityHasher {
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!("only u64 keys are supported");
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.0 = value;
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Clone, Default)]
+struct BuildIdentityHasher;
+
+impl BuildHasher for BuildIdentityHasher {
+    type Hasher = IdentityHasher;
+
+    fn build_hasher(&self) -> IdentityHasher {
+        IdentityHasher::default()
+    }
+}
+
+/// Number of bytes that the gear hash depends on. Older bytes are shifted out.
+const GEAR_WINDOW: usize = 64;
+
+/// `estimate_ratio` only looks at this many bytes of `data`, split into `DATA_CHUNK` sized
+/// chunks spread evenly over it. Indexing `base` has to be done in full, but a sample of `data`
+/// is enough to estimate the ratio.
+const DATA_LIMIT: usize = 1 << 20;
+const DATA_CHUNK: usize = 64 << 10;
+
+/// Call `f` with the gear hash of the 64 bytes ending at each position of `data` that is
+/// sampled. Since the choice only depends on the content, the same content is sampled in both
+/// inputs, wherever it is. The first `skip` bytes only warm up the hash and are not sampled.
+fn for_each_sampled_hash(data: &[u8], skip: usize, mut f: impl FnMut(u64)) {
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate() {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash >> (64 - SAMPLE_BITS) == 0 && i >= skip {
+            f(hash);
         }
     }
 }
 
+/// Estimate the size of the delta `diff(base, data)` relative to the size of `data`, without
+/// creating it. The result is between 0 (`data` is made of content in `base`) and 1 (`data`
+/// shares nothing with `base`).
+///
+/// This samples about one in 32 positions of each input by the content around it, and returns
+/// the fraction of sampled positions in `data` whose surrounding 64 bytes do not also occur in
+/// `base`. It is a single pass over `base` and over at most 1 MB of `data`, taken in 64 KB
+/// chunks spread evenly over it, with memory proportional to `base.len() / 32`. That is
+/// cheaper than `diff`, especially when the inputs are large or not similar.
+///
+/// The estimate is approximate:
+///
+/// - With `n = min(data.len(), 1 MB) / 32` samples, the statistical error is about
+///   `sqrt(r * (1 - r) / n)` for a true ratio `r`, i.e. under 0.03 for 10 KB of `data` and
+///   under 0.01 for 100 KB. Small inputs are estimated poorly; `data` of less than about 1 KB
+///   gives too few samples to be meaningful, and if there are no samples at all the result is 1.
+/// - For `data` larger than 1 MB, changes that are concentrated between the sampled chunks
+///   are missed or over-counted.
+/// - Each changed byte makes the 64 bytes around it count as new, so many small scattered
+///   changes make the estimate too high.
+/// - The estimate ignores compression of the new content and the small fixed overhead of the
+///   delta, so for compressible `data` the real ratio is lower.
+pub fn estimate_ratio(base: &[u8], data: &[u8]) -> f64 {
+    let mut base_hashes =
+        HashSet::with_capacity_and_hasher(base.len() >> SAMPLE_BITS, BuildIdentityHasher);
+    for_each_sampled_hash(base, 0, |hash| {
+        base_hashes.insert(hash);
+    });
+
+    let mut samples = 0u64;
+    let mut new = 0u64;
+    let mut check = |hash| {
+        samples += 1;
+        if !base_hashes.contains(&hash) {
+            new += 1;
+        }
+    };
+    if data.len() <= DATA_LIMIT {
+        for_each_sampled_hash(data, 0, &mut check);
+    } else {
+        let chunks = DATA_LIMIT / DATA_CHUNK;
+        let stride = data.len() / chunks;
+        for i in 0..chunks {
+            // Start a window early so the first sampled hash covers the same bytes as in `base`.
+            let start = (i * stride).saturating_sub(GEAR_WINDOW - 1);
+            let skip = i * stride - start;
+            for_each_sampled_hash(&data[start..i * stride + DATA_CHUNK], skip, &mut check);
+        }
+    }
+
+    if samples == 0 {
+        1.0
+    } else {
+        new as f64 / samples as f64
+    }
+}
+
 /// Apply a zstd `delta` generated by `diff` to `base`. Return reconstructed `data`.
+///
+/// `base` must be the same buffer that was passed to `diff`, including when it was empty.
+/// `delta` is never empty for output of `diff`, so an empty `delta` is rejected.
+///
+/// `base`, `delta` and the reconstructed data are limited to `DEFAULT_MAX_INPUT_SIZE`. The
+/// sizes that the delta declares are also checked against the sizes of `base` and `delta`
+/// before anything is allocated, see `MAX_EXPANSION`. So a corrupt or malicious delta is
+/// rejected with an error instead of causing a huge allocation, and any bytes can safely be
+/// passed as `delta`.
+///
+/// This uses a decompression context that is kept per thread, see `ZstdDeltaDecoder`.
 pub fn apply(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
-    unsafe {
-        let dctx = ZSTD_createDCtx();
+    with_thread_decoder(|decoder| decoder.apply(base, delta))
+}
+
+/// Like `apply`, but write the reconstructed data to `out` as it is decompressed instead of
+/// collecting it in a buffer. Return the number of bytes written.
+///
+/// Useful when the reconstructed data is large and is going to be written to a file or socket
+/// anyway. `base` still has to be in memory, since the delta can refer to any part of it.
+/// On error, part of the data might have been written to `out` already.
+pub fn apply_to_writer(base: &[u8], delta: &[u8], out: &mut impl Write) -> io::Result<u64> {
+    with_thread_decoder(|decoder| decoder.apply_to_writer(base, delta, out))
+}
+
+/// Applies zstd deltas like `apply`, reusing one decompression context for all of them.
+///
+/// Like `ZstdDeltaEncoder`, a decoder can be sent to another thread but is not `Sync`.
+pub struct ZstdDeltaDecoder {
+    dctx: *mut ZSTD_DCtx,
+    max_input_size: usize,
+}
+
+// The context is only accessed through `&mut self`.
+unsafe impl Send for ZstdDeltaDecoder {}
+
+impl ZstdDeltaDecoder {
+    pub fn new() -> io::Result<Self> {
+        let dctx = unsafe { ZSTD_createDCtx() };
         if dctx.is_null() {
             return Err(io::Error::new(io::ErrorKind::Other, "cannot create DCtx"));
         }
-        ZSTD_DCtx_setMaxWindowSize(dctx, 1 << ZSTD_WINDOWLOG_MAX);
+        unsafe { ZSTD_DCtx_setMaxWindowSize(dctx, 1 << ZSTD_WINDOWLOG_MAX) };
+        Ok(Self {
+            dctx,
+            max_input_size: DEFAULT_MAX_INPUT_SIZE,
+        })
+    }
 
-        let size = ZSTD_findDecompressedSize(delta.as_ptr() as *const c_void, delta.len()) as usize;
-        if size == ZSTD_CONTENTSIZE_ERROR as usize || size == ZSTD_CONTENTSIZE_UNKNOWN as usize {
-            ZSTD_freeDCtx(dctx);
-            let msg = "cannot get decompress size";
-            return Err(io::Error::new(io::ErrorKind::Other, msg));
+    /// Set the limit for the size of `base`, `delta` and the data reconstructed by `apply`,
+    /// which defaults to `DEFAULT_MAX_INPUT_SIZE`. `apply_to_writer` does not buffer the
+    /// reconstructed data, so its size is not limited there.
+    pub fn with_max_input_size(mut self, max_input_size: usize) -> Self {
+        self.max_input_size = max_input_size;
+        self
+    }
+
+    /// Apply a zstd `delta` to `base`. See `apply`.
+    pub fn apply(&mut self, base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+        // zstd reports a decompressed size of 0 for an input without any frame, which would
+        // make a truncated (empty) delta silently decode to nothing.
+        if delta.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty delta"));
         }
+        check_size(base.len() as u64, self.max_input_size)?;
+        check_size(delta.len() as u64, self.max_input_size)?;
+        if is_identity_delta(delta) {
+            return Ok(base.to_vec());
+        }
+        check_frame_header(base, delta)?;
 
-        let mut buf: Vec<u8> = Vec::with_capacity(size);
-        buf.set_len(size);
+        unsafe {
+            let size = ZSTD_findDecompressedSize(delta.as_ptr() as *const c_void, delta.len());
+            if size == ZSTD_CONTENTSIZE_ERROR as u64 || size == ZSTD_CONTENTSIZE_UNKNOWN as u64 {
+                let msg = "cannot get decompress size";
+                return Err(io::Error::new(io::ErrorKind::Other, msg));
+            }
+            // The size comes from the delta, which might be corrupt or untrusted. It covers all
+            // the frames of the delta, not only the first one.
+            check_size(size, self.max_input_size)?;
+            let ceiling = size_ceiling(base.len(), delta.len());
+            check_declared_size("content size", size, ceiling, base.len(), delta.len())?;
+            let size = size as usize;
 
-        let outsize = ZSTD_decompress_usingDict(
-            dctx,
-            buf.as_mut_ptr() as *mut c_void,
-            size,
-            delta.as_ptr() as *const c_void,
-            delta.len(),
-            base.as_ptr() as *const c_void,
-            base.len(),
-        );
-        ZSTD_freeDCtx(dctx);
-
-        if ZSTD_isError(outsize) != 0 {
-            let msg = format!("cannot decompress ({})", explain_error(outsize));
-            Err(io::Error::new(io::ErrorKind::Other, msg))
-        } else if outsize != size {
-            let msg = format!(
-                "decompress size mismatch (expected {}, got {})",
-                size, outsize
+            let mut buf: Vec<u8> = Vec::with_capacity(size);
+            buf.set_len(size);
+
+            let outsize = ZSTD_decompress_usingDict(
+                self.dctx,
+                buf.as_mut_ptr() as *mut c_void,
+                size,
+                delta.as_ptr() as *const c_void,
+                delta.len(),
+                base.as_ptr() as *const c_void,
+                base.len(),
             );
-            Err(io::Error::new(io::ErrorKind::Other, msg))
-        } else {
-            Ok(buf)
+
+            if ZSTD_isError(outsize) != 0 {
+                let msg = format!("cannot decompress ({})", explain_error(outsize));
+                Err(io::Error::new(io::ErrorKind::Other, msg))
+            } else if outsize != size {
+                let msg = format!(
+                    "decompress size mismatch (expected {}, got {})",
+                    size, outsize
+                );
+                Err(io::Error::new(io::ErrorKind::Other, msg))
+            } else {
+                Ok(buf)
+            }
+        }
+    }
+
+    /// Apply a zstd `delta` to `base`, writing the result to `out`. See `apply_to_writer`.
+    pub fn apply_to_writer(
+        &mut self,
+        base: &[u8],
+        delta: &[u8],
+        out: &mut impl Write,
+    ) -> io::Result<u64> {
+        if delta.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty delta"));
+        }
+        check_size(base.len() as u64, self.max_input_size)?;
+        check_size(delta.len() as u64, self.max_input_size)?;
+        if is_identity_delta(delta) {
+            out.write_all(base)?;
+            return Ok(base.len() as u64);
+        }
+        // The streaming decoder allocates the window declared by the frame.
+        check_frame_header(base, delta)?;
+
+        unsafe {
+            // A previous call might have stopped in the middle of a frame.
+            ZSTD_DCtx_reset(self.dctx, ZSTD_ResetDirective::ZSTD_reset_session_only);
+            decompress_stream(self.dctx, base, delta, out)
+        }
+    }
+}
+
+impl Drop for ZstdDeltaDecoder {
+    fn drop(&mut self) {
+        unsafe { ZSTD_freeDCtx(self.dctx) };
+    }
+}
+
+/// Decompress the single frame in `delta` using `base` as its prefix, writing to `out`.
+unsafe fn decompress_stream(
+    dctx: *mut ZSTD_DCtx,
+    base: &[u8],
+    delta: &[u8],
+    out: &mut impl Write,
+) -> io::Result<u64> {
+    let code = ZSTD_DCtx_refPrefix(dctx, base.as_ptr() as *const c_void, base.len());
+    if ZSTD_isError(code) != 0 {
+        let msg = format!("cannot use base ({})", explain_error(code));
+        return Err(io::Error::new(io::ErrorKind::Other, msg));
+    }
+
+    let mut buf = vec![0u8; ZSTD_DStreamOutSize()];
+    let mut input = ZSTD_inBuffer {
+        src: delta.as_ptr() as *const c_void,
+        size: delta.len(),
+        pos: 0,
+    };
+    let mut written = 0u64;
+    loop {
+        let mut output = ZSTD_outBuffer {
+            dst: buf.as_mut_ptr() as *mut c_void,
+            size: buf.len(),
+            pos: 0,
+        };
+        let hint = ZSTD_decompressStream(dctx, &mut output, &mut input);
+        if ZSTD_isError(hint) != 0 {
+            let msg = format!("cannot decompress ({})", explain_error(hint));
+            return Err(io::Error::new(io::ErrorKind::Other, msg));
+        }
+        out.write_all(&buf[..output.pos])?;
+        written += output.pos as u64;
+
+        if hint == 0 {
+            // The frame is complete. `diff` only ever produces a single frame.
+            if input.pos != input.size {
+                let msg = "unexpected data after delta";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+            return Ok(written);
+        }
+        if input.pos == input.size && output.pos < output.size {
+            // All input was consumed and the output buffer was not filled up, so zstd has
+            // nothing more to flush. The frame is incomplete.
+            let msg = "truncated delta";
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
         }
     }
 }
@@ -185,6 +874,105 @@ mod tests {
         assert!(check_round_trip(b"3", b"1234567890"));
     }
 
+    #[test]
+    fn test_adaptive_level() {
+        assert_eq!(adaptive_level(0), 1);
+        assert_eq!(adaptive_level(4 << 10), 1);
+        assert_eq!(adaptive_level((64 << 10) - 1), 1);
+        assert_eq!(adaptive_level(64 << 10), 3);
+        assert_eq!(adaptive_level(500 << 10), 3);
+        assert_eq!(adaptive_level(1 << 20), 6);
+        assert_eq!(adaptive_level(10 << 20), 6);
+        assert_eq!(adaptive_level(32 << 20), 9);
+        assert_eq!(adaptive_level(1 << 30), 9);
+    }
+
+    #[test]
+    fn test_diff_with_level() {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        let mut base = vec![0u8; 200000];
+        rng.fill_bytes(&mut base);
+        let mut data = base.clone();
+        rng.fill_bytes(&mut data[100000..101000]);
+
+        for level in [-1, 0, 1, 3, 9, 19, 100] {
+            let delta = diff_with_level(&base, &data, level).expect("delta");
+            assert!(delta.len() < 2000, "level {}: {}", level, delta.len());
+            assert_eq!(apply(&base, &delta).expect("apply"), data);
+        }
+
+        let delta = diff_adaptive(&base, &data).expect("delta");
+        assert_eq!(apply(&base, &delta).expect("apply"), data);
+        assert_eq!(
+            apply(b"", &diff_adaptive(b"", b"").expect("delta")).expect("apply"),
+            b""
+        );
+    }
+
+    #[test]
+    fn test_empty_inputs() {
+        for (base, data) in [
+            (&b""[..], &b"123"[..]),
+            (&b"123"[..], &b""[..]),
+            (&b""[..], &b""[..]),
+        ] {
+            let delta = diff(base, data).expect("delta");
+            assert!(!delta.is_empty());
+            assert_eq!(apply(base, &delta).expect("apply"), data);
+        }
+
+        assert!(apply(b"", b"").is_err());
+        assert!(apply(b"123", b"").is_err());
+    }
+
+    #[test]
+    fn test_identity_delta() {
+        let mut base = vec![0u8; 100000];
+        ChaChaRng::from_seed([3; 32]).fill_bytes(base.as_mut());
+        let data = base.clone();
+
+        // The identity delta is opt-in, so deltas stay readable by older readers by default.
+        for delta in [
+            diff(&base, &data).expect("diff"),
+            diff_with_level(&base, &data, 3).expect("diff"),
+            diff_adaptive(&base, &data).expect("diff"),
+            diff(b"", b"").expect("diff"),
+        ] {
+            assert!(!is_identity_delta(&delta));
+            assert_eq!(&delta[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+        }
+
+        let mut encoder = ZstdDeltaEncoder::new()
+            .expect("encoder")
+            .with_identity_delta(true);
+        for delta in [
+            encoder.diff(&base, &data).expect("diff"),
+            encoder.diff_with_level(&base, &data, 3).expect("diff"),
+            encoder.diff(b"", b"").expect("diff"),
+        ] {
+            assert!(is_identity_delta(&delta));
+        }
+        let delta = encoder.diff(&base, &data).expect("diff");
+        assert_eq!(apply(&base, &delta).expect("apply"), base);
+        let mut out = Vec::new();
+        assert_eq!(
+            apply_to_writer(&base, &delta, &mut out).expect("apply_to_writer"),
+            base.len() as u64
+        );
+        assert_eq!(out, base);
+
+        // Any other delta, including one that reconstructs `base`, is a zstd frame.
+        for (base, data) in [(&b"123"[..], &b"1234"[..]), (b"", &base)] {
+            assert!(!is_identity_delta(&encoder.diff(base, data).expect("diff")));
+        }
+
+        // The identity delta is only recognized in full.
+        assert!(apply(&base, &delta[..delta.len() - 1]).is_err());
+        let mut longer = delta.clone();
+        longer.push(0);
+        assert!(apply(&base, &longer).is_err());
+    }
+
     #[test]
     fn test_delta_efficiency() {
         // 1 MB incompressible random data
@@ -201,9 +989,251 @@ mod tests {
         assert!(delta.len() < 200);
     }
 
+    #[test]
+    fn test_apply_to_writer() {
+        // Larger than the streaming output buffer, so it is written in several chunks.
+        let mut base = vec![0u8; 1000000];
+        ChaChaRng::from_seed([0; 32]).fill_bytes(base.as_mut());
+        let mut data = base.clone();
+        data[500000] ^= 1;
+        data.extend_from_slice(b"tail");
+
+        for (base, data) in [
+            (&base[..], &data[..]),
+            (b"", b"123"),
+            (b"123", b""),
+            (b"", b""),
+        ] {
+            let delta = diff(base, data).expect("delta");
+            let mut out = Vec::new();
+            let written = apply_to_writer(base, &delta, &mut out).expect("apply_to_writer");
+            assert_eq!(written, data.len() as u64);
+            assert_eq!(out, data);
+        }
+
+        let delta = diff(&base, &data).expect("delta");
+        let mut out = Vec::new();
+        assert!(apply_to_writer(&base, b"", &mut out).is_err());
+        let err = apply_to_writer(&base, &delta[..delta.len() - 1], &mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let mut extended = delta.clone();
+        extended.push(0);
+        assert!(apply_to_writer(&base, &extended, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_diff_with_stats() {
+        let mut base = vec![0u8; 1000000];
+        ChaChaRng::from_seed([0; 32]).fill_bytes(base.as_mut());
+
+        // Mostly copied from base.
+        let mut data = base.clone();
+        data[10000] ^= 3;
+        data[900000] ^= 7;
+        let (delta, stats) = diff_with_stats(&base, &data).expect("diff_with_stats");
+        assert_eq!(delta, diff(&base, &data).expect("diff"));
+        assert_eq!(stats.literal_bytes + stats.copied_bytes, data.len() as u64);
+        assert!(stats.copies >= 3);
+        assert!(stats.literal_bytes < 100);
+
+        // Nothing to copy from.
+        let (_, stats) = diff_with_stats(b"", &base).expect("diff_with_stats");
+        assert_eq!(stats.copies, 0);
+        assert_eq!(stats.literal_bytes, base.len() as u64);
+
+        let (delta, stats) = diff_with_stats(b"", b"").expect("diff_with_stats");
+        assert_eq!(apply(b"", &delta).expect("apply"), b"");
+        assert_eq!(stats, DeltaStats::default());
+    }
+
+    #[test]
+    fn test_encoder_decoder_reuse() {
+        let mut rng = ChaChaRng::from_seed([1; 32]);
+        let mut encoder = ZstdDeltaEncoder::new().expect("encoder");
+        let mut decoder = ZstdDeltaDecoder::new().expect("decoder");
+
+        // Alternate between large and small inputs so the contexts get resized.
+        for i in 0..20 {
+            let len = if i % 2 == 0 { 100000 } else { 10 + i };
+            let mut base = vec![0u8; len];
+            rng.fill_bytes(base.as_mut());
+            let mut data = base.clone();
+            data[len / 2] ^= 1;
+
+            let delta = encoder.diff(&base, &data).expect("diff");
+            assert_eq!(delta, diff(&base, &data).expect("diff"));
+            assert_eq!(decoder.apply(&base, &delta).expect("apply"), data);
+
+            // Leave the decoder in the middle of a frame. The next call should not be affected.
+            let mut out = Vec::new();
+            assert!(
+                decoder
+                    .apply_to_writer(&base, &delta[..delta.len() - 1], &mut out)
+                    .is_err()
+            );
+            out.clear();
+            decoder
+                .apply_to_writer(&base, &delta, &mut out)
+                .expect("apply_to_writer");
+            assert_eq!(out, data);
+        }
+    }
+
+    #[test]
+    fn test_apply_to_writer_reentrant() {
+        // A writer that applies another delta while the thread's decoder is in use.
+        struct ApplyingWriter(Vec<u8>);
+        impl Write for ApplyingWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let delta = diff(b"base", buf)?;
+                self.0.extend(apply(b"base", &delta)?);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let delta = diff(b"abc", b"abcdef").expect("diff");
+        let mut out = ApplyingWriter(Vec::new());
+        apply_to_writer(b"abc", &delta, &mut out).expect("apply_to_writer");
+        assert_eq!(out.0, b"abcdef");
+    }
+
+    #[test]
+    fn test_input_too_large() {
+        let too_large = |err: io::Error| {
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let err = err.into_inner().unwrap();
+            let err = err.downcast_ref::<InputTooLarge>().unwrap();
+            (err.size, err.limit)
+        };
+
+        let mut encoder = ZstdDeltaEncoder::new()
+            .expect("encoder")
+            .with_max_input_size(10);
+        assert!(encoder.diff(b"0123456789", b"0123456789").is_ok());
+        let err = encoder.diff(b"0123456789", b"0123456789a").unwrap_err();
+        assert_eq!(too_large(err), (11, 10));
+        let err = encoder.diff(b"0123456789a", b"").unwrap_err();
+        assert_eq!(too_large(err), (11, 10));
+
+        // The reconstructed size is checked before allocating it.
+        let delta = diff(b"", &[0u8; 100]).expect("diff");
+        assert!(delta.len() <= 50);
+        let mut decoder = ZstdDeltaDecoder::new()
+            .expect("decoder")
+            .with_max_input_size(50);
+        let err = decoder.apply(b"", &delta).unwrap_err();
+        assert_eq!(too_large(err), (100, 50));
+        let mut out = Vec::new();
+        decoder
+            .apply_to_writer(b"", &delta, &mut out)
+            .expect("apply_to_writer");
+        assert_eq!(out, [0u8; 100]);
+        let err = decoder.apply(&[0u8; 51], &delta).unwrap_err();
+        assert_eq!(too_large(err), (51, 50));
+    }
+
+    #[test]
+    fn test_implausible_header() {
+        let invalid_data = |err: io::Error| {
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
+            err.to_string()
+        };
+
+        // A single segment frame, with an 8 byte content size of 512 MiB and no blocks. That is
+        // below `DEFAULT_MAX_INPUT_SIZE`, but far more than 13 bytes of delta can describe.
+        let mut delta = vec![0x28, 0xb5, 0x2f, 0xfd, 0xe0];
+        delta.extend_from_slice(&(512u64 << 20).to_le_bytes());
+        let err = apply(b"", &delta).unwrap_err();
+        assert!(invalid_data(err).contains("content size of 536870912 bytes"));
+        let mut out = Vec::new();
+        let err = apply_to_writer(b"", &delta, &mut out).unwrap_err();
+        assert!(invalid_data(err).contains("content size"));
+        assert!(out.is_empty());
+
+        // A frame with an unknown content size and a 512 MiB window, which only the streaming
+        // decoder would allocate.
+        let delta = [0x28, 0xb5, 0x2f, 0xfd, 0x00, 19 << 3];
+        let err = apply_to_writer(b"", &delta, &mut out).unwrap_err();
+        assert!(invalid_data(err).contains("window size of 536870912 bytes"));
+
+        // Truncated and invalid headers fail cleanly too.
+        let err = apply(b"", &[0x28, 0xb5, 0x2f]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = apply_to_writer(b"", b"not a delta", &mut out).unwrap_err();
+        invalid_data(err);
+
+        // A large base justifies a large window.
+        let base = vec![1u8; 100 << 20];
+        let delta = diff(&base, b"").expect("diff");
+        assert_eq!(apply(&base, &delta).expect("apply"), b"");
+    }
+
+    #[test]
+    fn test_estimate_ratio() {
+        let mut rng = ChaChaRng::from_seed([2; 32]);
+        let mut base = vec![0u8; 1000000];
+        rng.fill_bytes(base.as_mut());
+        let mut unrelated = vec![0u8; 1000000];
+        rng.fill_bytes(unrelated.as_mut());
+
+        assert_eq!(estimate_ratio(&base, &base), 0.0);
+        assert!(estimate_ratio(&base, &unrelated) > 0.99);
+        assert_eq!(estimate_ratio(&base, b""), 1.0);
+        assert_eq!(estimate_ratio(b"", &base), 1.0);
+
+        // Half of the data is new, and the other half is moved around.
+        let mut data = unrelated[..500000].to_vec();
+        data.extend_from_slice(&base[700000..]);
+        data.extend_from_slice(&base[..200000]);
+        let estimate = estimate_ratio(&base, &data);
+        assert!((estimate - 0.5).abs() < 0.05, "estimate {}", estimate);
+
+        // Compare with the real delta, which is dominated by the incompressible new half.
+        let real = diff(&base, &data).expect("diff").len() as f64 / data.len() as f64;
+        assert!(
+            (estimate - real).abs() < 0.05,
+            "estimate {} real {}",
+            estimate,
+            real
+        );
+
+        // Large data is only sampled in chunks.
+        let mut large_base = vec![0u8; 4000000];
+        rng.fill_bytes(large_base.as_mut());
+        let mut large_data = large_base.clone();
+        rng.fill_bytes(&mut large_data[1000000..3000000]);
+        assert_eq!(estimate_ratio(&large_base, &large_base), 0.0);
+        let estimate = estimate_ratio(&large_base, &large_data);
+        assert!((estimate - 0.5).abs() < 0.05, "estimate {}", estimate);
+    }
+
     quickcheck! {
         fn test_round_trip_quickcheck(a: Vec<u8>, b: Vec<u8>) -> bool {
             check_round_trip(&a, &b)
         }
+
+        fn test_apply_to_writer_quickcheck(a: Vec<u8>, b: Vec<u8>) -> bool {
+            let delta = diff(&a, &b).expect("delta");
+            let mut out = Vec::new();
+            apply_to_writer(&a, &delta, &mut out).expect("apply_to_writer");
+            out == b
+        }
+
+        fn test_diff_with_stats_quickcheck(a: Vec<u8>, b: Vec<u8>) -> bool {
+            let (delta, stats) = diff_with_stats(&a, &b).expect("diff_with_stats");
+            apply(&a, &delta).expect("apply") == b
+                && stats.literal_bytes + stats.copied_bytes == b.len() as u64
+        }
+
+        fn test_apply_arbitrary_quickcheck(base: Vec<u8>, delta: Vec<u8>) -> bool {
+            // Arbitrary deltas must fail cleanly, without panicking.
+            let mut out = Vec::new();
+            let _ = apply_to_writer(&base, &delta, &mut out);
+            let _ = apply(&base, &delta);
+            true
+        }
     }
 }